@@ -121,7 +121,7 @@ impl Resumption {
 fn make_server_config(clientauth: &ClientAuth, resume: &Resumption) -> ServerConfig {
   let mut cfg = ServerConfig::new();
   
-  cfg.set_single_cert(get_chain(), get_key());
+  cfg.set_single_cert(get_chain(), get_key()).unwrap();
 
   if clientauth == &ClientAuth::Yes {
     cfg.set_client_auth_roots(get_chain(), true);
@@ -148,7 +148,7 @@ fn make_client_config(suite: &'static rustls::SupportedCipherSuite,
   cfg.ciphersuites.push(suite);
 
   if clientauth == &ClientAuth::Yes {
-    cfg.set_single_client_cert(get_chain(), get_key());
+    cfg.set_single_client_cert(get_chain(), get_key()).unwrap();
   }
 
   if resume != &Resumption::No {
@@ -8,6 +8,7 @@
 extern crate rustls;
 extern crate webpki;
 extern crate env_logger;
+extern crate base64;
 
 use std::env;
 use std::process;
@@ -39,7 +40,12 @@ struct Options {
   key_file: String,
   cert_file: String,
   protocols: Vec<String>,
-  expect_curve: u16
+  expect_curve: u16,
+  require_ems: bool,
+  server_ocsp_response: Vec<u8>,
+  server_sct_list: Vec<u8>,
+  enable_ocsp_stapling: bool,
+  enable_signed_cert_timestamps: bool
 }
 
 impl Options {
@@ -56,7 +62,12 @@ impl Options {
       key_file: "".to_string(),
       cert_file: "".to_string(),
       protocols: vec![],
-      expect_curve: 0
+      expect_curve: 0,
+      require_ems: false,
+      server_ocsp_response: vec![],
+      server_sct_list: vec![],
+      enable_ocsp_stapling: false,
+      enable_signed_cert_timestamps: false
     }
   }
 }
@@ -69,16 +80,27 @@ fn load_cert(filename: &str) -> Vec<Vec<u8>> {
 }
 
 fn load_key(filename: &str) -> Vec<u8> {
-  if filename.contains("ecdsa") {
-    println_err!("No ECDSA key support");
+  if filename.contains("p521") {
+    println_err!("No ECDSA P-521 key support");
     process::exit(BOGO_NACK);
   }
 
+  /* fetch-and-build converts the keys into formats we can read. */
+  let is_ecdsa = filename.contains("ecdsa");
+  let filename = if is_ecdsa {
+    filename.replace(".pem", ".pkcs8")
+  } else {
+    filename.replace(".pem", ".rsa")
+  };
+
   let keyfile = fs::File::open(filename)
     .expect("cannot open private key file");
   let mut reader = BufReader::new(keyfile);
-  let keys = rustls::internal::pemfile::rsa_private_keys(&mut reader)
-    .unwrap();
+  let keys = if is_ecdsa {
+    rustls::internal::pemfile::pkcs8_private_keys(&mut reader)
+  } else {
+    rustls::internal::pemfile::rsa_private_keys(&mut reader)
+  }.unwrap();
   assert!(keys.len() == 1);
   keys[0].clone()
 }
@@ -103,20 +125,25 @@ fn make_server_cfg(opts: &Options) -> Arc<rustls::ServerConfig> {
   cfg.set_persistence(persist);
 
   let cert = load_cert(&opts.cert_file);
-  let key = load_key(&opts.key_file.replace(".pem", ".rsa"));
-  cfg.set_single_cert(cert.clone(), key);
-
-  if opts.offer_no_client_cas {
-    cfg.client_auth_offer = true;
-  } else if opts.require_any_client_cert {
-    cfg.client_auth_offer = true;
-    cfg.client_auth_mandatory = true;
+  let key = load_key(&opts.key_file);
+  cfg.set_single_cert_with_ocsp_and_sct(cert.clone(), key,
+                                        opts.server_ocsp_response.clone(),
+                                        opts.server_sct_list.clone())
+    .unwrap();
+
+  if opts.offer_no_client_cas || opts.require_any_client_cert {
+    let roots = rustls::RootCertStore::empty();
+    cfg.client_cert_verifier = Box::new(
+      rustls::WebPKIClientVerifier::new(roots, opts.require_any_client_cert)
+    );
   }
 
   if opts.tickets {
     cfg.ticketer = rustls::Ticketer::new();
   }
 
+  cfg.require_ems = opts.require_ems;
+
   if opts.protocols.len() > 0 {
     cfg.set_protocols(&opts.protocols);
   }
@@ -132,14 +159,25 @@ fn make_client_cfg(opts: &Options) -> Arc<rustls::ClientConfig> {
 
   if opts.cert_file.len() > 0 && opts.key_file.len() > 0 {
     let cert = load_cert(&opts.cert_file);
-    let key = load_key(&opts.key_file.replace(".pem", ".rsa"));
-    cfg.set_single_client_cert(cert, key);
+    let key = load_key(&opts.key_file);
+    cfg.set_single_client_cert(cert, key).unwrap();
   }
 
   if opts.protocols.len() > 0 {
     cfg.set_protocols(&opts.protocols);
   }
 
+  cfg.require_ems = opts.require_ems;
+
+  if opts.enable_ocsp_stapling {
+    cfg.ocsp_policy = rustls::OCSPPolicy::SoftFail;
+  }
+
+  if opts.enable_signed_cert_timestamps {
+    /* We have no logs to check against; just ask. */
+    cfg.ct_policy = Some(rustls::CTPolicy::new(0));
+  }
+
   Arc::new(cfg)
 }
 
@@ -261,11 +299,12 @@ fn main() {
       },
       "-expect-curve-id" | "-expect-peer-signature-algorithm" |
         "-expect-advertised-alpn" | "-expect-alpn" |
-        "-expect-server-name" | "-expect-certificate-types" => {
+        "-expect-server-name" | "-expect-certificate-types" |
+        "-expect-ocsp-response" | "-expect-signed-cert-timestamps" => {
         println!("not checking {} {}; NYI", arg, args.remove(0));
       },
       "-expect-no-session" | "-expect-session-miss" |
-        "-expect-ticket-renewal" => {},
+        "-expect-ticket-renewal" | "-expect-extended-master-secret" => {},
 
       "-select-alpn" => {
         opts.protocols.push(args.remove(0));
@@ -285,6 +324,21 @@ fn main() {
       "-use-null-client-ca-list" => {
         opts.offer_no_client_cas = true;
       },
+      "-require-extended-master-secret" => {
+        opts.require_ems = true;
+      },
+      "-ocsp-response" => {
+        opts.server_ocsp_response = base64::decode(&args.remove(0)).unwrap();
+      },
+      "-enable-ocsp-stapling" => {
+        opts.enable_ocsp_stapling = true;
+      },
+      "-signed-cert-timestamps" => {
+        opts.server_sct_list = base64::decode(&args.remove(0)).unwrap();
+      },
+      "-enable-signed-cert-timestamps" => {
+        opts.enable_signed_cert_timestamps = true;
+      },
 
       /* defaults: */
       "-enable-all-curves" | "-renegotiate-ignore" |
@@ -295,11 +349,10 @@ fn main() {
         "-use-old-client-cert-callback" | "-use-early-callback" => {},
 
       /* Not implemented things */
-      "-dtls" | "-enable-ocsp-stapling" | "-cipher" |
+      "-dtls" | "-cipher" |
         "-no-tls13" | "-no-ssl3" | "-max-version" | "-min-version" |
         "-psk" | "-renegotiate-freely" | "-false-start" |
         "-fallback-scsv" | "-fail-early-callback" | "-install-ddos-callback" |
-        "-enable-signed-cert-timestamps" | "-ocsp-response" |
         "-advertise-npn" | "-verify-fail" |
         "-verify-peer" | "-expect-channel-id" |
         "-shim-shuts-down" | "-check-close-notify" |
@@ -309,8 +362,7 @@ fn main() {
         "-export-keying-material" | "-tls-unique" |
         "-enable-server-custom-extension" |
         "-enable-client-custom-extension" |
-        "-expect-dhe-group-size" | "-use-ticket-callback" |
-        "-signed-cert-timestamps" => {
+        "-expect-dhe-group-size" | "-use-ticket-callback" => {
         println!("NYI option {:?}", arg);
         process::exit(BOGO_NACK);
       }
@@ -13,7 +13,8 @@ use std::io::{Read, Write, BufReader};
 
 extern crate env_logger;
 
-extern crate rustc_serialize;
+#[macro_use]
+extern crate serde_derive;
 extern crate docopt;
 use docopt::Docopt;
 
@@ -33,15 +34,15 @@ struct TlsClient {
   tls_session: rustls::ClientSession
 }
 
-impl mio::Handler for TlsClient {
+impl mio::deprecated::Handler for TlsClient {
   type Timeout = ();
   type Message = ();
 
   /// Called by mio each time events we register() for happen.
   fn ready(&mut self,
-           event_loop: &mut mio::EventLoop<TlsClient>,
+           event_loop: &mut mio::deprecated::EventLoop<TlsClient>,
            token: mio::Token,
-           events: mio::EventSet) {
+           events: mio::Ready) {
     assert_eq!(token, CLIENT);
 
     if events.is_readable() {
@@ -63,8 +64,8 @@ impl mio::Handler for TlsClient {
   /* XXX: this won't be called currently, but could be used in the future
    * to have timeout behaviour. */
   fn timeout(&mut self,
-             _event_loop: &mut mio::EventLoop<TlsClient>,
-             _timeout: <TlsClient as mio::Handler>::Timeout) {
+             _event_loop: &mut mio::deprecated::EventLoop<TlsClient>,
+             _timeout: <TlsClient as mio::deprecated::Handler>::Timeout) {
     println!("connection timed out");
     process::exit(1);
   }
@@ -158,7 +159,7 @@ impl TlsClient {
     self.tls_session.write_tls(&mut self.socket).unwrap();
   }
 
-  fn register(&self, event_loop: &mut mio::EventLoop<TlsClient>) {
+  fn register(&self, event_loop: &mut mio::deprecated::EventLoop<TlsClient>) {
     event_loop.register(&self.socket,
                         CLIENT,
                         self.event_set(),
@@ -166,7 +167,7 @@ impl TlsClient {
       .unwrap();
   }
 
-  fn reregister(&self, event_loop: &mut mio::EventLoop<TlsClient>) {
+  fn reregister(&self, event_loop: &mut mio::deprecated::EventLoop<TlsClient>) {
     event_loop.reregister(&self.socket,
                           CLIENT,
                           self.event_set(),
@@ -176,16 +177,16 @@ impl TlsClient {
 
   /* Use wants_read/wants_write to register for different mio-level
    * IO readiness events. */
-  fn event_set(&self) -> mio::EventSet {
+  fn event_set(&self) -> mio::Ready {
     let rd = self.tls_session.wants_read();
     let wr = self.tls_session.wants_write();
 
     if rd && wr {
-      mio::EventSet::readable() | mio::EventSet::writable()
+      mio::Ready::readable() | mio::Ready::writable()
     } else if wr {
-      mio::EventSet::writable()
+      mio::Ready::writable()
     } else {
-      mio::EventSet::readable()
+      mio::Ready::readable()
     }
   }
 
@@ -284,7 +285,7 @@ If --cafile is not supplied, a built-in set of CA certificates
 are used from the webpki-roots crate.
 
 Usage:
-  tlsclient [options] [--suite SUITE ...] [--proto PROTO ...] <hostname>
+  tlsclient [options] [--suite SUITE ...] [--proto PROTO ...] [--protover VERSION ...] <hostname>
   tlsclient (--version | -v)
   tlsclient (--help | -h)
 
@@ -299,6 +300,8 @@ Options:
                         SUITE instead.  May be used multiple times.
     --proto PROTOCOL    Send ALPN extension containing PROTOCOL.
                         May be used multiple times to offer serveral protocols.
+    --protover VERSION  Disable default TLS version list, and use
+                        VERSION instead.  May be used multiple times.
     --cache CACHE       Save session cache to file CACHE.
     --no-tickets        Disable session ticket support.
     --verbose           Emit log output.
@@ -307,13 +310,14 @@ Options:
     --help, -h          Show this screen.
 ";
 
-#[derive(Debug, RustcDecodable)]
+#[derive(Debug, Deserialize)]
 struct Args {
   flag_port: Option<u16>,
   flag_http: bool,
   flag_verbose: bool,
   flag_suite: Vec<String>,
   flag_proto: Vec<String>,
+  flag_protover: Vec<String>,
   flag_mtu: Option<usize>,
   flag_cafile: Option<String>,
   flag_cache: Option<String>,
@@ -352,6 +356,22 @@ fn find_suite(name: &str) -> Option<&'static rustls::SupportedCipherSuite> {
   None
 }
 
+/// Make a vector of protocol versions named in `versions`
+fn lookup_versions(versions: &Vec<String>) -> Vec<rustls::ProtocolVersion> {
+  let mut out = Vec::new();
+
+  for vname in versions {
+    let version = match vname.as_ref() {
+      "1.2" => rustls::ProtocolVersion::TLSv1_2,
+      "1.3" => rustls::ProtocolVersion::TLSv1_3,
+      _ => panic!("cannot look up version '{}', valid are '1.2' and '1.3'", vname)
+    };
+    out.push(version);
+  }
+
+  out
+}
+
 /// Make a vector of ciphersuites named in `suites`
 fn lookup_suites(suites: &Vec<String>) -> Vec<&'static rustls::SupportedCipherSuite> {
   let mut out = Vec::new();
@@ -392,7 +412,8 @@ fn load_key_and_cert(config: &mut rustls::ClientConfig,
   let certs = load_certs(certsfile);
   let privkey = load_private_key(keyfile);
 
-  config.set_single_client_cert(certs, privkey);
+  config.set_single_client_cert(certs, privkey)
+    .expect("invalid client auth certificate or key");
 }
 
 /// Build a ClientConfig from our arguments
@@ -403,6 +424,10 @@ fn make_config(args: &Args) -> Arc<rustls::ClientConfig> {
     config.ciphersuites = lookup_suites(&args.flag_suite);
   }
 
+  if args.flag_protover.len() != 0 {
+    config.versions = lookup_versions(&args.flag_protover);
+  }
+
   if args.flag_cafile.is_some() {
     let cafile = args.flag_cafile.as_ref().unwrap();
 
@@ -412,7 +437,7 @@ fn make_config(args: &Args) -> Arc<rustls::ClientConfig> {
     config.root_store.add_pem_file(&mut reader)
       .unwrap();
   } else {
-    config.root_store.add_trust_anchors(&webpki_roots::ROOTS);
+    config.root_store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0);
   }
 
   if args.flag_no_tickets {
@@ -444,7 +469,7 @@ fn main() {
   let args: Args = Docopt::new(USAGE)
     .and_then(|d| Ok(d.help(true)))
     .and_then(|d| Ok(d.version(Some(version))))
-    .and_then(|d| d.decode())
+    .and_then(|d| d.deserialize())
     .unwrap_or_else(|e| e.exit());
 
   if args.flag_verbose {
@@ -469,7 +494,7 @@ fn main() {
     tlsclient.read_source_to_end(&mut stdin).unwrap();
   }
 
-  let mut event_loop = mio::EventLoop::new().unwrap();
+  let mut event_loop = mio::deprecated::EventLoop::new().unwrap();
   tlsclient.register(&mut event_loop);
   event_loop.run(&mut tlsclient).unwrap();
 }
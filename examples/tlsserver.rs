@@ -1,19 +1,20 @@
 use std::sync::Arc;
 
 extern crate mio;
-use mio::util::Slab;
-use mio::TryRead;
+use mio::deprecated::TryRead;
 use mio::tcp::{TcpListener, TcpStream, Shutdown};
 
 #[macro_use]
 extern crate log;
 
 use std::fs;
+use std::collections::HashMap;
 use std::io;
 use std::net;
 use std::io::{Write, Read, BufReader};
 
-extern crate rustc_serialize;
+#[macro_use]
+extern crate serde_derive;
 extern crate docopt;
 use docopt::Docopt;
 
@@ -44,48 +45,48 @@ enum ServerMode {
 /// connections, and a TLS server configuration.
 struct TlsServer {
   server: TcpListener,
-  connections: Slab<Connection>,
+  connections: HashMap<mio::Token, Connection>,
+  next_id: usize,
   tls_config: Arc<rustls::ServerConfig>,
   mode: ServerMode
 }
 
 impl TlsServer {
   fn new(server: TcpListener, mode: ServerMode, cfg: Arc<rustls::ServerConfig>) -> TlsServer {
-    let slab = Slab::new_starting_at(mio::Token(1), 256);
-
     TlsServer {
       server: server,
-      connections: slab,
+      connections: HashMap::new(),
+      next_id: 1,
       tls_config: cfg,
       mode: mode
     }
   }
 }
 
-impl mio::Handler for TlsServer {
+impl mio::deprecated::Handler for TlsServer {
   type Timeout = ();
   type Message = ();
 
   fn ready(&mut self,
-           event_loop: &mut mio::EventLoop<TlsServer>,
+           event_loop: &mut mio::deprecated::EventLoop<TlsServer>,
            token: mio::Token,
-           events: mio::EventSet) {
+           events: mio::Ready) {
     match token {
       /* Our listening socket: we have a new connection. */
       LISTENER => {
         match self.server.accept() {
-          Ok(Some((socket, addr))) => {
+          Ok((socket, addr)) => {
             info!("Accepting new connection from {:?}", addr);
 
             let tls_session = rustls::ServerSession::new(&self.tls_config);
             let mode = self.mode.clone();
-            let token = self.connections
-              .insert_with(|token| Connection::new(socket, token, mode, tls_session))
-              .unwrap();
+            let token = mio::Token(self.next_id);
+            self.next_id += 1;
 
-            self.connections[token].register(event_loop);
+            self.connections.insert(token, Connection::new(socket, token, mode, tls_session));
+            self.connections[&token].register(event_loop);
           }
-          Ok(None) => {
+          Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
           }
           Err(e) => {
             println!("encountered error while accepting connection; err={:?}", e);
@@ -96,10 +97,16 @@ impl mio::Handler for TlsServer {
 
       /* A connection socket. */
       _ => {
-        self.connections[token].ready(event_loop, events);
+        let closed = match self.connections.get_mut(&token) {
+          Some(conn) => {
+            conn.ready(event_loop, events);
+            conn.is_closed()
+          }
+          None => false
+        };
 
-        if self.connections[token].is_closed() {
-          self.connections.remove(token);
+        if closed {
+          self.connections.remove(&token);
         }
       }
     }
@@ -151,8 +158,8 @@ impl Connection {
 
   /// We're a connection, and we have something to do.
   fn ready(&mut self,
-           event_loop: &mut mio::EventLoop<TlsServer>,
-           events: mio::EventSet) {
+           event_loop: &mut mio::deprecated::EventLoop<TlsServer>,
+           events: mio::Ready) {
     /* If we're readable: read some TLS.  Then
      * see if that yielded new plaintext.  Then
      * see if the backend is readable too. */
@@ -285,7 +292,7 @@ impl Connection {
     }
   }
 
-  fn register(&self, event_loop: &mut mio::EventLoop<TlsServer>) {
+  fn register(&self, event_loop: &mut mio::deprecated::EventLoop<TlsServer>) {
     event_loop.register(&self.socket,
                         self.token,
                         self.event_set(),
@@ -295,13 +302,13 @@ impl Connection {
     if self.back.is_some() {
       event_loop.register(self.back.as_ref().unwrap(),
                           self.token,
-                          mio::EventSet::readable(),
+                          mio::Ready::readable(),
                           mio::PollOpt::level() | mio::PollOpt::oneshot())
         .unwrap();
     }
   }
 
-  fn reregister(&self, event_loop: &mut mio::EventLoop<TlsServer>) {
+  fn reregister(&self, event_loop: &mut mio::deprecated::EventLoop<TlsServer>) {
     event_loop.reregister(&self.socket,
                           self.token,
                           self.event_set(),
@@ -311,7 +318,7 @@ impl Connection {
     if self.back.is_some() {
       event_loop.reregister(self.back.as_ref().unwrap(),
                             self.token,
-                            mio::EventSet::readable(),
+                            mio::Ready::readable(),
                             mio::PollOpt::level() | mio::PollOpt::oneshot())
         .unwrap();
     }
@@ -319,16 +326,16 @@ impl Connection {
 
   /// What IO events we're currently waiting for,
   /// based on wants_read/wants_write.
-  fn event_set(&self) -> mio::EventSet {
+  fn event_set(&self) -> mio::Ready {
     let rd = self.tls_session.wants_read();
     let wr = self.tls_session.wants_write();
 
     if rd && wr {
-      mio::EventSet::readable() | mio::EventSet::writable()
+      mio::Ready::readable() | mio::Ready::writable()
     } else if wr {
-      mio::EventSet::writable()
+      mio::Ready::writable()
     } else {
-      mio::EventSet::readable()
+      mio::Ready::readable()
     }
   }
 
@@ -351,9 +358,9 @@ localhost:fport.
 key.
 
 Usage:
-  tlsserver --certs CERTFILE --key KEYFILE [--suite SUITE ...] [--proto PROTO ...] [options] echo
-  tlsserver --certs CERTFILE --key KEYFILE [--suite SUITE ...] [--proto PROTO ...] [options] http
-  tlsserver --certs CERTFILE --key KEYFILE [--suite SUITE ...] [--proto PROTO ...] [options] forward <fport>
+  tlsserver --certs CERTFILE --key KEYFILE [--suite SUITE ...] [--proto PROTO ...] [--protover VERSION ...] [options] echo
+  tlsserver --certs CERTFILE --key KEYFILE [--suite SUITE ...] [--proto PROTO ...] [--protover VERSION ...] [options] http
+  tlsserver --certs CERTFILE --key KEYFILE [--suite SUITE ...] [--proto PROTO ...] [--protover VERSION ...] [options] forward <fport>
   tlsserver (--version | -v)
   tlsserver (--help | -h)
 
@@ -375,12 +382,14 @@ Options:
                         SUITE instead.  May be used multiple times.
     --proto PROTOCOL    Negotiate PROTOCOL using ALPN.
                         May be used multiple times.
+    --protover VERSION  Disable default TLS version list, and use
+                        VERSION instead.  May be used multiple times.
     --verbose           Emit log output.
     --version, -v       Show tool version.
     --help, -h          Show this screen.
 ";
 
-#[derive(Debug, RustcDecodable)]
+#[derive(Debug, Deserialize)]
 struct Args {
   cmd_echo: bool,
   cmd_http: bool,
@@ -389,6 +398,7 @@ struct Args {
   flag_verbose: bool,
   flag_suite: Vec<String>,
   flag_proto: Vec<String>,
+  flag_protover: Vec<String>,
   flag_certs: Option<String>,
   flag_key: Option<String>,
   flag_auth: Option<String>,
@@ -424,6 +434,21 @@ fn lookup_suites(suites: &Vec<String>) -> Vec<&'static rustls::SupportedCipherSu
   out
 }
 
+fn lookup_versions(versions: &Vec<String>) -> Vec<rustls::ProtocolVersion> {
+  let mut out = Vec::new();
+
+  for vname in versions {
+    let version = match vname.as_ref() {
+      "1.2" => rustls::ProtocolVersion::TLSv1_2,
+      "1.3" => rustls::ProtocolVersion::TLSv1_3,
+      _ => panic!("cannot look up version '{}', valid are '1.2' and '1.3'", vname)
+    };
+    out.push(version);
+  }
+
+  out
+}
+
 fn load_certs(filename: &str) -> Vec<Vec<u8>> {
   let certfile = fs::File::open(filename)
     .expect("cannot open certificate file");
@@ -447,7 +472,8 @@ fn make_config(args: &Args) -> Arc<rustls::ServerConfig> {
 
   let certs = load_certs(&args.flag_certs.as_ref().expect("--certs option missing"));
   let privkey = load_private_key(&args.flag_key.as_ref().expect("--key option missing"));
-  config.set_single_cert(certs, privkey);
+  config.set_single_cert(certs, privkey)
+    .expect("invalid certificate or key");
 
   if args.flag_auth.is_some() {
     let client_auth_roots = load_certs(&args.flag_auth.as_ref().unwrap());
@@ -458,6 +484,10 @@ fn make_config(args: &Args) -> Arc<rustls::ServerConfig> {
     config.ciphersuites = lookup_suites(&args.flag_suite);
   }
 
+  if args.flag_protover.len() != 0 {
+    config.versions = lookup_versions(&args.flag_protover);
+  }
+
   if args.flag_resumption {
     config.set_persistence(rustls::ServerSessionMemoryCache::new(256));
   }
@@ -477,7 +507,7 @@ fn main() {
   let args: Args = Docopt::new(USAGE)
     .and_then(|d| Ok(d.help(true)))
     .and_then(|d| Ok(d.version(Some(version))))
-    .and_then(|d| d.decode())
+    .and_then(|d| d.deserialize())
     .unwrap_or_else(|e| e.exit());
 
   if args.flag_verbose {
@@ -492,9 +522,9 @@ fn main() {
   let config = make_config(&args);
 
   let listener = TcpListener::bind(&addr).expect("cannot listen on port");
-  let mut event_loop = mio::EventLoop::new().unwrap();
+  let mut event_loop = mio::deprecated::EventLoop::new().unwrap();
   event_loop.register(&listener, LISTENER,
-                      mio::EventSet::readable(),
+                      mio::Ready::readable(),
                       mio::PollOpt::level()).unwrap();
 
   let mode = if args.cmd_echo {
@@ -26,7 +26,7 @@ fn parse_args(args: &Vec<String>) -> Result<(String, u16, ClientConfig), Box<Err
     let mut config = ClientConfig::new();
     match args.len() {
         3 => {
-            config.root_store.add_trust_anchors(&webpki_roots::ROOTS);
+            config.root_store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0);
         }
         4 => {
             let f = try!(File::open(&args[3]));
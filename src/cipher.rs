@@ -2,11 +2,13 @@ use ring;
 use std::io::Write;
 use msgs::codec;
 use msgs::codec::Codec;
+use msgs::enums::{ContentType, ProtocolVersion};
 use msgs::message::{Message, MessagePayload};
 use msgs::fragmenter::MAX_FRAGMENT_LEN;
 use error::TLSError;
 use session::SessionSecrets;
 use suites::{SupportedCipherSuite, BulkAlgorithm};
+use key_schedule::{derive_traffic_key, derive_traffic_iv};
 
 // accum[i] ^= offset[i] for all i in 0..len(accum)
 fn xor(accum: &mut [u8], offset: &[u8]) {
@@ -15,74 +17,109 @@ fn xor(accum: &mut [u8], offset: &[u8]) {
   }
 }
 
-/// Objects with this trait can encrypt and decrypt TLS messages.
-pub trait MessageCipher {
+/// Objects with this trait can decrypt TLS messages.
+pub trait MessageDecrypter {
   fn decrypt(&self, m: Message, seq: u64) -> Result<Message, TLSError>;
+}
+
+/// Objects with this trait can encrypt TLS messages.
+pub trait MessageEncrypter {
   fn encrypt(&self, m: Message, seq: u64) -> Result<Message, TLSError>;
 }
 
-impl MessageCipher {
-  /// Make a MessageCipher that doesn't work.
-  pub fn invalid() -> Box<MessageCipher + Send + Sync> {
-    Box::new(InvalidMessageCipher {})
+impl MessageEncrypter {
+  /// Make a MessageEncrypter that doesn't work.
+  pub fn invalid() -> Box<MessageEncrypter + Send + Sync> {
+    Box::new(InvalidMessageEncrypter {})
   }
+}
 
-  /// Make a MessageCipher based on the given supported ciphersuite `scs`,
-  /// and the session's `secrets`.
-  pub fn new(scs: &'static SupportedCipherSuite, secrets: &SessionSecrets) -> Box<MessageCipher + Send + Sync> {
-    /* Make a key block, and chop it up. */
-    let key_block = secrets.make_key_block(scs.key_block_len());
-
-    let mut offs = 0;
-    let client_write_mac_key = &key_block[offs..offs+scs.mac_key_len]; offs += scs.mac_key_len;
-    let server_write_mac_key = &key_block[offs..offs+scs.mac_key_len]; offs += scs.mac_key_len;
-    let client_write_key = &key_block[offs..offs+scs.enc_key_len]; offs += scs.enc_key_len;
-    let server_write_key = &key_block[offs..offs+scs.enc_key_len]; offs += scs.enc_key_len;
-    let client_write_iv = &key_block[offs..offs+scs.fixed_iv_len]; offs += scs.fixed_iv_len;
-    let server_write_iv = &key_block[offs..offs+scs.fixed_iv_len]; offs += scs.fixed_iv_len;
-    let explicit_nonce_offs = &key_block[offs..offs+scs.explicit_nonce_len];
-
-    let (write_mac_key, write_key, write_iv) = if secrets.randoms.we_are_client {
-      (client_write_mac_key, client_write_key, client_write_iv)
-    } else {
-      (server_write_mac_key, server_write_key, server_write_iv)
-    };
-
-    let (read_mac_key, read_key, read_iv) = if secrets.randoms.we_are_client {
-      (server_write_mac_key, server_write_key, server_write_iv)
-    } else {
-      (client_write_mac_key, client_write_key, client_write_iv)
-    };
+impl MessageDecrypter {
+  /// Make a MessageDecrypter that doesn't work.
+  pub fn invalid() -> Box<MessageDecrypter + Send + Sync> {
+    Box::new(InvalidMessageDecrypter {})
+  }
+}
 
-    let aead_alg = scs.get_aead_alg();
-
-    if scs.bulk == BulkAlgorithm::CHACHA20_POLY1305 {
-      Box::new(ChaCha20Poly1305MessageCipher::new(aead_alg,
-                                                  write_mac_key, write_key, write_iv,
-                                                  read_mac_key, read_key, read_iv))
-    } else {
-      Box::new(GCMMessageCipher::new(aead_alg,
-                                     write_mac_key, write_key, write_iv,
-                                     read_mac_key, read_key, read_iv,
-                                     explicit_nonce_offs))
-    }
+pub type MessageCipherPair = (Box<MessageDecrypter + Send + Sync>, Box<MessageEncrypter + Send + Sync>);
+
+/// Make a `MessageCipherPair` based on the given supported ciphersuite `scs`,
+/// and the session's `secrets`.
+pub fn new_tls12(scs: &'static SupportedCipherSuite, secrets: &SessionSecrets) -> MessageCipherPair {
+  /* Make a key block, and chop it up. */
+  let key_block = secrets.make_key_block(scs.key_block_len());
+
+  let mut offs = 0;
+  let client_write_mac_key = &key_block[offs..offs+scs.mac_key_len]; offs += scs.mac_key_len;
+  let server_write_mac_key = &key_block[offs..offs+scs.mac_key_len]; offs += scs.mac_key_len;
+  let client_write_key = &key_block[offs..offs+scs.enc_key_len]; offs += scs.enc_key_len;
+  let server_write_key = &key_block[offs..offs+scs.enc_key_len]; offs += scs.enc_key_len;
+  let client_write_iv = &key_block[offs..offs+scs.fixed_iv_len]; offs += scs.fixed_iv_len;
+  let server_write_iv = &key_block[offs..offs+scs.fixed_iv_len]; offs += scs.fixed_iv_len;
+  let explicit_nonce_offs = &key_block[offs..offs+scs.explicit_nonce_len];
+
+  let (write_mac_key, write_key, write_iv) = if secrets.randoms.we_are_client {
+    (client_write_mac_key, client_write_key, client_write_iv)
+  } else {
+    (server_write_mac_key, server_write_key, server_write_iv)
+  };
+
+  let (read_mac_key, read_key, read_iv) = if secrets.randoms.we_are_client {
+    (server_write_mac_key, server_write_key, server_write_iv)
+  } else {
+    (client_write_mac_key, client_write_key, client_write_iv)
+  };
+
+  let aead_alg = scs.get_aead_alg();
+
+  if scs.bulk == BulkAlgorithm::CHACHA20_POLY1305 {
+    (Box::new(ChaCha20Poly1305MessageDecrypter::new(aead_alg, read_mac_key, read_key, read_iv)),
+     Box::new(ChaCha20Poly1305MessageEncrypter::new(aead_alg, write_mac_key, write_key, write_iv)))
+  } else {
+    (Box::new(GCMMessageDecrypter::new(aead_alg, read_mac_key, read_key, read_iv)),
+     Box::new(GCMMessageEncrypter::new(aead_alg, write_mac_key, write_key, write_iv,
+                                       explicit_nonce_offs)))
   }
 }
 
-/// A MessageCipher for AES-GCM AEAD ciphersuites.
-pub struct GCMMessageCipher {
+/// Make a TLS1.3 `MessageDecrypter` keyed from the traffic `secret`.
+pub fn new_tls13_read(scs: &'static SupportedCipherSuite, secret: &[u8]) -> Box<MessageDecrypter + Send + Sync> {
+  let hash = scs.get_hash();
+  let key = derive_traffic_key(hash, secret, scs.enc_key_len);
+  let iv = derive_traffic_iv(hash, secret, scs.fixed_iv_len);
+  let aead_alg = scs.get_aead_alg();
+
+  Box::new(TLS13MessageDecrypter::new(aead_alg, &key, &iv))
+}
+
+/// Make a TLS1.3 `MessageEncrypter` keyed from the traffic `secret`.
+pub fn new_tls13_write(scs: &'static SupportedCipherSuite, secret: &[u8]) -> Box<MessageEncrypter + Send + Sync> {
+  let hash = scs.get_hash();
+  let key = derive_traffic_key(hash, secret, scs.enc_key_len);
+  let iv = derive_traffic_iv(hash, secret, scs.fixed_iv_len);
+  let aead_alg = scs.get_aead_alg();
+
+  Box::new(TLS13MessageEncrypter::new(aead_alg, &key, &iv))
+}
+
+/// A `MessageEncrypter` for AES-GCM AEAD ciphersuites.
+pub struct GCMMessageEncrypter {
   alg: &'static ring::aead::Algorithm,
   enc_key: ring::aead::SealingKey,
   enc_salt: [u8; 4],
-  dec_key: ring::aead::OpeningKey,
-  dec_salt: [u8; 4],
   nonce_offset: [u8; 8]
 }
 
+/// A `MessageDecrypter` for AES-GCM AEAD ciphersuites.
+pub struct GCMMessageDecrypter {
+  dec_key: ring::aead::OpeningKey,
+  dec_salt: [u8; 4]
+}
+
 const GCM_EXPLICIT_NONCE_LEN: usize = 8;
 const GCM_OVERHEAD: usize = GCM_EXPLICIT_NONCE_LEN + 16;
 
-impl MessageCipher for GCMMessageCipher {
+impl MessageDecrypter for GCMMessageDecrypter {
   fn decrypt(&self, mut msg: Message, seq: u64) -> Result<Message, TLSError> {
     let payload = try!(msg.take_opaque_payload().ok_or(TLSError::DecryptError));
     let mut buf = payload.0;
@@ -103,10 +140,11 @@ impl MessageCipher for GCMMessageCipher {
 
     let plain_len = try!(
       ring::aead::open_in_place(&self.dec_key,
-                                &nonce,
+                                ring::aead::Nonce::assume_unique_for_key(nonce),
+                                ring::aead::Aad::from(&aad),
                                 GCM_EXPLICIT_NONCE_LEN,
-                                &mut buf,
-                                &aad)
+                                &mut buf)
+        .map(|plain| plain.len())
         .map_err(|_| TLSError::DecryptError)
     );
 
@@ -125,7 +163,9 @@ impl MessageCipher for GCMMessageCipher {
       }
     )
   }
+}
 
+impl MessageEncrypter for GCMMessageEncrypter {
   fn encrypt(&self, msg: Message, seq: u64) -> Result<Message, TLSError> {
     /* The GCM nonce is constructed from a 32-bit 'salt' derived
      * from the master-secret, and a 64-bit explicit part,
@@ -146,7 +186,7 @@ impl MessageCipher for GCMMessageCipher {
     let payload_len = buf.len();
 
     /* make room for tag */
-    let tag_len = self.alg.max_overhead_len();
+    let tag_len = self.alg.tag_len();
     let want_len = buf.len() + tag_len;
     buf.resize(want_len, 0u8);
 
@@ -158,10 +198,10 @@ impl MessageCipher for GCMMessageCipher {
 
     try!(
       ring::aead::seal_in_place(&self.enc_key,
-                                &nonce,
+                                ring::aead::Nonce::assume_unique_for_key(nonce),
+                                ring::aead::Aad::from(&aad),
                                 &mut buf,
-                                tag_len,
-                                &aad)
+                                tag_len)
         .map_err(|_| TLSError::General("encrypt failed".to_string()))
     );
 
@@ -177,53 +217,240 @@ impl MessageCipher for GCMMessageCipher {
   }
 }
 
-impl GCMMessageCipher {
+impl GCMMessageEncrypter {
   fn new(alg: &'static ring::aead::Algorithm,
          _enc_mac_key: &[u8], enc_key: &[u8], enc_iv: &[u8],
-         _dec_mac_key: &[u8], dec_key: &[u8], dec_iv: &[u8],
-         nonce_offset: &[u8]) -> GCMMessageCipher {
-    let mut ret = GCMMessageCipher {
+         nonce_offset: &[u8]) -> GCMMessageEncrypter {
+    let mut ret = GCMMessageEncrypter {
       alg: alg,
       enc_key: ring::aead::SealingKey::new(alg, enc_key).unwrap(),
       enc_salt: [0u8; 4],
-      dec_key: ring::aead::OpeningKey::new(alg, dec_key).unwrap(),
-      dec_salt: [0u8; 4],
       nonce_offset: [0u8; 8]
     };
 
     debug_assert_eq!(enc_iv.len(), 4);
-    debug_assert_eq!(dec_iv.len(), 4);
     debug_assert_eq!(nonce_offset.len(), 8);
 
     ret.enc_salt.as_mut().write(enc_iv).unwrap();
-    ret.dec_salt.as_mut().write(dec_iv).unwrap();
     ret.nonce_offset.as_mut().write(nonce_offset).unwrap();
     ret
   }
 }
 
-/// The RFC7905/RFC7539 ChaCha20Poly1305 construction
-pub struct ChaCha20Poly1305MessageCipher {
+impl GCMMessageDecrypter {
+  fn new(alg: &'static ring::aead::Algorithm,
+         _dec_mac_key: &[u8], dec_key: &[u8], dec_iv: &[u8]) -> GCMMessageDecrypter {
+    let mut ret = GCMMessageDecrypter {
+      dec_key: ring::aead::OpeningKey::new(alg, dec_key).unwrap(),
+      dec_salt: [0u8; 4]
+    };
+
+    debug_assert_eq!(dec_iv.len(), 4);
+    ret.dec_salt.as_mut().write(dec_iv).unwrap();
+    ret
+  }
+}
+
+/// A TLS1.3 `MessageEncrypter`.  The same construction is used
+/// for all TLS1.3 AEADs: the nonce is the traffic iv xored with
+/// the sequence number, and the real content type is hidden
+/// inside the encrypted payload.
+pub struct TLS13MessageEncrypter {
   alg: &'static ring::aead::Algorithm,
   enc_key: ring::aead::SealingKey,
-  enc_offset: [u8; 12],
+  enc_offset: [u8; 12]
+}
+
+/// A TLS1.3 `MessageDecrypter`.
+pub struct TLS13MessageDecrypter {
+  alg: &'static ring::aead::Algorithm,
   dec_key: ring::aead::OpeningKey,
   dec_offset: [u8; 12]
 }
 
-impl ChaCha20Poly1305MessageCipher {
+/// Remove any zero padding from `buf`, and then remove and return
+/// the real content type.  Returns None if there is no non-zero
+/// byte present (this is illegal).
+fn unpad_tls13(buf: &mut Vec<u8>) -> Option<ContentType> {
+  loop {
+    match buf.pop() {
+      Some(0) => {}
+      Some(content_type) => return Some(ContentType::read_bytes(&[content_type]).unwrap()),
+      None => return None
+    }
+  }
+}
+
+const TLS13_AAD_SIZE: usize = 1 + 2 + 2;
+fn make_tls13_aad(len: usize) -> [u8; TLS13_AAD_SIZE] {
+  [
+    0x17, // ContentType::ApplicationData
+    0x3, // ProtocolVersion (major)
+    0x3, // ProtocolVersion (minor)
+    (len >> 8) as u8,
+    len as u8
+  ]
+}
+
+impl MessageEncrypter for TLS13MessageEncrypter {
+  fn encrypt(&self, msg: Message, seq: u64) -> Result<Message, TLSError> {
+    let mut nonce = [0u8; 12];
+    codec::put_u64(seq, &mut nonce[4..]);
+    xor(&mut nonce, &self.enc_offset);
+
+    /* TLSInnerPlaintext is the content, then the real type.  We
+     * don't pad. */
+    let typ = msg.typ;
+    let mut buf = msg.take_payload();
+    typ.encode(&mut buf);
+
+    /* make room for tag */
+    let tag_len = self.alg.tag_len();
+    let want_len = buf.len() + tag_len;
+    buf.resize(want_len, 0u8);
+
+    let aad = make_tls13_aad(want_len);
+
+    try!(
+      ring::aead::seal_in_place(&self.enc_key,
+                                ring::aead::Nonce::assume_unique_for_key(nonce),
+                                ring::aead::Aad::from(&aad),
+                                &mut buf,
+                                tag_len)
+        .map_err(|_| TLSError::General("encrypt failed".to_string()))
+    );
+
+    Ok(Message {
+      typ: ContentType::ApplicationData,
+      version: ProtocolVersion::TLSv1_2,
+      payload: MessagePayload::opaque(buf)
+    })
+  }
+}
+
+impl MessageDecrypter for TLS13MessageDecrypter {
+  fn decrypt(&self, mut msg: Message, seq: u64) -> Result<Message, TLSError> {
+    let mut nonce = [0u8; 12];
+    codec::put_u64(seq, &mut nonce[4..]);
+    xor(&mut nonce, &self.dec_offset);
+
+    let payload = try!(msg.take_opaque_payload().ok_or(TLSError::DecryptError));
+    let mut buf = payload.0;
+
+    if buf.len() < self.alg.tag_len() {
+      return Err(TLSError::DecryptError);
+    }
+
+    let aad = make_tls13_aad(buf.len());
+
+    let plain_len = try!(
+      ring::aead::open_in_place(&self.dec_key,
+                                ring::aead::Nonce::assume_unique_for_key(nonce),
+                                ring::aead::Aad::from(&aad),
+                                0,
+                                &mut buf)
+        .map(|plain| plain.len())
+        .map_err(|_| TLSError::DecryptError)
+    );
+
+    buf.truncate(plain_len);
+
+    if buf.len() > MAX_FRAGMENT_LEN + 1 {
+      let msg = "peer sent oversized fragment".to_string();
+      return Err(TLSError::PeerMisbehavedError(msg));
+    }
+
+    let content_type = try!(
+      unpad_tls13(&mut buf)
+        .ok_or_else(|| TLSError::PeerMisbehavedError("peer sent bad TLSInnerPlaintext".to_string()))
+    );
+
+    if content_type == ContentType::Unknown(0) {
+      let msg = "peer sent bad TLSInnerPlaintext".to_string();
+      return Err(TLSError::PeerMisbehavedError(msg));
+    }
+
+    Ok(Message {
+      typ: content_type,
+      version: msg.version,
+      payload: MessagePayload::opaque(buf)
+    })
+  }
+}
+
+impl TLS13MessageEncrypter {
   fn new(alg: &'static ring::aead::Algorithm,
-         _enc_mac_key: &[u8], enc_key: &[u8], enc_iv: &[u8],
-         _dec_mac_key: &[u8], dec_key: &[u8], dec_iv: &[u8]) -> ChaCha20Poly1305MessageCipher {
-    let mut ret = ChaCha20Poly1305MessageCipher {
+         enc_key: &[u8],
+         enc_iv: &[u8]) -> TLS13MessageEncrypter {
+    let mut ret = TLS13MessageEncrypter {
       alg: alg,
       enc_key: ring::aead::SealingKey::new(alg, enc_key).unwrap(),
-      enc_offset: [0u8; 12],
+      enc_offset: [0u8; 12]
+    };
+
+    ret.enc_offset.as_mut().write(enc_iv).unwrap();
+    ret
+  }
+}
+
+impl TLS13MessageDecrypter {
+  fn new(alg: &'static ring::aead::Algorithm,
+         dec_key: &[u8],
+         dec_iv: &[u8]) -> TLS13MessageDecrypter {
+    let mut ret = TLS13MessageDecrypter {
+      alg: alg,
       dec_key: ring::aead::OpeningKey::new(alg, dec_key).unwrap(),
       dec_offset: [0u8; 12]
     };
 
+    ret.dec_offset.as_mut().write(dec_iv).unwrap();
+    ret
+  }
+}
+
+/// The RFC7905/RFC7539 ChaCha20Poly1305 construction.
+/// This implementation does the AAD construction required in TLS1.2.
+/// TLS1.3 uses `TLS13MessageEncrypter`.
+pub struct ChaCha20Poly1305MessageEncrypter {
+  alg: &'static ring::aead::Algorithm,
+  enc_key: ring::aead::SealingKey,
+  enc_offset: [u8; 12]
+}
+
+/// The RFC7905/RFC7539 ChaCha20Poly1305 construction.
+/// This implementation does the AAD construction required in TLS1.2.
+/// TLS1.3 uses `TLS13MessageDecrypter`.
+pub struct ChaCha20Poly1305MessageDecrypter {
+  dec_key: ring::aead::OpeningKey,
+  dec_offset: [u8; 12]
+}
+
+impl ChaCha20Poly1305MessageEncrypter {
+  fn new(alg: &'static ring::aead::Algorithm,
+         _enc_mac_key: &[u8],
+         enc_key: &[u8],
+         enc_iv: &[u8]) -> ChaCha20Poly1305MessageEncrypter {
+    let mut ret = ChaCha20Poly1305MessageEncrypter {
+      alg: alg,
+      enc_key: ring::aead::SealingKey::new(alg, enc_key).unwrap(),
+      enc_offset: [0u8; 12]
+    };
+
     ret.enc_offset.as_mut().write(enc_iv).unwrap();
+    ret
+  }
+}
+
+impl ChaCha20Poly1305MessageDecrypter {
+  fn new(alg: &'static ring::aead::Algorithm,
+         _dec_mac_key: &[u8],
+         dec_key: &[u8],
+         dec_iv: &[u8]) -> ChaCha20Poly1305MessageDecrypter {
+    let mut ret = ChaCha20Poly1305MessageDecrypter {
+      dec_key: ring::aead::OpeningKey::new(alg, dec_key).unwrap(),
+      dec_offset: [0u8; 12]
+    };
+
     ret.dec_offset.as_mut().write(dec_iv).unwrap();
     ret
   }
@@ -231,7 +458,7 @@ impl ChaCha20Poly1305MessageCipher {
 
 const CP_OVERHEAD: usize = 16;
 
-impl MessageCipher for ChaCha20Poly1305MessageCipher {
+impl MessageDecrypter for ChaCha20Poly1305MessageDecrypter {
   fn decrypt(&self, mut msg: Message, seq: u64) -> Result<Message, TLSError> {
     let payload = try!(msg.take_opaque_payload().ok_or(TLSError::DecryptError));
     let mut buf = payload.0;
@@ -253,10 +480,11 @@ impl MessageCipher for ChaCha20Poly1305MessageCipher {
 
     let plain_len = try!(
       ring::aead::open_in_place(&self.dec_key,
-                                &nonce,
+                                ring::aead::Nonce::assume_unique_for_key(nonce),
+                                ring::aead::Aad::from(&aad),
                                 0,
-                                &mut buf,
-                                &aad)
+                                &mut buf)
+        .map(|plain| plain.len())
         .map_err(|_| TLSError::DecryptError)
     );
 
@@ -275,7 +503,9 @@ impl MessageCipher for ChaCha20Poly1305MessageCipher {
       }
     )
   }
+}
 
+impl MessageEncrypter for ChaCha20Poly1305MessageEncrypter {
   fn encrypt(&self, msg: Message, seq: u64) -> Result<Message, TLSError> {
     let mut nonce = [0u8; 12];
     codec::put_u64(seq, &mut nonce[4..]);
@@ -288,7 +518,7 @@ impl MessageCipher for ChaCha20Poly1305MessageCipher {
     let payload_len = buf.len();
 
     /* make room for tag */
-    let tag_len = self.alg.max_overhead_len();
+    let tag_len = self.alg.tag_len();
     let want_len = buf.len() + tag_len;
     buf.resize(want_len, 0u8);
 
@@ -300,10 +530,10 @@ impl MessageCipher for ChaCha20Poly1305MessageCipher {
 
     try!(
       ring::aead::seal_in_place(&self.enc_key,
-                                &nonce,
+                                ring::aead::Nonce::assume_unique_for_key(nonce),
+                                ring::aead::Aad::from(&aad),
                                 &mut buf,
-                                tag_len,
-                                &aad)
+                                tag_len)
         .map_err(|_| TLSError::General("encrypt failed".to_string()))
     );
 
@@ -315,17 +545,20 @@ impl MessageCipher for ChaCha20Poly1305MessageCipher {
   }
 }
 
-/// A MessageCipher which doesn't work.
-pub struct InvalidMessageCipher {}
-
-impl MessageCipher for InvalidMessageCipher {
-  /* Neither of these errors should ever occur */
-  fn decrypt(&self, _m: Message, _seq: u64) -> Result<Message, TLSError> {
-    Err(TLSError::DecryptError)
-  }
+/// A `MessageEncrypter` which doesn't work.
+pub struct InvalidMessageEncrypter {}
 
+impl MessageEncrypter for InvalidMessageEncrypter {
   fn encrypt(&self, _m: Message, _seq: u64) -> Result<Message, TLSError> {
     Err(TLSError::General("encrypt not yet available".to_string()))
   }
 }
 
+/// A `MessageDecrypter` which doesn't work.
+pub struct InvalidMessageDecrypter {}
+
+impl MessageDecrypter for InvalidMessageDecrypter {
+  fn decrypt(&self, _m: Message, _seq: u64) -> Result<Message, TLSError> {
+    Err(TLSError::DecryptError)
+  }
+}
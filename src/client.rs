@@ -1,18 +1,28 @@
-use msgs::enums::CipherSuite;
+use msgs::enums::{CipherSuite, ProtocolVersion};
+use msgs::enums::{ClientCertificateType, SignatureAlgorithm};
 use msgs::enums::{AlertDescription, HandshakeType, ExtensionType};
+use session;
 use session::{Session, SessionSecrets, SessionRandoms, SessionCommon};
-use suites::{SupportedCipherSuite, ALL_CIPHERSUITES};
+use session::ExtractedSecrets;
+use suites::{SupportedCipherSuite, ALL_CIPHERSUITES, KeyExchange};
 use msgs::handshake::{CertificatePayload, DigitallySignedStruct, SessionID};
-use msgs::handshake::{DistinguishedNames, SupportedSignatureAlgorithms, ASN1Cert};
-use msgs::handshake::SignatureAndHashAlgorithm;
+use msgs::handshake::{DistinguishedNames, SupportedSignatureAlgorithms};
+use msgs::handshake::{SignatureAndHashAlgorithm, SCTList};
 use msgs::enums::ContentType;
 use msgs::message::Message;
 use msgs::persist;
+use msgs::base::PayloadU8;
+use msgs::codec::Codec;
 use client_hs;
 use hash_hs;
+use key_schedule::KeySchedule;
 use verify;
 use sign;
+use ocsp;
+use ct;
 use error::TLSError;
+use keylog::{KeyLog, NoKeyLog};
+use x509;
 
 use std::collections;
 use std::sync::{Arc, Mutex};
@@ -88,15 +98,20 @@ impl StoresClientSessions for ClientSessionMemoryCache {
 /// private key for the purposes of client authentication.
 pub trait ResolvesClientCert {
   /// With the server-supplied acceptable issuers in `acceptable_issuers`,
-  /// the server's supported signature algorithms in `sigalgs`,
-  /// return a certificate chain and signing key to authenticate.
+  /// the server's supported signature algorithms in `sigalgs` and
+  /// acceptable certificate types in `certtypes`, return a certificate
+  /// chain and signing key to authenticate.  `version` is the
+  /// negotiated protocol version.  TLS1.3 servers don't send
+  /// certificate types, so `certtypes` is empty in TLS1.3.
   ///
   /// Return None to continue the handshake without any client
   /// authentication.  The server may reject the handshake later
   /// if it requires authentication.
   fn resolve(&self,
              acceptable_issuers: &DistinguishedNames,
-             sigalgs: &SupportedSignatureAlgorithms)
+             sigalgs: &SupportedSignatureAlgorithms,
+             certtypes: &[ClientCertificateType],
+             version: ProtocolVersion)
     -> Option<(CertificatePayload, Arc<Box<sign::Signer + Send + Sync>>)>;
 
   /// Return true if any certificates at all are available.
@@ -108,7 +123,9 @@ struct FailResolveClientCert {}
 impl ResolvesClientCert for FailResolveClientCert {
   fn resolve(&self,
              _acceptable_issuers: &DistinguishedNames,
-             _sigalgs: &SupportedSignatureAlgorithms)
+             _sigalgs: &SupportedSignatureAlgorithms,
+             _certtypes: &[ClientCertificateType],
+             _version: ProtocolVersion)
     -> Option<(CertificatePayload, Arc<Box<sign::Signer + Send + Sync>>)>
   {
     None
@@ -123,22 +140,18 @@ struct AlwaysResolvesClientCert {
 }
 
 impl AlwaysResolvesClientCert {
-  fn new_rsa(chain: Vec<Vec<u8>>, priv_key: &[u8]) -> AlwaysResolvesClientCert {
-    let key = sign::RSASigner::new(priv_key)
-      .expect("Invalid RSA private key");
-    let mut payload = Vec::new();
-    for cert in chain {
-      payload.push(ASN1Cert::new(cert));
-    }
-
-    AlwaysResolvesClientCert { chain: payload, key: Arc::new(Box::new(key)) }
+  fn new(chain: Vec<Vec<u8>>, priv_key: &[u8]) -> Result<AlwaysResolvesClientCert, TLSError> {
+    let certkey = try!(sign::CertifiedKey::from_der(chain, priv_key));
+    Ok(AlwaysResolvesClientCert { chain: certkey.cert, key: certkey.key })
   }
 }
 
 impl ResolvesClientCert for AlwaysResolvesClientCert {
   fn resolve(&self,
              _acceptable_issuers: &DistinguishedNames,
-             _sigalgs: &SupportedSignatureAlgorithms)
+             _sigalgs: &SupportedSignatureAlgorithms,
+             _certtypes: &[ClientCertificateType],
+             _version: ProtocolVersion)
     -> Option<(CertificatePayload, Arc<Box<sign::Signer + Send + Sync>>)>
   {
     Some((self.chain.clone(), self.key.clone()))
@@ -147,6 +160,104 @@ impl ResolvesClientCert for AlwaysResolvesClientCert {
   fn has_certs(&self) -> bool { true }
 }
 
+/// Something which chooses among several client certificate
+/// chains by what the server asks for.
+///
+/// A chain is chosen if one of its certificates is issued by a
+/// name in the server's acceptable issuers (or the server didn't
+/// list any), and its key can produce one of the server's
+/// signature schemes in the negotiated version.  In TLS1.2 the
+/// key must also be of a certificate type the server accepts.
+/// Chains are tried in the order they were added.  If none is suitable, the handshake continues without
+/// client authentication.
+pub struct ResolvesClientCertUsingIssuers {
+  identities: Vec<sign::CertifiedKey>
+}
+
+impl ResolvesClientCertUsingIssuers {
+  /// Make a new resolver, with no identities.
+  pub fn new() -> ResolvesClientCertUsingIssuers {
+    ResolvesClientCertUsingIssuers { identities: Vec::new() }
+  }
+
+  /// Say how many identities we have.
+  pub fn len(&self) -> usize {
+    self.identities.len()
+  }
+
+  /// Offer `cert_chain` and `key_der` to servers which accept
+  /// them, if no identity added before it is suitable.  These are
+  /// as for `ClientConfig::set_single_client_cert`, and are checked
+  /// in the same way.
+  pub fn add(&mut self, cert_chain: Vec<Vec<u8>>, key_der: &[u8]) -> Result<(), TLSError> {
+    let certkey = try!(sign::CertifiedKey::from_der(cert_chain, key_der));
+    self.add_certified_key(certkey);
+    Ok(())
+  }
+
+  /// Like `add`, but with a `CertifiedKey`.
+  pub fn add_certified_key(&mut self, certkey: sign::CertifiedKey) {
+    self.identities.push(certkey);
+  }
+}
+
+/// Is any certificate in `certkey` issued by one of
+/// `acceptable_issuers`?  An empty list means any issuer is
+/// acceptable.
+fn issued_under(certkey: &sign::CertifiedKey,
+                acceptable_issuers: &DistinguishedNames) -> bool {
+  if acceptable_issuers.is_empty() {
+    return true;
+  }
+
+  certkey.cert.iter()
+    .filter_map(|cert| x509::parse_certificate(&cert.0))
+    .any(|cert| acceptable_issuers.iter().any(|name| &name.0[..] == cert.issuer))
+}
+
+/// The TLS1.2 certificate type of a client certificate whose
+/// key signs with `alg`.
+pub fn certificate_type_for(alg: SignatureAlgorithm) -> ClientCertificateType {
+  match alg {
+    SignatureAlgorithm::ECDSA |
+    SignatureAlgorithm::ED25519 => ClientCertificateType::ECDSASign,
+    _ => ClientCertificateType::RSASign
+  }
+}
+
+/// Can a server which negotiated `version` and asked for `sigalgs`
+/// and `certtypes` accept `certkey`?
+fn usable_by_server(certkey: &sign::CertifiedKey,
+                    version: ProtocolVersion,
+                    sigalgs: &SupportedSignatureAlgorithms,
+                    certtypes: &[ClientCertificateType]) -> bool {
+  if version == ProtocolVersion::TLSv1_3 {
+    certkey.key.choose_scheme_tls13(sigalgs).is_some()
+  } else {
+    certtypes.contains(&certificate_type_for(certkey.key.algorithm())) &&
+      certkey.key.choose_scheme_tls12(sigalgs).is_some()
+  }
+}
+
+impl ResolvesClientCert for ResolvesClientCertUsingIssuers {
+  fn resolve(&self,
+             acceptable_issuers: &DistinguishedNames,
+             sigalgs: &SupportedSignatureAlgorithms,
+             certtypes: &[ClientCertificateType],
+             version: ProtocolVersion)
+    -> Option<(CertificatePayload, Arc<Box<sign::Signer + Send + Sync>>)>
+  {
+    self.identities.iter()
+      .find(|certkey| {
+        issued_under(certkey, acceptable_issuers) &&
+          usable_by_server(certkey, version, sigalgs, certtypes)
+      })
+      .map(|certkey| (certkey.cert.clone(), certkey.key.clone()))
+  }
+
+  fn has_certs(&self) -> bool { !self.identities.is_empty() }
+}
+
 /// Common configuration for (typically) all connections made by
 /// a program.
 ///
@@ -159,11 +270,19 @@ pub struct ClientConfig {
   /// Collection of root certificates.
   pub root_store: verify::RootCertStore,
 
+  /// How to verify the server's certificate chain.  The default
+  /// uses webpki to check it against `root_store`.
+  pub verifier: Box<verify::ServerCertVerifier + Send + Sync>,
+
   /// Which ALPN protocols we include in our client hello.
   /// If empty, no ALPN extension is sent.
   pub alpn_protocols: Vec<String>,
 
   /// How we store session data or tickets.
+  ///
+  /// Only TLS1.2 sessions are stored: TLS1.3 tickets are
+  /// discarded, so set `versions` to TLS1.2 alone if you rely
+  /// on resumption.
   pub session_persistence: Mutex<Box<StoresClientSessions + Send + Sync>>,
 
   /// Our MTU.  If None, we don't limit TLS message sizes.
@@ -174,28 +293,68 @@ pub struct ClientConfig {
 
   /// Whether to support RFC5077 tickets.  You must provide a working
   /// `session_persistence` member for this to have any meaningful
-  /// effect.
+  /// effect.  This has no effect on TLS1.3 connections.
   ///
   /// The default is true.
-  pub enable_tickets: bool
+  pub enable_tickets: bool,
+
+  /// Supported versions, in no particular order.  The default
+  /// is all supported versions.
+  pub versions: Vec<ProtocolVersion>,
+
+  /// Whether to refuse TLS1.2 servers which don't support
+  /// extended master secret (RFC7627).  We always offer it.
+  ///
+  /// The default is false.
+  pub require_ems: bool,
+
+  /// Whether to ask servers to staple an OCSP response, and what
+  /// to do with it.
+  ///
+  /// The default is `OCSPPolicy::Ignore`.
+  pub ocsp_policy: ocsp::OCSPPolicy,
+
+  /// If set, ask servers for signed certificate timestamps, and
+  /// require the server's certificate to have been logged as
+  /// this policy says.
+  ///
+  /// The default is None: certificate transparency is not checked.
+  pub ct_policy: Option<ct::CTPolicy>,
+
+  /// How to log session secrets, for debugging.
+  ///
+  /// The default is `NoKeyLog`, which logs nothing.
+  pub key_log: Box<KeyLog + Send + Sync>
 }
 
 impl ClientConfig {
   /// Make a `ClientConfig` with a default set of ciphersuites,
   /// no root certificates, no ALPN protocols, no
   /// session persistence, and no client auth.
+  /// TLS1.3 and TLS1.2 are both enabled.
   pub fn new() -> ClientConfig {
     ClientConfig {
       ciphersuites: ALL_CIPHERSUITES.to_vec(),
       root_store: verify::RootCertStore::empty(),
+      verifier: Box::new(verify::WebPKIVerifier {}),
       alpn_protocols: Vec::new(),
       session_persistence: Mutex::new(Box::new(NoSessionStorage {})),
       mtu: None,
       client_auth_cert_resolver: Box::new(FailResolveClientCert {}),
-      enable_tickets: true
+      enable_tickets: true,
+      versions: vec![ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2],
+      require_ems: false,
+      ocsp_policy: ocsp::OCSPPolicy::Ignore,
+      ct_policy: None,
+      key_log: Box::new(NoKeyLog {})
     }
   }
 
+  pub fn supports_version(&self, v: ProtocolVersion) -> bool {
+    self.versions.contains(&v) &&
+      self.ciphersuites.iter().any(|cs| cs.usable_for_version(v))
+  }
+
   /// Set the ALPN protocol list to the given protocol names.
   /// Overwrites any existing configured protocols.
   /// The first element in the `protocols` list is the most
@@ -231,17 +390,26 @@ impl ClientConfig {
   /// This is blindly used for all servers that ask for client auth.
   ///
   /// `cert_chain` is a vector of DER-encoded certificates,
-  /// `key_der` is a DER-encoded RSA private key.
+  /// end-entity certificate first and each certificate issued by
+  /// the next.  `key_der` is a DER-encoded private key: RSA in
+  /// PKCS#1 or PKCS#8 format, ECDSA P-256/P-384 in PKCS#8 or SEC1
+  /// format, or Ed25519 in PKCS#8 format.
+  ///
+  /// This fails with `TLSError::InvalidCertifiedKey` if the chain
+  /// or key is unusable, as for `ServerConfig::set_single_cert`.
   pub fn set_single_client_cert(&mut self, cert_chain: Vec<Vec<u8>>,
-                                key_der: Vec<u8>) {
-    self.client_auth_cert_resolver = Box::new(
-      AlwaysResolvesClientCert::new_rsa(cert_chain, &key_der)
-    );
+                                key_der: Vec<u8>) -> Result<(), TLSError> {
+    let resolver = try!(AlwaysResolvesClientCert::new(cert_chain, &key_der));
+    self.client_auth_cert_resolver = Box::new(resolver);
+    Ok(())
   }
 }
 
 pub struct ClientHandshakeData {
   pub server_cert_chain: CertificatePayload,
+  pub server_cert_ocsp_response: Vec<u8>,
+  pub server_cert_scts: SCTList,
+  pub may_send_cert_status: bool,
   pub ciphersuite: Option<&'static SupportedCipherSuite>,
   pub dns_name: String,
   pub session_id: SessionID,
@@ -257,13 +425,23 @@ pub struct ClientHandshakeData {
   pub doing_client_auth: bool,
   pub client_auth_sigalg: Option<SignatureAndHashAlgorithm>,
   pub client_auth_cert: Option<CertificatePayload>,
-  pub client_auth_key: Option<Arc<Box<sign::Signer + Send + Sync>>>
+  pub client_auth_key: Option<Arc<Box<sign::Signer + Send + Sync>>>,
+  pub client_auth_context: Option<Vec<u8>>,
+  pub offered_key_shares: Vec<KeyExchange>,
+  pub key_schedule: Option<KeySchedule>,
+  pub done_retry: bool,
+  pub using_ems: bool,
+  /// The verify_data of the first Finished message in TLS1.2.
+  pub tls_unique: Vec<u8>
 }
 
 impl ClientHandshakeData {
   fn new(host_name: &str) -> ClientHandshakeData {
     ClientHandshakeData {
       server_cert_chain: Vec::new(),
+      server_cert_ocsp_response: Vec::new(),
+      server_cert_scts: SCTList::new(),
+      may_send_cert_status: false,
       ciphersuite: None,
       dns_name: host_name.to_string(),
       session_id: SessionID::empty(),
@@ -279,7 +457,13 @@ impl ClientHandshakeData {
       doing_client_auth: false,
       client_auth_sigalg: None,
       client_auth_cert: None,
-      client_auth_key: None
+      client_auth_key: None,
+      client_auth_context: None,
+      offered_key_shares: Vec::new(),
+      key_schedule: None,
+      done_retry: false,
+      using_ems: false,
+      tls_unique: Vec::new()
     }
   }
 }
@@ -288,6 +472,7 @@ impl ClientHandshakeData {
 pub enum ConnState {
   ExpectServerHello,
   ExpectCertificate,
+  ExpectCertificateStatusOrServerKX,
   ExpectServerKX,
   ExpectServerHelloDoneOrCertRequest,
   ExpectServerHelloDone,
@@ -297,7 +482,19 @@ pub enum ConnState {
   ExpectNewTicketResume,
   ExpectCCSResume,
   ExpectFinishedResume,
-  Traffic
+  Traffic,
+  ExpectEncryptedExtensions,
+  ExpectCertificateOrCertReq,
+  ExpectCertificateTLS13,
+  ExpectCertificateVerify,
+  ExpectFinishedTLS13,
+  TrafficTLS13
+}
+
+impl ConnState {
+  fn is_traffic(&self) -> bool {
+    *self == ConnState::Traffic || *self == ConnState::TrafficTLS13
+  }
 }
 
 pub struct ClientSessionImpl {
@@ -333,7 +530,10 @@ impl ClientSessionImpl {
     let mut ret = Vec::new();
 
     for cs in &self.config.ciphersuites {
-      ret.push(cs.suite);
+      /* Only offer suites usable for versions we're offering. */
+      if self.config.versions.iter().any(|v| cs.usable_for_version(*v)) {
+        ret.push(cs.suite);
+      }
     }
 
     /* We don't do renegotation at all, in fact. */
@@ -344,7 +544,11 @@ impl ClientSessionImpl {
 
   pub fn start_encryption(&mut self) {
     let scs = self.handshake_data.ciphersuite.as_ref().unwrap();
-    self.common.start_encryption(scs, self.secrets.as_ref().unwrap());
+    let secrets = self.secrets.as_ref().unwrap();
+    self.config.key_log.log("CLIENT_RANDOM",
+                            &secrets.randoms.client,
+                            &secrets.get_master_secret());
+    self.common.start_encryption_tls12(scs, secrets);
   }
 
   pub fn find_cipher_suite(&self, suite: &CipherSuite) -> Option<&'static SupportedCipherSuite> {
@@ -372,10 +576,17 @@ impl ClientSessionImpl {
   }
 
   pub fn is_handshaking(&self) -> bool {
-    self.state != ConnState::Traffic
+    !self.state.is_traffic()
   }
 
   pub fn process_msg(&mut self, mut msg: Message) -> Result<(), TLSError> {
+    /* TLS1.3 servers may send a ChangeCipherSpec for middlebox
+     * compatibility.  It has no meaning, so drop it. */
+    if self.common.is_tls13() && self.is_handshaking() &&
+      msg.is_content_type(ContentType::ChangeCipherSpec) {
+      return Ok(());
+    }
+
     /* Decrypt if demanded by current state. */
     if self.common.peer_encrypting {
       let dm = try!(self.common.decrypt_incoming(msg));
@@ -427,7 +638,7 @@ impl ClientSessionImpl {
   fn process_hello_req(&mut self) {
     /* If we're post handshake, send a refusal alert.
      * Otherwise, drop it silently. */
-    if self.state == ConnState::Traffic {
+    if self.state.is_traffic() {
       self.common.send_warning_alert(AlertDescription::NoRenegotiation);
     }
   }
@@ -447,7 +658,7 @@ impl ClientSessionImpl {
     self.state = new_state;
 
     /* Once we're connected, start flushing sendable_plaintext. */
-    if self.state == ConnState::Traffic && !self.common.traffic {
+    if self.state.is_traffic() && !self.common.traffic {
       self.common.start_traffic();
     }
 
@@ -458,6 +669,7 @@ impl ClientSessionImpl {
     match self.state {
       ConnState::ExpectServerHello => &client_hs::EXPECT_SERVER_HELLO,
       ConnState::ExpectCertificate => &client_hs::EXPECT_CERTIFICATE,
+      ConnState::ExpectCertificateStatusOrServerKX => &client_hs::EXPECT_CERTIFICATE_STATUS_OR_SERVER_KX,
       ConnState::ExpectServerKX => &client_hs::EXPECT_SERVER_KX,
       ConnState::ExpectServerHelloDoneOrCertRequest => &client_hs::EXPECT_DONE_OR_CERTREQ,
       ConnState::ExpectServerHelloDone => &client_hs::EXPECT_SERVER_HELLO_DONE,
@@ -467,7 +679,13 @@ impl ClientSessionImpl {
       ConnState::ExpectNewTicketResume => &client_hs::EXPECT_NEW_TICKET_RESUME,
       ConnState::ExpectCCSResume => &client_hs::EXPECT_CCS_RESUME,
       ConnState::ExpectFinishedResume => &client_hs::EXPECT_FINISHED_RESUME,
-      ConnState::Traffic => &client_hs::TRAFFIC
+      ConnState::Traffic => &client_hs::TRAFFIC,
+      ConnState::ExpectEncryptedExtensions => &client_hs::EXPECT_ENCRYPTED_EXTENSIONS,
+      ConnState::ExpectCertificateOrCertReq => &client_hs::EXPECT_CERTIFICATE_OR_CERTREQ,
+      ConnState::ExpectCertificateTLS13 => &client_hs::EXPECT_CERTIFICATE_TLS13,
+      ConnState::ExpectCertificateVerify => &client_hs::EXPECT_CERTIFICATE_VERIFY,
+      ConnState::ExpectFinishedTLS13 => &client_hs::EXPECT_FINISHED_TLS13,
+      ConnState::TrafficTLS13 => &client_hs::TRAFFIC_TLS13
     }
   }
 
@@ -499,6 +717,127 @@ impl ClientSessionImpl {
   pub fn get_alpn_protocol(&self) -> Option<String> {
     self.alpn_protocol.clone()
   }
+
+  pub fn get_protocol_version(&self) -> Option<ProtocolVersion> {
+    self.common.negotiated_version
+  }
+
+  pub fn export_keying_material(&self,
+                                label: &[u8],
+                                context: Option<&[u8]>,
+                                len: usize) -> Result<Vec<u8>, TLSError> {
+    if self.is_handshaking() {
+      return Err(TLSError::HandshakeNotComplete);
+    }
+
+    let mut output = vec![0u8; len];
+    if self.common.is_tls13() {
+      try!(self.handshake_data.key_schedule.as_ref().unwrap()
+           .export_keying_material(&mut output, label, context));
+    } else {
+      try!(self.secrets.as_ref().unwrap()
+           .export_keying_material(&mut output, label, context));
+    }
+
+    Ok(output)
+  }
+
+  pub fn get_tls_unique(&self) -> Option<Vec<u8>> {
+    if self.is_handshaking() || self.common.is_tls13() ||
+      self.handshake_data.tls_unique.is_empty() {
+      return None;
+    }
+
+    Some(self.handshake_data.tls_unique.clone())
+  }
+
+  pub fn get_tls_server_end_point(&self) -> Option<Vec<u8>> {
+    if self.is_handshaking() {
+      return None;
+    }
+
+    self.handshake_data.server_cert_chain.first()
+      .and_then(session::tls_server_end_point)
+  }
+
+  pub fn extract_secrets(&self) -> Result<ExtractedSecrets, TLSError> {
+    if self.is_handshaking() {
+      return Err(TLSError::HandshakeNotComplete);
+    }
+
+    if self.common.has_buffered_data() {
+      return Err(TLSError::General("session has buffered data".to_string()));
+    }
+
+    let scs = self.handshake_data.ciphersuite.unwrap();
+    if self.common.is_tls13() {
+      Ok(ExtractedSecrets::from_tls13(scs,
+                                      self.handshake_data.key_schedule.as_ref().unwrap(),
+                                      true,
+                                      &self.common))
+    } else {
+      Ok(ExtractedSecrets::from_tls12(scs, self.secrets.as_ref().unwrap(), &self.common))
+    }
+  }
+
+  pub fn save_state(&self) -> Result<Vec<u8>, TLSError> {
+    if self.is_handshaking() {
+      return Err(TLSError::HandshakeNotComplete);
+    }
+
+    let mut state = try!(session::save_session_state(self.handshake_data.ciphersuite.unwrap(),
+                                                     &self.handshake_data.randoms,
+                                                     self.secrets.as_ref(),
+                                                     self.handshake_data.key_schedule.as_ref(),
+                                                     &self.common));
+    let alpn_protocol = self.alpn_protocol.clone().unwrap_or_else(String::new);
+    state.alpn_protocol = PayloadU8::new(alpn_protocol.into_bytes());
+    state.dns_name = PayloadU8::new(self.handshake_data.dns_name.as_bytes().to_vec());
+    state.server_cert_chain = self.handshake_data.server_cert_chain.clone();
+    state.tls_unique = PayloadU8::new(self.handshake_data.tls_unique.clone());
+    Ok(state.get_encoding())
+  }
+
+  pub fn restore_state(config: &Arc<ClientConfig>, bytes: &[u8]) -> Result<ClientSessionImpl, TLSError> {
+    let state = try!(session::read_session_state(bytes, true));
+    let scs = try!(session::find_saved_suite(&config.ciphersuites, &config.versions, &state));
+    let dns_name = try!(String::from_utf8(state.dns_name.0.clone())
+                        .map_err(|_| session::bad_session_state()));
+    let alpn_protocol = try!(String::from_utf8(state.alpn_protocol.0.clone())
+                             .map_err(|_| session::bad_session_state()));
+
+    let mut sess = ClientSessionImpl {
+      config: config.clone(),
+      handshake_data: ClientHandshakeData::new(&dns_name),
+      secrets: None,
+      alpn_protocol: if alpn_protocol.is_empty() { None } else { Some(alpn_protocol) },
+      common: SessionCommon::new(config.mtu),
+      state: ConnState::Traffic
+    };
+
+    let (randoms, secrets, key_schedule) =
+      try!(session::restore_session_state(scs, &state, &mut sess.common));
+    sess.secrets = secrets;
+    sess.handshake_data.ciphersuite = Some(scs);
+    sess.handshake_data.randoms = randoms;
+    sess.handshake_data.key_schedule = key_schedule;
+    sess.handshake_data.server_cert_chain = state.server_cert_chain;
+    sess.handshake_data.tls_unique = state.tls_unique.0;
+
+    if sess.common.is_tls13() {
+      sess.state = ConnState::TrafficTLS13;
+    }
+
+    Ok(sess)
+  }
+
+  pub fn get_ocsp_response(&self) -> Option<&[u8]> {
+    if self.handshake_data.server_cert_ocsp_response.is_empty() {
+      None
+    } else {
+      Some(&self.handshake_data.server_cert_ocsp_response[..])
+    }
+  }
 }
 
 /// This represents a single TLS client session.
@@ -515,6 +854,49 @@ impl ClientSession {
              hostname: &str) -> ClientSession {
     ClientSession { imp: ClientSessionImpl::new(config, hostname) }
   }
+
+  /// Return the OCSP response stapled by the server, if any.
+  /// This is only requested if `ClientConfig::ocsp_policy` is
+  /// not `OCSPPolicy::Ignore`.
+  pub fn get_ocsp_response(&self) -> Option<&[u8]> {
+    self.imp.get_ocsp_response()
+  }
+
+  /// Consume this session after its handshake, returning the
+  /// state of its record layer.  The caller is then responsible
+  /// for protecting traffic, for example by configuring kernel
+  /// TLS on the underlying socket.
+  ///
+  /// This fails if the handshake isn't complete, or if any
+  /// data is waiting to be read, written or processed: call
+  /// this only once `wants_write` is false and all plaintext
+  /// has been read.  Any later TLS1.3 post-handshake messages,
+  /// like key updates or tickets, must be handled by the caller.
+  pub fn extract_secrets(self) -> Result<ExtractedSecrets, TLSError> {
+    self.imp.extract_secrets()
+  }
+
+  /// Save the state of this session after its handshake, so it
+  /// can be carried on elsewhere with `restore_state` -- for
+  /// example, in a new process which inherits the socket.  The
+  /// result contains the session's secrets, so protect it as
+  /// you would a private key.
+  ///
+  /// This consumes the session: two sessions sharing the same
+  /// keys and sequence numbers would reuse nonces.  It fails if
+  /// the handshake is incomplete.
+  pub fn save_state(self) -> Result<Vec<u8>, TLSError> {
+    self.imp.save_state()
+  }
+
+  /// Rebuild a session saved with `save_state`.  `config` should
+  /// be equivalent to the one the session was made with; the
+  /// session's cipher suite and protocol version must be
+  /// enabled in it.
+  pub fn restore_state(config: &Arc<ClientConfig>, bytes: &[u8]) -> Result<ClientSession, TLSError> {
+    let imp = try!(ClientSessionImpl::restore_state(config, bytes));
+    Ok(ClientSession { imp: imp })
+  }
 }
 
 impl Session for ClientSession {
@@ -554,6 +936,25 @@ impl Session for ClientSession {
   fn get_alpn_protocol(&self) -> Option<String> {
     self.imp.get_alpn_protocol()
   }
+
+  fn get_protocol_version(&self) -> Option<ProtocolVersion> {
+    self.imp.get_protocol_version()
+  }
+
+  fn export_keying_material(&self,
+                            label: &[u8],
+                            context: Option<&[u8]>,
+                            len: usize) -> Result<Vec<u8>, TLSError> {
+    self.imp.export_keying_material(label, context, len)
+  }
+
+  fn get_tls_unique(&self) -> Option<Vec<u8>> {
+    self.imp.get_tls_unique()
+  }
+
+  fn get_tls_server_end_point(&self) -> Option<Vec<u8>> {
+    self.imp.get_tls_server_end_point()
+  }
 }
 
 impl io::Read for ClientSession {
@@ -1,28 +1,39 @@
 use msgs::enums::{ContentType, HandshakeType, ExtensionType};
-use msgs::enums::{Compression, ProtocolVersion, AlertDescription};
+use msgs::enums::{Compression, ProtocolVersion, AlertDescription, NamedCurve};
 use msgs::message::{Message, MessagePayload};
 use msgs::base::{Payload, PayloadU8};
 use msgs::handshake::{HandshakePayload, HandshakeMessagePayload, ClientHelloPayload};
-use msgs::handshake::{SessionID, Random};
-use msgs::handshake::{ClientExtension, ServerExtension};
+use msgs::handshake::{SessionID, Random, DOWNGRADE_SENTINEL};
+use msgs::handshake::{ClientExtension, ServerExtension, HasServerExtensions};
+use msgs::handshake::{ServerHelloPayload, HelloRetryRequest, KeyShareEntry};
+use msgs::handshake::{CertificatePayloadTLS13, CertificateEntry};
 use msgs::handshake::{SupportedSignatureAlgorithms, SupportedMandatedSignatureAlgorithms};
 use msgs::handshake::{EllipticCurveList, SupportedCurves};
 use msgs::handshake::{ECPointFormatList, SupportedPointFormats};
 use msgs::handshake::{ProtocolNameList, ConvertProtocolNameList};
 use msgs::handshake::ServerKeyExchangePayload;
 use msgs::handshake::DigitallySignedStruct;
-use msgs::enums::ClientCertificateType;
+use msgs::handshake::{CertificateStatusRequest, SCTList};
+use msgs::enums::KeyUpdateRequest;
 use msgs::codec::Codec;
 use msgs::persist;
 use msgs::ccs::ChangeCipherSpecPayload;
-use client::{ClientSessionImpl, ConnState};
+use client::{ClientSessionImpl, ConnState, certificate_type_for};
 use session::SessionSecrets;
+use key_schedule::{KeySchedule, SecretKind};
+use cipher;
 use suites;
 use verify;
+use ocsp;
+use ocsp::OCSPPolicy;
+use ct;
 use rand;
 use error::TLSError;
 use handshake::Expectation;
 
+use ring;
+use time;
+
 use std::mem;
 
 macro_rules! extract_handshake(
@@ -74,11 +85,21 @@ fn randomise_sessionid_for_ticket(csv: &mut persist::ClientSessionValue) {
 }
 
 pub fn emit_client_hello(sess: &mut ClientSessionImpl) {
-  /* Do we have a SessionID or ticket cached for this host? */
-  sess.handshake_data.resuming_session = find_session(sess);
-  let (session_id, ticket) = if sess.handshake_data.resuming_session.is_some() {
-    let mut resuming = sess.handshake_data.resuming_session.as_mut().unwrap();
-    randomise_sessionid_for_ticket(resuming);
+  emit_client_hello_for_retry(sess, None)
+}
+
+fn emit_client_hello_for_retry(sess: &mut ClientSessionImpl,
+                               retryreq: Option<&HelloRetryRequest>) {
+  /* Do we have a SessionID or ticket cached for this host?
+   * A retried ClientHello must offer the same session. */
+  if retryreq.is_none() {
+    sess.handshake_data.resuming_session = find_session(sess);
+    if let Some(ref mut resuming) = sess.handshake_data.resuming_session {
+      randomise_sessionid_for_ticket(resuming);
+    }
+  }
+
+  let (session_id, ticket) = if let Some(ref resuming) = sess.handshake_data.resuming_session {
     info!("Resuming session");
     (resuming.session_id.clone(), resuming.ticket.0.clone())
   } else {
@@ -86,12 +107,46 @@ pub fn emit_client_hello(sess: &mut ClientSessionImpl) {
     (SessionID::empty(), Vec::new())
   };
 
+  let support_tls12 = sess.config.supports_version(ProtocolVersion::TLSv1_2);
+  let support_tls13 = sess.config.supports_version(ProtocolVersion::TLSv1_3);
+
+  let mut supported_versions = Vec::new();
+  if support_tls13 {
+    supported_versions.push(ProtocolVersion::TLSv1_3);
+  }
+  if support_tls12 {
+    supported_versions.push(ProtocolVersion::TLSv1_2);
+  }
+
   let mut exts = Vec::new();
+  if support_tls13 {
+    exts.push(ClientExtension::SupportedVersions(supported_versions));
+  }
   exts.push(ClientExtension::make_sni(&sess.handshake_data.dns_name));
   exts.push(ClientExtension::ECPointFormats(ECPointFormatList::supported()));
   exts.push(ClientExtension::EllipticCurves(EllipticCurveList::supported()));
   exts.push(ClientExtension::SignatureAlgorithms(SupportedSignatureAlgorithms::supported_verify()));
 
+  if support_tls12 {
+    exts.push(ClientExtension::ExtendedMasterSecretRequest);
+  }
+
+  if sess.config.ocsp_policy != OCSPPolicy::Ignore {
+    exts.push(ClientExtension::CertificateStatusRequest(CertificateStatusRequest::build_ocsp()));
+  }
+
+  if sess.config.ct_policy.is_some() {
+    exts.push(ClientExtension::SignedCertificateTimestampRequest);
+  }
+
+  if support_tls13 {
+    exts.push(ClientExtension::KeyShare(make_key_shares(sess, retryreq)));
+
+    if let Some(cookie) = retryreq.and_then(|req| req.get_cookie()) {
+      exts.push(ClientExtension::Cookie(cookie.clone()));
+    }
+  }
+
   if sess.config.enable_tickets {
     /* If we have a ticket, include it.  Otherwise, request one. */
     if ticket.is_empty() {
@@ -136,7 +191,33 @@ pub fn emit_client_hello(sess: &mut ClientSessionImpl) {
   sess.common.send_msg(ch, false);
 }
 
-fn sent_unsolicited_extensions(sess: &ClientSessionImpl, exts: &Vec<ServerExtension>) -> bool {
+/// Generate (or reuse) the key shares we offer in a TLS1.3 ClientHello.
+/// On retry, the server may ask us for a share in a specific group.
+fn make_key_shares(sess: &mut ClientSessionImpl,
+                   retryreq: Option<&HelloRetryRequest>) -> Vec<KeyShareEntry> {
+  if let Some(group) = retryreq.and_then(|req| req.get_requested_key_share_group()) {
+    /* The server has told us which group it wants: forget the others. */
+    sess.handshake_data.offered_key_shares.clear();
+    if let Some(kx) = suites::KeyExchange::start_ecdhe(&group) {
+      sess.handshake_data.offered_key_shares.push(kx);
+    }
+  }
+
+  if sess.handshake_data.offered_key_shares.is_empty() {
+    /* Optimistically send a share for our most preferred group. */
+    let group = EllipticCurveList::supported()[0];
+    let kx = suites::KeyExchange::start_ecdhe(&group)
+      .expect("cannot start key exchange for preferred group");
+    sess.handshake_data.offered_key_shares.push(kx);
+  }
+
+  sess.handshake_data.offered_key_shares
+    .iter()
+    .map(|kx| KeyShareEntry::new(kx.group, &kx.pubkey))
+    .collect()
+}
+
+fn sent_unsolicited_extensions(sess: &ClientSessionImpl, exts: &[ServerExtension]) -> bool {
   let allowed_unsolicited = vec![ ExtensionType::RenegotiationInfo ];
 
   let sent = &sess.handshake_data.sent_extensions;
@@ -151,13 +232,103 @@ fn sent_unsolicited_extensions(sess: &ClientSessionImpl, exts: &Vec<ServerExtens
   false
 }
 
+fn process_alpn_protocol(sess: &mut ClientSessionImpl,
+                        proto: Option<String>) -> Result<(), TLSError> {
+  sess.alpn_protocol = proto;
+  if sess.alpn_protocol.is_some() {
+    if !sess.config.alpn_protocols.contains(sess.alpn_protocol.as_ref().unwrap()) {
+      sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+      return Err(TLSError::PeerMisbehavedError("server sent non-offered ALPN protocol".to_string()));
+    }
+  }
+  info!("ALPN protocol is {:?}", sess.alpn_protocol);
+  Ok(())
+}
+
+fn find_key_share(sess: &mut ClientSessionImpl,
+                  group: NamedCurve) -> Option<suites::KeyExchange> {
+  /* We only ever need one key share, so the others can go. */
+  let shares = mem::replace(&mut sess.handshake_data.offered_key_shares, Vec::new());
+  shares.into_iter()
+    .find(|share| share.group == group)
+}
+
+/// Complete the TLS1.3 key exchange given the `server_hello`, and
+/// start encrypting using the handshake traffic secrets.
+fn start_handshake_traffic(sess: &mut ClientSessionImpl,
+                           server_hello: &ServerHelloPayload) -> Result<(), TLSError> {
+  let suite = sess.handshake_data.ciphersuite.unwrap();
+
+  /* Only key_share and supported_versions may appear in a TLS1.3
+   * ServerHello: everything else moves to EncryptedExtensions. */
+  let allowed_plaintext_exts = [ ExtensionType::KeyShare, ExtensionType::SupportedVersions ];
+  if server_hello.extensions.iter().any(|ext| !allowed_plaintext_exts.contains(&ext.get_type())) {
+    sess.common.send_fatal_alert(AlertDescription::UnsupportedExtension);
+    return Err(TLSError::PeerMisbehavedError("server sent unexpected cleartext ext".to_string()));
+  }
+
+  let their_key_share = try!(server_hello.get_key_share()
+    .ok_or_else(|| {
+      sess.common.send_fatal_alert(AlertDescription::MissingExtension);
+      TLSError::PeerMisbehavedError("missing key share".to_string())
+    }));
+
+  let our_key_share = try!(find_key_share(sess, their_key_share.group)
+    .ok_or_else(|| {
+      sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+      TLSError::PeerMisbehavedError("wrong group for key share".to_string())
+    }));
+
+  let shared = try!(our_key_share.complete(&their_key_share.payload.0)
+    .ok_or_else(|| TLSError::PeerMisbehavedError("key exchange failed".to_string())));
+
+  let mut key_schedule = KeySchedule::new(suite.get_hash());
+  key_schedule.input_empty();
+  key_schedule.input_secret(&shared.premaster_secret);
+
+  let handshake_hash = sess.handshake_data.transcript.get_current_hash();
+  let write_key = key_schedule.derive(SecretKind::ClientHandshakeTrafficSecret, &handshake_hash);
+  let read_key = key_schedule.derive(SecretKind::ServerHandshakeTrafficSecret, &handshake_hash);
+  let client_random = &sess.handshake_data.randoms.client;
+  sess.config.key_log.log("CLIENT_HANDSHAKE_TRAFFIC_SECRET", client_random, &write_key);
+  sess.config.key_log.log("SERVER_HANDSHAKE_TRAFFIC_SECRET", client_random, &read_key);
+  sess.common.set_message_decrypter(cipher::new_tls13_read(suite, &read_key));
+  sess.common.set_message_encrypter(cipher::new_tls13_write(suite, &write_key));
+  key_schedule.current_client_traffic_secret = write_key;
+  key_schedule.current_server_traffic_secret = read_key;
+  sess.handshake_data.key_schedule = Some(key_schedule);
+
+  /* TLS1.3 client auth signs the handshake hash, so we needn't
+   * keep the whole transcript around. */
+  sess.handshake_data.transcript.abandon_client_auth();
+
+  /* Certificate and friends look different from now on. */
+  sess.common.handshake_joiner.set_version(ProtocolVersion::TLSv1_3);
+  Ok(())
+}
+
 fn handle_server_hello(sess: &mut ClientSessionImpl, m: Message) -> Result<ConnState, TLSError> {
   let server_hello = extract_handshake!(m, HandshakePayload::ServerHello).unwrap();
   debug!("We got ServerHello {:#?}", server_hello);
 
-  if server_hello.server_version != ProtocolVersion::TLSv1_2 {
-    sess.common.send_fatal_alert(AlertDescription::HandshakeFailure);
-    return Err(TLSError::PeerIncompatibleError("server does not support TLSv1_2".to_string()));
+  let version = match server_hello.get_supported_versions() {
+    Some(ProtocolVersion::TLSv1_3) if sess.config.supports_version(ProtocolVersion::TLSv1_3) =>
+      ProtocolVersion::TLSv1_3,
+    Some(_) => {
+      sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+      return Err(TLSError::PeerMisbehavedError("server chose unoffered version".to_string()));
+    },
+    None if server_hello.server_version == ProtocolVersion::TLSv1_2 &&
+      sess.config.supports_version(ProtocolVersion::TLSv1_2) => ProtocolVersion::TLSv1_2,
+    None => {
+      sess.common.send_fatal_alert(AlertDescription::HandshakeFailure);
+      return Err(TLSError::PeerIncompatibleError("server does not support TLSv1_2".to_string()));
+    }
+  };
+
+  if sess.handshake_data.done_retry && version != ProtocolVersion::TLSv1_3 {
+    sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+    return Err(TLSError::PeerMisbehavedError("server sent HRR, then chose TLS1.2".to_string()));
   }
 
   if server_hello.compression_method != Compression::Null {
@@ -175,16 +346,6 @@ fn handle_server_hello(sess: &mut ClientSessionImpl, m: Message) -> Result<ConnS
     return Err(TLSError::PeerMisbehavedError("server sent unsolicited extension".to_string()));
   }
 
-  /* Extract ALPN protocol */
-  sess.alpn_protocol = server_hello.get_alpn_protocol();
-  if sess.alpn_protocol.is_some() {
-    if !sess.config.alpn_protocols.contains(sess.alpn_protocol.as_ref().unwrap()) {
-      sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
-      return Err(TLSError::PeerMisbehavedError("server sent non-offered ALPN protocol".to_string()));
-    }
-  }
-  info!("ALPN protocol is {:?}", sess.alpn_protocol);
-
   let scs = sess.find_cipher_suite(&server_hello.cipher_suite);
 
   if scs.is_none() {
@@ -192,10 +353,24 @@ fn handle_server_hello(sess: &mut ClientSessionImpl, m: Message) -> Result<ConnS
     return Err(TLSError::PeerMisbehavedError("server chose non-offered ciphersuite".to_string()));
   }
 
+  if !scs.unwrap().usable_for_version(version) {
+    sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+    return Err(TLSError::PeerMisbehavedError("server chose unusable ciphersuite for version".to_string()));
+  }
+
+  if sess.handshake_data.done_retry && sess.handshake_data.ciphersuite != scs {
+    sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+    return Err(TLSError::PeerMisbehavedError("server varied selected ciphersuite".to_string()));
+  }
+
   info!("Using ciphersuite {:?}", server_hello.cipher_suite);
+  sess.common.negotiated_version = Some(version);
 
-  /* Start our handshake hash, and input this reply. */
-  sess.handshake_data.transcript.start_hash(scs.as_ref().unwrap().get_hash());
+  /* Start our handshake hash, and input this reply.  After a
+   * HelloRetryRequest the hash is already running. */
+  if !sess.handshake_data.done_retry {
+    sess.handshake_data.transcript.start_hash(scs.as_ref().unwrap().get_hash());
+  }
   sess.handshake_data.transcript.add_message(&m);
 
   sess.handshake_data.ciphersuite = scs;
@@ -204,6 +379,38 @@ fn handle_server_hello(sess: &mut ClientSessionImpl, m: Message) -> Result<ConnS
   server_hello.random.write_slice(&mut sess.handshake_data.randoms.server);
   sess.handshake_data.session_id = server_hello.session_id.clone();
 
+  if version == ProtocolVersion::TLSv1_3 {
+    try!(start_handshake_traffic(sess, server_hello));
+    return Ok(ConnState::ExpectEncryptedExtensions);
+  }
+
+  /* A TLS1.3-capable server tells us (in its random) if it chose TLS1.2
+   * even though we offered TLS1.3.  That means someone has tampered
+   * with our ClientHello. */
+  if sess.config.supports_version(ProtocolVersion::TLSv1_3) &&
+    &sess.handshake_data.randoms.server[24..] == DOWNGRADE_SENTINEL {
+    sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+    return Err(TLSError::PeerMisbehavedError("downgrade to TLS1.2 when TLS1.3 is supported".to_string()));
+  }
+
+  /* Did the server agree to extended master secret? */
+  sess.handshake_data.using_ems = server_hello.ems_support_acked();
+  if sess.config.require_ems && !sess.handshake_data.using_ems {
+    sess.common.send_fatal_alert(AlertDescription::HandshakeFailure);
+    return Err(TLSError::PeerIncompatibleError("server does not support extended master secret".to_string()));
+  }
+
+  /* Extract ALPN protocol */
+  try!(process_alpn_protocol(sess, server_hello.get_alpn_protocol()));
+
+  /* Will the server staple an OCSP response? */
+  sess.handshake_data.may_send_cert_status = server_hello.ocsp_stapling_acked();
+
+  /* Save any SCTs for checking with the certificate. */
+  sess.handshake_data.server_cert_scts = server_hello.get_sct_list()
+    .cloned()
+    .unwrap_or_else(SCTList::new);
+
   /* Might the server send a ticket? */
   if server_hello.find_extension(ExtensionType::SessionTicket).is_some() {
     info!("Server supports tickets");
@@ -223,6 +430,13 @@ fn handle_server_hello(sess: &mut ClientSessionImpl, m: Message) -> Result<ConnS
         return Err(TLSError::PeerMisbehavedError(error_msg));
       }
 
+      /* RFC7627: the server must resume with the same EMS state. */
+      if resuming.extended_ms != sess.handshake_data.using_ems {
+        sess.common.send_fatal_alert(AlertDescription::HandshakeFailure);
+        let error_msg = "server varied ems support over resume".to_string();
+        return Err(TLSError::PeerMisbehavedError(error_msg));
+      }
+
       sess.secrets = Some(SessionSecrets::new_resume(&sess.handshake_data.randoms,
                                                      scs.unwrap().get_hash(),
                                                      &resuming.master_secret.0));
@@ -242,19 +456,97 @@ fn handle_server_hello(sess: &mut ClientSessionImpl, m: Message) -> Result<ConnS
   }
 }
 
+fn handle_hello_retry_request(sess: &mut ClientSessionImpl, m: Message) -> Result<ConnState, TLSError> {
+  let hrr = extract_handshake!(m, HandshakePayload::HelloRetryRequest).unwrap();
+  debug!("Got HRR {:?}", hrr);
+
+  if !sess.config.supports_version(ProtocolVersion::TLSv1_3) ||
+    hrr.get_supported_versions() != Some(ProtocolVersion::TLSv1_3) {
+    sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+    return Err(TLSError::PeerMisbehavedError("server requested retry for unoffered version".to_string()));
+  }
+
+  if sess.handshake_data.done_retry {
+    sess.common.send_fatal_alert(AlertDescription::UnexpectedMessage);
+    return Err(TLSError::PeerMisbehavedError("server sent two HRRs".to_string()));
+  }
+
+  if hrr.has_duplicate_extension() {
+    sess.common.send_fatal_alert(AlertDescription::DecodeError);
+    return Err(TLSError::PeerMisbehavedError("server sent duplicate HRR extensions".to_string()));
+  }
+
+  if hrr.has_unknown_extension() {
+    sess.common.send_fatal_alert(AlertDescription::UnsupportedExtension);
+    return Err(TLSError::PeerMisbehavedError("server sent HRR with unknown extension".to_string()));
+  }
+
+  let cookie = hrr.get_cookie();
+  let req_group = hrr.get_requested_key_share_group();
+
+  /* A retry request is illegal if it wouldn't change our ClientHello. */
+  if let Some(group) = req_group {
+    if sess.handshake_data.offered_key_shares.iter().any(|share| share.group == group) {
+      sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+      return Err(TLSError::PeerMisbehavedError("server requested hrr with our group".to_string()));
+    }
+
+    if !EllipticCurveList::supported().contains(&group) ||
+      suites::KeyExchange::named_curve_to_ecdh_alg(&group).is_none() {
+      sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+      return Err(TLSError::PeerMisbehavedError("server requested hrr with bad group".to_string()));
+    }
+  } else if cookie.is_none() {
+    sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+    return Err(TLSError::PeerMisbehavedError("server requested hrr with no changes".to_string()));
+  }
+
+  let scs = sess.find_cipher_suite(&hrr.cipher_suite);
+  if scs.is_none() || !scs.unwrap().usable_for_version(ProtocolVersion::TLSv1_3) {
+    sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+    return Err(TLSError::PeerMisbehavedError("server requested unsupported cs in hrr".to_string()));
+  }
+
+  /* The transcript continues with a hash of our first ClientHello,
+   * then this message. */
+  sess.handshake_data.ciphersuite = scs;
+  sess.handshake_data.done_retry = true;
+  sess.common.negotiated_version = Some(ProtocolVersion::TLSv1_3);
+  sess.handshake_data.transcript.start_hash(scs.unwrap().get_hash());
+  sess.handshake_data.transcript.abandon_client_auth();
+  sess.handshake_data.transcript.rollup_for_hrr();
+  sess.handshake_data.transcript.add_message(&m);
+
+  emit_client_hello_for_retry(sess, Some(hrr));
+  Ok(ConnState::ExpectServerHello)
+}
+
+fn handle_server_hello_or_retry(sess: &mut ClientSessionImpl, m: Message) -> Result<ConnState, TLSError> {
+  if m.is_handshake_type(HandshakeType::HelloRetryRequest) {
+    handle_hello_retry_request(sess, m)
+  } else {
+    handle_server_hello(sess, m)
+  }
+}
+
 pub static EXPECT_SERVER_HELLO: Handler = Handler {
   expect: Expectation {
     content_types: &[ContentType::Handshake],
-    handshake_types: &[HandshakeType::ServerHello]
+    handshake_types: &[HandshakeType::ServerHello, HandshakeType::HelloRetryRequest]
   },
-  handle: handle_server_hello
+  handle: handle_server_hello_or_retry
 };
 
 fn handle_certificate(sess: &mut ClientSessionImpl, m: Message) -> Result<ConnState, TLSError> {
   let cert_chain = extract_handshake!(m, HandshakePayload::Certificate).unwrap();
   sess.handshake_data.transcript.add_message(&m);
   sess.handshake_data.server_cert_chain = cert_chain.clone();
-  Ok(ConnState::ExpectServerKX)
+
+  if sess.handshake_data.may_send_cert_status {
+    Ok(ConnState::ExpectCertificateStatusOrServerKX)
+  } else {
+    Ok(ConnState::ExpectServerKX)
+  }
 }
 
 pub static EXPECT_CERTIFICATE: Handler = Handler {
@@ -288,6 +580,32 @@ fn handle_server_kx(sess: &mut ClientSessionImpl, m: Message) -> Result<ConnStat
   Ok(ConnState::ExpectServerHelloDoneOrCertRequest)
 }
 
+fn handle_certificate_status(sess: &mut ClientSessionImpl, m: Message) -> Result<ConnState, TLSError> {
+  let status = extract_handshake!(m, HandshakePayload::CertificateStatus).unwrap();
+  sess.handshake_data.transcript.add_message(&m);
+  sess.handshake_data.server_cert_ocsp_response = status.ocsp_response.0.clone();
+  info!("Server stapled OCSP response is {:?}", sess.handshake_data.server_cert_ocsp_response);
+  Ok(ConnState::ExpectServerKX)
+}
+
+/* The server may decline to staple a response even after
+ * acking our request. */
+fn handle_certificate_status_or_server_kx(sess: &mut ClientSessionImpl, m: Message) -> Result<ConnState, TLSError> {
+  if extract_handshake!(m, HandshakePayload::CertificateStatus).is_some() {
+    handle_certificate_status(sess, m)
+  } else {
+    handle_server_kx(sess, m)
+  }
+}
+
+pub static EXPECT_CERTIFICATE_STATUS_OR_SERVER_KX: Handler = Handler {
+  expect: Expectation {
+    content_types: &[ContentType::Handshake],
+    handshake_types: &[HandshakeType::CertificateStatus, HandshakeType::ServerKeyExchange]
+  },
+  handle: handle_certificate_status_or_server_kx
+};
+
 pub static EXPECT_SERVER_KX: Handler = Handler {
   expect: Expectation {
     content_types: &[ContentType::Handshake],
@@ -349,7 +667,7 @@ fn emit_certverify(sess: &mut ClientSessionImpl) {
   let sigalg = sess.handshake_data.client_auth_sigalg
     .clone()
     .unwrap();
-  let sig = key.sign(&sigalg.hash, &message)
+  let sig = key.sign(&sigalg, &message)
     .expect("client auth signing failed unexpectedly");
   let body = DigitallySignedStruct::new(&sigalg, sig);
 
@@ -379,9 +697,18 @@ fn emit_ccs(sess: &mut ClientSessionImpl) {
   sess.common.we_now_encrypting();
 }
 
+/// Remember `verify_data` if it's from the first Finished
+/// message of the handshake: this is tls-unique (RFC5929).
+fn note_finished(sess: &mut ClientSessionImpl, verify_data: &[u8]) {
+  if sess.handshake_data.tls_unique.is_empty() {
+    sess.handshake_data.tls_unique = verify_data.to_vec();
+  }
+}
+
 fn emit_finished(sess: &mut ClientSessionImpl) {
   let vh = sess.handshake_data.transcript.get_current_hash();
   let verify_data = sess.secrets.as_ref().unwrap().client_verify_data(&vh);
+  note_finished(sess, &verify_data);
   let verify_data_payload = Payload::new(verify_data);
 
   let f = Message {
@@ -411,28 +738,31 @@ fn handle_certificate_req(sess: &mut ClientSessionImpl, m: Message) -> Result<Co
   /* The RFC jovially describes the design here as 'somewhat complicated'
    * and 'somewhat underspecified'.  So thanks for that. */
 
-  /* We only support RSA signing at the moment.  If you don't support that,
-   * we're not doing client auth. */
-  if !certreq.certtypes.contains(&ClientCertificateType::RSASign) {
-    warn!("Server asked for client auth but without RSASign");
-    return Ok(ConnState::ExpectServerHelloDone);
-  }
-
   let maybe_certkey = sess.config.client_auth_cert_resolver.resolve(
-    &certreq.canames, &certreq.sigalgs
+    &certreq.canames, &certreq.sigalgs, &certreq.certtypes, ProtocolVersion::TLSv1_2
   );
 
-  let scs = sess.handshake_data.ciphersuite.as_ref().unwrap();
-  let maybe_sigalg = scs.resolve_sig_alg(&certreq.sigalgs);
-
-  if maybe_certkey.is_some() && maybe_sigalg.is_some() {
-    let (cert, key) = maybe_certkey.unwrap();
-    info!("Attempting client auth, will use {:?}", maybe_sigalg.as_ref().unwrap());
-    sess.handshake_data.client_auth_cert = Some(cert);
-    sess.handshake_data.client_auth_key = Some(key);
-    sess.handshake_data.client_auth_sigalg = maybe_sigalg;
+  if let Some((cert, key)) = maybe_certkey {
+    /* The server must accept our type of key. */
+    let certtype = certificate_type_for(key.algorithm());
+
+    if !certreq.certtypes.contains(&certtype) {
+      warn!("Server asked for client auth but without {:?}", certtype);
+      return Ok(ConnState::ExpectServerHelloDone);
+    }
+
+    let maybe_sigalg = key.choose_scheme_tls12(&certreq.sigalgs);
+
+    if maybe_sigalg.is_some() {
+      info!("Attempting client auth, will use {:?}", maybe_sigalg.as_ref().unwrap());
+      sess.handshake_data.client_auth_cert = Some(cert);
+      sess.handshake_data.client_auth_key = Some(key);
+      sess.handshake_data.client_auth_sigalg = maybe_sigalg;
+    } else {
+      info!("Client auth requested but no sigalg available");
+    }
   } else {
-    info!("Client auth requested but no cert/sigalg available");
+    info!("Client auth requested but no cert selected");
   }
 
   Ok(ConnState::ExpectServerHelloDone)
@@ -455,6 +785,43 @@ pub static EXPECT_DONE_OR_CERTREQ: Handler = Handler {
   handle: handle_done_or_certreq
 };
 
+/// Apply the configured OCSP policy to the verified server
+/// certificate chain.
+fn check_ocsp_policy(sess: &mut ClientSessionImpl) -> Result<(), TLSError> {
+  let rc = ocsp::check_stapled_response(sess.config.ocsp_policy,
+                                        &sess.handshake_data.server_cert_chain,
+                                        &sess.handshake_data.server_cert_ocsp_response,
+                                        time::get_time());
+
+  if let Err(ref err) = rc {
+    let desc = match *err {
+      TLSError::CertificateRevoked => AlertDescription::CertificateRevoked,
+      _ => AlertDescription::BadCertificateStatusResponse
+    };
+    sess.common.send_fatal_alert(desc);
+  }
+
+  rc
+}
+
+/// Apply any configured certificate transparency policy to the
+/// verified server certificate chain.
+fn check_ct_policy(sess: &mut ClientSessionImpl) -> Result<(), TLSError> {
+  let rc = match sess.config.ct_policy {
+    Some(ref policy) => ct::check_scts(policy,
+                                       &sess.handshake_data.server_cert_chain,
+                                       &sess.handshake_data.server_cert_scts,
+                                       time::get_time()),
+    None => Ok(())
+  };
+
+  if rc.is_err() {
+    sess.common.send_fatal_alert(AlertDescription::BadCertificate);
+  }
+
+  rc
+}
+
 fn handle_server_hello_done(sess: &mut ClientSessionImpl, m: Message) -> Result<ConnState, TLSError> {
   sess.handshake_data.transcript.add_message(&m);
 
@@ -473,9 +840,12 @@ fn handle_server_hello_done(sess: &mut ClientSessionImpl, m: Message) -> Result<
    * 5. emit a Finished, our first encrypted message under the new keys. */
 
   /* 1. */
-  try!(verify::verify_server_cert(&sess.config.root_store,
-                                  &sess.handshake_data.server_cert_chain,
-                                  &sess.handshake_data.dns_name));
+  try!(sess.config.verifier.verify_server_cert(&sess.config.root_store,
+                                               &sess.handshake_data.server_cert_chain,
+                                               &sess.handshake_data.dns_name,
+                                               &sess.handshake_data.server_cert_ocsp_response));
+  try!(check_ocsp_policy(sess));
+  try!(check_ct_policy(sess));
 
   /* 2. */
   /* Build up the contents of the signed message.
@@ -489,7 +859,7 @@ fn handle_server_hello_done(sess: &mut ClientSessionImpl, m: Message) -> Result<
     /* Check the signature is compatible with the ciphersuite. */
     let sig = sess.handshake_data.server_kx_sig.as_ref().unwrap();
     let scs = sess.handshake_data.ciphersuite.as_ref().unwrap();
-    if scs.sign != sig.alg.sign {
+    if !scs.usable_for_sigalg(&sig.alg.sign) {
       let error_message = format!("peer signed kx with wrong algorithm (got {:?} expect {:?})",
                                   sig.alg.sign, scs.sign);
       return Err(TLSError::PeerMisbehavedError(error_message));
@@ -514,6 +884,9 @@ fn handle_server_hello_done(sess: &mut ClientSessionImpl, m: Message) -> Result<
   /* 4b. */
   emit_clientkx(sess, &kxd);
 
+  /* An extended master secret covers the handshake up to here. */
+  let handshake_hash = sess.handshake_data.transcript.get_current_hash();
+
   /* 4c. */
   if sess.handshake_data.doing_client_auth {
     emit_certverify(sess);
@@ -524,9 +897,16 @@ fn handle_server_hello_done(sess: &mut ClientSessionImpl, m: Message) -> Result<
 
   /* 4e. Now commit secrets. */
   let hashalg = sess.handshake_data.ciphersuite.as_ref().unwrap().get_hash();
-  sess.secrets = Some(SessionSecrets::new(&sess.handshake_data.randoms,
-                                          hashalg,
-                                          &kxd.premaster_secret));
+  if sess.handshake_data.using_ems {
+    sess.secrets = Some(SessionSecrets::new_ems(&sess.handshake_data.randoms,
+                                                &handshake_hash,
+                                                hashalg,
+                                                &kxd.premaster_secret));
+  } else {
+    sess.secrets = Some(SessionSecrets::new(&sess.handshake_data.randoms,
+                                            hashalg,
+                                            &kxd.premaster_secret));
+  }
   sess.start_encryption();
 
   /* 5. */
@@ -636,7 +1016,8 @@ fn save_session(sess: &mut ClientSessionImpl) {
   let value = persist::ClientSessionValue::new(&scs.suite,
                                                &sess.handshake_data.session_id,
                                                ticket,
-                                               master_secret);
+                                               master_secret,
+                                               sess.handshake_data.using_ems);
   let value_buf = value.get_encoding();
 
   let mut persist = sess.config.session_persistence.lock().unwrap();
@@ -658,11 +1039,11 @@ fn handle_finished(sess: &mut ClientSessionImpl, m: Message) -> Result<ConnState
 
   /* Constant-time verification of this is relatively unimportant: they only
    * get one chance.  But it can't hurt. */
-  use ring;
   try!(
     ring::constant_time::verify_slices_are_equal(&expect_verify_data, &finished.0)
       .map_err(|_| TLSError::DecryptError)
   );
+  note_finished(sess, &finished.0);
 
   /* Hash this message too. */
   sess.handshake_data.transcript.add_message(&m);
@@ -709,3 +1090,400 @@ pub static TRAFFIC: Handler = Handler {
   },
   handle: handle_traffic
 };
+
+/* --- TLS1.3 --- */
+fn handle_encrypted_extensions(sess: &mut ClientSessionImpl, m: Message) -> Result<ConnState, TLSError> {
+  let exts = extract_handshake!(m, HandshakePayload::EncryptedExtensions).unwrap();
+  info!("TLS1.3 encrypted extensions: {:?}", exts);
+  sess.handshake_data.transcript.add_message(&m);
+
+  if exts.has_duplicate_extension() {
+    sess.common.send_fatal_alert(AlertDescription::DecodeError);
+    return Err(TLSError::PeerMisbehavedError("server sent duplicate encrypted extensions".to_string()));
+  }
+
+  if sent_unsolicited_extensions(sess, exts) {
+    sess.common.send_fatal_alert(AlertDescription::UnsupportedExtension);
+    return Err(TLSError::PeerMisbehavedError("server sent unsolicited encrypted extension".to_string()));
+  }
+
+  /* These belong in the ServerHello, not here. */
+  if exts.find_extension(ExtensionType::KeyShare).is_some() ||
+    exts.find_extension(ExtensionType::SupportedVersions).is_some() {
+    sess.common.send_fatal_alert(AlertDescription::UnsupportedExtension);
+    return Err(TLSError::PeerMisbehavedError("server sent inappropriate encrypted extension".to_string()));
+  }
+
+  try!(process_alpn_protocol(sess, exts.get_alpn_protocol()));
+  Ok(ConnState::ExpectCertificateOrCertReq)
+}
+
+pub static EXPECT_ENCRYPTED_EXTENSIONS: Handler = Handler {
+  expect: Expectation {
+    content_types: &[ContentType::Handshake],
+    handshake_types: &[HandshakeType::EncryptedExtensions]
+  },
+  handle: handle_encrypted_extensions
+};
+
+fn handle_certificate_req_tls13(sess: &mut ClientSessionImpl, m: Message) -> Result<ConnState, TLSError> {
+  let certreq = extract_handshake!(m, HandshakePayload::CertificateRequestTLS13).unwrap();
+  sess.handshake_data.transcript.add_message(&m);
+  info!("Got CertificateRequest {:?}", certreq);
+
+  /* Fortunately the problems here in TLS1.2 and prior are corrected in
+   * TLS1.3. */
+
+  /* Must be empty during handshake. */
+  if !certreq.context.0.is_empty() {
+    warn!("Server sent non-empty certreq context");
+    sess.common.send_fatal_alert(AlertDescription::DecodeError);
+    return Err(TLSError::CorruptMessagePayload(ContentType::Handshake));
+  }
+
+  let sigalgs = try!(certreq.get_sigalgs_extension()
+    .ok_or_else(|| {
+      sess.common.send_fatal_alert(AlertDescription::MissingExtension);
+      TLSError::PeerMisbehavedError("server sent certreq without sigalgs".to_string())
+    }));
+
+  let no_canames = Vec::new();
+  let canames = certreq.get_authorities_extension()
+    .unwrap_or(&no_canames);
+  let maybe_certkey = sess.config.client_auth_cert_resolver.resolve(
+    canames, sigalgs, &[], ProtocolVersion::TLSv1_3
+  );
+
+  sess.handshake_data.doing_client_auth = true;
+  sess.handshake_data.client_auth_context = Some(certreq.context.0.clone());
+
+  if let Some((cert, key)) = maybe_certkey {
+    let maybe_sigalg = key.choose_scheme_tls13(sigalgs);
+
+    if maybe_sigalg.is_some() {
+      info!("Attempting client auth, will use {:?}", maybe_sigalg.as_ref().unwrap());
+      sess.handshake_data.client_auth_cert = Some(cert);
+      sess.handshake_data.client_auth_key = Some(key);
+      sess.handshake_data.client_auth_sigalg = maybe_sigalg;
+    } else {
+      info!("Client auth requested but no sigalg available");
+    }
+  } else {
+    info!("Client auth requested but no cert selected");
+  }
+
+  Ok(ConnState::ExpectCertificateTLS13)
+}
+
+fn handle_certificate_tls13(sess: &mut ClientSessionImpl, m: Message) -> Result<ConnState, TLSError> {
+  let cert_chain = extract_handshake!(m, HandshakePayload::CertificateTLS13).unwrap();
+  sess.handshake_data.transcript.add_message(&m);
+
+  /* This is only non-empty for client auth. */
+  if !cert_chain.context.0.is_empty() {
+    warn!("certificate with non-empty context during handshake");
+    sess.common.send_fatal_alert(AlertDescription::DecodeError);
+    return Err(TLSError::CorruptMessagePayload(ContentType::Handshake));
+  }
+
+  if cert_chain.any_entry_has_duplicate_extension() ||
+    cert_chain.any_entry_has_unknown_extension() {
+    warn!("certificate chain contains unsolicited/unknown extension");
+    sess.common.send_fatal_alert(AlertDescription::UnsupportedExtension);
+    return Err(TLSError::PeerMisbehavedError("bad cert chain extensions".to_string()));
+  }
+
+  sess.handshake_data.server_cert_chain = cert_chain.convert();
+  sess.handshake_data.server_cert_ocsp_response = cert_chain.get_end_entity_ocsp();
+  sess.handshake_data.server_cert_scts = cert_chain.get_end_entity_scts();
+  Ok(ConnState::ExpectCertificateVerify)
+}
+
+pub static EXPECT_CERTIFICATE_TLS13: Handler = Handler {
+  expect: Expectation {
+    content_types: &[ContentType::Handshake],
+    handshake_types: &[HandshakeType::Certificate]
+  },
+  handle: handle_certificate_tls13
+};
+
+fn handle_certificate_or_certreq(sess: &mut ClientSessionImpl, m: Message) -> Result<ConnState, TLSError> {
+  if extract_handshake!(m, HandshakePayload::CertificateRequestTLS13).is_some() {
+    handle_certificate_req_tls13(sess, m)
+  } else {
+    handle_certificate_tls13(sess, m)
+  }
+}
+
+pub static EXPECT_CERTIFICATE_OR_CERTREQ: Handler = Handler {
+  expect: Expectation {
+    content_types: &[ContentType::Handshake],
+    handshake_types: &[HandshakeType::Certificate, HandshakeType::CertificateRequest]
+  },
+  handle: handle_certificate_or_certreq
+};
+
+fn handle_certificate_verify(sess: &mut ClientSessionImpl, m: Message) -> Result<ConnState, TLSError> {
+  let cert_verify = extract_handshake!(m, HandshakePayload::CertificateVerify).unwrap();
+
+  info!("Server cert is {:?}", sess.handshake_data.server_cert_chain);
+  info!("Server DNS name is {:?}", sess.handshake_data.dns_name);
+
+  /* 1. Verify the certificate chain.
+   * 2. Verify their signature on the handshake. */
+  if sess.handshake_data.server_cert_chain.is_empty() {
+    sess.common.send_fatal_alert(AlertDescription::DecodeError);
+    return Err(TLSError::NoCertificatesPresented);
+  }
+
+  /* 1. */
+  try!(sess.config.verifier.verify_server_cert(&sess.config.root_store,
+                                               &sess.handshake_data.server_cert_chain,
+                                               &sess.handshake_data.dns_name,
+                                               &sess.handshake_data.server_cert_ocsp_response));
+  try!(check_ocsp_policy(sess));
+  try!(check_ct_policy(sess));
+
+  /* 2. */
+  let handshake_hash = sess.handshake_data.transcript.get_current_hash();
+  try!(verify::verify_tls13(&sess.handshake_data.server_cert_chain[0],
+                            cert_verify,
+                            &handshake_hash,
+                            b"TLS 1.3, server CertificateVerify\x00"));
+
+  sess.handshake_data.transcript.add_message(&m);
+  Ok(ConnState::ExpectFinishedTLS13)
+}
+
+pub static EXPECT_CERTIFICATE_VERIFY: Handler = Handler {
+  expect: Expectation {
+    content_types: &[ContentType::Handshake],
+    handshake_types: &[HandshakeType::CertificateVerify]
+  },
+  handle: handle_certificate_verify
+};
+
+fn emit_certificate_tls13(sess: &mut ClientSessionImpl) {
+  let context = sess.handshake_data.client_auth_context
+    .take()
+    .unwrap_or_else(Vec::new);
+
+  let mut cert_payload = CertificatePayloadTLS13 {
+    context: PayloadU8::new(context),
+    list: Vec::new()
+  };
+
+  if let Some(cert_chain) = sess.handshake_data.client_auth_cert.take() {
+    for cert in cert_chain {
+      cert_payload.list.push(CertificateEntry::new(cert));
+    }
+  }
+
+  let m = Message {
+    typ: ContentType::Handshake,
+    version: ProtocolVersion::TLSv1_2,
+    payload: MessagePayload::Handshake(
+      HandshakeMessagePayload {
+        typ: HandshakeType::Certificate,
+        payload: HandshakePayload::CertificateTLS13(cert_payload)
+      }
+    )
+  };
+
+  sess.handshake_data.transcript.add_message(&m);
+  sess.common.send_msg(m, true);
+}
+
+fn emit_certverify_tls13(sess: &mut ClientSessionImpl) -> Result<(), TLSError> {
+  if sess.handshake_data.client_auth_key.is_none() {
+    debug!("Skipping certverify message (no client key)");
+    return Ok(());
+  }
+
+  let message = verify::construct_tls13_verify_message(
+    &sess.handshake_data.transcript.get_current_hash(),
+    b"TLS 1.3, client CertificateVerify\x00"
+  );
+
+  let key = sess.handshake_data.client_auth_key.take().unwrap();
+  let sigalg = sess.handshake_data.client_auth_sigalg
+    .clone()
+    .unwrap();
+  let sig = try!(key.sign(&sigalg, &message)
+                 .map_err(|_| TLSError::General("cannot sign".to_string())));
+  let verify = DigitallySignedStruct::new(&sigalg, sig);
+
+  let m = Message {
+    typ: ContentType::Handshake,
+    version: ProtocolVersion::TLSv1_2,
+    payload: MessagePayload::Handshake(
+      HandshakeMessagePayload {
+        typ: HandshakeType::CertificateVerify,
+        payload: HandshakePayload::CertificateVerify(verify)
+      }
+    )
+  };
+
+  sess.handshake_data.transcript.add_message(&m);
+  sess.common.send_msg(m, true);
+  Ok(())
+}
+
+fn emit_finished_tls13(sess: &mut ClientSessionImpl) {
+  let handshake_hash = sess.handshake_data.transcript.get_current_hash();
+  let verify_data = sess.handshake_data.key_schedule
+    .as_ref()
+    .unwrap()
+    .sign_finish(SecretKind::ClientHandshakeTrafficSecret, &handshake_hash);
+  let verify_data_payload = Payload::new(verify_data);
+
+  let m = Message {
+    typ: ContentType::Handshake,
+    version: ProtocolVersion::TLSv1_2,
+    payload: MessagePayload::Handshake(
+      HandshakeMessagePayload {
+        typ: HandshakeType::Finished,
+        payload: HandshakePayload::Finished(verify_data_payload)
+      }
+    )
+  };
+
+  sess.handshake_data.transcript.add_message(&m);
+  sess.common.send_msg(m, true);
+}
+
+fn handle_finished_tls13(sess: &mut ClientSessionImpl, m: Message) -> Result<ConnState, TLSError> {
+  let finished = extract_handshake!(m, HandshakePayload::Finished).unwrap();
+
+  let handshake_hash = sess.handshake_data.transcript.get_current_hash();
+  let expect_verify_data = sess.handshake_data.key_schedule
+    .as_ref()
+    .unwrap()
+    .sign_finish(SecretKind::ServerHandshakeTrafficSecret, &handshake_hash);
+
+  try!(
+    ring::constant_time::verify_slices_are_equal(&expect_verify_data, &finished.0)
+      .map_err(|_| {
+        sess.common.send_fatal_alert(AlertDescription::DecryptError);
+        TLSError::DecryptError
+      })
+  );
+
+  sess.handshake_data.transcript.add_message(&m);
+
+  /* Now move to application data keys.  Our handshake
+   * traffic secret is still needed for our Finished. */
+  let suite = sess.handshake_data.ciphersuite.unwrap();
+  let handshake_hash = sess.handshake_data.transcript.get_current_hash();
+  let write_key = {
+    let key_schedule = sess.handshake_data.key_schedule.as_mut().unwrap();
+    key_schedule.input_empty();
+    let write_key = key_schedule.derive(SecretKind::ClientApplicationTrafficSecret, &handshake_hash);
+    let read_key = key_schedule.derive(SecretKind::ServerApplicationTrafficSecret, &handshake_hash);
+    key_schedule.current_exporter_secret = key_schedule.derive(SecretKind::ExporterMasterSecret,
+                                                               &handshake_hash);
+
+    let client_random = &sess.handshake_data.randoms.client;
+    let key_log = &sess.config.key_log;
+    key_log.log("CLIENT_TRAFFIC_SECRET_0", client_random, &write_key);
+    key_log.log("SERVER_TRAFFIC_SECRET_0", client_random, &read_key);
+    key_log.log("EXPORTER_SECRET", client_random, &key_schedule.current_exporter_secret);
+
+    sess.common.set_message_decrypter(cipher::new_tls13_read(suite, &read_key));
+    key_schedule.current_server_traffic_secret = read_key;
+    write_key
+  };
+
+  if sess.handshake_data.doing_client_auth {
+    emit_certificate_tls13(sess);
+    try!(emit_certverify_tls13(sess));
+  }
+
+  emit_finished_tls13(sess);
+
+  sess.common.set_message_encrypter(cipher::new_tls13_write(suite, &write_key));
+  sess.handshake_data.key_schedule.as_mut().unwrap()
+    .current_client_traffic_secret = write_key;
+
+  Ok(ConnState::TrafficTLS13)
+}
+
+pub static EXPECT_FINISHED_TLS13: Handler = Handler {
+  expect: Expectation {
+    content_types: &[ContentType::Handshake],
+    handshake_types: &[HandshakeType::Finished]
+  },
+  handle: handle_finished_tls13
+};
+
+/* -- Traffic transit state (TLS1.3) -- */
+fn handle_new_ticket_tls13(_sess: &mut ClientSessionImpl, m: Message) -> Result<(), TLSError> {
+  /* We don't do TLS1.3 resumption yet, so there's nothing to
+   * keep here.  This means TLS1.3 connections are never resumed,
+   * and callers who depend on resumption need to restrict
+   * ClientConfig::versions to TLS1.2.  See the docs for
+   * ClientConfig::session_persistence. */
+  let ticket = extract_handshake!(m, HandshakePayload::NewSessionTicketTLS13).unwrap();
+  debug!("Ignoring TLS1.3 ticket {:?}: resumption is only supported in TLS1.2", ticket);
+  Ok(())
+}
+
+fn send_key_update_notify(sess: &mut ClientSessionImpl) {
+  let m = Message {
+    typ: ContentType::Handshake,
+    version: ProtocolVersion::TLSv1_2,
+    payload: MessagePayload::Handshake(HandshakeMessagePayload::build_key_update_notify())
+  };
+
+  /* The notification goes under the old key, and we move to the
+   * new one immediately afterwards. */
+  sess.common.send_msg(m, true);
+
+  let suite = sess.handshake_data.ciphersuite.unwrap();
+  let key_schedule = sess.handshake_data.key_schedule.as_mut().unwrap();
+  let write_key = key_schedule.derive_next(SecretKind::ClientApplicationTrafficSecret);
+  sess.common.set_message_encrypter(cipher::new_tls13_write(suite, &write_key));
+  key_schedule.current_client_traffic_secret = write_key;
+}
+
+fn handle_key_update(sess: &mut ClientSessionImpl, m: Message) -> Result<(), TLSError> {
+  let kur = extract_handshake!(m, HandshakePayload::KeyUpdate).unwrap();
+
+  match *kur {
+    KeyUpdateRequest::UpdateNotRequested => {},
+    KeyUpdateRequest::UpdateRequested => send_key_update_notify(sess),
+    _ => {
+      sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+      return Err(TLSError::CorruptMessagePayload(ContentType::Handshake));
+    }
+  }
+
+  /* Update our read-side keys. */
+  let suite = sess.handshake_data.ciphersuite.unwrap();
+  let key_schedule = sess.handshake_data.key_schedule.as_mut().unwrap();
+  let read_key = key_schedule.derive_next(SecretKind::ServerApplicationTrafficSecret);
+  sess.common.set_message_decrypter(cipher::new_tls13_read(suite, &read_key));
+  key_schedule.current_server_traffic_secret = read_key;
+
+  Ok(())
+}
+
+fn handle_traffic_tls13(sess: &mut ClientSessionImpl, m: Message) -> Result<ConnState, TLSError> {
+  if m.is_content_type(ContentType::ApplicationData) {
+    try!(handle_traffic(sess, m));
+  } else if m.is_handshake_type(HandshakeType::NewSessionTicket) {
+    try!(handle_new_ticket_tls13(sess, m));
+  } else if m.is_handshake_type(HandshakeType::KeyUpdate) {
+    try!(handle_key_update(sess, m));
+  }
+
+  Ok(ConnState::TrafficTLS13)
+}
+
+pub static TRAFFIC_TLS13: Handler = Handler {
+  expect: Expectation {
+    content_types: &[ContentType::ApplicationData, ContentType::Handshake],
+    handshake_types: &[HandshakeType::NewSessionTicket, HandshakeType::KeyUpdate]
+  },
+  handle: handle_traffic_tls13
+};
@@ -0,0 +1,195 @@
+/* Certificate revocation lists (RFC5280) for checking verified chains. */
+
+use webpki;
+
+use msgs::handshake::ASN1Cert;
+use error::TLSError;
+use pemfile;
+use verify;
+use x509;
+use x509::DerReader;
+
+use std::io;
+
+/// The parts of a CRL we keep.
+struct OwnedCRL {
+  /// The complete encoding of the issuer Name.
+  issuer: Vec<u8>,
+  tbs: Vec<u8>,
+  signature_alg: Vec<u8>,
+  signature: Vec<u8>,
+  /// The contents of each revoked serialNumber.
+  revoked: Vec<Vec<u8>>
+}
+
+fn parse_crl(der: &[u8]) -> Option<OwnedCRL> {
+  let outer = try_ret!(x509::read_all(der, |rd| rd.read(x509::SEQUENCE)));
+
+  x509::read_all(outer, |rd| {
+    let (tbs_body, tbs) = try_ret!(rd.read_with_encoding(x509::SEQUENCE));
+    let signature_alg = try_ret!(rd.read(x509::SEQUENCE));
+    let signature = try_ret!(rd.read_bit_string());
+
+    x509::read_all(tbs_body, |rd| {
+      try_ret!(rd.read_optional(x509::INTEGER));
+      try_ret!(rd.read(x509::SEQUENCE));
+      let (_, issuer) = try_ret!(rd.read_with_encoding(x509::SEQUENCE));
+      try_ret!(rd.read_time());
+
+      let tag = rd.peek_tag();
+      if tag == Some(x509::UTC_TIME) || tag == Some(x509::GENERALIZED_TIME) {
+        try_ret!(rd.read_time());
+      }
+
+      let mut revoked = Vec::new();
+      if let Some(list) = try_ret!(rd.read_optional(x509::SEQUENCE)) {
+        let mut rd = DerReader::new(list);
+        while !rd.at_end() {
+          let entry = try_ret!(rd.read(x509::SEQUENCE));
+          let serial = try_ret!(x509::read_all(entry, |rd| {
+            let serial = try_ret!(rd.read(x509::INTEGER));
+            try_ret!(rd.read_time());
+            try_ret!(rd.read_optional(x509::SEQUENCE));
+            Some(serial)
+          }));
+          revoked.push(serial.to_vec());
+        }
+      }
+
+      try_ret!(rd.read_optional(x509::explicit_tag(0)));
+
+      Some(OwnedCRL {
+        issuer: issuer.to_vec(),
+        tbs: tbs.to_vec(),
+        signature_alg: signature_alg.to_vec(),
+        signature: signature.to_vec(),
+        revoked: revoked
+      })
+    })
+  })
+}
+
+impl OwnedCRL {
+  /// Is the CRL signed by its issuer?  The issuer is found among
+  /// `presented_certs` or `anchors`.
+  fn is_authentic(&self,
+                  presented_certs: &[ASN1Cert],
+                  anchors: &[webpki::TrustAnchor]) -> bool {
+    let signed_by = |spki: &[u8]| {
+      verify::verify_signed_data_with_spki(spki,
+                                           &self.signature_alg,
+                                           &self.tbs,
+                                           &self.signature).is_ok()
+    };
+
+    let by_presented = presented_certs.iter()
+      .filter_map(|cert| x509::parse_certificate(&cert.0))
+      .filter(|cert| cert.subject == &self.issuer[..])
+      .filter_map(|cert| x509::read_all(cert.spki, |rd| rd.read(x509::SEQUENCE)))
+      .any(|spki| signed_by(spki));
+
+    if by_presented {
+      return true;
+    }
+
+    /* Trust anchors keep the contents of their subject Name. */
+    let issuer = x509::read_all(&self.issuer, |rd| rd.read(x509::SEQUENCE));
+    anchors.iter()
+      .filter(|ta| Some(ta.subject) == issuer)
+      .any(|ta| signed_by(ta.spki))
+  }
+}
+
+/// A collection of certificate revocation lists.  Attach one to a
+/// `RootCertStore` with `set_crls` to refuse revoked certificates
+/// in chains verified against that store.
+///
+/// A CRL is only believed if it is signed by the issuer of the
+/// certificate in question.  CRLs are used regardless of their
+/// thisUpdate and nextUpdate times: a stale CRL still says which
+/// certificates were revoked.  Certificates not covered by
+/// any CRL are accepted.  Delta and indirect CRLs are not
+/// supported.
+pub struct CRLStore {
+  crls: Vec<OwnedCRL>
+}
+
+impl CRLStore {
+  /// Make a new, empty `CRLStore`.
+  pub fn empty() -> CRLStore {
+    CRLStore { crls: Vec::new() }
+  }
+
+  /// Say how many CRLs are in the container.
+  pub fn len(&self) -> usize {
+    self.crls.len()
+  }
+
+  /// Add a single DER-encoded CRL to the store.  Its signature
+  /// is not checked until it is used.
+  pub fn add(&mut self, der: &[u8]) -> Result<(), ()> {
+    let crl = try!(parse_crl(der).ok_or(()));
+    self.crls.push(crl);
+    Ok(())
+  }
+
+  /// Parse a PEM file and add all CRLs found inside ('BEGIN X509
+  /// CRL' sections).  Errors are io errors in `rd` and PEM format
+  /// errors.
+  ///
+  /// Returns the number of CRLs added, and the number which were
+  /// extracted from the PEM but ultimately unparseable.
+  pub fn add_pem_file(&mut self, rd: &mut io::BufRead) -> Result<(usize, usize), ()> {
+    let ders = try!(pemfile::crls(rd));
+    let mut valid_count = 0;
+    let mut invalid_count = 0;
+
+    for der in ders {
+      match self.add(&der) {
+        Ok(_) => valid_count += 1,
+        Err(_) => {
+          debug!("invalid crl der {:?}", der);
+          invalid_count += 1
+        }
+      }
+    }
+
+    info!("add_pem_file processed {} valid and {} invalid crls",
+          valid_count, invalid_count);
+
+    Ok((valid_count, invalid_count))
+  }
+
+  /// Check no certificate in the verified chain `presented_certs`
+  /// is revoked.  `anchors` are the trust anchors the chain was
+  /// verified against.
+  pub fn check_chain(&self,
+                     presented_certs: &[ASN1Cert],
+                     anchors: &[webpki::TrustAnchor]) -> Result<(), TLSError> {
+    if self.crls.is_empty() {
+      return Ok(());
+    }
+
+    for cert in presented_certs {
+      let cert = match x509::parse_certificate(&cert.0) {
+        Some(cert) => cert,
+        None => continue
+      };
+
+      for crl in &self.crls {
+        if crl.issuer != cert.issuer ||
+          !crl.revoked.iter().any(|serial| &serial[..] == cert.serial) {
+          continue;
+        }
+
+        if crl.is_authentic(presented_certs, anchors) {
+          return Err(TLSError::CertificateRevoked);
+        }
+
+        warn!("ignoring CRL with invalid signature");
+      }
+    }
+
+    Ok(())
+  }
+}
@@ -0,0 +1,343 @@
+/* Certificate transparency (RFC6962) checking by clients. */
+
+use ring::digest;
+use ring::signature;
+use untrusted;
+use time;
+
+use msgs::codec;
+use msgs::codec::{Codec, Reader};
+use msgs::enums::{HashAlgorithm, SignatureAlgorithm};
+use msgs::handshake::{ASN1Cert, SCTList, DigitallySignedStruct};
+use error::TLSError;
+use pemfile;
+use x509;
+use x509::DerReader;
+
+use std::io;
+
+/// The X.509 extension carrying SCTs embedded in a certificate
+/// (1.3.6.1.4.1.11129.2.4.2).
+static EMBEDDED_SCT_LIST: &'static [u8] = b"\x2b\x06\x01\x04\x01\xd6\x79\x02\x04\x02";
+
+/// A log we trust.  Logs sign with ECDSA P-256 or RSA, always
+/// with SHA-256.
+struct CTLog {
+  /// The SHA-256 hash of the log's SubjectPublicKeyInfo.
+  id: Vec<u8>,
+  sign: SignatureAlgorithm,
+  alg: &'static signature::VerificationAlgorithm,
+  /// The subjectPublicKey bits.
+  key: Vec<u8>
+}
+
+impl CTLog {
+  fn from_spki(spki: &[u8]) -> Option<CTLog> {
+    let (alg_id, key) = try_ret!(x509::read_all(spki, |rd| {
+      let body = try_ret!(rd.read(x509::SEQUENCE));
+      x509::read_all(body, |rd| {
+        let alg_id = try_ret!(rd.read(x509::SEQUENCE));
+        let key = try_ret!(rd.read_bit_string());
+        Some((alg_id, key))
+      })
+    }));
+
+    let mut rd = DerReader::new(alg_id);
+    let oid = try_ret!(rd.read(x509::OID));
+
+    let (sign, alg): (SignatureAlgorithm, &'static signature::VerificationAlgorithm) =
+      if oid == x509::EC_PUBLIC_KEY {
+        if try_ret!(rd.read(x509::OID)) != x509::SECP256R1 {
+          return None;
+        }
+        (SignatureAlgorithm::ECDSA, &signature::ECDSA_P256_SHA256_ASN1)
+      } else if oid == x509::RSA_ENCRYPTION {
+        (SignatureAlgorithm::RSA, &signature::RSA_PKCS1_2048_8192_SHA256)
+      } else {
+        return None;
+      };
+
+    Some(CTLog {
+      id: digest::digest(&digest::SHA256, spki).as_ref().to_vec(),
+      sign: sign,
+      alg: alg,
+      key: key.to_vec()
+    })
+  }
+}
+
+/// Which certificate transparency logs a client trusts, and how
+/// many signed certificate timestamps (SCTs) it requires for each
+/// server certificate.
+///
+/// SCTs are collected from the TLS extension and from the server
+/// certificate itself.  Only SCTs with a valid signature from a
+/// log in this set count, and each log counts at most once.
+pub struct CTPolicy {
+  logs: Vec<CTLog>,
+
+  /// How many logs must have vouched for the server certificate.
+  pub min_valid_scts: usize
+}
+
+impl CTPolicy {
+  /// Make a new `CTPolicy` trusting no logs, and requiring
+  /// `min_valid_scts` valid SCTs.
+  pub fn new(min_valid_scts: usize) -> CTPolicy {
+    CTPolicy {
+      logs: Vec::new(),
+      min_valid_scts: min_valid_scts
+    }
+  }
+
+  /// Say how many logs are trusted.
+  pub fn len(&self) -> usize {
+    self.logs.len()
+  }
+
+  /// Trust the log with DER-encoded SubjectPublicKeyInfo `spki`.
+  /// Only ECDSA P-256 and RSA keys are accepted.
+  pub fn add_log(&mut self, spki: &[u8]) -> Result<(), ()> {
+    let log = try!(CTLog::from_spki(spki).ok_or(()));
+    self.logs.push(log);
+    Ok(())
+  }
+
+  /// Parse a PEM file and trust all the log public keys found
+  /// inside ('BEGIN PUBLIC KEY' sections).  Errors are io errors
+  /// in `rd` and PEM format errors.
+  ///
+  /// Returns the number of logs added, and the number of keys
+  /// which were extracted from the PEM but ultimately unsuitable.
+  pub fn add_pem_file(&mut self, rd: &mut io::BufRead) -> Result<(usize, usize), ()> {
+    let ders = try!(pemfile::public_keys(rd));
+    let mut valid_count = 0;
+    let mut invalid_count = 0;
+
+    for der in ders {
+      match self.add_log(&der) {
+        Ok(_) => valid_count += 1,
+        Err(_) => {
+          info!("unsupported log key {:?}", der);
+          invalid_count += 1
+        }
+      }
+    }
+
+    info!("add_pem_file processed {} valid and {} invalid log keys",
+          valid_count, invalid_count);
+
+    Ok((valid_count, invalid_count))
+  }
+
+  fn find_log(&self, id: &[u8]) -> Option<&CTLog> {
+    self.logs.iter().find(|log| log.id == id)
+  }
+}
+
+/// A parsed SignedCertificateTimestamp.
+struct SCT {
+  log_id: Vec<u8>,
+  timestamp: u64,
+  extensions: Vec<u8>,
+  dss: DigitallySignedStruct
+}
+
+fn parse_sct(bytes: &[u8]) -> Option<SCT> {
+  let mut rd = Reader::init(bytes);
+
+  if try_ret!(codec::read_u8(&mut rd)) != 0 {
+    return None;
+  }
+
+  let log_id = try_ret!(rd.take(32)).to_vec();
+  let timestamp = try_ret!(codec::read_u64(&mut rd));
+  let ext_len = try_ret!(codec::read_u16(&mut rd)) as usize;
+  let extensions = try_ret!(rd.take(ext_len)).to_vec();
+  let dss = try_ret!(DigitallySignedStruct::read(&mut rd));
+
+  if rd.any_left() {
+    return None;
+  }
+
+  Some(SCT {
+    log_id: log_id,
+    timestamp: timestamp,
+    extensions: extensions,
+    dss: dss
+  })
+}
+
+/// What the SCT is about.  SCTs sent in TLS are about the
+/// certificate itself.  SCTs embedded in the certificate are
+/// about the precertificate the log saw, which is the TBSCertificate
+/// less the embedded SCTs.
+enum SignedEntry<'a> {
+  X509(&'a [u8]),
+  Precert { issuer_key_hash: digest::Digest, tbs: Vec<u8> }
+}
+
+/// Construct the message a log signs for an SCT.
+fn construct_signed_data(sct: &SCT, entry: &SignedEntry) -> Vec<u8> {
+  let mut msg = Vec::new();
+  codec::encode_u8(0, &mut msg); /* v1 */
+  codec::encode_u8(0, &mut msg); /* certificate_timestamp */
+  codec::encode_u64(sct.timestamp, &mut msg);
+
+  match *entry {
+    SignedEntry::X509(cert) => {
+      codec::encode_u16(0, &mut msg);
+      codec::encode_u24(cert.len() as u32, &mut msg);
+      msg.extend_from_slice(cert);
+    }
+    SignedEntry::Precert { ref issuer_key_hash, ref tbs } => {
+      codec::encode_u16(1, &mut msg);
+      msg.extend_from_slice(issuer_key_hash.as_ref());
+      codec::encode_u24(tbs.len() as u32, &mut msg);
+      msg.extend_from_slice(tbs);
+    }
+  }
+
+  codec::encode_u16(sct.extensions.len() as u16, &mut msg);
+  msg.extend_from_slice(&sct.extensions);
+  msg
+}
+
+/// Rebuild the TBSCertificate of `cert` without its embedded SCT
+/// list extension.
+fn precert_tbs(cert: &x509::CertificateFields) -> Option<Vec<u8>> {
+  let tbs_body = try_ret!(x509::read_all(cert.tbs, |rd| rd.read(x509::SEQUENCE)));
+  let mut rd = DerReader::new(tbs_body);
+  let mut tbs = Vec::new();
+
+  while !rd.at_end() {
+    let (tag, body, all) = try_ret!(rd.read_any());
+    if tag != x509::explicit_tag(3) {
+      tbs.extend_from_slice(all);
+      continue;
+    }
+
+    let exts = try_ret!(x509::read_all(body, |rd| rd.read(x509::SEQUENCE)));
+    let mut exts_rd = DerReader::new(exts);
+    let mut kept = Vec::new();
+
+    while !exts_rd.at_end() {
+      let (ext, ext_all) = try_ret!(exts_rd.read_with_encoding(x509::SEQUENCE));
+      if try_ret!(DerReader::new(ext).read(x509::OID)) != EMBEDDED_SCT_LIST {
+        kept.extend_from_slice(ext_all);
+      }
+    }
+
+    x509::wrap_in_sequence(&mut kept);
+    x509::wrap_in_tag(x509::explicit_tag(3), &mut kept);
+    tbs.extend_from_slice(&kept);
+  }
+
+  x509::wrap_in_sequence(&mut tbs);
+  Some(tbs)
+}
+
+/// Return the SCTs embedded in `cert`.  Malformed lists are
+/// ignored.
+fn embedded_scts(cert: &x509::CertificateFields) -> SCTList {
+  cert.find_extension(EMBEDDED_SCT_LIST)
+    .and_then(|ext| x509::read_all(ext.value, |rd| rd.read(x509::OCTET_STRING)))
+    .and_then(|list| SCTList::read_bytes(list))
+    .unwrap_or_else(SCTList::new)
+}
+
+/// Check `sct` is a valid SCT over `entry` from a log in `policy`
+/// and not from the future.  Returns the id of the log.
+fn verify_sct<'a>(policy: &'a CTPolicy,
+                  sct: &[u8],
+                  entry: &SignedEntry,
+                  now: time::Timespec) -> Result<&'a [u8], &'static str> {
+  let sct = try!(parse_sct(sct).ok_or("malformed SCT"));
+  let log = try!(policy.find_log(&sct.log_id).ok_or("SCT from unknown log"));
+
+  let now_ms = (now.sec as u64) * 1000 + (now.nsec as u64) / 1000000;
+  if sct.timestamp > now_ms {
+    return Err("SCT timestamp in the future");
+  }
+
+  if sct.dss.alg.hash != HashAlgorithm::SHA256 || sct.dss.alg.sign != log.sign {
+    return Err("SCT signature algorithm does not match log");
+  }
+
+  let msg = construct_signed_data(&sct, entry);
+  try!(
+    signature::verify(log.alg,
+                      untrusted::Input::from(&log.key),
+                      untrusted::Input::from(&msg),
+                      untrusted::Input::from(&sct.dss.sig.0))
+      .map_err(|_| "invalid SCT signature")
+  );
+
+  Ok(&log.id[..])
+}
+
+/// Count `sct` in `valid_logs` if it is valid.
+fn note_sct<'a>(policy: &'a CTPolicy,
+                sct: &[u8],
+                entry: &SignedEntry,
+                now: time::Timespec,
+                valid_logs: &mut Vec<&'a [u8]>) {
+  match verify_sct(policy, sct, entry, now) {
+    Ok(id) => {
+      if !valid_logs.contains(&id) {
+        valid_logs.push(id);
+      }
+    }
+    Err(why) => info!("ignoring SCT: {}", why)
+  }
+}
+
+/// Apply `policy` to the verified chain `presented_certs`.
+/// `sct_list` is the SCTs the server sent in TLS, which may
+/// be empty.
+pub fn check_scts(policy: &CTPolicy,
+                  presented_certs: &[ASN1Cert],
+                  sct_list: &SCTList,
+                  now: time::Timespec) -> Result<(), TLSError> {
+  let end_entity = try!(
+    presented_certs.first()
+      .ok_or(TLSError::NoCertificatesPresented)
+  );
+
+  let mut valid_logs = Vec::new();
+
+  let tls_entry = SignedEntry::X509(&end_entity.0);
+  for sct in sct_list {
+    note_sct(policy, &sct.0, &tls_entry, now, &mut valid_logs);
+  }
+
+  /* Embedded SCTs need the issuer's key. */
+  let cert = x509::parse_certificate(&end_entity.0);
+  let issuer = presented_certs.get(1)
+    .and_then(|issuer| x509::parse_certificate(&issuer.0));
+
+  if let (Some(cert), Some(issuer)) = (cert, issuer) {
+    let scts = embedded_scts(&cert);
+
+    if !scts.is_empty() {
+      if let Some(tbs) = precert_tbs(&cert) {
+        let precert_entry = SignedEntry::Precert {
+          issuer_key_hash: digest::digest(&digest::SHA256, issuer.spki),
+          tbs: tbs
+        };
+
+        for sct in &scts {
+          note_sct(policy, &sct.0, &precert_entry, now, &mut valid_logs);
+        }
+      }
+    }
+  }
+
+  let count = valid_logs.len();
+  if count < policy.min_valid_scts {
+    let why = format!("{} valid SCTs, but {} required", count, policy.min_valid_scts);
+    return Err(TLSError::CertificateTransparencyError(why));
+  }
+
+  Ok(())
+}
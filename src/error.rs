@@ -44,6 +44,25 @@ pub enum TLSError {
   /// The presented certificate chain is invalid.
   WebPKIError(webpki::Error),
 
+  /// The peer's certificate has been revoked.
+  CertificateRevoked,
+
+  /// A stapled OCSP response was missing or unacceptable.
+  /// The parameter gives a hint as to why.
+  InvalidOCSPResponse(String),
+
+  /// The peer's certificate didn't have enough valid signed
+  /// certificate timestamps.  The parameter gives a hint as to why.
+  CertificateTransparencyError(String),
+
+  /// A certificate chain and private key we were configured with
+  /// are unusable.  The parameter gives a hint as to why.
+  InvalidCertifiedKey(String),
+
+  /// We were asked for something which is only available once
+  /// the handshake is complete.
+  HandshakeNotComplete,
+
   /// A catch-all error for unlikely errors.
   General(String)
 }
@@ -70,6 +89,9 @@ impl fmt::Display for TLSError {
       TLSError::CorruptMessagePayload(ref typ)
         => write!(f, "{} of type {:?}", self.description(), typ),
       TLSError::PeerIncompatibleError(ref why) | TLSError::PeerMisbehavedError(ref why)
+        | TLSError::InvalidOCSPResponse(ref why)
+        | TLSError::CertificateTransparencyError(ref why)
+        | TLSError::InvalidCertifiedKey(ref why)
         => write!(f, "{}: {}", self.description(), why),
       TLSError::AlertReceived(ref alert)
         => write!(f, "{}: {:?}", self.description(), alert),
@@ -78,6 +100,8 @@ impl fmt::Display for TLSError {
       TLSError::CorruptMessage
         | TLSError::NoCertificatesPresented
         | TLSError::DecryptError
+        | TLSError::CertificateRevoked
+        | TLSError::HandshakeNotComplete
         => write!(f, "{}", self.description()),
       _ => write!(f, "{}: {:?}", self.description(), self)
     }
@@ -97,6 +121,11 @@ impl error::Error for TLSError {
       TLSError::PeerMisbehavedError(_) => "peer misbehaved",
       TLSError::AlertReceived(_) => "received fatal alert",
       TLSError::WebPKIError(_) => "invalid certificate",
+      TLSError::CertificateRevoked => "certificate revoked",
+      TLSError::InvalidOCSPResponse(_) => "invalid OCSP response",
+      TLSError::CertificateTransparencyError(_) => "certificate transparency requirements not met",
+      TLSError::InvalidCertifiedKey(_) => "invalid certificate chain or private key",
+      TLSError::HandshakeNotComplete => "handshake not complete",
       TLSError::General(_) => "unexpected error" // (please file a bug)
     }
   }
@@ -128,6 +157,11 @@ mod tests {
       TLSError::PeerMisbehavedError("inconsistent something".to_string()),
       TLSError::AlertReceived(AlertDescription::ExportRestriction),
       TLSError::WebPKIError(webpki::Error::ExtensionValueInvalid),
+      TLSError::CertificateRevoked,
+      TLSError::InvalidOCSPResponse("stale".to_string()),
+      TLSError::CertificateTransparencyError("no SCTs".to_string()),
+      TLSError::InvalidCertifiedKey("expired".to_string()),
+      TLSError::HandshakeNotComplete,
       TLSError::General("undocumented error".to_string())
     ];
 
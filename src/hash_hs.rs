@@ -2,6 +2,7 @@ use ring::digest;
 use std::mem;
 use msgs::codec::Codec;
 use msgs::message::{Message, MessagePayload};
+use msgs::handshake::HandshakeMessagePayload;
 
 /// This deals with keeping a running hash of the handshake
 /// payloads.  This is computed by buffering initially.  Once
@@ -70,6 +71,17 @@ impl HandshakeHash {
     self
   }
 
+  /// Replace the transcript so far with a single synthetic
+  /// message_hash message containing its hash.  This is needed
+  /// after a TLS1.3 HelloRetryRequest.
+  pub fn rollup_for_hrr(&mut self) {
+    let old_hash = self.ctx.take().unwrap().finish();
+    let old_handshake_hash_msg = HandshakeMessagePayload::build_handshake_hash(old_hash.as_ref());
+
+    self.ctx = Some(digest::Context::new(old_hash.algorithm()));
+    self.update_raw(&old_handshake_hash_msg.get_encoding());
+  }
+
   /// Hash or buffer a byte slice.
   fn update_raw(&mut self, buf: &[u8]) -> &mut Self {
     if self.ctx.is_some() {
@@ -157,4 +169,17 @@ mod test {
     assert_eq!(h[2], 0x18);
     assert_eq!(h[3], 0x5c);
   }
+
+  #[test]
+  fn rollup() {
+    let mut hh = HandshakeHash::new();
+    hh.update_raw(b"hello");
+    hh.start_hash(&digest::SHA256);
+    hh.rollup_for_hrr();
+
+    let mut expect = vec![ 0xfe, 0x00, 0x00, 0x20 ];
+    expect.extend_from_slice(digest::digest(&digest::SHA256, b"hello").as_ref());
+    assert_eq!(hh.get_current_hash(),
+               digest::digest(&digest::SHA256, &expect).as_ref().to_vec());
+  }
 }
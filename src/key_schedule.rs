@@ -0,0 +1,361 @@
+/// Key schedule maintenance for TLS1.3
+
+use ring::{hmac, digest, hkdf};
+use msgs::codec;
+use error::TLSError;
+
+/// The kinds of secret we can extract from `KeySchedule`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SecretKind {
+  ClientHandshakeTrafficSecret,
+  ServerHandshakeTrafficSecret,
+  ClientApplicationTrafficSecret,
+  ServerApplicationTrafficSecret,
+  ExporterMasterSecret,
+  DerivedSecret
+}
+
+impl SecretKind {
+  fn to_bytes(&self) -> &'static [u8] {
+    match *self {
+      SecretKind::ClientHandshakeTrafficSecret => b"c hs traffic",
+      SecretKind::ServerHandshakeTrafficSecret => b"s hs traffic",
+      SecretKind::ClientApplicationTrafficSecret => b"c ap traffic",
+      SecretKind::ServerApplicationTrafficSecret => b"s ap traffic",
+      SecretKind::ExporterMasterSecret => b"exp master",
+      SecretKind::DerivedSecret => b"derived"
+    }
+  }
+}
+
+/// This is the TLS1.3 key schedule.  It stores the current secret,
+/// the type of hash, and the current traffic secrets for each
+/// direction.  This isn't guaranteed to be used in a way that matches
+/// the RFC: the caller is trusted to input and derive secrets in the
+/// right order.
+pub struct KeySchedule {
+  current: hmac::SigningKey,
+  need_derive_for_extract: bool,
+  hash: &'static digest::Algorithm,
+  hash_of_empty_message: [u8; digest::MAX_OUTPUT_LEN],
+  pub current_client_traffic_secret: Vec<u8>,
+  pub current_server_traffic_secret: Vec<u8>,
+  pub current_exporter_secret: Vec<u8>
+}
+
+impl KeySchedule {
+  pub fn new(hash: &'static digest::Algorithm) -> KeySchedule {
+    let zeroes = [0u8; digest::MAX_OUTPUT_LEN];
+
+    let mut empty_hash = [0u8; digest::MAX_OUTPUT_LEN];
+    empty_hash[..hash.output_len]
+      .clone_from_slice(digest::digest(hash, &[]).as_ref());
+
+    KeySchedule {
+      current: hmac::SigningKey::new(hash, &zeroes[..hash.output_len]),
+      need_derive_for_extract: false,
+      hash: hash,
+      hash_of_empty_message: empty_hash,
+      current_server_traffic_secret: Vec::new(),
+      current_client_traffic_secret: Vec::new(),
+      current_exporter_secret: Vec::new()
+    }
+  }
+
+  pub fn get_hash_of_empty_message(&self) -> &[u8] {
+    &self.hash_of_empty_message[..self.hash.output_len]
+  }
+
+  /// Input the empty secret.
+  pub fn input_empty(&mut self) {
+    let zeroes = [0u8; digest::MAX_OUTPUT_LEN];
+    let hash_len = self.hash.output_len;
+    self.input_secret(&zeroes[..hash_len]);
+  }
+
+  /// Input the given secret.
+  pub fn input_secret(&mut self, secret: &[u8]) {
+    if self.need_derive_for_extract {
+      let derived = self.derive(SecretKind::DerivedSecret,
+                                self.get_hash_of_empty_message());
+      self.current = hmac::SigningKey::new(self.hash, &derived);
+    }
+    self.need_derive_for_extract = true;
+    self.current = hkdf::extract(&self.current, secret);
+  }
+
+  /// Derive a secret of given `kind`, using current handshake hash `hs_hash`.
+  pub fn derive(&self, kind: SecretKind, hs_hash: &[u8]) -> Vec<u8> {
+    debug_assert_eq!(hs_hash.len(), self.hash.output_len);
+
+    _hkdf_expand_label_vec(&self.current,
+                           kind.to_bytes(),
+                           hs_hash,
+                           self.hash.output_len)
+  }
+
+  /// Sign the finished message consisting of `hs_hash` using a current
+  /// traffic secret.
+  pub fn sign_finish(&self, kind: SecretKind, hs_hash: &[u8]) -> Vec<u8> {
+    let base_key = match kind {
+      SecretKind::ServerHandshakeTrafficSecret => &self.current_server_traffic_secret,
+      SecretKind::ClientHandshakeTrafficSecret => &self.current_client_traffic_secret,
+      _ => unreachable!()
+    };
+
+    self.sign_verify_data(base_key, hs_hash)
+  }
+
+  /// Sign the finished message consisting of `hs_hash` using the key material
+  /// `base_key`.
+  fn sign_verify_data(&self, base_key: &[u8], hs_hash: &[u8]) -> Vec<u8> {
+    debug_assert_eq!(hs_hash.len(), self.hash.output_len);
+
+    let hmac_key = _hkdf_expand_label_vec(&hmac::SigningKey::new(self.hash, base_key),
+                                          b"finished",
+                                          &[],
+                                          self.hash.output_len);
+
+    hmac::sign(&hmac::SigningKey::new(self.hash, &hmac_key), hs_hash)
+      .as_ref()
+      .to_vec()
+  }
+
+  /// Derive the next application traffic secret of given `kind`,
+  /// for a KeyUpdate.
+  pub fn derive_next(&self, kind: SecretKind) -> Vec<u8> {
+    let base_key = match kind {
+      SecretKind::ServerApplicationTrafficSecret => &self.current_server_traffic_secret,
+      SecretKind::ClientApplicationTrafficSecret => &self.current_client_traffic_secret,
+      _ => unreachable!()
+    };
+
+    _hkdf_expand_label_vec(&hmac::SigningKey::new(self.hash, base_key),
+                           b"traffic upd",
+                           &[],
+                           self.hash.output_len)
+  }
+
+  /// Fill `out` with keying material exported as described in
+  /// RFC8446, using the exporter master secret.  A `context`
+  /// of `None` is the same as an empty one.
+  pub fn export_keying_material(&self,
+                                out: &mut [u8],
+                                label: &[u8],
+                                context: Option<&[u8]>) -> Result<(), TLSError> {
+    if out.len() > 255 * self.hash.output_len {
+      return Err(TLSError::General("exported keying material too long".to_string()));
+    }
+
+    if label.len() > MAX_LABEL_LEN {
+      return Err(TLSError::General("exporter label too long".to_string()));
+    }
+
+    let secret = hmac::SigningKey::new(self.hash, &self.current_exporter_secret);
+    let derived = _hkdf_expand_label_vec(&secret,
+                                         label,
+                                         self.get_hash_of_empty_message(),
+                                         self.hash.output_len);
+
+    let context_hash = digest::digest(self.hash, context.unwrap_or(&[]));
+    _hkdf_expand_label(out,
+                       &hmac::SigningKey::new(self.hash, &derived),
+                       b"exporter",
+                       context_hash.as_ref());
+    Ok(())
+  }
+}
+
+fn _hkdf_expand_label_vec(secret: &hmac::SigningKey,
+                          label: &[u8],
+                          context: &[u8],
+                          len: usize) -> Vec<u8> {
+  let mut v = Vec::new();
+  v.resize(len, 0u8);
+  _hkdf_expand_label(&mut v,
+                     secret,
+                     label,
+                     context);
+  v
+}
+
+const LABEL_PREFIX: &'static [u8] = b"tls13 ";
+
+/// The longest label which fits in an HkdfLabel after
+/// `LABEL_PREFIX`.
+const MAX_LABEL_LEN: usize = 255 - 6;
+
+fn _hkdf_expand_label(output: &mut [u8],
+                      secret: &hmac::SigningKey,
+                      label: &[u8],
+                      context: &[u8]) {
+  assert!(label.len() <= MAX_LABEL_LEN && context.len() <= 255);
+
+  let mut hkdflabel = Vec::new();
+  codec::encode_u16(output.len() as u16, &mut hkdflabel);
+  codec::encode_u8((LABEL_PREFIX.len() + label.len()) as u8, &mut hkdflabel);
+  hkdflabel.extend_from_slice(LABEL_PREFIX);
+  hkdflabel.extend_from_slice(label);
+  codec::encode_u8(context.len() as u8, &mut hkdflabel);
+  hkdflabel.extend_from_slice(context);
+
+  hkdf::expand(secret, &hkdflabel, output)
+}
+
+/// Derive the AEAD key of length `len` from traffic `secret`.
+pub fn derive_traffic_key(hash: &'static digest::Algorithm, secret: &[u8], len: usize) -> Vec<u8> {
+  _hkdf_expand_label_vec(&hmac::SigningKey::new(hash, secret), b"key", &[], len)
+}
+
+/// Derive the AEAD iv of length `len` from traffic `secret`.
+pub fn derive_traffic_iv(hash: &'static digest::Algorithm, secret: &[u8], len: usize) -> Vec<u8> {
+  _hkdf_expand_label_vec(&hmac::SigningKey::new(hash, secret), b"iv", &[], len)
+}
+
+#[cfg(test)]
+mod test {
+  use super::{KeySchedule, SecretKind, derive_traffic_key, derive_traffic_iv};
+  use ring::digest;
+
+  #[test]
+  fn test_empty_derived_secret() {
+    /* From RFC8448: 'derived' secret computed from the early secret
+     * of a handshake without PSK. */
+    let expect = [
+      0x6f, 0x26, 0x15, 0xa1, 0x08, 0xc7, 0x02, 0xc5,
+      0x67, 0x8f, 0x54, 0xfc, 0x9d, 0xba, 0xb6, 0x97,
+      0x16, 0xc0, 0x76, 0x18, 0x9c, 0x48, 0x25, 0x0c,
+      0xeb, 0xea, 0xc3, 0x57, 0x6c, 0x36, 0x11, 0xba
+    ];
+
+    let mut ks = KeySchedule::new(&digest::SHA256);
+    ks.input_empty();
+
+    let empty_hash = ks.get_hash_of_empty_message().to_vec();
+    let derived = ks.derive(SecretKind::DerivedSecret, &empty_hash);
+    assert_eq!(derived, expect.to_vec());
+  }
+
+  #[test]
+  fn test_traffic_key_lengths() {
+    let secret = [0x42u8; 32];
+    assert_eq!(derive_traffic_key(&digest::SHA256, &secret, 16).len(), 16);
+    assert_eq!(derive_traffic_iv(&digest::SHA256, &secret, 12).len(), 12);
+    assert!(derive_traffic_key(&digest::SHA256, &secret, 16) !=
+            derive_traffic_iv(&digest::SHA256, &secret, 16));
+  }
+
+  #[test]
+  fn test_rfc8448_simple_1rtt() {
+    /* RFC8448 section 3: the early, handshake and master secrets
+     * are checked through the traffic secrets derived from them,
+     * and those in turn through their traffic keys and ivs. */
+    let ecdhe = [
+      0x8b, 0xd4, 0x05, 0x4f, 0xb5, 0x5b, 0x9d, 0x63,
+      0xfd, 0xfb, 0xac, 0xf9, 0xf0, 0x4b, 0x9f, 0x0d,
+      0x35, 0xe6, 0xd6, 0x3f, 0x53, 0x75, 0x63, 0xef,
+      0xd4, 0x62, 0x72, 0x90, 0x0f, 0x89, 0x49, 0x2d
+    ];
+    let hs_hash = [
+      0x86, 0x0c, 0x06, 0xed, 0xc0, 0x78, 0x58, 0xee,
+      0x8e, 0x78, 0xf0, 0xe7, 0x42, 0x8c, 0x58, 0xed,
+      0xd6, 0xb4, 0x3f, 0x2c, 0xa3, 0xe6, 0xe9, 0x5f,
+      0x02, 0xed, 0x06, 0x3c, 0xf0, 0xe1, 0xca, 0xd8
+    ];
+    let client_hs_secret = [
+      0xb3, 0xed, 0xdb, 0x12, 0x6e, 0x06, 0x7f, 0x35,
+      0xa7, 0x80, 0xb3, 0xab, 0xf4, 0x5e, 0x2d, 0x8f,
+      0x3b, 0x1a, 0x95, 0x07, 0x38, 0xf5, 0x2e, 0x96,
+      0x00, 0x74, 0x6a, 0x0e, 0x27, 0xa5, 0x5a, 0x21
+    ];
+    let server_hs_secret = [
+      0xb6, 0x7b, 0x7d, 0x69, 0x0c, 0xc1, 0x6c, 0x4e,
+      0x75, 0xe5, 0x42, 0x13, 0xcb, 0x2d, 0x37, 0xb4,
+      0xe9, 0xc9, 0x12, 0xbc, 0xde, 0xd9, 0x10, 0x5d,
+      0x42, 0xbe, 0xfd, 0x59, 0xd3, 0x91, 0xad, 0x38
+    ];
+    let client_hs_key = [
+      0xdb, 0xfa, 0xa6, 0x93, 0xd1, 0x76, 0x2c, 0x5b,
+      0x66, 0x6a, 0xf5, 0xd9, 0x50, 0x25, 0x8d, 0x01
+    ];
+    let client_hs_iv = [
+      0x5b, 0xd3, 0xc7, 0x1b, 0x83, 0x6e, 0x0b, 0x76,
+      0xbb, 0x73, 0x26, 0x5f
+    ];
+    let server_hs_key = [
+      0x3f, 0xce, 0x51, 0x60, 0x09, 0xc2, 0x17, 0x27,
+      0xd0, 0xf2, 0xe4, 0xe8, 0x6e, 0xe4, 0x03, 0xbc
+    ];
+    let server_hs_iv = [
+      0x5d, 0x31, 0x3e, 0xb2, 0x67, 0x12, 0x76, 0xee,
+      0x13, 0x00, 0x0b, 0x30
+    ];
+
+    let mut ks = KeySchedule::new(&digest::SHA256);
+    ks.input_empty();
+    ks.input_secret(&ecdhe);
+
+    let client_hs = ks.derive(SecretKind::ClientHandshakeTrafficSecret, &hs_hash);
+    let server_hs = ks.derive(SecretKind::ServerHandshakeTrafficSecret, &hs_hash);
+    assert_eq!(client_hs, client_hs_secret.to_vec());
+    assert_eq!(server_hs, server_hs_secret.to_vec());
+    assert_eq!(derive_traffic_key(&digest::SHA256, &client_hs, 16), client_hs_key.to_vec());
+    assert_eq!(derive_traffic_iv(&digest::SHA256, &client_hs, 12), client_hs_iv.to_vec());
+    assert_eq!(derive_traffic_key(&digest::SHA256, &server_hs, 16), server_hs_key.to_vec());
+    assert_eq!(derive_traffic_iv(&digest::SHA256, &server_hs, 12), server_hs_iv.to_vec());
+
+    let ap_hash = [
+      0x96, 0x08, 0x10, 0x2a, 0x0f, 0x1c, 0xcc, 0x6d,
+      0xb6, 0x25, 0x0b, 0x7b, 0x7e, 0x41, 0x7b, 0x1a,
+      0x00, 0x0e, 0xaa, 0xda, 0x3d, 0xaa, 0xe4, 0x77,
+      0x7a, 0x76, 0x86, 0xc9, 0xff, 0x83, 0xdf, 0x13
+    ];
+    let client_ap_secret = [
+      0x9e, 0x40, 0x64, 0x6c, 0xe7, 0x9a, 0x7f, 0x9d,
+      0xc0, 0x5a, 0xf8, 0x88, 0x9b, 0xce, 0x65, 0x52,
+      0x87, 0x5a, 0xfa, 0x0b, 0x06, 0xdf, 0x00, 0x87,
+      0xf7, 0x92, 0xeb, 0xb7, 0xc1, 0x75, 0x04, 0xa5
+    ];
+    let server_ap_secret = [
+      0xa1, 0x1a, 0xf9, 0xf0, 0x55, 0x31, 0xf8, 0x56,
+      0xad, 0x47, 0x11, 0x6b, 0x45, 0xa9, 0x50, 0x32,
+      0x82, 0x04, 0xb4, 0xf4, 0x4b, 0xfb, 0x6b, 0x3a,
+      0x4b, 0x4f, 0x1f, 0x3f, 0xcb, 0x63, 0x16, 0x43
+    ];
+    let exporter_secret = [
+      0xfe, 0x22, 0xf8, 0x81, 0x17, 0x6e, 0xda, 0x18,
+      0xeb, 0x8f, 0x44, 0x52, 0x9e, 0x67, 0x92, 0xc5,
+      0x0c, 0x9a, 0x3f, 0x89, 0x45, 0x2f, 0x68, 0xd8,
+      0xae, 0x31, 0x1b, 0x43, 0x09, 0xd3, 0xcf, 0x50
+    ];
+    let client_ap_key = [
+      0x17, 0x42, 0x2d, 0xda, 0x59, 0x6e, 0xd5, 0xd9,
+      0xac, 0xd8, 0x90, 0xe3, 0xc6, 0x3f, 0x50, 0x51
+    ];
+    let client_ap_iv = [
+      0x5b, 0x78, 0x92, 0x3d, 0xee, 0x08, 0x57, 0x90,
+      0x33, 0xe5, 0x23, 0xd9
+    ];
+    let server_ap_key = [
+      0x9f, 0x02, 0x28, 0x3b, 0x6c, 0x9c, 0x07, 0xef,
+      0xc2, 0x6b, 0xb9, 0xf2, 0xac, 0x92, 0xe3, 0x56
+    ];
+    let server_ap_iv = [
+      0xcf, 0x78, 0x2b, 0x88, 0xdd, 0x83, 0x54, 0x9a,
+      0xad, 0xf1, 0xe9, 0x84
+    ];
+
+    ks.input_empty();
+
+    let client_ap = ks.derive(SecretKind::ClientApplicationTrafficSecret, &ap_hash);
+    let server_ap = ks.derive(SecretKind::ServerApplicationTrafficSecret, &ap_hash);
+    assert_eq!(client_ap, client_ap_secret.to_vec());
+    assert_eq!(server_ap, server_ap_secret.to_vec());
+    assert_eq!(ks.derive(SecretKind::ExporterMasterSecret, &ap_hash),
+               exporter_secret.to_vec());
+    assert_eq!(derive_traffic_key(&digest::SHA256, &client_ap, 16), client_ap_key.to_vec());
+    assert_eq!(derive_traffic_iv(&digest::SHA256, &client_ap, 12), client_ap_iv.to_vec());
+    assert_eq!(derive_traffic_key(&digest::SHA256, &server_ap, 16), server_ap_key.to_vec());
+    assert_eq!(derive_traffic_iv(&digest::SHA256, &server_ap, 12), server_ap_iv.to_vec());
+  }
+}
@@ -0,0 +1,76 @@
+/* Writing session secrets in the NSS key log format, so that
+ * traffic can be decrypted by tools like Wireshark. */
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Something which is told the secrets of each session, for
+/// debugging.
+///
+/// `label` is the line type from the NSS key log format, like
+/// `CLIENT_RANDOM` for a TLS1.2 master secret or
+/// `CLIENT_HANDSHAKE_TRAFFIC_SECRET` for TLS1.3.
+/// `client_random` identifies the session.
+pub trait KeyLog {
+  fn log(&self, label: &str, client_random: &[u8], secret: &[u8]);
+}
+
+/// A `KeyLog` which forgets everything.  This is the default.
+pub struct NoKeyLog {}
+
+impl KeyLog for NoKeyLog {
+  fn log(&self, _label: &str, _client_random: &[u8], _secret: &[u8]) {}
+}
+
+/// A `KeyLog` which appends to a file: normally the one named
+/// by the `SSLKEYLOGFILE` environment variable.  If that is
+/// unset or the file can't be opened, nothing is logged.
+pub struct KeyLogFile {
+  file: Mutex<Option<fs::File>>
+}
+
+impl KeyLogFile {
+  /// Open the file named by `SSLKEYLOGFILE`, if any.
+  pub fn new() -> KeyLogFile {
+    match env::var_os("SSLKEYLOGFILE") {
+      Some(path) => KeyLogFile::for_path(path),
+      None => KeyLogFile { file: Mutex::new(None) }
+    }
+  }
+
+  /// Append to the file at `path`, creating it if needed.
+  /// If it can't be opened, nothing is logged.
+  pub fn for_path<P: AsRef<Path>>(path: P) -> KeyLogFile {
+    let path = path.as_ref();
+    let file = fs::OpenOptions::new()
+      .append(true)
+      .create(true)
+      .open(path)
+      .map_err(|err| warn!("unable to open key log file {:?}: {}", path, err))
+      .ok();
+
+    KeyLogFile { file: Mutex::new(file) }
+  }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter()
+    .map(|b| format!("{:02x}", b))
+    .collect()
+}
+
+impl KeyLog for KeyLogFile {
+  fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+    let mut file = self.file.lock().unwrap();
+
+    if let Some(ref mut file) = *file {
+      let line = format!("{} {} {}\n", label, to_hex(client_random), to_hex(secret));
+      if let Err(err) = file.write_all(line.as_bytes()) {
+        warn!("unable to write key log file: {}", err);
+      }
+    }
+  }
+}
@@ -5,26 +5,37 @@
 //!
 //! ## Current features
 //!
-//! * TLS1.2 only.
-//! * ECDSA or RSA server authentication by clients.
-//! * RSA server authentication by servers.
+//! * TLS1.2 and TLS1.3.
+//! * Ed25519, ECDSA or RSA server authentication by clients.
+//! * Ed25519, ECDSA or RSA server authentication by servers.
 //! * Forward secrecy using ECDHE; with curve25519, nistp256 or nistp384 curves.
 //! * AES128-GCM and AES256-GCM bulk encryption, with safe nonces.
 //! * Chacha20Poly1305 bulk encryption.
 //! * ALPN support.
 //! * SNI support.
 //! * Tunable MTU to make TLS messages match size of underlying transport.
-//! * Resumption.
-//! * Resumption via tickets (RFC5077).
+//! * Resumption, in TLS1.2.
+//! * Resumption via tickets (RFC5077), in TLS1.2.
+//! * Extended master secret support (RFC7627).
 //! * Client authentication by clients.
 //! * Client authentication by servers.
+//! * OCSP stapling by servers, and OCSP response checking by clients.
+//! * Certificate transparency SCTs sent by servers, and checked by clients.
+//! * Certificate revocation list checking of server and client certificates.
+//! * Loading trusted root certificates from the system store on Linux.
+//! * Several certificate chains per hostname, chosen by what the client supports.
+//! * Several client certificate chains, chosen by the issuers the server accepts.
+//! * Exporting keying material (RFC5705).
+//! * Channel bindings (RFC5929): tls-unique and tls-server-end-point.
+//! * Logging session secrets to `SSLKEYLOGFILE`, for debugging.
+//! * Extracting record layer state after the handshake, for kernel TLS.
+//! * Saving and restoring sessions after the handshake, for handing them to another process.
+//! * A blocking `Stream` over any `io::Read + io::Write` transport.
 //!
 //! ## Possible future features
 //!
-//! * ECDSA server authentication by servers.
 //! * PSK support.
-//! * TLS1.3.
-//! * OCSP stapling.
+//! * Resumption in TLS1.3.
 //! * Certificate pinning.
 //!
 //! ## Non-features
@@ -97,6 +108,9 @@
 //! config.root_store.add_trust_anchors(&webpki_roots::ROOTS);
 //! ```
 //!
+//! Alternatively, `RootCertStore::from_system()` loads the root certificates
+//! trusted by the operating system, including any installed locally.
+//!
 //! Now we can make a session.  You need to provide the server's hostname so we
 //! know what to expect to find in the server's certificate.
 //!
@@ -152,6 +166,23 @@
 //! }
 //! ```
 //!
+//! If you're happy to block, `Stream` does all of this for you.  It
+//! owns a session and a transport like a `TcpStream`, and is itself
+//! `io::Read` and `io::Write`:
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use std::io::{Read, Write};
+//! # let rc_config = Arc::new(rustls::ClientConfig::new());
+//! let client = rustls::ClientSession::new(&rc_config, "example.com");
+//! let socket = std::net::TcpStream::connect("example.com:443").unwrap();
+//! let mut stream = rustls::Stream::new(Box::new(client), socket);
+//!
+//! stream.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+//! let mut plaintext = Vec::new();
+//! stream.read_to_end(&mut plaintext).unwrap();
+//! ```
+//!
 //! # Examples
 //! `tlsserver` and `tlsclient` are full worked examples.  These both use mio.
 //!
@@ -195,11 +226,16 @@ mod rand;
 mod hash_hs;
 mod vecbuf;
 mod prf;
+mod key_schedule;
 mod cipher;
 mod session;
 mod pemfile;
 mod x509;
-mod sign;
+mod crl;
+mod ocsp;
+mod ct;
+mod keylog;
+pub mod sign;
 mod verify;
 mod handshake;
 mod server_hs;
@@ -208,13 +244,15 @@ mod suites;
 mod ticketer;
 mod server;
 mod client;
+mod stream;
 
 /// Internal classes which may be useful outside the library.
 /// The contents of this section DO NOT form part of the stable interface.
 pub mod internal {
   /// Functions for parsing PEM files containing certificates/keys.
   pub mod pemfile {
-    pub use pemfile::{certs, rsa_private_keys};
+    pub use pemfile::{certs, crls, rsa_private_keys, pkcs8_private_keys, public_keys};
+    pub use pemfile::{ec_private_keys, private_keys, PrivateKey};
   }
 
   /// Low-level TLS message parsing and encoding functions.
@@ -224,11 +262,20 @@ pub mod internal {
 }
 
 /* The public interface is: */
+pub use msgs::enums::ProtocolVersion;
 pub use error::TLSError;
-pub use session::Session;
-pub use verify::{RootCertStore};
+pub use session::{Session, ExtractedSecrets, DirectionSecrets};
+pub use verify::{RootCertStore, ServerCertVerifier, WebPKIVerifier};
+pub use crl::CRLStore;
+pub use verify::{ClientCertVerifier, NoClientAuth, WebPKIClientVerifier};
+pub use ocsp::OCSPPolicy;
+pub use ct::CTPolicy;
+pub use keylog::{KeyLog, NoKeyLog, KeyLogFile};
 pub use client::{StoresClientSessions, ClientSessionMemoryCache, ClientConfig, ClientSession};
+pub use client::{ResolvesClientCert, ResolvesClientCertUsingIssuers};
 pub use server::{StoresServerSessions, ServerSessionMemoryCache, ServerConfig, ServerSession};
-pub use server::ProducesTickets;
+pub use server::{ProducesTickets, ResolvesCert, ResolvesServerCertUsingSNI};
+pub use server::ResolvesMultipleServerCerts;
 pub use ticketer::Ticketer;
+pub use stream::Stream;
 pub use suites::{ALL_CIPHERSUITES, SupportedCipherSuite};
@@ -76,6 +76,18 @@ impl MessageDeframer {
     Ok(new_bytes)
   }
 
+  /// Return the bytes we hold: whole messages not yet taken
+  /// from `frames`, then any partial message.  Feeding these to
+  /// `read` on a new deframer reproduces our state.
+  pub fn buffered_bytes(&self) -> Vec<u8> {
+    let mut ret = Vec::new();
+    for m in &self.frames {
+      m.encode(&mut ret);
+    }
+    ret.extend_from_slice(&self.buf);
+    ret
+  }
+
   /// Returns true if we have messages for the caller
   /// to process, either whole messages in our output
   /// queue or partial messages in our buffer.
@@ -8,6 +8,7 @@ pub enum ProtocolVersion {
   TLSv1_0,
   TLSv1_1,
   TLSv1_2,
+  TLSv1_3,
   Unknown(u16)
 }
 
@@ -29,6 +30,7 @@ impl Codec for ProtocolVersion {
       0x0301 => ProtocolVersion::TLSv1_0,
       0x0302 => ProtocolVersion::TLSv1_1,
       0x0303 => ProtocolVersion::TLSv1_2,
+      0x0304 => ProtocolVersion::TLSv1_3,
       x => ProtocolVersion::Unknown(x)
     })
   }
@@ -42,6 +44,7 @@ impl ProtocolVersion {
       ProtocolVersion::TLSv1_0 => 0x0301,
       ProtocolVersion::TLSv1_1 => 0x0302,
       ProtocolVersion::TLSv1_2 => 0x0303,
+      ProtocolVersion::TLSv1_3 => 0x0304,
       ProtocolVersion::Unknown(v) => v
     }
   }
@@ -56,6 +59,7 @@ pub enum HashAlgorithm {
   SHA256,
   SHA384,
   SHA512,
+  Intrinsic,
   Unknown(u8)
 }
 
@@ -79,6 +83,7 @@ impl Codec for HashAlgorithm {
       0x04 => HashAlgorithm::SHA256,
       0x05 => HashAlgorithm::SHA384,
       0x06 => HashAlgorithm::SHA512,
+      0x08 => HashAlgorithm::Intrinsic,
       x => HashAlgorithm::Unknown(x)
     })
   }
@@ -94,6 +99,7 @@ impl HashAlgorithm {
       HashAlgorithm::SHA256 => 0x04,
       HashAlgorithm::SHA384 => 0x05,
       HashAlgorithm::SHA512 => 0x06,
+      HashAlgorithm::Intrinsic => 0x08,
       HashAlgorithm::Unknown(v) => v
     }
   }
@@ -105,6 +111,10 @@ pub enum SignatureAlgorithm {
   RSA,
   DSA,
   ECDSA,
+  RSAPSS_SHA256,
+  RSAPSS_SHA384,
+  RSAPSS_SHA512,
+  ED25519,
   Unknown(u8)
 }
 
@@ -125,6 +135,10 @@ impl Codec for SignatureAlgorithm {
       0x01 => SignatureAlgorithm::RSA,
       0x02 => SignatureAlgorithm::DSA,
       0x03 => SignatureAlgorithm::ECDSA,
+      0x04 => SignatureAlgorithm::RSAPSS_SHA256,
+      0x05 => SignatureAlgorithm::RSAPSS_SHA384,
+      0x06 => SignatureAlgorithm::RSAPSS_SHA512,
+      0x07 => SignatureAlgorithm::ED25519,
       x => SignatureAlgorithm::Unknown(x)
     })
   }
@@ -137,6 +151,10 @@ impl SignatureAlgorithm {
       SignatureAlgorithm::RSA => 0x01,
       SignatureAlgorithm::DSA => 0x02,
       SignatureAlgorithm::ECDSA => 0x03,
+      SignatureAlgorithm::RSAPSS_SHA256 => 0x04,
+      SignatureAlgorithm::RSAPSS_SHA384 => 0x05,
+      SignatureAlgorithm::RSAPSS_SHA512 => 0x06,
+      SignatureAlgorithm::ED25519 => 0x07,
       SignatureAlgorithm::Unknown(v) => v
     }
   }
@@ -295,6 +313,9 @@ pub enum HandshakeType {
   ClientHello,
   ServerHello,
   NewSessionTicket,
+  EndOfEarlyData,
+  HelloRetryRequest,
+  EncryptedExtensions,
   Certificate,
   ServerKeyExchange,
   CertificateRequest,
@@ -304,6 +325,8 @@ pub enum HandshakeType {
   Finished,
   CertificateURL,
   CertificateStatus,
+  KeyUpdate,
+  MessageHash,
   Unknown(u8)
 }
 
@@ -324,6 +347,9 @@ impl Codec for HandshakeType {
       0x01 => HandshakeType::ClientHello,
       0x02 => HandshakeType::ServerHello,
       0x04 => HandshakeType::NewSessionTicket,
+      0x05 => HandshakeType::EndOfEarlyData,
+      0x06 => HandshakeType::HelloRetryRequest,
+      0x08 => HandshakeType::EncryptedExtensions,
       0x0b => HandshakeType::Certificate,
       0x0c => HandshakeType::ServerKeyExchange,
       0x0d => HandshakeType::CertificateRequest,
@@ -333,6 +359,8 @@ impl Codec for HandshakeType {
       0x14 => HandshakeType::Finished,
       0x15 => HandshakeType::CertificateURL,
       0x16 => HandshakeType::CertificateStatus,
+      0x18 => HandshakeType::KeyUpdate,
+      0xfe => HandshakeType::MessageHash,
       x => HandshakeType::Unknown(x)
     })
   }
@@ -345,6 +373,9 @@ impl HandshakeType {
       HandshakeType::ClientHello => 0x01,
       HandshakeType::ServerHello => 0x02,
       HandshakeType::NewSessionTicket => 0x04,
+      HandshakeType::EndOfEarlyData => 0x05,
+      HandshakeType::HelloRetryRequest => 0x06,
+      HandshakeType::EncryptedExtensions => 0x08,
       HandshakeType::Certificate => 0x0b,
       HandshakeType::ServerKeyExchange => 0x0c,
       HandshakeType::CertificateRequest => 0x0d,
@@ -354,6 +385,8 @@ impl HandshakeType {
       HandshakeType::Finished => 0x14,
       HandshakeType::CertificateURL => 0x15,
       HandshakeType::CertificateStatus => 0x16,
+      HandshakeType::KeyUpdate => 0x18,
+      HandshakeType::MessageHash => 0xfe,
       HandshakeType::Unknown(v) => v
     }
   }
@@ -422,8 +455,12 @@ pub enum AlertDescription {
   InternalError,
   UserCanceled,
   NoRenegotiation,
+  MissingExtension,
   UnsupportedExtension,
   UnrecognisedName,
+  BadCertificateStatusResponse,
+  CertificateRequired,
+  NoApplicationProtocol,
   Unknown(u8)
 }
 
@@ -464,8 +501,12 @@ impl Codec for AlertDescription {
       0x50 => AlertDescription::InternalError,
       0x5a => AlertDescription::UserCanceled,
       0x64 => AlertDescription::NoRenegotiation,
+      0x6d => AlertDescription::MissingExtension,
       0x6e => AlertDescription::UnsupportedExtension,
       0x70 => AlertDescription::UnrecognisedName,
+      0x71 => AlertDescription::BadCertificateStatusResponse,
+      0x74 => AlertDescription::CertificateRequired,
+      0x78 => AlertDescription::NoApplicationProtocol,
       x => AlertDescription::Unknown(x)
     })
   }
@@ -498,8 +539,12 @@ impl AlertDescription {
       AlertDescription::InternalError => 0x50,
       AlertDescription::UserCanceled => 0x5a,
       AlertDescription::NoRenegotiation => 0x64,
+      AlertDescription::MissingExtension => 0x6d,
       AlertDescription::UnsupportedExtension => 0x6e,
       AlertDescription::UnrecognisedName => 0x70,
+      AlertDescription::BadCertificateStatusResponse => 0x71,
+      AlertDescription::CertificateRequired => 0x74,
+      AlertDescription::NoApplicationProtocol => 0x78,
       AlertDescription::Unknown(v) => v
     }
   }
@@ -561,9 +606,20 @@ pub enum ExtensionType {
   UseSRTP,
   Heartbeat,
   ALProtocolNegotiation,
+  SCT,
   Padding,
   ExtendedMasterSecret,
   SessionTicket,
+  PreSharedKey,
+  EarlyData,
+  SupportedVersions,
+  Cookie,
+  PSKKeyExchangeModes,
+  CertificateAuthorities,
+  OIDFilters,
+  PostHandshakeAuth,
+  SignatureAlgorithmsCert,
+  KeyShare,
   NextProtocolNegotiation,
   ChannelId,
   RenegotiationInfo,
@@ -600,9 +656,20 @@ impl Codec for ExtensionType {
       0x000e => ExtensionType::UseSRTP,
       0x000f => ExtensionType::Heartbeat,
       0x0010 => ExtensionType::ALProtocolNegotiation,
+      0x0012 => ExtensionType::SCT,
       0x0015 => ExtensionType::Padding,
       0x0017 => ExtensionType::ExtendedMasterSecret,
       0x0023 => ExtensionType::SessionTicket,
+      0x0029 => ExtensionType::PreSharedKey,
+      0x002a => ExtensionType::EarlyData,
+      0x002b => ExtensionType::SupportedVersions,
+      0x002c => ExtensionType::Cookie,
+      0x002d => ExtensionType::PSKKeyExchangeModes,
+      0x002f => ExtensionType::CertificateAuthorities,
+      0x0030 => ExtensionType::OIDFilters,
+      0x0031 => ExtensionType::PostHandshakeAuth,
+      0x0032 => ExtensionType::SignatureAlgorithmsCert,
+      0x0033 => ExtensionType::KeyShare,
       0x3374 => ExtensionType::NextProtocolNegotiation,
       0x754f => ExtensionType::ChannelId,
       0xff01 => ExtensionType::RenegotiationInfo,
@@ -631,9 +698,20 @@ impl ExtensionType {
       ExtensionType::UseSRTP => 0x000e,
       ExtensionType::Heartbeat => 0x000f,
       ExtensionType::ALProtocolNegotiation => 0x0010,
+      ExtensionType::SCT => 0x0012,
       ExtensionType::Padding => 0x0015,
       ExtensionType::ExtendedMasterSecret => 0x0017,
       ExtensionType::SessionTicket => 0x0023,
+      ExtensionType::PreSharedKey => 0x0029,
+      ExtensionType::EarlyData => 0x002a,
+      ExtensionType::SupportedVersions => 0x002b,
+      ExtensionType::Cookie => 0x002c,
+      ExtensionType::PSKKeyExchangeModes => 0x002d,
+      ExtensionType::CertificateAuthorities => 0x002f,
+      ExtensionType::OIDFilters => 0x0030,
+      ExtensionType::PostHandshakeAuth => 0x0031,
+      ExtensionType::SignatureAlgorithmsCert => 0x0032,
+      ExtensionType::KeyShare => 0x0033,
       ExtensionType::NextProtocolNegotiation => 0x3374,
       ExtensionType::ChannelId => 0x754f,
       ExtensionType::RenegotiationInfo => 0xff01,
@@ -992,6 +1070,9 @@ pub enum CipherSuite {
   TLS_DHE_RSA_WITH_CAMELLIA_256_CBC_SHA256,
   TLS_DH_anon_WITH_CAMELLIA_256_CBC_SHA256,
   TLS_EMPTY_RENEGOTIATION_INFO_SCSV,
+  TLS13_AES_128_GCM_SHA256,
+  TLS13_AES_256_GCM_SHA384,
+  TLS13_CHACHA20_POLY1305_SHA256,
   TLS_ECDH_ECDSA_WITH_NULL_SHA,
   TLS_ECDH_ECDSA_WITH_RC4_128_SHA,
   TLS_ECDH_ECDSA_WITH_3DES_EDE_CBC_SHA,
@@ -1375,6 +1456,9 @@ impl Codec for CipherSuite {
       0x00c4 => CipherSuite::TLS_DHE_RSA_WITH_CAMELLIA_256_CBC_SHA256,
       0x00c5 => CipherSuite::TLS_DH_anon_WITH_CAMELLIA_256_CBC_SHA256,
       0x00ff => CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV,
+      0x1301 => CipherSuite::TLS13_AES_128_GCM_SHA256,
+      0x1302 => CipherSuite::TLS13_AES_256_GCM_SHA384,
+      0x1303 => CipherSuite::TLS13_CHACHA20_POLY1305_SHA256,
       0xc001 => CipherSuite::TLS_ECDH_ECDSA_WITH_NULL_SHA,
       0xc002 => CipherSuite::TLS_ECDH_ECDSA_WITH_RC4_128_SHA,
       0xc003 => CipherSuite::TLS_ECDH_ECDSA_WITH_3DES_EDE_CBC_SHA,
@@ -1750,6 +1834,9 @@ impl CipherSuite {
       CipherSuite::TLS_DHE_RSA_WITH_CAMELLIA_256_CBC_SHA256 => 0x00c4,
       CipherSuite::TLS_DH_anon_WITH_CAMELLIA_256_CBC_SHA256 => 0x00c5,
       CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV => 0x00ff,
+      CipherSuite::TLS13_AES_128_GCM_SHA256 => 0x1301,
+      CipherSuite::TLS13_AES_256_GCM_SHA384 => 0x1302,
+      CipherSuite::TLS13_CHACHA20_POLY1305_SHA256 => 0x1303,
       CipherSuite::TLS_ECDH_ECDSA_WITH_NULL_SHA => 0xc001,
       CipherSuite::TLS_ECDH_ECDSA_WITH_RC4_128_SHA => 0xc002,
       CipherSuite::TLS_ECDH_ECDSA_WITH_3DES_EDE_CBC_SHA => 0xc003,
@@ -2051,3 +2138,74 @@ impl ECCurveType {
     }
   }
 }
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum KeyUpdateRequest {
+  UpdateNotRequested,
+  UpdateRequested,
+  Unknown(u8)
+}
+
+impl Codec for KeyUpdateRequest {
+  fn encode(&self, bytes: &mut Vec<u8>) {
+    encode_u8(self.get_u8(), bytes);
+  }
+
+  fn read(r: &mut Reader) -> Option<KeyUpdateRequest> {
+    let u = read_u8(r);
+
+    if u.is_none() {
+      return None
+    }
+
+    Some(match u.unwrap() {
+      0x00 => KeyUpdateRequest::UpdateNotRequested,
+      0x01 => KeyUpdateRequest::UpdateRequested,
+      x => KeyUpdateRequest::Unknown(x)
+    })
+  }
+}
+
+impl KeyUpdateRequest {
+  pub fn get_u8(&self) -> u8 {
+    match *self {
+      KeyUpdateRequest::UpdateNotRequested => 0x00,
+      KeyUpdateRequest::UpdateRequested => 0x01,
+      KeyUpdateRequest::Unknown(v) => v
+    }
+  }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CertificateStatusType {
+  OCSP,
+  Unknown(u8)
+}
+
+impl Codec for CertificateStatusType {
+  fn encode(&self, bytes: &mut Vec<u8>) {
+    encode_u8(self.get_u8(), bytes);
+  }
+
+  fn read(r: &mut Reader) -> Option<CertificateStatusType> {
+    let u = read_u8(r);
+
+    if u.is_none() {
+      return None
+    }
+
+    Some(match u.unwrap() {
+      0x01 => CertificateStatusType::OCSP,
+      x => CertificateStatusType::Unknown(x)
+    })
+  }
+}
+
+impl CertificateStatusType {
+  pub fn get_u8(&self) -> u8 {
+    match *self {
+      CertificateStatusType::OCSP => 0x01,
+      CertificateStatusType::Unknown(v) => v
+    }
+  }
+}
@@ -46,15 +46,15 @@ fn test_enum8<T: Codec>(first: T, last: T) {
 
 #[test]
 fn test_enums() {
-  test_enum16::<ProtocolVersion>(ProtocolVersion::SSLv2, ProtocolVersion::TLSv1_2);
-  test_enum8::<HashAlgorithm>(HashAlgorithm::NONE, HashAlgorithm::SHA512);
-  test_enum8::<SignatureAlgorithm>(SignatureAlgorithm::Anonymous, SignatureAlgorithm::ECDSA);
+  test_enum16::<ProtocolVersion>(ProtocolVersion::SSLv2, ProtocolVersion::TLSv1_3);
+  test_enum8::<HashAlgorithm>(HashAlgorithm::NONE, HashAlgorithm::Intrinsic);
+  test_enum8::<SignatureAlgorithm>(SignatureAlgorithm::Anonymous, SignatureAlgorithm::ED25519);
   test_enum8::<ClientCertificateType>(ClientCertificateType::RSASign, ClientCertificateType::ECDSAFixedECDH);
   test_enum8::<Compression>(Compression::Null, Compression::LSZ);
   test_enum8::<ContentType>(ContentType::ChangeCipherSpec, ContentType::Heartbeat);
-  test_enum8::<HandshakeType>(HandshakeType::HelloRequest, HandshakeType::CertificateStatus);
+  test_enum8::<HandshakeType>(HandshakeType::HelloRequest, HandshakeType::MessageHash);
   test_enum8::<AlertLevel>(AlertLevel::Warning, AlertLevel::Fatal);
-  test_enum8::<AlertDescription>(AlertDescription::CloseNotify, AlertDescription::UnrecognisedName);
+  test_enum8::<AlertDescription>(AlertDescription::CloseNotify, AlertDescription::NoApplicationProtocol);
   test_enum8::<HeartbeatMessageType>(HeartbeatMessageType::Request, HeartbeatMessageType::Response);
   test_enum16::<ExtensionType>(ExtensionType::ServerName, ExtensionType::RenegotiationInfo);
   test_enum8::<ServerNameType>(ServerNameType::HostName, ServerNameType::HostName);
@@ -63,4 +63,6 @@ fn test_enums() {
   test_enum8::<ECPointFormat>(ECPointFormat::Uncompressed, ECPointFormat::ANSIX962CompressedChar2);
   test_enum8::<HeartbeatMode>(HeartbeatMode::PeerAllowedToSend, HeartbeatMode::PeerNotAllowedToSend);
   test_enum8::<ECCurveType>(ECCurveType::ExplicitPrime, ECCurveType::NamedCurve);
+  test_enum8::<KeyUpdateRequest>(KeyUpdateRequest::UpdateNotRequested, KeyUpdateRequest::UpdateRequested);
+  test_enum8::<CertificateStatusType>(CertificateStatusType::OCSP, CertificateStatusType::OCSP);
 }
@@ -3,6 +3,8 @@ use msgs::enums::{CipherSuite, Compression, ExtensionType, ECPointFormat, NamedC
 use msgs::enums::{HashAlgorithm, SignatureAlgorithm, HeartbeatMode, ServerNameType};
 use msgs::enums::ClientCertificateType;
 use msgs::enums::ECCurveType;
+use msgs::enums::KeyUpdateRequest;
+use msgs::enums::CertificateStatusType;
 use msgs::base::{Payload, PayloadU8, PayloadU16, PayloadU24};
 use msgs::codec;
 use msgs::codec::{Codec, Reader};
@@ -42,7 +44,7 @@ macro_rules! declare_u16_vec(
   }
 );
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Random {
   pub gmt_unix_time: u32,
   pub opaque: [u8; 28]
@@ -76,6 +78,20 @@ impl Random {
   }
 }
 
+static HELLO_RETRY_REQUEST_RANDOM: Random = Random {
+  gmt_unix_time: 0xcf21ad74,
+  opaque: [
+    0xe5, 0x9a, 0x61, 0x11, 0xbe, 0x1d, 0x8c, 0x02,
+    0x1e, 0x65, 0xb8, 0x91, 0xc2, 0xa2, 0x11, 0x16,
+    0x7a, 0xbb, 0x8c, 0x5e, 0x07, 0x9e, 0x09, 0xe2,
+    0xc8, 0xa8, 0x33, 0x9c
+  ]
+};
+
+/// This is the last eight bytes of a TLS1.3 server's random, when it
+/// negotiates TLS1.2 with a client which offered TLS1.3.
+pub static DOWNGRADE_SENTINEL: &'static [u8] = b"DOWNGRD\x01";
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct SessionID {
   bytes: Vec<u8>
@@ -119,7 +135,7 @@ impl SessionID {
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct UnknownExtension {
   typ: ExtensionType,
   payload: Payload
@@ -201,16 +217,20 @@ impl SupportedMandatedSignatureAlgorithms for SupportedSignatureAlgorithms {
   /// Supported signature verification algorithms in decreasing order of expected security.
   fn supported_verify() -> SupportedSignatureAlgorithms {
     vec![
-      SignatureAndHashAlgorithm { hash: HashAlgorithm::SHA512, sign: SignatureAlgorithm::ECDSA },
+      SignatureAndHashAlgorithm { hash: HashAlgorithm::Intrinsic, sign: SignatureAlgorithm::ED25519 },
+
       SignatureAndHashAlgorithm { hash: HashAlgorithm::SHA384, sign: SignatureAlgorithm::ECDSA },
       SignatureAndHashAlgorithm { hash: HashAlgorithm::SHA256, sign: SignatureAlgorithm::ECDSA },
 
+      SignatureAndHashAlgorithm { hash: HashAlgorithm::Intrinsic, sign: SignatureAlgorithm::RSAPSS_SHA512 },
+      SignatureAndHashAlgorithm { hash: HashAlgorithm::Intrinsic, sign: SignatureAlgorithm::RSAPSS_SHA384 },
+      SignatureAndHashAlgorithm { hash: HashAlgorithm::Intrinsic, sign: SignatureAlgorithm::RSAPSS_SHA256 },
+
       SignatureAndHashAlgorithm { hash: HashAlgorithm::SHA512, sign: SignatureAlgorithm::RSA },
       SignatureAndHashAlgorithm { hash: HashAlgorithm::SHA384, sign: SignatureAlgorithm::RSA },
       SignatureAndHashAlgorithm { hash: HashAlgorithm::SHA256, sign: SignatureAlgorithm::RSA },
 
       /* Leave the truly crap ones for last */
-      SignatureAndHashAlgorithm { hash: HashAlgorithm::SHA1, sign: SignatureAlgorithm::ECDSA },
       SignatureAndHashAlgorithm { hash: HashAlgorithm::SHA1, sign: SignatureAlgorithm::RSA },
     ]
   }
@@ -275,6 +295,23 @@ impl Codec for ServerName {
 
 declare_u16_vec!(ServerNameRequest, ServerName);
 
+pub trait ConvertServerNameList {
+  fn get_hostname(&self) -> Option<&str>;
+}
+
+impl ConvertServerNameList for ServerNameRequest {
+  /// Returns the first host name in the list, if any.
+  fn get_hostname(&self) -> Option<&str> {
+    for name in self {
+      if let ServerNamePayload::HostName(ref host) = name.payload {
+        return Some(host);
+      }
+    }
+
+    None
+  }
+}
+
 pub type ProtocolName = PayloadU8;
 declare_u16_vec!(ProtocolNameList, ProtocolName);
 
@@ -315,6 +352,108 @@ impl ConvertProtocolNameList for ProtocolNameList {
   }
 }
 
+/* -- TLS1.3 extension payloads -- */
+#[derive(Debug, Clone)]
+pub struct KeyShareEntry {
+  pub group: NamedCurve,
+  pub payload: PayloadU16
+}
+
+impl KeyShareEntry {
+  pub fn new(group: NamedCurve, payload: &[u8]) -> KeyShareEntry {
+    KeyShareEntry { group: group, payload: PayloadU16::new(payload.to_vec()) }
+  }
+}
+
+impl Codec for KeyShareEntry {
+  fn encode(&self, bytes: &mut Vec<u8>) {
+    self.group.encode(bytes);
+    self.payload.encode(bytes);
+  }
+
+  fn read(r: &mut Reader) -> Option<KeyShareEntry> {
+    let group = try_ret!(NamedCurve::read(r));
+    let payload = try_ret!(PayloadU16::read(r));
+
+    Some(KeyShareEntry { group: group, payload: payload })
+  }
+}
+
+declare_u16_vec!(KeyShareEntries, KeyShareEntry);
+declare_u8_vec!(ProtocolVersions, ProtocolVersion);
+
+/* OCSP ResponderIDs, the RFC6962 SignedCertificateTimestampList
+ * and DistinguishedNames are all u16-length lists of u16-length
+ * opaque items. */
+declare_u16_vec!(PayloadU16List, PayloadU16);
+pub type ResponderIDs = PayloadU16List;
+pub type SCTList = PayloadU16List;
+
+#[derive(Debug)]
+pub struct OCSPCertificateStatusRequest {
+  pub responder_ids: ResponderIDs,
+  pub extensions: PayloadU16
+}
+
+impl Codec for OCSPCertificateStatusRequest {
+  fn encode(&self, bytes: &mut Vec<u8>) {
+    CertificateStatusType::OCSP.encode(bytes);
+    self.responder_ids.encode(bytes);
+    self.extensions.encode(bytes);
+  }
+
+  fn read(r: &mut Reader) -> Option<OCSPCertificateStatusRequest> {
+    Some(OCSPCertificateStatusRequest {
+      responder_ids: try_ret!(ResponderIDs::read(r)),
+      extensions: try_ret!(PayloadU16::read(r))
+    })
+  }
+}
+
+#[derive(Debug)]
+pub enum CertificateStatusRequest {
+  OCSP(OCSPCertificateStatusRequest),
+  Unknown((CertificateStatusType, Payload))
+}
+
+impl Codec for CertificateStatusRequest {
+  fn encode(&self, bytes: &mut Vec<u8>) {
+    match *self {
+      CertificateStatusRequest::OCSP(ref r) => r.encode(bytes),
+      CertificateStatusRequest::Unknown((typ, ref payload)) => {
+        typ.encode(bytes);
+        payload.encode(bytes);
+      }
+    }
+  }
+
+  fn read(r: &mut Reader) -> Option<CertificateStatusRequest> {
+    let typ = try_ret!(CertificateStatusType::read(r));
+
+    match typ {
+      CertificateStatusType::OCSP => {
+        let ocsp_req = try_ret!(OCSPCertificateStatusRequest::read(r));
+        Some(CertificateStatusRequest::OCSP(ocsp_req))
+      }
+      _ => {
+        let data = try_ret!(Payload::read(r));
+        Some(CertificateStatusRequest::Unknown((typ, data)))
+      }
+    }
+  }
+}
+
+impl CertificateStatusRequest {
+  /// An OCSP status request naming no particular responders.
+  pub fn build_ocsp() -> CertificateStatusRequest {
+    let ocsp = OCSPCertificateStatusRequest {
+      responder_ids: ResponderIDs::new(),
+      extensions: PayloadU16::new(Vec::new())
+    };
+    CertificateStatusRequest::OCSP(ocsp)
+  }
+}
+
 #[derive(Debug)]
 pub enum ClientExtension {
   ECPointFormats(ECPointFormatList),
@@ -325,6 +464,12 @@ pub enum ClientExtension {
   SessionTicketRequest,
   SessionTicketOffer(Payload),
   Protocols(ProtocolNameList),
+  SupportedVersions(ProtocolVersions),
+  KeyShare(KeyShareEntries),
+  Cookie(PayloadU16),
+  ExtendedMasterSecretRequest,
+  CertificateStatusRequest(CertificateStatusRequest),
+  SignedCertificateTimestampRequest,
   Unknown(UnknownExtension)
 }
 
@@ -339,6 +484,12 @@ impl ClientExtension {
       ClientExtension::SessionTicketRequest => ExtensionType::SessionTicket,
       ClientExtension::SessionTicketOffer(_) => ExtensionType::SessionTicket,
       ClientExtension::Protocols(_) => ExtensionType::ALProtocolNegotiation,
+      ClientExtension::SupportedVersions(_) => ExtensionType::SupportedVersions,
+      ClientExtension::KeyShare(_) => ExtensionType::KeyShare,
+      ClientExtension::Cookie(_) => ExtensionType::Cookie,
+      ClientExtension::ExtendedMasterSecretRequest => ExtensionType::ExtendedMasterSecret,
+      ClientExtension::CertificateStatusRequest(_) => ExtensionType::StatusRequest,
+      ClientExtension::SignedCertificateTimestampRequest => ExtensionType::SCT,
       ClientExtension::Unknown(ref r) => r.typ
     }
   }
@@ -358,6 +509,12 @@ impl Codec for ClientExtension {
       ClientExtension::SessionTicketRequest => (),
       ClientExtension::SessionTicketOffer(ref r) => r.encode(&mut sub),
       ClientExtension::Protocols(ref r) => r.encode(&mut sub),
+      ClientExtension::SupportedVersions(ref r) => r.encode(&mut sub),
+      ClientExtension::KeyShare(ref r) => r.encode(&mut sub),
+      ClientExtension::Cookie(ref r) => r.encode(&mut sub),
+      ClientExtension::ExtendedMasterSecretRequest => (),
+      ClientExtension::CertificateStatusRequest(ref r) => r.encode(&mut sub),
+      ClientExtension::SignedCertificateTimestampRequest => (),
       ClientExtension::Unknown(ref r) => r.encode(&mut sub)
     }
 
@@ -389,6 +546,18 @@ impl Codec for ClientExtension {
         },
       ExtensionType::ALProtocolNegotiation =>
         ClientExtension::Protocols(try_ret!(ProtocolNameList::read(&mut sub))),
+      ExtensionType::SupportedVersions =>
+        ClientExtension::SupportedVersions(try_ret!(ProtocolVersions::read(&mut sub))),
+      ExtensionType::KeyShare =>
+        ClientExtension::KeyShare(try_ret!(KeyShareEntries::read(&mut sub))),
+      ExtensionType::Cookie =>
+        ClientExtension::Cookie(try_ret!(PayloadU16::read(&mut sub))),
+      ExtensionType::ExtendedMasterSecret =>
+        ClientExtension::ExtendedMasterSecretRequest,
+      ExtensionType::StatusRequest =>
+        ClientExtension::CertificateStatusRequest(try_ret!(CertificateStatusRequest::read(&mut sub))),
+      ExtensionType::SCT =>
+        ClientExtension::SignedCertificateTimestampRequest,
       _ =>
         ClientExtension::Unknown(try_ret!(UnknownExtension::read(typ, &mut sub)))
     })
@@ -417,6 +586,11 @@ pub enum ServerExtension {
   SessionTicketAcknowledgement,
   RenegotiationInfo(PayloadU8),
   Protocols(ProtocolNameList),
+  KeyShare(KeyShareEntry),
+  SupportedVersions(ProtocolVersion),
+  ExtendedMasterSecretAck,
+  CertificateStatusAck,
+  SignedCertificateTimestamp(SCTList),
   Unknown(UnknownExtension)
 }
 
@@ -429,6 +603,11 @@ impl ServerExtension {
       ServerExtension::SessionTicketAcknowledgement => ExtensionType::SessionTicket,
       ServerExtension::RenegotiationInfo(_) => ExtensionType::RenegotiationInfo,
       ServerExtension::Protocols(_) => ExtensionType::ALProtocolNegotiation,
+      ServerExtension::KeyShare(_) => ExtensionType::KeyShare,
+      ServerExtension::SupportedVersions(_) => ExtensionType::SupportedVersions,
+      ServerExtension::ExtendedMasterSecretAck => ExtensionType::ExtendedMasterSecret,
+      ServerExtension::CertificateStatusAck => ExtensionType::StatusRequest,
+      ServerExtension::SignedCertificateTimestamp(_) => ExtensionType::SCT,
       ServerExtension::Unknown(ref r) => r.typ
     }
   }
@@ -446,6 +625,11 @@ impl Codec for ServerExtension {
       ServerExtension::SessionTicketAcknowledgement => (),
       ServerExtension::RenegotiationInfo(ref r) => r.encode(&mut sub),
       ServerExtension::Protocols(ref r) => r.encode(&mut sub),
+      ServerExtension::KeyShare(ref r) => r.encode(&mut sub),
+      ServerExtension::SupportedVersions(ref r) => r.encode(&mut sub),
+      ServerExtension::ExtendedMasterSecretAck => (),
+      ServerExtension::CertificateStatusAck => (),
+      ServerExtension::SignedCertificateTimestamp(ref r) => r.encode(&mut sub),
       ServerExtension::Unknown(ref r) => r.encode(&mut sub)
     }
 
@@ -471,6 +655,16 @@ impl Codec for ServerExtension {
         ServerExtension::RenegotiationInfo(try_ret!(PayloadU8::read(&mut sub))),
       ExtensionType::ALProtocolNegotiation =>
         ServerExtension::Protocols(try_ret!(ProtocolNameList::read(&mut sub))),
+      ExtensionType::KeyShare =>
+        ServerExtension::KeyShare(try_ret!(KeyShareEntry::read(&mut sub))),
+      ExtensionType::SupportedVersions =>
+        ServerExtension::SupportedVersions(try_ret!(ProtocolVersion::read(&mut sub))),
+      ExtensionType::ExtendedMasterSecret =>
+        ServerExtension::ExtendedMasterSecretAck,
+      ExtensionType::StatusRequest =>
+        ServerExtension::CertificateStatusAck,
+      ExtensionType::SCT =>
+        ServerExtension::SignedCertificateTimestamp(try_ret!(SCTList::read(&mut sub))),
       _ =>
         ServerExtension::Unknown(try_ret!(UnknownExtension::read(typ, &mut sub)))
     })
@@ -595,6 +789,70 @@ impl ClientHelloPayload {
   pub fn get_ticket_extension(&self) -> Option<&ClientExtension> {
     self.find_extension(ExtensionType::SessionTicket)
   }
+
+  pub fn get_versions_extension(&self) -> Option<&ProtocolVersions> {
+    let ext = try_ret!(self.find_extension(ExtensionType::SupportedVersions));
+    match *ext {
+      ClientExtension::SupportedVersions(ref vers) => Some(vers),
+      _ => None
+    }
+  }
+
+  pub fn get_status_request(&self) -> Option<&CertificateStatusRequest> {
+    let ext = try_ret!(self.find_extension(ExtensionType::StatusRequest));
+    match *ext {
+      ClientExtension::CertificateStatusRequest(ref csr) => Some(csr),
+      _ => None
+    }
+  }
+
+  /// Returns true if the client asked for a stapled OCSP response.
+  pub fn ocsp_stapling_requested(&self) -> bool {
+    match self.get_status_request() {
+      Some(&CertificateStatusRequest::OCSP(_)) => true,
+      _ => false
+    }
+  }
+
+  /// Returns true if the client asked for signed certificate
+  /// timestamps (RFC6962).
+  pub fn sct_requested(&self) -> bool {
+    self.find_extension(ExtensionType::SCT).is_some()
+  }
+
+  pub fn ems_support_offered(&self) -> bool {
+    self.find_extension(ExtensionType::ExtendedMasterSecret).is_some()
+  }
+
+  pub fn get_keyshare_extension(&self) -> Option<&KeyShareEntries> {
+    let ext = try_ret!(self.find_extension(ExtensionType::KeyShare));
+    match *ext {
+      ClientExtension::KeyShare(ref shares) => Some(shares),
+      _ => None
+    }
+  }
+
+  /// Returns true if the key_share extension offers more than
+  /// one share in any group.
+  pub fn has_keyshare_extension_with_duplicates(&self) -> bool {
+    let entries = match self.get_keyshare_extension() {
+      Some(entries) => entries,
+      None => return false
+    };
+
+    let mut seen = collections::HashSet::new();
+
+    for kse in entries {
+      let grp = kse.group.get_u16();
+
+      if seen.contains(&grp) {
+        return true;
+      }
+      seen.insert(grp);
+    }
+
+    false
+  }
 }
 
 #[derive(Debug)]
@@ -667,6 +925,201 @@ impl ServerHelloPayload {
       _ => None
     }
   }
+
+  pub fn get_key_share(&self) -> Option<&KeyShareEntry> {
+    let ext = try_ret!(self.find_extension(ExtensionType::KeyShare));
+    match *ext {
+      ServerExtension::KeyShare(ref share) => Some(share),
+      _ => None
+    }
+  }
+
+  pub fn get_supported_versions(&self) -> Option<ProtocolVersion> {
+    let ext = try_ret!(self.find_extension(ExtensionType::SupportedVersions));
+    match *ext {
+      ServerExtension::SupportedVersions(vers) => Some(vers),
+      _ => None
+    }
+  }
+
+  pub fn ems_support_acked(&self) -> bool {
+    self.find_extension(ExtensionType::ExtendedMasterSecret).is_some()
+  }
+
+  pub fn ocsp_stapling_acked(&self) -> bool {
+    self.find_extension(ExtensionType::StatusRequest).is_some()
+  }
+
+  pub fn get_sct_list(&self) -> Option<&SCTList> {
+    let ext = try_ret!(self.find_extension(ExtensionType::SCT));
+    match *ext {
+      ServerExtension::SignedCertificateTimestamp(ref sct) => Some(sct),
+      _ => None
+    }
+  }
+}
+
+/* -- HelloRetryRequest -- */
+#[derive(Debug)]
+pub enum HelloRetryExtension {
+  KeyShare(NamedCurve),
+  Cookie(PayloadU16),
+  SupportedVersions(ProtocolVersion),
+  Unknown(UnknownExtension)
+}
+
+impl HelloRetryExtension {
+  pub fn get_type(&self) -> ExtensionType {
+    match *self {
+      HelloRetryExtension::KeyShare(_) => ExtensionType::KeyShare,
+      HelloRetryExtension::Cookie(_) => ExtensionType::Cookie,
+      HelloRetryExtension::SupportedVersions(_) => ExtensionType::SupportedVersions,
+      HelloRetryExtension::Unknown(ref r) => r.typ
+    }
+  }
+}
+
+impl Codec for HelloRetryExtension {
+  fn encode(&self, bytes: &mut Vec<u8>) {
+    self.get_type().encode(bytes);
+
+    let mut sub: Vec<u8> = Vec::new();
+    match *self {
+      HelloRetryExtension::KeyShare(ref r) => r.encode(&mut sub),
+      HelloRetryExtension::Cookie(ref r) => r.encode(&mut sub),
+      HelloRetryExtension::SupportedVersions(ref r) => r.encode(&mut sub),
+      HelloRetryExtension::Unknown(ref r) => r.encode(&mut sub)
+    }
+
+    codec::encode_u16(sub.len() as u16, bytes);
+    bytes.append(&mut sub);
+  }
+
+  fn read(r: &mut Reader) -> Option<HelloRetryExtension> {
+    let typ = try_ret!(ExtensionType::read(r));
+    let len = try_ret!(codec::read_u16(r)) as usize;
+    let mut sub = try_ret!(r.sub(len));
+
+    Some(match typ {
+      ExtensionType::KeyShare =>
+        HelloRetryExtension::KeyShare(try_ret!(NamedCurve::read(&mut sub))),
+      ExtensionType::Cookie =>
+        HelloRetryExtension::Cookie(try_ret!(PayloadU16::read(&mut sub))),
+      ExtensionType::SupportedVersions =>
+        HelloRetryExtension::SupportedVersions(try_ret!(ProtocolVersion::read(&mut sub))),
+      _ =>
+        HelloRetryExtension::Unknown(try_ret!(UnknownExtension::read(typ, &mut sub)))
+    })
+  }
+}
+
+/// A HelloRetryRequest.  On the wire, this is a ServerHello
+/// with a special, fixed, `random` value.
+#[derive(Debug)]
+pub struct HelloRetryRequest {
+  pub legacy_version: ProtocolVersion,
+  pub session_id: SessionID,
+  pub cipher_suite: CipherSuite,
+  pub extensions: Vec<HelloRetryExtension>
+}
+
+impl Codec for HelloRetryRequest {
+  fn encode(&self, bytes: &mut Vec<u8>) {
+    self.legacy_version.encode(bytes);
+    HELLO_RETRY_REQUEST_RANDOM.encode(bytes);
+    self.session_id.encode(bytes);
+    self.cipher_suite.encode(bytes);
+    Compression::Null.encode(bytes);
+    codec::encode_vec_u16(bytes, &self.extensions);
+  }
+
+  fn read(r: &mut Reader) -> Option<HelloRetryRequest> {
+    let legacy_version = try_ret!(ProtocolVersion::read(r));
+    let random = try_ret!(Random::read(r));
+
+    if random != HELLO_RETRY_REQUEST_RANDOM {
+      return None;
+    }
+
+    let session_id = try_ret!(SessionID::read(r));
+    let cipher_suite = try_ret!(CipherSuite::read(r));
+    let compression = try_ret!(Compression::read(r));
+
+    if compression != Compression::Null {
+      return None;
+    }
+
+    Some(HelloRetryRequest {
+      legacy_version: legacy_version,
+      session_id: session_id,
+      cipher_suite: cipher_suite,
+      extensions: try_ret!(codec::read_vec_u16::<HelloRetryExtension>(r))
+    })
+  }
+}
+
+fn is_hello_retry_request(body: &[u8]) -> bool {
+  body.len() >= 34 &&
+    Random::read_bytes(&body[2..34])
+      .map(|random| random == HELLO_RETRY_REQUEST_RANDOM)
+      .unwrap_or(false)
+}
+
+impl HelloRetryRequest {
+  /// Returns true if there is more than one extension of a given
+  /// type.
+  pub fn has_duplicate_extension(&self) -> bool {
+    let mut seen = collections::HashSet::new();
+
+    for ext in &self.extensions {
+      let typ = ext.get_type().get_u16();
+
+      if seen.contains(&typ) {
+        return true;
+      }
+      seen.insert(typ);
+    }
+
+    false
+  }
+
+  pub fn has_unknown_extension(&self) -> bool {
+    self.extensions
+      .iter()
+      .any(|ext| {
+        ext.get_type() != ExtensionType::KeyShare &&
+          ext.get_type() != ExtensionType::SupportedVersions &&
+          ext.get_type() != ExtensionType::Cookie
+      })
+  }
+
+  fn find_extension(&self, ext: ExtensionType) -> Option<&HelloRetryExtension> {
+    self.extensions.iter().find(|x| x.get_type() == ext)
+  }
+
+  pub fn get_requested_key_share_group(&self) -> Option<NamedCurve> {
+    let ext = try_ret!(self.find_extension(ExtensionType::KeyShare));
+    match *ext {
+      HelloRetryExtension::KeyShare(grp) => Some(grp),
+      _ => None
+    }
+  }
+
+  pub fn get_cookie(&self) -> Option<&PayloadU16> {
+    let ext = try_ret!(self.find_extension(ExtensionType::Cookie));
+    match *ext {
+      HelloRetryExtension::Cookie(ref ck) => Some(ck),
+      _ => None
+    }
+  }
+
+  pub fn get_supported_versions(&self) -> Option<ProtocolVersion> {
+    let ext = try_ret!(self.find_extension(ExtensionType::SupportedVersions));
+    match *ext {
+      HelloRetryExtension::SupportedVersions(ver) => Some(ver),
+      _ => None
+    }
+  }
 }
 
 pub type ASN1Cert = PayloadU24;
@@ -682,8 +1135,234 @@ impl Codec for CertificatePayload {
   }
 }
 
+/* -- CertificateStatus (RFC6066) -- */
+#[derive(Debug, Clone)]
+pub struct CertificateStatus {
+  pub ocsp_response: PayloadU24
+}
+
+impl Codec for CertificateStatus {
+  fn encode(&self, bytes: &mut Vec<u8>) {
+    CertificateStatusType::OCSP.encode(bytes);
+    self.ocsp_response.encode(bytes);
+  }
+
+  fn read(r: &mut Reader) -> Option<CertificateStatus> {
+    let typ = try_ret!(CertificateStatusType::read(r));
+
+    match typ {
+      CertificateStatusType::OCSP => {
+        Some(CertificateStatus {
+          ocsp_response: try_ret!(PayloadU24::read(r))
+        })
+      }
+      _ => None
+    }
+  }
+}
+
+impl CertificateStatus {
+  pub fn new(ocsp: Vec<u8>) -> CertificateStatus {
+    CertificateStatus { ocsp_response: PayloadU24::new(ocsp) }
+  }
+}
+
+/* -- TLS1.3 Certificate -- */
+/// In TLS1.3, a stapled OCSP response and any signed certificate
+/// timestamps are carried in extensions of the end-entity
+/// certificate.  We don't understand any other certificate
+/// extensions, so they are carried opaquely.
+#[derive(Debug, Clone)]
+pub enum CertificateExtension {
+  CertificateStatus(CertificateStatus),
+  SignedCertificateTimestamp(SCTList),
+  Unknown(UnknownExtension)
+}
+
+impl CertificateExtension {
+  pub fn get_type(&self) -> ExtensionType {
+    match *self {
+      CertificateExtension::CertificateStatus(_) => ExtensionType::StatusRequest,
+      CertificateExtension::SignedCertificateTimestamp(_) => ExtensionType::SCT,
+      CertificateExtension::Unknown(ref r) => r.typ
+    }
+  }
+}
+
+impl Codec for CertificateExtension {
+  fn encode(&self, bytes: &mut Vec<u8>) {
+    self.get_type().encode(bytes);
+
+    let mut sub: Vec<u8> = Vec::new();
+    match *self {
+      CertificateExtension::CertificateStatus(ref r) => r.encode(&mut sub),
+      CertificateExtension::SignedCertificateTimestamp(ref r) => r.encode(&mut sub),
+      CertificateExtension::Unknown(ref r) => r.encode(&mut sub)
+    }
+
+    codec::encode_u16(sub.len() as u16, bytes);
+    bytes.append(&mut sub);
+  }
+
+  fn read(r: &mut Reader) -> Option<CertificateExtension> {
+    let typ = try_ret!(ExtensionType::read(r));
+    let len = try_ret!(codec::read_u16(r)) as usize;
+    let mut sub = try_ret!(r.sub(len));
+
+    Some(match typ {
+      ExtensionType::StatusRequest =>
+        CertificateExtension::CertificateStatus(try_ret!(CertificateStatus::read(&mut sub))),
+      ExtensionType::SCT =>
+        CertificateExtension::SignedCertificateTimestamp(try_ret!(SCTList::read(&mut sub))),
+      _ =>
+        CertificateExtension::Unknown(try_ret!(UnknownExtension::read(typ, &mut sub)))
+    })
+  }
+}
+
+declare_u16_vec!(CertificateExtensions, CertificateExtension);
+
+#[derive(Debug, Clone)]
+pub struct CertificateEntry {
+  pub cert: ASN1Cert,
+  pub exts: CertificateExtensions
+}
+
+impl Codec for CertificateEntry {
+  fn encode(&self, bytes: &mut Vec<u8>) {
+    self.cert.encode(bytes);
+    self.exts.encode(bytes);
+  }
+
+  fn read(r: &mut Reader) -> Option<CertificateEntry> {
+    Some(CertificateEntry {
+      cert: try_ret!(ASN1Cert::read(r)),
+      exts: try_ret!(CertificateExtensions::read(r))
+    })
+  }
+}
+
+impl CertificateEntry {
+  pub fn new(cert: ASN1Cert) -> CertificateEntry {
+    CertificateEntry {
+      cert: cert,
+      exts: Vec::new()
+    }
+  }
+
+  pub fn has_duplicate_extension(&self) -> bool {
+    let mut seen = collections::HashSet::new();
+
+    for ext in &self.exts {
+      let typ = ext.get_type().get_u16();
+
+      if seen.contains(&typ) {
+        return true;
+      }
+      seen.insert(typ);
+    }
+
+    false
+  }
+
+  /// The only certificate extensions we understand are a stapled
+  /// OCSP response and signed certificate timestamps.
+  pub fn has_unknown_extension(&self) -> bool {
+    self.exts
+      .iter()
+      .any(|ext| ext.get_type() != ExtensionType::StatusRequest &&
+           ext.get_type() != ExtensionType::SCT)
+  }
+
+  pub fn get_ocsp_response(&self) -> Option<&Vec<u8>> {
+    self.exts
+      .iter()
+      .find(|ext| ext.get_type() == ExtensionType::StatusRequest)
+      .and_then(|ext| match *ext {
+        CertificateExtension::CertificateStatus(ref cs) => Some(&cs.ocsp_response.0),
+        _ => None
+      })
+  }
+
+  pub fn get_scts(&self) -> Option<&SCTList> {
+    self.exts
+      .iter()
+      .find(|ext| ext.get_type() == ExtensionType::SCT)
+      .and_then(|ext| match *ext {
+        CertificateExtension::SignedCertificateTimestamp(ref sct) => Some(sct),
+        _ => None
+      })
+  }
+}
+
 #[derive(Debug)]
+pub struct CertificatePayloadTLS13 {
+  pub context: PayloadU8,
+  pub list: Vec<CertificateEntry>
+}
+
+impl Codec for CertificatePayloadTLS13 {
+  fn encode(&self, bytes: &mut Vec<u8>) {
+    self.context.encode(bytes);
+    codec::encode_vec_u24(bytes, &self.list);
+  }
+
+  fn read(r: &mut Reader) -> Option<CertificatePayloadTLS13> {
+    Some(CertificatePayloadTLS13 {
+      context: try_ret!(PayloadU8::read(r)),
+      list: try_ret!(codec::read_vec_u24::<CertificateEntry>(r))
+    })
+  }
+}
+
+impl CertificatePayloadTLS13 {
+  pub fn new(certs: &CertificatePayload) -> CertificatePayloadTLS13 {
+    CertificatePayloadTLS13 {
+      context: PayloadU8::new(Vec::new()),
+      list: certs.iter()
+        .map(|cert| CertificateEntry::new(cert.clone()))
+        .collect()
+    }
+  }
+
+  pub fn any_entry_has_duplicate_extension(&self) -> bool {
+    self.list.iter().any(|ent| ent.has_duplicate_extension())
+  }
+
+  pub fn any_entry_has_unknown_extension(&self) -> bool {
+    self.list.iter().any(|ent| ent.has_unknown_extension())
+  }
+
+  /// Return any OCSP response stapled to the end-entity
+  /// certificate, or an empty vector.
+  pub fn get_end_entity_ocsp(&self) -> Vec<u8> {
+    self.list.first()
+      .and_then(|ent| ent.get_ocsp_response())
+      .cloned()
+      .unwrap_or_else(Vec::new)
+  }
+
+  /// Return any signed certificate timestamps sent with the
+  /// end-entity certificate, or an empty list.
+  pub fn get_end_entity_scts(&self) -> SCTList {
+    self.list.first()
+      .and_then(|ent| ent.get_scts())
+      .cloned()
+      .unwrap_or_else(SCTList::new)
+  }
+
+  /// Discard the TLS1.3-specific parts, returning just
+  /// the certificate chain.
+  pub fn convert(&self) -> CertificatePayload {
+    self.list.iter()
+      .map(|ent| ent.cert.clone())
+      .collect()
+  }
+}
+
+#[derive(Debug, PartialEq)]
 pub enum KeyExchangeAlgorithm {
+  BulkOnly,
   DH,
   DHE,
   RSA,
@@ -869,7 +1548,7 @@ impl ServerKeyExchangePayload {
 /* -- CertificateRequest and sundries -- */
 declare_u8_vec!(ClientCertificateTypes, ClientCertificateType);
 pub type DistinguishedName = PayloadU16;
-declare_u16_vec!(DistinguishedNames, DistinguishedName);
+pub type DistinguishedNames = PayloadU16List;
 
 #[derive(Debug)]
 pub struct CertificateRequestPayload {
@@ -898,6 +1577,101 @@ impl Codec for CertificateRequestPayload {
   }
 }
 
+#[derive(Debug)]
+pub enum CertReqExtension {
+  SignatureAlgorithms(SupportedSignatureAlgorithms),
+  AuthorityNames(DistinguishedNames),
+  Unknown(UnknownExtension)
+}
+
+impl CertReqExtension {
+  pub fn get_type(&self) -> ExtensionType {
+    match *self {
+      CertReqExtension::SignatureAlgorithms(_) => ExtensionType::SignatureAlgorithms,
+      CertReqExtension::AuthorityNames(_) => ExtensionType::CertificateAuthorities,
+      CertReqExtension::Unknown(ref r) => r.typ
+    }
+  }
+}
+
+impl Codec for CertReqExtension {
+  fn encode(&self, bytes: &mut Vec<u8>) {
+    self.get_type().encode(bytes);
+
+    let mut sub: Vec<u8> = Vec::new();
+    match *self {
+      CertReqExtension::SignatureAlgorithms(ref r) => r.encode(&mut sub),
+      CertReqExtension::AuthorityNames(ref r) => r.encode(&mut sub),
+      CertReqExtension::Unknown(ref r) => r.encode(&mut sub)
+    }
+
+    codec::encode_u16(sub.len() as u16, bytes);
+    bytes.append(&mut sub);
+  }
+
+  fn read(r: &mut Reader) -> Option<CertReqExtension> {
+    let typ = try_ret!(ExtensionType::read(r));
+    let len = try_ret!(codec::read_u16(r)) as usize;
+    let mut sub = try_ret!(r.sub(len));
+
+    Some(match typ {
+      ExtensionType::SignatureAlgorithms =>
+        CertReqExtension::SignatureAlgorithms(try_ret!(SupportedSignatureAlgorithms::read(&mut sub))),
+      ExtensionType::CertificateAuthorities =>
+        CertReqExtension::AuthorityNames(try_ret!(DistinguishedNames::read(&mut sub))),
+      _ =>
+        CertReqExtension::Unknown(try_ret!(UnknownExtension::read(typ, &mut sub)))
+    })
+  }
+}
+
+declare_u16_vec!(CertReqExtensions, CertReqExtension);
+
+#[derive(Debug)]
+pub struct CertificateRequestPayloadTLS13 {
+  pub context: PayloadU8,
+  pub extensions: CertReqExtensions
+}
+
+impl Codec for CertificateRequestPayloadTLS13 {
+  fn encode(&self, bytes: &mut Vec<u8>) {
+    self.context.encode(bytes);
+    self.extensions.encode(bytes);
+  }
+
+  fn read(r: &mut Reader) -> Option<CertificateRequestPayloadTLS13> {
+    let context = try_ret!(PayloadU8::read(r));
+    let extensions = try_ret!(CertReqExtensions::read(r));
+
+    Some(CertificateRequestPayloadTLS13 {
+      context: context,
+      extensions: extensions
+    })
+  }
+}
+
+impl CertificateRequestPayloadTLS13 {
+  pub fn find_extension(&self, ext: ExtensionType) -> Option<&CertReqExtension> {
+    self.extensions.iter().find(|x| x.get_type() == ext)
+  }
+
+  pub fn get_sigalgs_extension(&self) -> Option<&SupportedSignatureAlgorithms> {
+    let ext = try_ret!(self.find_extension(ExtensionType::SignatureAlgorithms));
+    match *ext {
+      CertReqExtension::SignatureAlgorithms(ref sa) => Some(sa),
+      _ => None
+    }
+  }
+
+  pub fn get_authorities_extension(&self) -> Option<&DistinguishedNames> {
+    let ext = try_ret!(self.find_extension(ExtensionType::CertificateAuthorities));
+    match *ext {
+      CertReqExtension::AuthorityNames(ref an) => Some(an),
+      _ => None
+    }
+  }
+}
+
 /* -- NewSessionTicket -- */
 #[derive(Debug)]
 pub struct NewSessionTicketPayload {
@@ -931,19 +1705,106 @@ impl Codec for NewSessionTicketPayload {
   }
 }
 
+/* -- TLS1.3 NewSessionTicket -- */
+/// We don't do TLS1.3 resumption, so the ticket extensions
+/// are carried opaquely.
+#[derive(Debug)]
+pub struct NewSessionTicketPayloadTLS13 {
+  pub lifetime: u32,
+  pub age_add: u32,
+  pub nonce: PayloadU8,
+  pub ticket: PayloadU16,
+  pub exts: PayloadU16
+}
+
+impl Codec for NewSessionTicketPayloadTLS13 {
+  fn encode(&self, bytes: &mut Vec<u8>) {
+    codec::encode_u32(self.lifetime, bytes);
+    codec::encode_u32(self.age_add, bytes);
+    self.nonce.encode(bytes);
+    self.ticket.encode(bytes);
+    self.exts.encode(bytes);
+  }
+
+  fn read(r: &mut Reader) -> Option<NewSessionTicketPayloadTLS13> {
+    let lifetime = try_ret!(codec::read_u32(r));
+    let age_add = try_ret!(codec::read_u32(r));
+    let nonce = try_ret!(PayloadU8::read(r));
+    let ticket = try_ret!(PayloadU16::read(r));
+    let exts = try_ret!(PayloadU16::read(r));
+
+    Some(NewSessionTicketPayloadTLS13 {
+      lifetime: lifetime,
+      age_add: age_add,
+      nonce: nonce,
+      ticket: ticket,
+      exts: exts
+    })
+  }
+}
+
+declare_u16_vec!(EncryptedExtensions, ServerExtension);
+
+pub trait HasServerExtensions {
+  fn get_extensions(&self) -> &[ServerExtension];
+
+  /// Returns true if there is more than one extension of a given
+  /// type.
+  fn has_duplicate_extension(&self) -> bool {
+    let mut seen = collections::HashSet::new();
+
+    for ext in self.get_extensions() {
+      let typ = ext.get_type().get_u16();
+
+      if seen.contains(&typ) {
+        return true;
+      }
+      seen.insert(typ);
+    }
+
+    false
+  }
+
+  fn find_extension(&self, ext: ExtensionType) -> Option<&ServerExtension> {
+    self.get_extensions().iter().find(|x| x.get_type() == ext)
+  }
+
+  fn get_alpn_protocol(&self) -> Option<String> {
+    let ext = try_ret!(self.find_extension(ExtensionType::ALProtocolNegotiation));
+    match *ext {
+      ServerExtension::Protocols(ref protos) => protos.to_single_string(),
+      _ => None
+    }
+  }
+}
+
+impl HasServerExtensions for EncryptedExtensions {
+  fn get_extensions(&self) -> &[ServerExtension] {
+    self
+  }
+}
+
 #[derive(Debug)]
 pub enum HandshakePayload {
   HelloRequest,
   ClientHello(ClientHelloPayload),
   ServerHello(ServerHelloPayload),
+  HelloRetryRequest(HelloRetryRequest),
   Certificate(CertificatePayload),
+  CertificateTLS13(CertificatePayloadTLS13),
   ServerKeyExchange(ServerKeyExchangePayload),
   CertificateRequest(CertificateRequestPayload),
+  CertificateRequestTLS13(CertificateRequestPayloadTLS13),
   CertificateVerify(DigitallySignedStruct),
+  CertificateStatus(CertificateStatus),
   ServerHelloDone,
   ClientKeyExchange(Payload),
   NewSessionTicket(NewSessionTicketPayload),
+  NewSessionTicketTLS13(NewSessionTicketPayloadTLS13),
+  EncryptedExtensions(EncryptedExtensions),
+  KeyUpdate(KeyUpdateRequest),
   Finished(Payload),
+  MessageHash(Payload),
   Unknown(Payload)
 }
 
@@ -953,14 +1814,22 @@ impl HandshakePayload {
       HandshakePayload::HelloRequest => {},
       HandshakePayload::ClientHello(ref x) => x.encode(bytes),
       HandshakePayload::ServerHello(ref x) => x.encode(bytes),
+      HandshakePayload::HelloRetryRequest(ref x) => x.encode(bytes),
       HandshakePayload::Certificate(ref x) => x.encode(bytes),
+      HandshakePayload::CertificateTLS13(ref x) => x.encode(bytes),
       HandshakePayload::ServerKeyExchange(ref x) => x.encode(bytes),
       HandshakePayload::ServerHelloDone => {},
       HandshakePayload::ClientKeyExchange(ref x) => x.encode(bytes),
       HandshakePayload::CertificateRequest(ref x) => x.encode(bytes),
+      HandshakePayload::CertificateRequestTLS13(ref x) => x.encode(bytes),
       HandshakePayload::CertificateVerify(ref x) => x.encode(bytes),
+      HandshakePayload::CertificateStatus(ref x) => x.encode(bytes),
       HandshakePayload::NewSessionTicket(ref x) => x.encode(bytes),
+      HandshakePayload::NewSessionTicketTLS13(ref x) => x.encode(bytes),
+      HandshakePayload::EncryptedExtensions(ref x) => x.encode(bytes),
+      HandshakePayload::KeyUpdate(ref x) => x.encode(bytes),
       HandshakePayload::Finished(ref x) => x.encode(bytes),
+      HandshakePayload::MessageHash(ref x) => x.encode(bytes),
       HandshakePayload::Unknown(ref x) => x.encode(bytes)
     }
   }
@@ -979,16 +1848,35 @@ impl Codec for HandshakeMessagePayload {
     self.payload.encode(&mut sub);
 
     /* output type, length, and encoded payload */
-    self.typ.encode(bytes);
+    match self.typ {
+      HandshakeType::HelloRetryRequest => HandshakeType::ServerHello,
+      _ => self.typ
+    }.encode(bytes);
     codec::encode_u24(sub.len() as u32, bytes);
     bytes.append(&mut sub);
   }
 
   fn read(r: &mut Reader) -> Option<HandshakeMessagePayload> {
-    let typ = try_ret!(HandshakeType::read(r));
+    HandshakeMessagePayload::read_version(r, ProtocolVersion::TLSv1_2)
+  }
+}
+
+impl HandshakeMessagePayload {
+  /// Read a handshake message, whose encoding depends on the
+  /// negotiated protocol `vers`.
+  pub fn read_version(r: &mut Reader, vers: ProtocolVersion) -> Option<HandshakeMessagePayload> {
+    let mut typ = try_ret!(HandshakeType::read(r));
     let len = try_ret!(codec::read_u24(r)) as usize;
     let mut sub = try_ret!(r.sub(len));
 
+    /* A HelloRetryRequest is a ServerHello with a magic random
+     * value: tell them apart here. */
+    let is_hrr = typ == HandshakeType::ServerHello &&
+      is_hello_retry_request(sub.rest());
+    if is_hrr {
+      typ = HandshakeType::HelloRetryRequest;
+    }
+
     let payload = match typ {
       HandshakeType::HelloRequest if sub.left() == 0 =>
         HandshakePayload::HelloRequest,
@@ -996,6 +1884,10 @@ impl Codec for HandshakeMessagePayload {
         HandshakePayload::ClientHello(try_ret!(ClientHelloPayload::read(&mut sub))),
       HandshakeType::ServerHello =>
         HandshakePayload::ServerHello(try_ret!(ServerHelloPayload::read(&mut sub))),
+      HandshakeType::HelloRetryRequest if is_hrr =>
+        HandshakePayload::HelloRetryRequest(try_ret!(HelloRetryRequest::read(&mut sub))),
+      HandshakeType::Certificate if vers == ProtocolVersion::TLSv1_3 =>
+        HandshakePayload::CertificateTLS13(try_ret!(CertificatePayloadTLS13::read(&mut sub))),
       HandshakeType::Certificate =>
         HandshakePayload::Certificate(try_ret!(CertificatePayload::read(&mut sub))),
       HandshakeType::ServerKeyExchange =>
@@ -1004,12 +1896,22 @@ impl Codec for HandshakeMessagePayload {
         HandshakePayload::ServerHelloDone,
       HandshakeType::ClientKeyExchange =>
         HandshakePayload::ClientKeyExchange(try_ret!(Payload::read(&mut sub))),
+      HandshakeType::CertificateRequest if vers == ProtocolVersion::TLSv1_3 =>
+        HandshakePayload::CertificateRequestTLS13(try_ret!(CertificateRequestPayloadTLS13::read(&mut sub))),
       HandshakeType::CertificateRequest =>
         HandshakePayload::CertificateRequest(try_ret!(CertificateRequestPayload::read(&mut sub))),
       HandshakeType::CertificateVerify =>
         HandshakePayload::CertificateVerify(try_ret!(DigitallySignedStruct::read(&mut sub))),
+      HandshakeType::CertificateStatus =>
+        HandshakePayload::CertificateStatus(try_ret!(CertificateStatus::read(&mut sub))),
+      HandshakeType::NewSessionTicket if vers == ProtocolVersion::TLSv1_3 =>
+        HandshakePayload::NewSessionTicketTLS13(try_ret!(NewSessionTicketPayloadTLS13::read(&mut sub))),
       HandshakeType::NewSessionTicket =>
         HandshakePayload::NewSessionTicket(try_ret!(NewSessionTicketPayload::read(&mut sub))),
+      HandshakeType::EncryptedExtensions =>
+        HandshakePayload::EncryptedExtensions(try_ret!(EncryptedExtensions::read(&mut sub))),
+      HandshakeType::KeyUpdate =>
+        HandshakePayload::KeyUpdate(try_ret!(KeyUpdateRequest::read(&mut sub))),
       HandshakeType::Finished =>
         HandshakePayload::Finished(try_ret!(Payload::read(&mut sub))),
       _ =>
@@ -1018,12 +1920,26 @@ impl Codec for HandshakeMessagePayload {
 
     Some(HandshakeMessagePayload { typ: typ, payload: payload })
   }
-}
 
-impl HandshakeMessagePayload {
   pub fn len(&self) -> usize {
     let mut buf = Vec::new();
     self.encode(&mut buf);
     buf.len()
   }
+
+  /// Make the synthetic message which stands in for
+  /// a ClientHello in the transcript after a HelloRetryRequest.
+  pub fn build_handshake_hash(hash: &[u8]) -> HandshakeMessagePayload {
+    HandshakeMessagePayload {
+      typ: HandshakeType::MessageHash,
+      payload: HandshakePayload::MessageHash(Payload::new(hash.to_vec()))
+    }
+  }
+
+  pub fn build_key_update_notify() -> HandshakeMessagePayload {
+    HandshakeMessagePayload {
+      typ: HandshakeType::KeyUpdate,
+      payload: HandshakePayload::KeyUpdate(KeyUpdateRequest::UpdateNotRequested)
+    }
+  }
 }
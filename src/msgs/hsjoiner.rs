@@ -2,7 +2,6 @@
 use std::collections::VecDeque;
 
 use msgs::codec;
-use msgs::codec::Codec;
 use msgs::message::{Message, MessagePayload};
 use msgs::enums::{ContentType, ProtocolVersion};
 use msgs::handshake::HandshakeMessagePayload;
@@ -18,17 +17,27 @@ pub struct HandshakeJoiner {
   pub frames: VecDeque<Message>,
 
   /// The message payload we're currently accumulating.
-  buf: Vec<u8>
+  buf: Vec<u8>,
+
+  /// The protocol version, which changes how some handshake
+  /// messages are decoded.
+  version: ProtocolVersion
 }
 
 impl HandshakeJoiner {
   pub fn new() -> HandshakeJoiner{
     HandshakeJoiner {
       frames: VecDeque::new(),
-      buf: Vec::new()
+      buf: Vec::new(),
+      version: ProtocolVersion::TLSv1_2
     }
   }
 
+  /// Decode future handshake messages as per protocol `version`.
+  pub fn set_version(&mut self, version: ProtocolVersion) {
+    self.version = version;
+  }
+
   /// Do we want to process this message?
   pub fn want_message(&self, msg: &Message) -> bool {
     msg.is_content_type(ContentType::Handshake)
@@ -80,7 +89,7 @@ impl HandshakeJoiner {
   fn deframe_one(&mut self) -> bool {
     let used = {
       let mut rd = codec::Reader::init(&self.buf);
-      let payload = HandshakeMessagePayload::read(&mut rd);
+      let payload = HandshakeMessagePayload::read_version(&mut rd, self.version);
 
       if payload.is_none() {
         return false;
@@ -1,6 +1,7 @@
 use msgs::handshake::SessionID;
-use msgs::enums::CipherSuite;
+use msgs::enums::{CipherSuite, ProtocolVersion};
 use msgs::codec::{Reader, Codec};
+use msgs::codec;
 use msgs::handshake::CertificatePayload;
 use msgs::base::{PayloadU8, PayloadU16};
 
@@ -41,7 +42,8 @@ pub struct ClientSessionValue {
   pub cipher_suite: CipherSuite,
   pub session_id: SessionID,
   pub ticket: PayloadU16,
-  pub master_secret: PayloadU8
+  pub master_secret: PayloadU8,
+  pub extended_ms: bool
 }
 
 impl Codec for ClientSessionValue {
@@ -50,6 +52,7 @@ impl Codec for ClientSessionValue {
     self.session_id.encode(bytes);
     self.ticket.encode(bytes);
     self.master_secret.encode(bytes);
+    codec::encode_u8(if self.extended_ms { 1 } else { 0 }, bytes);
   }
 
   fn read(r: &mut Reader) -> Option<ClientSessionValue> {
@@ -57,12 +60,14 @@ impl Codec for ClientSessionValue {
     let sid = try_ret!(SessionID::read(r));
     let ticket = try_ret!(PayloadU16::read(r));
     let ms = try_ret!(PayloadU8::read(r));
+    let ems = try_ret!(codec::read_u8(r));
 
     Some(ClientSessionValue {
       cipher_suite: cs,
       session_id: sid,
       ticket: ticket,
-      master_secret: ms
+      master_secret: ms,
+      extended_ms: ems == 1
     })
   }
 }
@@ -71,12 +76,14 @@ impl ClientSessionValue {
   pub fn new(cs: &CipherSuite,
              sessid: &SessionID,
              ticket: Vec<u8>,
-             ms: Vec<u8>) -> ClientSessionValue {
+             ms: Vec<u8>,
+             extended_ms: bool) -> ClientSessionValue {
     ClientSessionValue {
       cipher_suite: *cs,
       session_id: sessid.clone(),
       ticket: PayloadU16::new(ticket),
-      master_secret: PayloadU8::new(ms)
+      master_secret: PayloadU8::new(ms),
+      extended_ms: extended_ms
     }
   }
 
@@ -94,6 +101,7 @@ pub type ServerSessionKey = SessionID;
 pub struct ServerSessionValue {
   pub cipher_suite: CipherSuite,
   pub master_secret: PayloadU8,
+  pub extended_ms: bool,
   pub client_cert_chain: Option<CertificatePayload>
 }
 
@@ -101,6 +109,7 @@ impl Codec for ServerSessionValue {
   fn encode(&self, bytes: &mut Vec<u8>) {
     self.cipher_suite.encode(bytes);
     self.master_secret.encode(bytes);
+    codec::encode_u8(if self.extended_ms { 1 } else { 0 }, bytes);
     if self.client_cert_chain.is_some() {
       self.client_cert_chain.as_ref().unwrap().encode(bytes);
     }
@@ -109,6 +118,7 @@ impl Codec for ServerSessionValue {
   fn read(r: &mut Reader) -> Option<ServerSessionValue> {
     let cs = try_ret!(CipherSuite::read(r));
     let ms = try_ret!(PayloadU8::read(r));
+    let ems = try_ret!(codec::read_u8(r));
     let ccert = if r.any_left() {
       CertificatePayload::read(r)
     } else {
@@ -118,17 +128,160 @@ impl Codec for ServerSessionValue {
     Some(ServerSessionValue {
       cipher_suite: cs,
       master_secret: ms,
+      extended_ms: ems == 1,
       client_cert_chain: ccert
     })
   }
 }
 
 impl ServerSessionValue {
-  pub fn new(cs: &CipherSuite, ms: Vec<u8>, cert_chain: &Option<CertificatePayload>) -> ServerSessionValue {
+  pub fn new(cs: &CipherSuite,
+             ms: Vec<u8>,
+             extended_ms: bool,
+             cert_chain: &Option<CertificatePayload>) -> ServerSessionValue {
     ServerSessionValue {
       cipher_suite: cs.clone(),
       master_secret: PayloadU8::new(ms),
+      extended_ms: extended_ms,
       client_cert_chain: cert_chain.clone()
     }
   }
 }
+
+/* --- Sessions after their handshake --- */
+
+/* Buffered data can exceed the 24-bit lengths used elsewhere. */
+fn encode_bytes_u32(data: &[u8], bytes: &mut Vec<u8>) {
+  codec::encode_u32(data.len() as u32, bytes);
+  bytes.extend_from_slice(data);
+}
+
+fn read_bytes_u32(r: &mut Reader) -> Option<Vec<u8>> {
+  let len = try_ret!(codec::read_u32(r)) as usize;
+  r.take(len).map(|data| data.to_vec())
+}
+
+fn encode_bool(v: bool, bytes: &mut Vec<u8>) {
+  codec::encode_u8(if v { 1 } else { 0 }, bytes);
+}
+
+fn read_bool(r: &mut Reader) -> Option<bool> {
+  match try_ret!(codec::read_u8(r)) {
+    0 => Some(false),
+    1 => Some(true),
+    _ => None
+  }
+}
+
+/// Everything needed to carry on a session whose handshake is
+/// complete, perhaps in another process.
+///
+/// The record layer keys are rederived from `master_secret` in
+/// TLS1.2, and from the traffic secrets in TLS1.3.  Secrets not
+/// used by the session's version are empty.
+#[derive(Debug)]
+pub struct SessionState {
+  pub we_are_client: bool,
+  pub version: ProtocolVersion,
+  pub cipher_suite: CipherSuite,
+  pub client_random: PayloadU8,
+  pub server_random: PayloadU8,
+  pub master_secret: PayloadU8,
+  pub client_traffic_secret: PayloadU8,
+  pub server_traffic_secret: PayloadU8,
+  pub exporter_secret: PayloadU8,
+  pub write_seq: u64,
+  pub read_seq: u64,
+  pub peer_eof: bool,
+  /// Plaintext received but not yet read by the application.
+  pub received_plaintext: Vec<u8>,
+  /// TLS data received but not yet processed.
+  pub received_tls: Vec<u8>,
+  /// TLS data not yet written to the peer.
+  pub sendable_tls: Vec<u8>,
+  /// Empty if no protocol was agreed.
+  pub alpn_protocol: PayloadU8,
+  /// For clients, the hostname we connected to.
+  pub dns_name: PayloadU8,
+  pub server_cert_chain: CertificatePayload,
+  pub client_cert_chain: Option<CertificatePayload>,
+  pub tls_unique: PayloadU8
+}
+
+impl Codec for SessionState {
+  fn encode(&self, bytes: &mut Vec<u8>) {
+    encode_bool(self.we_are_client, bytes);
+    self.version.encode(bytes);
+    self.cipher_suite.encode(bytes);
+    self.client_random.encode(bytes);
+    self.server_random.encode(bytes);
+    self.master_secret.encode(bytes);
+    self.client_traffic_secret.encode(bytes);
+    self.server_traffic_secret.encode(bytes);
+    self.exporter_secret.encode(bytes);
+    codec::encode_u64(self.write_seq, bytes);
+    codec::encode_u64(self.read_seq, bytes);
+    encode_bool(self.peer_eof, bytes);
+    encode_bytes_u32(&self.received_plaintext, bytes);
+    encode_bytes_u32(&self.received_tls, bytes);
+    encode_bytes_u32(&self.sendable_tls, bytes);
+    self.alpn_protocol.encode(bytes);
+    self.dns_name.encode(bytes);
+    self.server_cert_chain.encode(bytes);
+    encode_bool(self.client_cert_chain.is_some(), bytes);
+    if let Some(ref chain) = self.client_cert_chain {
+      chain.encode(bytes);
+    }
+    self.tls_unique.encode(bytes);
+  }
+
+  fn read(r: &mut Reader) -> Option<SessionState> {
+    let we_are_client = try_ret!(read_bool(r));
+    let version = try_ret!(ProtocolVersion::read(r));
+    let cs = try_ret!(CipherSuite::read(r));
+    let client_random = try_ret!(PayloadU8::read(r));
+    let server_random = try_ret!(PayloadU8::read(r));
+    let ms = try_ret!(PayloadU8::read(r));
+    let client_traffic_secret = try_ret!(PayloadU8::read(r));
+    let server_traffic_secret = try_ret!(PayloadU8::read(r));
+    let exporter_secret = try_ret!(PayloadU8::read(r));
+    let write_seq = try_ret!(codec::read_u64(r));
+    let read_seq = try_ret!(codec::read_u64(r));
+    let peer_eof = try_ret!(read_bool(r));
+    let received_plaintext = try_ret!(read_bytes_u32(r));
+    let received_tls = try_ret!(read_bytes_u32(r));
+    let sendable_tls = try_ret!(read_bytes_u32(r));
+    let alpn_protocol = try_ret!(PayloadU8::read(r));
+    let dns_name = try_ret!(PayloadU8::read(r));
+    let server_cert_chain = try_ret!(CertificatePayload::read(r));
+    let client_cert_chain = if try_ret!(read_bool(r)) {
+      Some(try_ret!(CertificatePayload::read(r)))
+    } else {
+      None
+    };
+    let tls_unique = try_ret!(PayloadU8::read(r));
+
+    Some(SessionState {
+      we_are_client: we_are_client,
+      version: version,
+      cipher_suite: cs,
+      client_random: client_random,
+      server_random: server_random,
+      master_secret: ms,
+      client_traffic_secret: client_traffic_secret,
+      server_traffic_secret: server_traffic_secret,
+      exporter_secret: exporter_secret,
+      write_seq: write_seq,
+      read_seq: read_seq,
+      peer_eof: peer_eof,
+      received_plaintext: received_plaintext,
+      received_tls: received_tls,
+      sendable_tls: sendable_tls,
+      alpn_protocol: alpn_protocol,
+      dns_name: dns_name,
+      server_cert_chain: server_cert_chain,
+      client_cert_chain: client_cert_chain,
+      tls_unique: tls_unique
+    })
+  }
+}
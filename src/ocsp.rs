@@ -0,0 +1,365 @@
+/* Checking of stapled OCSP responses (RFC6960) by clients. */
+
+use ring::digest;
+use time;
+
+use msgs::handshake::ASN1Cert;
+use error::TLSError;
+use verify;
+use x509;
+use x509::DerReader;
+
+/// What a client does about OCSP stapling.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OCSPPolicy {
+  /// Don't ask for a stapled OCSP response, and don't look at
+  /// one if the server sends it anyway.
+  Ignore,
+
+  /// Ask for a stapled OCSP response.  Fail if it validly says the
+  /// server's certificate is revoked, or if the certificate demands
+  /// stapling (RFC7633 'must-staple') and we don't get a valid
+  /// response.  Otherwise, missing or unusable responses are
+  /// tolerated.
+  SoftFail,
+
+  /// Ask for a stapled OCSP response, and fail unless it is valid,
+  /// current and says the server's certificate is good.
+  HardFail
+}
+
+/// id-pkix-ocsp-basic
+static OCSP_BASIC: &'static [u8] = b"\x2b\x06\x01\x05\x05\x07\x30\x01\x01";
+
+/// id-pe-tlsfeature
+static TLS_FEATURE: &'static [u8] = b"\x2b\x06\x01\x05\x05\x07\x01\x18";
+
+/// id-kp-OCSPSigning
+static OCSP_SIGNING: &'static [u8] = b"\x2b\x06\x01\x05\x05\x07\x03\x09";
+
+/// id-ce-extKeyUsage
+static EXT_KEY_USAGE: &'static [u8] = b"\x55\x1d\x25";
+
+/// How long we'll believe a response which doesn't
+/// say when it should be refreshed.
+const MAX_AGE_WITHOUT_NEXT_UPDATE: i64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug, PartialEq)]
+enum CertStatus {
+  Good,
+  Revoked,
+  Unknown
+}
+
+fn bad(why: &str) -> TLSError {
+  TLSError::InvalidOCSPResponse(why.to_string())
+}
+
+fn hash_for_oid(alg_id: &[u8]) -> Option<&'static digest::Algorithm> {
+  let oid = try_ret!(DerReader::new(alg_id).read(x509::OID));
+
+  match oid {
+    b"\x2b\x0e\x03\x02\x1a" => Some(&digest::SHA1),
+    b"\x60\x86\x48\x01\x65\x03\x04\x02\x01" => Some(&digest::SHA256),
+    b"\x60\x86\x48\x01\x65\x03\x04\x02\x02" => Some(&digest::SHA384),
+    b"\x60\x86\x48\x01\x65\x03\x04\x02\x03" => Some(&digest::SHA512),
+    _ => None
+  }
+}
+
+/// Returns true if `cert` carries the TLS feature extension
+/// asking for status_request (RFC7633).
+fn is_must_staple(cert: &x509::CertificateFields) -> bool {
+  let ext = match cert.find_extension(TLS_FEATURE) {
+    Some(ext) => ext,
+    None => return false
+  };
+
+  let features = x509::read_all(ext.value, |rd| rd.read(x509::SEQUENCE))
+    .unwrap_or(&[]);
+  let mut rd = DerReader::new(features);
+
+  while let Some(feature) = rd.read(x509::INTEGER) {
+    if feature == &[5][..] {
+      return true;
+    }
+  }
+
+  false
+}
+
+/// Returns true if `cert` is marked for signing OCSP responses.
+fn has_ocsp_signing_eku(cert: &x509::CertificateFields) -> bool {
+  let ext = match cert.find_extension(EXT_KEY_USAGE) {
+    Some(ext) => ext,
+    None => return false
+  };
+
+  let purposes = x509::read_all(ext.value, |rd| rd.read(x509::SEQUENCE))
+    .unwrap_or(&[]);
+  let mut rd = DerReader::new(purposes);
+
+  while let Some(purpose) = rd.read(x509::OID) {
+    if purpose == OCSP_SIGNING {
+      return true;
+    }
+  }
+
+  false
+}
+
+/// The parts of a BasicOCSPResponse we look at.
+struct BasicResponse<'a> {
+  tbs: &'a [u8],
+  tbs_body: &'a [u8],
+  signature_alg: &'a [u8],
+  signature: &'a [u8],
+  certs: Vec<&'a [u8]>
+}
+
+fn parse_response<'a>(ocsp: &'a [u8]) -> Option<BasicResponse<'a>> {
+  let outer = try_ret!(x509::read_all(ocsp, |rd| rd.read(x509::SEQUENCE)));
+
+  let basic = try_ret!(x509::read_all(outer, |rd| {
+    let status = try_ret!(rd.read(x509::ENUMERATED));
+    if status != &[0][..] {
+      return None;
+    }
+
+    let bytes = try_ret!(rd.read(x509::explicit_tag(0)));
+    let bytes = try_ret!(x509::read_all(bytes, |rd| rd.read(x509::SEQUENCE)));
+
+    x509::read_all(bytes, |rd| {
+      let typ = try_ret!(rd.read(x509::OID));
+      if typ != OCSP_BASIC {
+        return None;
+      }
+      rd.read(x509::OCTET_STRING)
+    })
+  }));
+
+  let basic = try_ret!(x509::read_all(basic, |rd| rd.read(x509::SEQUENCE)));
+
+  x509::read_all(basic, |rd| {
+    let (tbs_body, tbs) = try_ret!(rd.read_with_encoding(x509::SEQUENCE));
+    let signature_alg = try_ret!(rd.read(x509::SEQUENCE));
+    let signature = try_ret!(rd.read_bit_string());
+
+    let mut certs = Vec::new();
+    if let Some(list) = try_ret!(rd.read_optional(x509::explicit_tag(0))) {
+      let list = try_ret!(x509::read_all(list, |rd| rd.read(x509::SEQUENCE)));
+      let mut rd = DerReader::new(list);
+      while !rd.at_end() {
+        let (_, cert) = try_ret!(rd.read_with_encoding(x509::SEQUENCE));
+        certs.push(cert);
+      }
+    }
+
+    Some(BasicResponse {
+      tbs: tbs,
+      tbs_body: tbs_body,
+      signature_alg: signature_alg,
+      signature: signature,
+      certs: certs
+    })
+  })
+}
+
+/// Does the CertID in `cert_id` name `cert`, as issued by `issuer`?
+fn cert_id_matches(cert_id: &[u8],
+                   cert: &x509::CertificateFields,
+                   issuer: &x509::CertificateFields) -> Option<bool> {
+  x509::read_all(cert_id, |rd| {
+    let hash_alg = try_ret!(rd.read(x509::SEQUENCE));
+    let name_hash = try_ret!(rd.read(x509::OCTET_STRING));
+    let key_hash = try_ret!(rd.read(x509::OCTET_STRING));
+    let serial = try_ret!(rd.read(x509::INTEGER));
+
+    let hash = try_ret!(hash_for_oid(hash_alg));
+    let issuer_key = try_ret!(issuer.public_key());
+
+    Some(serial == cert.serial &&
+         digest::digest(hash, cert.issuer).as_ref() == name_hash &&
+         digest::digest(hash, issuer_key).as_ref() == key_hash)
+  })
+}
+
+/// Find the status of `cert` in the ResponseData contents `tbs_body`.
+/// Fails if there is no status for it, or if the status isn't
+/// current at `now`.
+fn find_status(tbs_body: &[u8],
+               cert: &x509::CertificateFields,
+               issuer: &x509::CertificateFields,
+               now: time::Timespec) -> Result<CertStatus, TLSError> {
+  let responses = try!(
+    x509::read_all(tbs_body, |rd| {
+      try_ret!(rd.read_optional(x509::explicit_tag(0)));
+
+      /* responderID: byName or byKey.  We don't use this to find the
+       * signer; we just try the candidates. */
+      let (tag, _, _) = try_ret!(rd.read_any());
+      if tag != x509::explicit_tag(1) && tag != x509::explicit_tag(2) {
+        return None;
+      }
+
+      try_ret!(rd.read(x509::GENERALIZED_TIME));
+      let responses = try_ret!(rd.read(x509::SEQUENCE));
+      try_ret!(rd.read_optional(x509::explicit_tag(1)));
+      Some(responses)
+    }).ok_or_else(|| bad("malformed ResponseData"))
+  );
+
+  let mut rd = DerReader::new(responses);
+  while !rd.at_end() {
+    let single = try!(rd.read(x509::SEQUENCE)
+                      .ok_or_else(|| bad("malformed SingleResponse")));
+
+    let parsed = x509::read_all(single, |rd| {
+      let cert_id = try_ret!(rd.read(x509::SEQUENCE));
+      let (tag, _, _) = try_ret!(rd.read_any());
+      let this_update = try_ret!(rd.read_time());
+      let next_update = match try_ret!(rd.read_optional(x509::explicit_tag(0))) {
+        Some(next) => Some(try_ret!(x509::read_all(next, |rd| rd.read_time()))),
+        None => None
+      };
+      try_ret!(rd.read_optional(x509::explicit_tag(1)));
+      Some((cert_id, tag, this_update, next_update))
+    });
+
+    let (cert_id, tag, this_update, next_update) = try!(
+      parsed.ok_or_else(|| bad("malformed SingleResponse"))
+    );
+
+    if !try!(cert_id_matches(cert_id, cert, issuer)
+             .ok_or_else(|| bad("malformed or unsupported CertID"))) {
+      continue;
+    }
+
+    let expiry = next_update.unwrap_or_else(|| {
+      time::Timespec::new(this_update.sec + MAX_AGE_WITHOUT_NEXT_UPDATE, 0)
+    });
+
+    if now < this_update || now >= expiry {
+      return Err(bad("response is not current"));
+    }
+
+    return match tag {
+      0x80 => Ok(CertStatus::Good),
+      0xa1 => Ok(CertStatus::Revoked),
+      0x82 => Ok(CertStatus::Unknown),
+      _ => Err(bad("unknown certStatus"))
+    };
+  }
+
+  Err(bad("no response for certificate"))
+}
+
+/// Validate `ocsp` as a response about `presented_certs[0]`, which
+/// is issued by `presented_certs[1]`.  The response must be signed
+/// by the issuer, or by a responder certificate the issuer
+/// authorised for OCSP signing.
+///
+/// `presented_certs` MUST already have been verified.
+fn check_response(presented_certs: &[ASN1Cert],
+                  ocsp: &[u8],
+                  now: time::Timespec) -> Result<CertStatus, TLSError> {
+  if presented_certs.len() < 2 {
+    return Err(bad("cannot check response without issuer certificate"));
+  }
+
+  let cert = try!(x509::parse_certificate(&presented_certs[0].0)
+                  .ok_or_else(|| bad("cannot parse certificate")));
+  let issuer_der = &presented_certs[1].0;
+  let issuer = try!(x509::parse_certificate(issuer_der)
+                    .ok_or_else(|| bad("cannot parse issuer certificate")));
+
+  if cert.issuer != issuer.subject {
+    return Err(bad("certificate chain is not in order"));
+  }
+
+  let response = try!(parse_response(ocsp)
+                      .ok_or_else(|| bad("malformed response")));
+
+  /* Find who signed it. */
+  let mut signed = verify::verify_signed_data(issuer_der,
+                                              response.signature_alg,
+                                              response.tbs,
+                                              response.signature).is_ok();
+
+  for responder_der in &response.certs {
+    if signed {
+      break;
+    }
+
+    let responder = match x509::parse_certificate(responder_der) {
+      Some(responder) => responder,
+      None => continue
+    };
+
+    if responder.issuer != issuer.subject ||
+      !has_ocsp_signing_eku(&responder) ||
+      now < responder.not_before || now > responder.not_after {
+      continue;
+    }
+
+    let authorised = verify::verify_signed_data(issuer_der,
+                                                responder.signature_alg,
+                                                responder.tbs,
+                                                responder.signature).is_ok();
+
+    signed = authorised &&
+      verify::verify_signed_data(responder_der,
+                                 response.signature_alg,
+                                 response.tbs,
+                                 response.signature).is_ok();
+  }
+
+  if !signed {
+    return Err(bad("response not signed by issuer or authorised responder"));
+  }
+
+  find_status(response.tbs_body, &cert, &issuer, now)
+}
+
+/// Apply `policy` to the stapled OCSP response `ocsp` (empty if none
+/// was stapled) for the verified chain `presented_certs`.
+pub fn check_stapled_response(policy: OCSPPolicy,
+                              presented_certs: &[ASN1Cert],
+                              ocsp: &[u8],
+                              now: time::Timespec) -> Result<(), TLSError> {
+  if policy == OCSPPolicy::Ignore {
+    return Ok(());
+  }
+
+  let must_staple = presented_certs.first()
+    .and_then(|cert| x509::parse_certificate(&cert.0))
+    .map(|cert| is_must_staple(&cert))
+    .unwrap_or(false);
+
+  let status = if ocsp.is_empty() {
+    Err(bad("no response stapled"))
+  } else {
+    check_response(presented_certs, ocsp, now)
+  };
+
+  match status {
+    Ok(CertStatus::Good) => Ok(()),
+    Ok(CertStatus::Revoked) => Err(TLSError::CertificateRevoked),
+    Ok(CertStatus::Unknown) => {
+      if policy == OCSPPolicy::HardFail || must_staple {
+        Err(bad("certificate status unknown"))
+      } else {
+        warn!("OCSP responder doesn't know server certificate");
+        Ok(())
+      }
+    }
+    Err(err) => {
+      if policy == OCSPPolicy::HardFail || must_staple {
+        Err(err)
+      } else {
+        warn!("Ignoring unusable OCSP response: {:?}", err);
+        Ok(())
+      }
+    }
+  }
+}
@@ -4,6 +4,7 @@
 /// they panic on error.
 
 use ring;
+use ring::rand::SecureRandom;
 
 /// Fill the whole slice with random material.
 pub fn fill_random(bytes: &mut [u8]) {
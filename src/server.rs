@@ -1,21 +1,28 @@
+use session;
 use session::{Session, SessionRandoms, SessionSecrets, SessionCommon};
+use session::ExtractedSecrets;
 use suites::{SupportedCipherSuite, ALL_CIPHERSUITES, KeyExchange};
-use msgs::enums::ContentType;
+use msgs::enums::{ContentType, ProtocolVersion, NamedCurve};
 use msgs::enums::{AlertDescription, HandshakeType};
 use msgs::handshake::{SessionID, CertificatePayload, ASN1Cert};
 use msgs::handshake::{ServerNameRequest, SupportedSignatureAlgorithms};
-use msgs::handshake::{EllipticCurveList, ECPointFormatList};
+use msgs::handshake::ConvertServerNameList;
+use msgs::handshake::{EllipticCurveList, ECPointFormatList, SCTList};
 use msgs::message::Message;
 use msgs::codec::Codec;
+use msgs::base::PayloadU8;
 use hash_hs;
+use key_schedule::KeySchedule;
 use server_hs;
 use error::TLSError;
+use keylog::{KeyLog, NoKeyLog};
 use rand;
 use sign;
 use verify;
+use x509;
 
 use std::collections;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 use std::io;
 
 /// A trait for the ability to generate Session IDs, and store
@@ -75,18 +82,24 @@ pub trait ProducesTickets {
   fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>>;
 }
 
+/// How to choose a certificate chain and signing key for
+/// a new connection.
 pub trait ResolvesCert {
   /// Choose a certificate chain and matching key given any SNI,
   /// sigalgs, EC curves and EC point format extensions
-  /// from the client.
+  /// from the client.  `cipher_suites` are the suites offered by
+  /// the client which are enabled in our config and usable with
+  /// `version`, the negotiated protocol version.
   ///
-  /// The certificate chain is returned as a `CertificatePayload`,
-  /// the key is inside a `Signer`.
+  /// The certificate chain and key are returned in a `CertifiedKey`,
+  /// along with any OCSP response to staple.
   fn resolve(&self,
              server_name: Option<&ServerNameRequest>,
              sigalgs: &SupportedSignatureAlgorithms,
              ec_curves: &EllipticCurveList,
-             ec_pointfmts: &ECPointFormatList) -> Result<(CertificatePayload, Arc<Box<sign::Signer + Send + Sync>>), ()>;
+             ec_pointfmts: &ECPointFormatList,
+             cipher_suites: &[&'static SupportedCipherSuite],
+             version: ProtocolVersion) -> Result<sign::CertifiedKey, ()>;
 }
 
 /// Common configuration for a set of server sessions.
@@ -115,15 +128,24 @@ pub struct ServerConfig {
   /// If empty we don't do ALPN at all.
   pub alpn_protocols: Vec<String>,
 
-  /// List of client authentication root certificates.
-  pub client_auth_roots: verify::RootCertStore,
+  /// How to decide whether to do client auth, and how
+  /// to verify client certificates.  The default does no
+  /// client auth.
+  pub client_cert_verifier: Box<verify::ClientCertVerifier + Send + Sync>,
 
-  /// Whether to attempt client auth.
-  pub client_auth_offer: bool,
+  /// Supported protocol versions, in no particular order.
+  /// The default is all supported versions.
+  pub versions: Vec<ProtocolVersion>,
 
-  /// Whether to complete handshakes with clients which
-  /// don't do client auth.
-  pub client_auth_mandatory: bool
+  /// Whether to refuse TLS1.2 clients which don't offer
+  /// extended master secret (RFC7627).  We always accept
+  /// it when offered.
+  pub require_ems: bool,
+
+  /// How to log session secrets, for debugging.
+  ///
+  /// The default is `NoKeyLog`, which logs nothing.
+  pub key_log: Box<KeyLog + Send + Sync>
 }
 
 /// Something which never stores sessions.
@@ -202,26 +224,36 @@ impl ResolvesCert for FailResolveChain {
              _server_name: Option<&ServerNameRequest>,
              _sigalgs: &SupportedSignatureAlgorithms,
              _ec_curves: &EllipticCurveList,
-             _ec_pointfmts: &ECPointFormatList) -> Result<(CertificatePayload, Arc<Box<sign::Signer + Send + Sync>>), ()> {
+             _ec_pointfmts: &ECPointFormatList,
+             _cipher_suites: &[&'static SupportedCipherSuite],
+             _version: ProtocolVersion) -> Result<sign::CertifiedKey, ()> {
     Err(())
   }
 }
 
 /// Something which always resolves to the same cert chain.
-struct AlwaysResolvesChain {
-  chain: CertificatePayload,
-  key: Arc<Box<sign::Signer + Send + Sync>>
-}
+struct AlwaysResolvesChain(sign::CertifiedKey);
 
 impl AlwaysResolvesChain {
-  fn new_rsa(chain: Vec<Vec<u8>>, priv_key: &[u8]) -> AlwaysResolvesChain {
-    let key = sign::RSASigner::new(priv_key)
-      .expect("Invalid RSA private key");
-    let mut payload = Vec::new();
-    for cert in chain {
-      payload.push(ASN1Cert::new(cert));
+  fn new(chain: Vec<Vec<u8>>, priv_key: &[u8]) -> Result<AlwaysResolvesChain, TLSError> {
+    let certkey = try!(sign::CertifiedKey::from_der(chain, priv_key));
+    Ok(AlwaysResolvesChain(certkey))
+  }
+
+  fn new_with_extras(chain: Vec<Vec<u8>>, priv_key: &[u8],
+                     ocsp: Vec<u8>, sct_list: Vec<u8>) -> Result<AlwaysResolvesChain, TLSError> {
+    let mut r = try!(AlwaysResolvesChain::new(chain, priv_key));
+    if !ocsp.is_empty() {
+      r.0.ocsp = Some(ocsp);
     }
-    AlwaysResolvesChain { chain: payload, key: Arc::new(Box::new(key)) }
+    if !sct_list.is_empty() {
+      try!(
+        SCTList::read_bytes(&sct_list)
+          .ok_or_else(|| TLSError::InvalidCertifiedKey("SCT list is malformed".to_string()))
+      );
+      r.0.sct_list = Some(sct_list);
+    }
+    Ok(r)
   }
 }
 
@@ -230,8 +262,302 @@ impl ResolvesCert for AlwaysResolvesChain {
              _server_name: Option<&ServerNameRequest>,
              _sigalgs: &SupportedSignatureAlgorithms,
              _ec_curves: &EllipticCurveList,
-             _ec_pointfmts: &ECPointFormatList) -> Result<(CertificatePayload, Arc<Box<sign::Signer + Send + Sync>>), ()> {
-    Ok((self.chain.clone(), self.key.clone()))
+             _ec_pointfmts: &ECPointFormatList,
+             _cipher_suites: &[&'static SupportedCipherSuite],
+             _version: ProtocolVersion) -> Result<sign::CertifiedKey, ()> {
+    Ok(self.0.clone())
+  }
+}
+
+/// Something which resolves a cert chain by looking up the
+/// hostname the client asked for with SNI.
+///
+/// Names are either exact DNS names like `www.example.com`, or
+/// wildcards like `*.example.com` which match exactly one extra
+/// leftmost label.  Exact names are preferred over wildcards.
+/// The default chain, if any, is used for clients which don't
+/// send SNI and for names which don't match; if there is no
+/// default such clients are refused.
+///
+/// This can be updated at runtime: it is cheaply cloneable,
+/// and clones share the same set of names.  Keep a clone
+/// before putting it in your `ServerConfig`.
+#[derive(Clone)]
+pub struct ResolvesServerCertUsingSNI {
+  inner: Arc<RwLock<SNIMap<sign::CertifiedKey>>>
+}
+
+/// Things indexed by DNS name or wildcard, with a default.
+struct SNIMap<T> {
+  by_name: collections::HashMap<String, T>,
+  default: Option<T>
+}
+
+impl<T> SNIMap<T> {
+  fn new() -> SNIMap<T> {
+    SNIMap {
+      by_name: collections::HashMap::new(),
+      default: None
+    }
+  }
+
+  /// Find the entry for `name`: an exact match, then a wildcard
+  /// match, then the default.
+  fn lookup(&self, name: Option<&str>) -> Option<&T> {
+    if let Some(name) = name {
+      let name = normalise_dns_name(name);
+
+      if let Some(entry) = self.by_name.get(&name) {
+        return Some(entry);
+      }
+
+      if let Some(dot) = name.find('.') {
+        let wildcard = format!("*{}", &name[dot..]);
+        if let Some(entry) = self.by_name.get(&wildcard) {
+          return Some(entry);
+        }
+      }
+    }
+
+    self.default.as_ref()
+  }
+}
+
+impl ResolvesServerCertUsingSNI {
+  /// Make a new resolver, with no names and no default.
+  pub fn new() -> ResolvesServerCertUsingSNI {
+    ResolvesServerCertUsingSNI {
+      inner: Arc::new(RwLock::new(SNIMap::new()))
+    }
+  }
+
+  /// Serve `cert_chain` and `key_der` to clients asking for `name`,
+  /// replacing any existing chain for `name`.  `name` may be a
+  /// wildcard like `*.example.com`.
+  pub fn add(&self, name: &str, cert_chain: Vec<Vec<u8>>, key_der: &[u8]) -> Result<(), TLSError> {
+    let certkey = try!(sign::CertifiedKey::from_der(cert_chain, key_der));
+
+    self.add_certified_key(name, certkey);
+    Ok(())
+  }
+
+  /// Serve `certkey` to clients asking for `name`, replacing any
+  /// existing chain for `name`.  Use this to staple an OCSP response
+  /// or send signed certificate timestamps.
+  pub fn add_certified_key(&self, name: &str, certkey: sign::CertifiedKey) {
+    self.inner.write().unwrap()
+      .by_name.insert(normalise_dns_name(name), certkey);
+  }
+
+  /// Stop serving anything specific for `name`.  Returns true
+  /// if `name` was present.
+  pub fn remove(&self, name: &str) -> bool {
+    self.inner.write().unwrap()
+      .by_name.remove(&normalise_dns_name(name))
+      .is_some()
+  }
+
+  /// Serve `cert_chain` and `key_der` to clients which don't
+  /// send SNI, or ask for a name we don't have.
+  pub fn set_default(&self, cert_chain: Vec<Vec<u8>>, key_der: &[u8]) -> Result<(), TLSError> {
+    let certkey = try!(sign::CertifiedKey::from_der(cert_chain, key_der));
+
+    self.inner.write().unwrap().default = Some(certkey);
+    Ok(())
+  }
+
+  /// Remove the default chain.  Clients which don't send
+  /// SNI, or ask for a name we don't have, are then refused.
+  pub fn clear_default(&self) {
+    self.inner.write().unwrap().default = None;
+  }
+}
+
+/// DNS names are case-insensitive, and may have a trailing dot.
+fn normalise_dns_name(name: &str) -> String {
+  name.trim_right_matches('.').to_lowercase()
+}
+
+impl ResolvesCert for ResolvesServerCertUsingSNI {
+  fn resolve(&self,
+             server_name: Option<&ServerNameRequest>,
+             _sigalgs: &SupportedSignatureAlgorithms,
+             _ec_curves: &EllipticCurveList,
+             _ec_pointfmts: &ECPointFormatList,
+             _cipher_suites: &[&'static SupportedCipherSuite],
+             _version: ProtocolVersion) -> Result<sign::CertifiedKey, ()> {
+    let name = server_name.and_then(|req| req.get_hostname());
+    let map = self.inner.read().unwrap();
+    let certkey = map.lookup(name).cloned();
+    certkey.ok_or(())
+  }
+}
+
+/// Something which resolves a cert chain by looking up the
+/// hostname the client asked for with SNI, then choosing among
+/// several chains for that name by what the client supports.
+/// This lets a host serve ECDSA to clients which can use it,
+/// and RSA to the rest.
+///
+/// Names work as for `ResolvesServerCertUsingSNI`.  The chains
+/// for the best-matching name are tried in the order they were
+/// added, and the first the client can use is chosen.  A chain
+/// is usable if:
+///
+/// - the client offered a ciphersuite which can be used with its
+///   key type (any TLS1.3 suite, or a TLS1.2 suite with matching
+///   authentication),
+/// - the client offered a signature scheme the key can produce in
+///   the negotiated protocol version, and
+/// - for ECDSA keys in TLS1.2, the client listed the key's curve as
+///   supported.
+///
+/// So add your preferred chains first: for example P-256 ECDSA,
+/// then P-384 ECDSA, then RSA.
+///
+/// This can be updated at runtime, like `ResolvesServerCertUsingSNI`.
+#[derive(Clone)]
+pub struct ResolvesMultipleServerCerts {
+  inner: Arc<RwLock<SNIMap<Vec<sign::CertifiedKey>>>>
+}
+
+impl ResolvesMultipleServerCerts {
+  /// Make a new resolver, with no names and no default.
+  pub fn new() -> ResolvesMultipleServerCerts {
+    ResolvesMultipleServerCerts {
+      inner: Arc::new(RwLock::new(SNIMap::new()))
+    }
+  }
+
+  /// Also serve `cert_chain` and `key_der` to clients asking for
+  /// `name`, if they can't use any chain added before it.  `name`
+  /// may be a wildcard like `*.example.com`.
+  pub fn add(&self, name: &str, cert_chain: Vec<Vec<u8>>, key_der: &[u8]) -> Result<(), TLSError> {
+    let certkey = try!(sign::CertifiedKey::from_der(cert_chain, key_der));
+
+    self.add_certified_key(name, certkey);
+    Ok(())
+  }
+
+  /// Also serve `certkey` to clients asking for `name`, if they
+  /// can't use any chain added before it.
+  pub fn add_certified_key(&self, name: &str, certkey: sign::CertifiedKey) {
+    self.inner.write().unwrap()
+      .by_name.entry(normalise_dns_name(name))
+      .or_insert_with(Vec::new)
+      .push(certkey);
+  }
+
+  /// Stop serving anything specific for `name`.  Returns true
+  /// if `name` was present.
+  pub fn remove(&self, name: &str) -> bool {
+    self.inner.write().unwrap()
+      .by_name.remove(&normalise_dns_name(name))
+      .is_some()
+  }
+
+  /// Also serve `cert_chain` and `key_der` to clients which don't
+  /// send SNI, or ask for a name we don't have, if they can't use
+  /// any default chain added before it.
+  pub fn add_default(&self, cert_chain: Vec<Vec<u8>>, key_der: &[u8]) -> Result<(), TLSError> {
+    let certkey = try!(sign::CertifiedKey::from_der(cert_chain, key_der));
+
+    self.add_default_certified_key(certkey);
+    Ok(())
+  }
+
+  /// Like `add_default`, but with a `CertifiedKey`.
+  pub fn add_default_certified_key(&self, certkey: sign::CertifiedKey) {
+    let mut map = self.inner.write().unwrap();
+    if map.default.is_none() {
+      map.default = Some(Vec::new());
+    }
+    map.default.as_mut().unwrap().push(certkey);
+  }
+
+  /// Remove all the default chains.  Clients which don't send
+  /// SNI, or ask for a name we don't have, are then refused.
+  pub fn clear_default(&self) {
+    self.inner.write().unwrap().default = None;
+  }
+}
+
+/// The named curve of the end-entity certificate's key in
+/// `certkey`, if it is an ECDSA key.
+fn end_entity_curve(certkey: &sign::CertifiedKey) -> Option<NamedCurve> {
+  let cert = try_ret!(certkey.cert.first().and_then(|cert| x509::parse_certificate(&cert.0)));
+  let alg_id = try_ret!(x509::read_all(cert.spki, |rd| {
+    let body = try_ret!(rd.read(x509::SEQUENCE));
+    x509::DerReader::new(body).read(x509::SEQUENCE)
+  }));
+
+  let mut rd = x509::DerReader::new(alg_id);
+  if try_ret!(rd.read(x509::OID)) != x509::EC_PUBLIC_KEY {
+    return None;
+  }
+
+  let curve = try_ret!(rd.read(x509::OID));
+  if curve == x509::SECP256R1 {
+    Some(NamedCurve::secp256r1)
+  } else if curve == x509::SECP384R1 {
+    Some(NamedCurve::secp384r1)
+  } else {
+    None
+  }
+}
+
+/// Can a client which negotiated `version` and offered `sigalgs`,
+/// `ec_curves` and `cipher_suites` use `certkey`?
+fn usable_by_client(certkey: &sign::CertifiedKey,
+                    version: ProtocolVersion,
+                    sigalgs: &SupportedSignatureAlgorithms,
+                    ec_curves: &EllipticCurveList,
+                    cipher_suites: &[&'static SupportedCipherSuite]) -> bool {
+  let alg = certkey.key.algorithm();
+  let tls13 = version == ProtocolVersion::TLSv1_3;
+
+  let suite_ok = cipher_suites.iter()
+    .any(|scs| scs.usable_for_version(ProtocolVersion::TLSv1_3) ||
+               scs.usable_for_sigalg(&alg));
+
+  let sigalg_ok = if tls13 {
+    certkey.key.choose_scheme_tls13(sigalgs).is_some()
+  } else {
+    certkey.key.choose_scheme_tls12(sigalgs).is_some()
+  };
+
+  /* In TLS1.3 the ECDSA signature schemes name the curve, and
+   * the supported groups are only about key exchange. */
+  let curve_ok = match end_entity_curve(certkey) {
+    Some(curve) => tls13 || ec_curves.contains(&curve),
+    None => true
+  };
+
+  suite_ok && sigalg_ok && curve_ok
+}
+
+impl ResolvesCert for ResolvesMultipleServerCerts {
+  fn resolve(&self,
+             server_name: Option<&ServerNameRequest>,
+             sigalgs: &SupportedSignatureAlgorithms,
+             ec_curves: &EllipticCurveList,
+             _ec_pointfmts: &ECPointFormatList,
+             cipher_suites: &[&'static SupportedCipherSuite],
+             version: ProtocolVersion) -> Result<sign::CertifiedKey, ()> {
+    let name = server_name.and_then(|req| req.get_hostname());
+    let map = self.inner.read().unwrap();
+    let certkey = map.lookup(name)
+      .and_then(|certkeys| {
+        certkeys.iter()
+          .find(|certkey| usable_by_client(certkey, version, sigalgs, ec_curves, cipher_suites))
+      })
+      .cloned();
+
+    if certkey.is_none() {
+      info!("no usable certificate chain for {:?}", name);
+    }
+
+    certkey.ok_or(())
   }
 }
 
@@ -247,12 +573,18 @@ impl ServerConfig {
       ticketer: Box::new(NeverProducesTickets {}),
       alpn_protocols: Vec::new(),
       cert_resolver: Box::new(FailResolveChain {}),
-      client_auth_roots: verify::RootCertStore::empty(),
-      client_auth_offer: false,
-      client_auth_mandatory: false
+      client_cert_verifier: Box::new(verify::NoClientAuth {}),
+      versions: vec![ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2],
+      require_ems: false,
+      key_log: Box::new(NoKeyLog {})
     }
   }
 
+  pub fn supports_version(&self, v: ProtocolVersion) -> bool {
+    self.versions.contains(&v) &&
+      self.ciphersuites.iter().any(|cs| cs.usable_for_version(v))
+  }
+
   /// Sets the session persistence layer to `persist`.
   pub fn set_persistence(&mut self, persist: Box<StoresServerSessions + Send + Sync>) {
     self.session_storage = Mutex::new(persist);
@@ -262,10 +594,54 @@ impl ServerConfig {
   /// certificate and key is used for all subsequent connections,
   /// irrespective of things like SNI hostname.
   ///
-  /// `cert_chain` is a vector of DER-encoded certificates.
-  /// `key_der` is a DER-encoded RSA private key.
-  pub fn set_single_cert(&mut self, cert_chain: Vec<Vec<u8>>, key_der: Vec<u8>) {
-    self.cert_resolver = Box::new(AlwaysResolvesChain::new_rsa(cert_chain, &key_der));
+  /// `cert_chain` is a vector of DER-encoded certificates, end-entity
+  /// certificate first and each certificate issued by the next.
+  /// `key_der` is a DER-encoded private key: RSA in PKCS#1 or PKCS#8
+  /// format, ECDSA P-256/P-384 in PKCS#8 or SEC1 format, or Ed25519
+  /// in PKCS#8 format.
+  ///
+  /// This fails with `TLSError::InvalidCertifiedKey` if the chain
+  /// or key is malformed, the chain doesn't link, the key doesn't
+  /// match the end-entity certificate, or the end-entity certificate
+  /// isn't currently valid.  See `sign::CertifiedKey::check`.
+  pub fn set_single_cert(&mut self, cert_chain: Vec<Vec<u8>>,
+                         key_der: Vec<u8>) -> Result<(), TLSError> {
+    let resolver = try!(AlwaysResolvesChain::new(cert_chain, &key_der));
+    self.cert_resolver = Box::new(resolver);
+    Ok(())
+  }
+
+  /// Sets a single certificate chain, matching private key and
+  /// OCSP response.  This certificate and key is used for all
+  /// subsequent connections, irrespective of things like SNI
+  /// hostname.  The OCSP response is stapled for clients which
+  /// ask for it.
+  ///
+  /// `cert_chain` and `key_der` are as for `set_single_cert`, and
+  /// are checked in the same way.  `ocsp` is a DER-encoded OCSP
+  /// response; if empty, nothing is stapled.
+  pub fn set_single_cert_with_ocsp(&mut self, cert_chain: Vec<Vec<u8>>, key_der: Vec<u8>,
+                                   ocsp: Vec<u8>) -> Result<(), TLSError> {
+    self.set_single_cert_with_ocsp_and_sct(cert_chain, key_der, ocsp, Vec::new())
+  }
+
+  /// Sets a single certificate chain, matching private key, OCSP
+  /// response and signed certificate timestamps.  This certificate
+  /// and key is used for all subsequent connections, irrespective of
+  /// things like SNI hostname.  The OCSP response and SCTs are sent
+  /// to clients which ask for them.
+  ///
+  /// `cert_chain`, `key_der` and `ocsp` are as for
+  /// `set_single_cert_with_ocsp`.  `sct_list` is a TLS-encoded
+  /// SignedCertificateTimestampList (RFC6962 section 3.3); if empty,
+  /// no SCTs are sent.
+  pub fn set_single_cert_with_ocsp_and_sct(&mut self, cert_chain: Vec<Vec<u8>>, key_der: Vec<u8>,
+                                           ocsp: Vec<u8>, sct_list: Vec<u8>) -> Result<(), TLSError> {
+    let resolver = try!(
+      AlwaysResolvesChain::new_with_extras(cert_chain, &key_der, ocsp, sct_list)
+    );
+    self.cert_resolver = Box::new(resolver);
+    Ok(())
   }
 
   /// Set the ALPN protocol list to the given protocol names.
@@ -283,12 +659,12 @@ impl ServerConfig {
   /// `certs`.  If `mandatory` is true, the server will fail
   /// to handshake with a client if it does not do client auth.
   pub fn set_client_auth_roots(&mut self, certs: Vec<Vec<u8>>, mandatory: bool) {
+    let mut roots = verify::RootCertStore::empty();
     for cert in certs {
-      self.client_auth_roots.add(&cert)
+      roots.add(&cert)
         .unwrap()
     }
-    self.client_auth_offer = true;
-    self.client_auth_mandatory = mandatory;
+    self.client_cert_verifier = Box::new(verify::WebPKIClientVerifier::new(roots, mandatory));
   }
 }
 
@@ -302,7 +678,15 @@ pub struct ServerHandshakeData {
   pub doing_resume: bool,
   pub send_ticket: bool,
   pub doing_client_auth: bool,
-  pub valid_client_cert_chain: Option<Vec<ASN1Cert>>
+  pub valid_client_cert_chain: Option<Vec<ASN1Cert>>,
+  pub key_schedule: Option<KeySchedule>,
+  pub hash_at_server_fin: Vec<u8>,
+  pub ocsp_response: Option<Vec<u8>>,
+  pub sct_list: Option<SCTList>,
+  pub done_retry: bool,
+  pub using_ems: bool,
+  /// The verify_data of the first Finished message in TLS1.2.
+  pub tls_unique: Vec<u8>
 }
 
 impl ServerHandshakeData {
@@ -317,7 +701,14 @@ impl ServerHandshakeData {
       send_ticket: false,
       doing_resume: false,
       doing_client_auth: false,
-      valid_client_cert_chain: None
+      valid_client_cert_chain: None,
+      key_schedule: None,
+      hash_at_server_fin: Vec::new(),
+      ocsp_response: None,
+      sct_list: None,
+      done_retry: false,
+      using_ems: false,
+      tls_unique: Vec::new()
     }
   }
 
@@ -335,7 +726,17 @@ pub enum ConnState {
   ExpectCertificateVerify,
   ExpectCCS,
   ExpectFinished,
-  Traffic
+  Traffic,
+  ExpectCertificateTLS13,
+  ExpectCertificateVerifyTLS13,
+  ExpectFinishedTLS13,
+  TrafficTLS13
+}
+
+impl ConnState {
+  fn is_traffic(&self) -> bool {
+    *self == ConnState::Traffic || *self == ConnState::TrafficTLS13
+  }
 }
 
 pub struct ServerSessionImpl {
@@ -358,7 +759,7 @@ impl ServerSessionImpl {
       state: ConnState::ExpectClientHello
     };
 
-    if sess.config.client_auth_offer {
+    if sess.config.client_cert_verifier.offer_client_auth() {
       sess.handshake_data.transcript.set_client_auth_enabled();
     }
 
@@ -380,10 +781,17 @@ impl ServerSessionImpl {
   }
 
   pub fn is_handshaking(&self) -> bool {
-    self.state != ConnState::Traffic
+    !self.state.is_traffic()
   }
 
   pub fn process_msg(&mut self, mut msg: Message) -> Result<(), TLSError> {
+    /* TLS1.3 clients may send a ChangeCipherSpec for middlebox
+     * compatibility.  It has no meaning, so drop it. */
+    if self.common.is_tls13() && self.is_handshaking() &&
+      msg.is_content_type(ContentType::ChangeCipherSpec) {
+      return Ok(());
+    }
+
     /* Decrypt if demanded by current state. */
     if self.common.peer_encrypting {
       let dm = try!(self.common.decrypt_incoming(msg));
@@ -434,7 +842,7 @@ impl ServerSessionImpl {
     let new_state = try!((handler.handle)(self, msg));
     self.state = new_state;
 
-    if self.state == ConnState::Traffic && !self.common.traffic {
+    if self.state.is_traffic() && !self.common.traffic {
       self.common.start_traffic();
     }
 
@@ -449,7 +857,11 @@ impl ServerSessionImpl {
       ConnState::ExpectCertificateVerify => &server_hs::EXPECT_CERTIFICATE_VERIFY,
       ConnState::ExpectCCS => &server_hs::EXPECT_CCS,
       ConnState::ExpectFinished => &server_hs::EXPECT_FINISHED,
-      ConnState::Traffic => &server_hs::TRAFFIC
+      ConnState::Traffic => &server_hs::TRAFFIC,
+      ConnState::ExpectCertificateTLS13 => &server_hs::EXPECT_CERTIFICATE_TLS13,
+      ConnState::ExpectCertificateVerifyTLS13 => &server_hs::EXPECT_CERTIFICATE_VERIFY_TLS13,
+      ConnState::ExpectFinishedTLS13 => &server_hs::EXPECT_FINISHED_TLS13,
+      ConnState::TrafficTLS13 => &server_hs::TRAFFIC_TLS13
     }
   }
 
@@ -467,7 +879,11 @@ impl ServerSessionImpl {
 
   pub fn start_encryption(&mut self) {
     let scs = self.handshake_data.ciphersuite.as_ref().unwrap();
-    self.common.start_encryption(scs, self.secrets.as_ref().unwrap());
+    let secrets = self.secrets.as_ref().unwrap();
+    self.config.key_log.log("CLIENT_RANDOM",
+                            &secrets.randoms.client,
+                            &secrets.get_master_secret());
+    self.common.start_encryption_tls12(scs, secrets);
   }
 
   pub fn get_peer_certificates(&self) -> Option<Vec<Vec<u8>>> {
@@ -487,6 +903,121 @@ impl ServerSessionImpl {
   pub fn get_alpn_protocol(&self) -> Option<String> {
     self.alpn_protocol.clone()
   }
+
+  pub fn get_protocol_version(&self) -> Option<ProtocolVersion> {
+    self.common.negotiated_version
+  }
+
+  pub fn export_keying_material(&self,
+                                label: &[u8],
+                                context: Option<&[u8]>,
+                                len: usize) -> Result<Vec<u8>, TLSError> {
+    if self.is_handshaking() {
+      return Err(TLSError::HandshakeNotComplete);
+    }
+
+    let mut output = vec![0u8; len];
+    if self.common.is_tls13() {
+      try!(self.handshake_data.key_schedule.as_ref().unwrap()
+           .export_keying_material(&mut output, label, context));
+    } else {
+      try!(self.secrets.as_ref().unwrap()
+           .export_keying_material(&mut output, label, context));
+    }
+
+    Ok(output)
+  }
+
+  pub fn get_tls_unique(&self) -> Option<Vec<u8>> {
+    if self.is_handshaking() || self.common.is_tls13() ||
+      self.handshake_data.tls_unique.is_empty() {
+      return None;
+    }
+
+    Some(self.handshake_data.tls_unique.clone())
+  }
+
+  pub fn get_tls_server_end_point(&self) -> Option<Vec<u8>> {
+    if self.is_handshaking() {
+      return None;
+    }
+
+    self.handshake_data.server_cert_chain.as_ref()
+      .and_then(|chain| chain.first())
+      .and_then(session::tls_server_end_point)
+  }
+
+  pub fn extract_secrets(&self) -> Result<ExtractedSecrets, TLSError> {
+    if self.is_handshaking() {
+      return Err(TLSError::HandshakeNotComplete);
+    }
+
+    if self.common.has_buffered_data() {
+      return Err(TLSError::General("session has buffered data".to_string()));
+    }
+
+    let scs = self.handshake_data.ciphersuite.unwrap();
+    if self.common.is_tls13() {
+      Ok(ExtractedSecrets::from_tls13(scs,
+                                      self.handshake_data.key_schedule.as_ref().unwrap(),
+                                      false,
+                                      &self.common))
+    } else {
+      Ok(ExtractedSecrets::from_tls12(scs, self.secrets.as_ref().unwrap(), &self.common))
+    }
+  }
+
+  pub fn save_state(&self) -> Result<Vec<u8>, TLSError> {
+    if self.is_handshaking() {
+      return Err(TLSError::HandshakeNotComplete);
+    }
+
+    let mut state = try!(session::save_session_state(self.handshake_data.ciphersuite.unwrap(),
+                                                     &self.handshake_data.randoms,
+                                                     self.secrets.as_ref(),
+                                                     self.handshake_data.key_schedule.as_ref(),
+                                                     &self.common));
+    let alpn_protocol = self.alpn_protocol.clone().unwrap_or_else(String::new);
+    state.alpn_protocol = PayloadU8::new(alpn_protocol.into_bytes());
+    state.server_cert_chain = self.handshake_data.server_cert_chain.clone().unwrap_or_else(Vec::new);
+    state.client_cert_chain = self.handshake_data.valid_client_cert_chain.clone();
+    state.tls_unique = PayloadU8::new(self.handshake_data.tls_unique.clone());
+    Ok(state.get_encoding())
+  }
+
+  pub fn restore_state(config: &Arc<ServerConfig>, bytes: &[u8]) -> Result<ServerSessionImpl, TLSError> {
+    let state = try!(session::read_session_state(bytes, false));
+    let scs = try!(session::find_saved_suite(&config.ciphersuites, &config.versions, &state));
+    let alpn_protocol = try!(String::from_utf8(state.alpn_protocol.0.clone())
+                             .map_err(|_| session::bad_session_state()));
+
+    let mut sess = ServerSessionImpl {
+      config: config.clone(),
+      handshake_data: ServerHandshakeData::new(),
+      secrets: None,
+      common: SessionCommon::new(None),
+      alpn_protocol: if alpn_protocol.is_empty() { None } else { Some(alpn_protocol) },
+      state: ConnState::Traffic
+    };
+
+    let (randoms, secrets, key_schedule) =
+      try!(session::restore_session_state(scs, &state, &mut sess.common));
+    sess.secrets = secrets;
+    sess.handshake_data.ciphersuite = Some(scs);
+    sess.handshake_data.randoms = randoms;
+    sess.handshake_data.key_schedule = key_schedule;
+    if !state.server_cert_chain.is_empty() {
+      sess.handshake_data.server_cert_chain = Some(state.server_cert_chain);
+    }
+    sess.handshake_data.valid_client_cert_chain = state.client_cert_chain;
+    sess.handshake_data.tls_unique = state.tls_unique.0;
+
+    if sess.common.is_tls13() {
+      sess.state = ConnState::TrafficTLS13;
+    }
+
+    Ok(sess)
+  }
 }
 
 /// This represents a single TLS server session.
@@ -504,6 +1035,42 @@ impl ServerSession {
   pub fn new(config: &Arc<ServerConfig>) -> ServerSession {
     ServerSession { imp: ServerSessionImpl::new(config) }
   }
+
+  /// Consume this session after its handshake, returning the
+  /// state of its record layer.  The caller is then responsible
+  /// for protecting traffic, for example by configuring kernel
+  /// TLS on the underlying socket.
+  ///
+  /// This fails if the handshake isn't complete, or if any
+  /// data is waiting to be read, written or processed: call
+  /// this only once `wants_write` is false and all plaintext
+  /// has been read.  Any later TLS1.3 post-handshake messages,
+  /// like key updates or tickets, must be handled by the caller.
+  pub fn extract_secrets(self) -> Result<ExtractedSecrets, TLSError> {
+    self.imp.extract_secrets()
+  }
+
+  /// Save the state of this session after its handshake, so it
+  /// can be carried on elsewhere with `restore_state` -- for
+  /// example, in a new process which inherits the socket.  The
+  /// result contains the session's secrets, so protect it as
+  /// you would a private key.
+  ///
+  /// This consumes the session: two sessions sharing the same
+  /// keys and sequence numbers would reuse nonces.  It fails if
+  /// the handshake is incomplete.
+  pub fn save_state(self) -> Result<Vec<u8>, TLSError> {
+    self.imp.save_state()
+  }
+
+  /// Rebuild a session saved with `save_state`.  `config` should
+  /// be equivalent to the one the session was made with; the
+  /// session's cipher suite and protocol version must be
+  /// enabled in it.
+  pub fn restore_state(config: &Arc<ServerConfig>, bytes: &[u8]) -> Result<ServerSession, TLSError> {
+    let imp = try!(ServerSessionImpl::restore_state(config, bytes));
+    Ok(ServerSession { imp: imp })
+  }
 }
 
 impl Session for ServerSession {
@@ -543,6 +1110,25 @@ impl Session for ServerSession {
   fn get_alpn_protocol(&self) -> Option<String> {
     self.imp.get_alpn_protocol()
   }
+
+  fn get_protocol_version(&self) -> Option<ProtocolVersion> {
+    self.imp.get_protocol_version()
+  }
+
+  fn export_keying_material(&self,
+                            label: &[u8],
+                            context: Option<&[u8]>,
+                            len: usize) -> Result<Vec<u8>, TLSError> {
+    self.imp.export_keying_material(label, context, len)
+  }
+
+  fn get_tls_unique(&self) -> Option<Vec<u8>> {
+    self.imp.get_tls_unique()
+  }
+
+  fn get_tls_server_end_point(&self) -> Option<Vec<u8>> {
+    self.imp.get_tls_server_end_point()
+  }
 }
 
 impl io::Read for ServerSession {
@@ -1,12 +1,16 @@
 use msgs::enums::{ContentType, HandshakeType, ProtocolVersion};
 use msgs::enums::{Compression, NamedCurve, ECPointFormat, CipherSuite};
 use msgs::enums::{ExtensionType, AlertDescription};
-use msgs::enums::ClientCertificateType;
+use msgs::enums::{ClientCertificateType, KeyUpdateRequest};
 use msgs::message::{Message, MessagePayload};
-use msgs::base::Payload;
+use msgs::base::{Payload, PayloadU8};
 use msgs::handshake::{HandshakePayload, SupportedSignatureAlgorithms};
 use msgs::handshake::{HandshakeMessagePayload, ServerHelloPayload, Random};
 use msgs::handshake::{ClientHelloPayload, ServerExtension, SessionID};
+use msgs::handshake::{HelloRetryRequest, HelloRetryExtension, KeyShareEntry};
+use msgs::handshake::{CertificatePayloadTLS13, CertificateRequestPayloadTLS13};
+use msgs::handshake::{CertReqExtension, DOWNGRADE_SENTINEL};
+use msgs::handshake::{CertificateStatus, CertificateExtension, SCTList};
 use msgs::handshake::ConvertProtocolNameList;
 use msgs::handshake::SignatureAndHashAlgorithm;
 use msgs::handshake::{EllipticCurveList, SupportedCurves, ClientExtension};
@@ -20,13 +24,17 @@ use msgs::codec::Codec;
 use msgs::persist;
 use session::SessionSecrets;
 use server::{ServerSessionImpl, ConnState};
+use key_schedule::{KeySchedule, SecretKind};
+use cipher;
 use suites;
+use suites::SupportedCipherSuite;
 use sign;
 use verify;
 use util;
 use error::TLSError;
 use handshake::Expectation;
 
+use ring;
 use std::sync::Arc;
 
 macro_rules! extract_handshake(
@@ -75,6 +83,12 @@ fn process_extensions(sess: &mut ServerSessionImpl, hello: &ClientHelloPayload)
     };
   }
 
+  /* The rest are TLS1.2 only: TLS1.3 has no renegotiation, and
+   * does resumption differently. */
+  if sess.common.is_tls13() {
+    return Ok(ret);
+  }
+
   /* Renegotiation.
    * (We don't do reneg at all, but would support the secure version if we did.) */
   let secure_reneg_offered =
@@ -85,6 +99,23 @@ fn process_extensions(sess: &mut ServerSessionImpl, hello: &ClientHelloPayload)
     ret.push(ServerExtension::make_empty_renegotiation_info());
   }
 
+  /* OCSP stapling: we've already decided whether we have a response
+   * and whether the client wants it. */
+  if sess.handshake_data.ocsp_response.is_some() {
+    ret.push(ServerExtension::CertificateStatusAck);
+  }
+
+  /* Signed certificate timestamps (RFC6962). */
+  if let Some(sct_list) = sess.handshake_data.sct_list.take() {
+    ret.push(ServerExtension::SignedCertificateTimestamp(sct_list));
+  }
+
+  /* Extended master secret (RFC7627). */
+  if hello.ems_support_offered() {
+    sess.handshake_data.using_ems = true;
+    ret.push(ServerExtension::ExtendedMasterSecretAck);
+  }
+
   /* Tickets:
    * If we get any SessionTicket extension and have tickets enabled,
    * we send an ack. */
@@ -150,6 +181,27 @@ fn emit_certificate(sess: &mut ServerSessionImpl) {
   sess.common.send_msg(c, false);
 }
 
+fn emit_cert_status(sess: &mut ServerSessionImpl) {
+  let ocsp = match sess.handshake_data.ocsp_response.take() {
+    Some(ocsp) => ocsp,
+    None => return
+  };
+
+  let c = Message {
+    typ: ContentType::Handshake,
+    version: ProtocolVersion::TLSv1_2,
+    payload: MessagePayload::Handshake(
+      HandshakeMessagePayload {
+        typ: HandshakeType::CertificateStatus,
+        payload: HandshakePayload::CertificateStatus(CertificateStatus::new(ocsp))
+      }
+    )
+  };
+
+  sess.handshake_data.transcript.add_message(&c);
+  sess.common.send_msg(c, false);
+}
+
 fn emit_server_kx(sess: &mut ServerSessionImpl,
                   sigalg: &SignatureAndHashAlgorithm,
                   curve: &NamedCurve,
@@ -167,7 +219,7 @@ fn emit_server_kx(sess: &mut ServerSessionImpl,
   secdh.encode(&mut msg);
 
   let sig = try!(
-    signer.sign(&sigalg.hash, &msg)
+    signer.sign(&sigalg, &msg)
     .map_err(|_| TLSError::General("signing failed".to_string()))
   );
 
@@ -196,11 +248,11 @@ fn emit_server_kx(sess: &mut ServerSessionImpl,
 }
 
 fn emit_certificate_req(sess: &mut ServerSessionImpl) {
-  if !sess.config.client_auth_offer {
+  if !sess.config.client_cert_verifier.offer_client_auth() {
     return;
   }
 
-  let names = sess.config.client_auth_roots.get_subjects();
+  let names = sess.config.client_cert_verifier.client_auth_root_subjects();
 
   let cr = CertificateRequestPayload {
     certtypes: vec![ ClientCertificateType::RSASign,
@@ -259,7 +311,14 @@ fn start_resumption(sess: &mut ServerSessionImpl,
     return Err(TLSError::PeerMisbehavedError("client varied ciphersuite over resumption".to_string()));
   }
 
+  /* RFC7627 section 5.3: a session using EMS can only be resumed with it. */
+  if resumedata.extended_ms && !client_hello.ems_support_offered() {
+    return Err(incompatible(sess, "client did not offer EMS when resuming EMS session"));
+  }
+
   sess.handshake_data.session_id = id.clone();
+  sess.handshake_data.ocsp_response = None;
+  sess.handshake_data.sct_list = None;
   try!(emit_server_hello(sess, client_hello));
 
   let hashalg = sess.handshake_data.ciphersuite.as_ref().unwrap().get_hash();
@@ -276,14 +335,389 @@ fn start_resumption(sess: &mut ServerSessionImpl,
   return Ok(ConnState::ExpectCCS);
 }
 
+/// Returns true if `resumedata` is present and may be resumed.
+/// RFC7627 section 5.3 says a session established without EMS
+/// must not be resumed if the client now offers EMS: instead we
+/// do a full handshake.
+fn can_resume(client_hello: &ClientHelloPayload,
+              resumedata: &Option<persist::ServerSessionValue>) -> bool {
+  match *resumedata {
+    Some(ref resumedata) => resumedata.extended_ms || !client_hello.ems_support_offered(),
+    None => false
+  }
+}
+
+/* --- TLS1.3 ServerHello and friends --- */
+fn emit_hello_retry_request(sess: &mut ServerSessionImpl,
+                            session_id: &SessionID,
+                            group: NamedCurve) {
+  let mut req = HelloRetryRequest {
+    legacy_version: ProtocolVersion::TLSv1_2,
+    session_id: session_id.clone(),
+    cipher_suite: sess.handshake_data.ciphersuite.unwrap().suite,
+    extensions: Vec::new()
+  };
+
+  req.extensions.push(HelloRetryExtension::KeyShare(group));
+  req.extensions.push(HelloRetryExtension::SupportedVersions(ProtocolVersion::TLSv1_3));
+
+  let m = Message {
+    typ: ContentType::Handshake,
+    version: ProtocolVersion::TLSv1_2,
+    payload: MessagePayload::Handshake(
+      HandshakeMessagePayload {
+        typ: HandshakeType::HelloRetryRequest,
+        payload: HandshakePayload::HelloRetryRequest(req)
+      }
+    )
+  };
+
+  debug!("Requesting retry {:?}", m);
+  sess.handshake_data.transcript.rollup_for_hrr();
+  sess.handshake_data.transcript.add_message(&m);
+  sess.common.send_msg(m, false);
+  sess.handshake_data.done_retry = true;
+}
+
+fn emit_server_hello_tls13(sess: &mut ServerSessionImpl,
+                           session_id: &SessionID,
+                           share: &KeyShareEntry) -> Result<(), TLSError> {
+  let mut extensions = Vec::new();
+
+  /* Do key exchange */
+  let kxr = try!(
+    suites::KeyExchange::start_ecdhe(&share.group)
+      .and_then(|kx| kx.complete(&share.payload.0))
+      .ok_or_else(|| TLSError::PeerMisbehavedError("key exchange failed".to_string()))
+  );
+
+  let kse = KeyShareEntry::new(share.group, &kxr.pubkey);
+  extensions.push(ServerExtension::KeyShare(kse));
+  extensions.push(ServerExtension::SupportedVersions(ProtocolVersion::TLSv1_3));
+
+  let sh = Message {
+    typ: ContentType::Handshake,
+    version: ProtocolVersion::TLSv1_2,
+    payload: MessagePayload::Handshake(
+      HandshakeMessagePayload {
+        typ: HandshakeType::ServerHello,
+        payload: HandshakePayload::ServerHello(
+          ServerHelloPayload {
+            server_version: ProtocolVersion::TLSv1_2,
+            random: Random::from_slice(&sess.handshake_data.randoms.server),
+            session_id: session_id.clone(),
+            cipher_suite: sess.handshake_data.ciphersuite.unwrap().suite,
+            compression_method: Compression::Null,
+            extensions: extensions
+          }
+        )
+      }
+    )
+  };
+
+  debug!("sending server hello {:?}", sh);
+  sess.handshake_data.transcript.add_message(&sh);
+  sess.common.send_msg(sh, false);
+
+  /* Start key schedule, and encrypt everything from here on
+   * with the handshake traffic secrets. */
+  let suite = sess.handshake_data.ciphersuite.unwrap();
+  let mut key_schedule = KeySchedule::new(suite.get_hash());
+  key_schedule.input_empty();
+  key_schedule.input_secret(&kxr.premaster_secret);
+
+  let handshake_hash = sess.handshake_data.transcript.get_current_hash();
+  let write_key = key_schedule.derive(SecretKind::ServerHandshakeTrafficSecret, &handshake_hash);
+  let read_key = key_schedule.derive(SecretKind::ClientHandshakeTrafficSecret, &handshake_hash);
+  let client_random = &sess.handshake_data.randoms.client;
+  sess.config.key_log.log("SERVER_HANDSHAKE_TRAFFIC_SECRET", client_random, &write_key);
+  sess.config.key_log.log("CLIENT_HANDSHAKE_TRAFFIC_SECRET", client_random, &read_key);
+  sess.common.set_message_encrypter(cipher::new_tls13_write(suite, &write_key));
+  sess.common.set_message_decrypter(cipher::new_tls13_read(suite, &read_key));
+  key_schedule.current_server_traffic_secret = write_key;
+  key_schedule.current_client_traffic_secret = read_key;
+  sess.handshake_data.key_schedule = Some(key_schedule);
+
+  /* Certificate and friends look different from now on. */
+  sess.common.handshake_joiner.set_version(ProtocolVersion::TLSv1_3);
+  Ok(())
+}
+
+fn emit_encrypted_extensions(sess: &mut ServerSessionImpl,
+                             hello: &ClientHelloPayload) -> Result<(), TLSError> {
+  let encrypted_exts = try!(process_extensions(sess, hello));
+
+  let ee = Message {
+    typ: ContentType::Handshake,
+    version: ProtocolVersion::TLSv1_2,
+    payload: MessagePayload::Handshake(
+      HandshakeMessagePayload {
+        typ: HandshakeType::EncryptedExtensions,
+        payload: HandshakePayload::EncryptedExtensions(encrypted_exts)
+      }
+    )
+  };
+
+  debug!("sending encrypted extensions {:?}", ee);
+  sess.handshake_data.transcript.add_message(&ee);
+  sess.common.send_msg(ee, true);
+  Ok(())
+}
+
+fn emit_certificate_req_tls13(sess: &mut ServerSessionImpl) {
+  if !sess.config.client_cert_verifier.offer_client_auth() {
+    return;
+  }
+
+  let mut cr = CertificateRequestPayloadTLS13 {
+    context: PayloadU8::new(Vec::new()),
+    extensions: Vec::new()
+  };
+
+  let schemes = SupportedSignatureAlgorithms::supported_verify();
+  cr.extensions.push(CertReqExtension::SignatureAlgorithms(schemes));
+
+  /* This extension must not be empty if present. */
+  let names = sess.config.client_cert_verifier.client_auth_root_subjects();
+  if !names.is_empty() {
+    cr.extensions.push(CertReqExtension::AuthorityNames(names));
+  }
+
+  let m = Message {
+    typ: ContentType::Handshake,
+    version: ProtocolVersion::TLSv1_2,
+    payload: MessagePayload::Handshake(
+      HandshakeMessagePayload {
+        typ: HandshakeType::CertificateRequest,
+        payload: HandshakePayload::CertificateRequestTLS13(cr)
+      }
+    )
+  };
+
+  debug!("Sending CertificateRequest {:?}", m);
+  sess.handshake_data.transcript.add_message(&m);
+  sess.common.send_msg(m, true);
+  sess.handshake_data.doing_client_auth = true;
+}
+
+fn emit_certificate_tls13(sess: &mut ServerSessionImpl) {
+  let mut cert_body = CertificatePayloadTLS13::new(
+    sess.handshake_data.server_cert_chain.as_ref().unwrap()
+  );
+
+  /* Staple any OCSP response on the end-entity certificate. */
+  if let Some(ocsp) = sess.handshake_data.ocsp_response.take() {
+    let cst = CertificateStatus::new(ocsp);
+    cert_body.list[0].exts.push(CertificateExtension::CertificateStatus(cst));
+  }
+
+  /* And any SCTs. */
+  if let Some(sct_list) = sess.handshake_data.sct_list.take() {
+    cert_body.list[0].exts.push(CertificateExtension::SignedCertificateTimestamp(sct_list));
+  }
+
+  let c = Message {
+    typ: ContentType::Handshake,
+    version: ProtocolVersion::TLSv1_2,
+    payload: MessagePayload::Handshake(
+      HandshakeMessagePayload {
+        typ: HandshakeType::Certificate,
+        payload: HandshakePayload::CertificateTLS13(cert_body)
+      }
+    )
+  };
+
+  debug!("sending certificate {:?}", c);
+  sess.handshake_data.transcript.add_message(&c);
+  sess.common.send_msg(c, true);
+}
+
+fn emit_certificate_verify_tls13(sess: &mut ServerSessionImpl,
+                                 signer: Arc<Box<sign::Signer + Send + Sync>>,
+                                 scheme: &SignatureAndHashAlgorithm) -> Result<(), TLSError> {
+  let message = verify::construct_tls13_verify_message(
+    &sess.handshake_data.transcript.get_current_hash(),
+    b"TLS 1.3, server CertificateVerify\x00"
+  );
+
+  let sig = try!(
+    signer.sign(scheme, &message)
+      .map_err(|_| TLSError::General("cannot sign".to_string()))
+  );
+
+  let cv = DigitallySignedStruct::new(scheme, sig);
+
+  let m = Message {
+    typ: ContentType::Handshake,
+    version: ProtocolVersion::TLSv1_2,
+    payload: MessagePayload::Handshake(
+      HandshakeMessagePayload {
+        typ: HandshakeType::CertificateVerify,
+        payload: HandshakePayload::CertificateVerify(cv)
+      }
+    )
+  };
+
+  debug!("sending certificate-verify {:?}", m);
+  sess.handshake_data.transcript.add_message(&m);
+  sess.common.send_msg(m, true);
+  Ok(())
+}
+
+fn emit_finished_tls13(sess: &mut ServerSessionImpl) {
+  let handshake_hash = sess.handshake_data.transcript.get_current_hash();
+  let verify_data = sess.handshake_data.key_schedule
+    .as_ref()
+    .unwrap()
+    .sign_finish(SecretKind::ServerHandshakeTrafficSecret, &handshake_hash);
+  let verify_data_payload = Payload::new(verify_data);
+
+  let m = Message {
+    typ: ContentType::Handshake,
+    version: ProtocolVersion::TLSv1_2,
+    payload: MessagePayload::Handshake(
+      HandshakeMessagePayload {
+        typ: HandshakeType::Finished,
+        payload: HandshakePayload::Finished(verify_data_payload)
+      }
+    )
+  };
+
+  debug!("sending finished {:?}", m);
+  sess.handshake_data.transcript.add_message(&m);
+  sess.common.send_msg(m, true);
+
+  /* Now move to application data keys for our traffic.  The
+   * client's handshake traffic secret is still needed to check
+   * its Finished, and its application traffic secret is derived
+   * from the transcript as it is now. */
+  let suite = sess.handshake_data.ciphersuite.unwrap();
+  let handshake_hash = sess.handshake_data.transcript.get_current_hash();
+  let key_schedule = sess.handshake_data.key_schedule.as_mut().unwrap();
+  key_schedule.input_empty();
+  let write_key = key_schedule.derive(SecretKind::ServerApplicationTrafficSecret, &handshake_hash);
+  key_schedule.current_exporter_secret = key_schedule.derive(SecretKind::ExporterMasterSecret,
+                                                             &handshake_hash);
+
+  let client_random = &sess.handshake_data.randoms.client;
+  sess.config.key_log.log("SERVER_TRAFFIC_SECRET_0", client_random, &write_key);
+  sess.config.key_log.log("EXPORTER_SECRET", client_random, &key_schedule.current_exporter_secret);
+
+  sess.common.set_message_encrypter(cipher::new_tls13_write(suite, &write_key));
+  key_schedule.current_server_traffic_secret = write_key;
+  sess.handshake_data.hash_at_server_fin = handshake_hash;
+}
+
+fn handle_client_hello_tls13(sess: &mut ServerSessionImpl,
+                             m: &Message,
+                             client_hello: &ClientHelloPayload,
+                             sigalgs_ext: &SupportedSignatureAlgorithms,
+                             eccurves_ext: &EllipticCurveList,
+                             signer: Arc<Box<sign::Signer + Send + Sync>>) -> Result<ConnState, TLSError> {
+  /* Start handshake hash, unless we're continuing one after
+   * a retry. */
+  if !sess.handshake_data.done_retry {
+    sess.handshake_data.start_handshake_hash();
+
+    /* TLS1.3 client auth signs the handshake hash, so we needn't
+     * keep the whole transcript around. */
+    sess.handshake_data.transcript.abandon_client_auth();
+  }
+  sess.handshake_data.transcript.add_message(m);
+
+  let sigscheme = try!(
+    signer.choose_scheme_tls13(sigalgs_ext)
+      .ok_or_else(|| incompatible(sess, "no overlapping sigschemes"))
+  );
+
+  let shares_ext = try!(
+    client_hello.get_keyshare_extension()
+      .ok_or_else(|| {
+        sess.common.send_fatal_alert(AlertDescription::MissingExtension);
+        TLSError::PeerIncompatibleError("client didn't send keyshares".to_string())
+      })
+  );
+
+  if client_hello.has_keyshare_extension_with_duplicates() {
+    sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+    return Err(TLSError::PeerMisbehavedError("client sent duplicate keyshares".to_string()));
+  }
+
+  /* Choose the first of our groups for which the client sent
+   * a share. */
+  let our_groups = EllipticCurveList::supported();
+  let chosen_share = our_groups.iter()
+    .filter_map(|group| shares_ext.iter().find(|share| share.group == *group))
+    .next();
+
+  if chosen_share.is_none() {
+    /* We don't have a suitable key share.  Choose a suitable group and
+     * send a HelloRetryRequest. */
+    let retry_group_maybe = util::first_in_both(our_groups.as_slice(),
+                                                eccurves_ext.as_slice());
+
+    if let Some(group) = retry_group_maybe {
+      if sess.handshake_data.done_retry {
+        sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+        return Err(TLSError::PeerMisbehavedError("did not follow retry request".to_string()));
+      }
+
+      emit_hello_retry_request(sess, &client_hello.session_id, group);
+      return Ok(ConnState::ExpectClientHello);
+    }
+
+    return Err(incompatible(sess, "no kx group overlap with client"));
+  }
+
+  try!(emit_server_hello_tls13(sess, &client_hello.session_id, chosen_share.unwrap()));
+  try!(emit_encrypted_extensions(sess, client_hello));
+  emit_certificate_req_tls13(sess);
+  emit_certificate_tls13(sess);
+  try!(emit_certificate_verify_tls13(sess, signer, &sigscheme));
+  emit_finished_tls13(sess);
+
+  if sess.handshake_data.doing_client_auth {
+    Ok(ConnState::ExpectCertificateTLS13)
+  } else {
+    Ok(ConnState::ExpectFinishedTLS13)
+  }
+}
+
 fn handle_client_hello(sess: &mut ServerSessionImpl, m: Message) -> Result<ConnState, TLSError> {
   let client_hello = extract_handshake!(m, HandshakePayload::ClientHello).unwrap();
 
+  let tls13_enabled = sess.config.supports_version(ProtocolVersion::TLSv1_3);
+  let tls12_enabled = sess.config.supports_version(ProtocolVersion::TLSv1_2);
+
   if client_hello.client_version.get_u16() < ProtocolVersion::TLSv1_2.get_u16() {
     sess.common.send_fatal_alert(AlertDescription::ProtocolVersion);
     return Err(TLSError::PeerIncompatibleError("client does not support TLSv1_2".to_string()));
   }
 
+  /* Are we doing TLS1.3? */
+  let version = if let Some(versions) = client_hello.get_versions_extension() {
+    if versions.contains(&ProtocolVersion::TLSv1_3) && tls13_enabled {
+      ProtocolVersion::TLSv1_3
+    } else if !versions.contains(&ProtocolVersion::TLSv1_2) || !tls12_enabled {
+      sess.common.send_fatal_alert(AlertDescription::ProtocolVersion);
+      return Err(TLSError::PeerIncompatibleError("TLS1.2 not offered/enabled".to_string()));
+    } else {
+      ProtocolVersion::TLSv1_2
+    }
+  } else if !tls12_enabled {
+    sess.common.send_fatal_alert(AlertDescription::ProtocolVersion);
+    return Err(TLSError::PeerIncompatibleError("server requires TLS1.3, but client omitted versions ext".to_string()));
+  } else {
+    ProtocolVersion::TLSv1_2
+  };
+
+  if sess.handshake_data.done_retry && version != ProtocolVersion::TLSv1_3 {
+    sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+    return Err(TLSError::PeerMisbehavedError("client abandoned TLS1.3 after retry".to_string()));
+  }
+
+  sess.common.negotiated_version = Some(version);
+
   if !client_hello.compression_methods.contains(&Compression::Null) {
     sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
     return Err(TLSError::PeerIncompatibleError("client did not offer Null compression".to_string()));
@@ -298,14 +732,22 @@ fn handle_client_hello(sess: &mut ServerSessionImpl, m: Message) -> Result<ConnS
   client_hello.random.write_slice(&mut sess.handshake_data.randoms.client);
 
   let default_sigalgs_ext = SupportedSignatureAlgorithms::default();
+  let default_ecpoints_ext = ECPointFormatList::supported();
 
   let sni_ext = client_hello.get_sni_extension();
   let sigalgs_ext = client_hello.get_sigalgs_extension()
     .unwrap_or(&default_sigalgs_ext);
   let eccurves_ext = try!(client_hello.get_eccurves_extension()
                           .ok_or_else(|| incompatible(sess, "client didn't describe ec curves")));
-  let ecpoints_ext = try!(client_hello.get_ecpoints_extension()
-                          .ok_or_else(|| incompatible(sess, "client didn't describe ec points")));
+
+  /* TLS1.3 clients needn't send this. */
+  let ecpoints_ext = if sess.common.is_tls13() {
+    client_hello.get_ecpoints_extension()
+      .unwrap_or(&default_ecpoints_ext)
+  } else {
+    try!(client_hello.get_ecpoints_extension()
+         .ok_or_else(|| incompatible(sess, "client didn't describe ec points")))
+  };
 
   debug!("we got a clienthello {:?}", client_hello);
   debug!("sni {:?}", sni_ext);
@@ -318,18 +760,53 @@ fn handle_client_hello(sess: &mut ServerSessionImpl, m: Message) -> Result<ConnS
     return Err(TLSError::PeerIncompatibleError("client didn't support uncompressed ec points".to_string()));
   }
 
-  /* Choose a certificate. */
-  let maybe_cert_key = sess.config.cert_resolver.resolve(sni_ext, sigalgs_ext, eccurves_ext, ecpoints_ext);
+  /* Choose a certificate, given the suites we could use with
+   * this client. */
+  let ciphersuites_suitable_for_version = suites::reduce_given_version(&sess.config.ciphersuites,
+                                                                       version);
+  let ciphersuites_offered: Vec<&'static SupportedCipherSuite> = ciphersuites_suitable_for_version.iter()
+    .filter(|scs| client_hello.cipher_suites.contains(&scs.suite))
+    .cloned()
+    .collect();
+  let maybe_cert_key = sess.config.cert_resolver.resolve(sni_ext, sigalgs_ext, eccurves_ext, ecpoints_ext,
+                                                         &ciphersuites_offered, version);
   if maybe_cert_key.is_err() {
-    sess.common.send_fatal_alert(AlertDescription::AccessDenied);
+    /* If the client asked for a name, tell it we don't have it. */
+    if sni_ext.is_some() {
+      sess.common.send_fatal_alert(AlertDescription::UnrecognisedName);
+    } else {
+      sess.common.send_fatal_alert(AlertDescription::AccessDenied);
+    }
     return Err(TLSError::General("no server certificate chain resolved".to_string()));
   }
-  let (cert_chain, private_key) = maybe_cert_key.unwrap();
+  let certkey = maybe_cert_key.unwrap();
+  let private_key = certkey.key;
+
+  /* Only staple an OCSP response if the client asked for one. */
+  sess.handshake_data.ocsp_response = if client_hello.ocsp_stapling_requested() {
+    certkey.ocsp
+  } else {
+    None
+  };
+
+  /* Likewise SCTs. */
+  sess.handshake_data.sct_list = if client_hello.sct_requested() {
+    certkey.sct_list
+      .and_then(|scts| SCTList::read_bytes(&scts))
+  } else {
+    None
+  };
 
-  /* Reduce our supported ciphersuites by the certificate. */
-  let ciphersuites_suitable_for_cert = suites::reduce_given_sigalg(&sess.config.ciphersuites,
-                                                                   &private_key.algorithm());
-  sess.handshake_data.server_cert_chain = Some(cert_chain);
+  /* Reduce our supported ciphersuites by the version and, for
+   * TLS1.2, by the certificate.  TLS1.3 suites don't depend on
+   * the certificate type. */
+  let ciphersuites_suitable_for_cert = if sess.common.is_tls13() {
+    ciphersuites_suitable_for_version
+  } else {
+    suites::reduce_given_sigalg(&ciphersuites_suitable_for_version,
+                                &private_key.algorithm())
+  };
+  sess.handshake_data.server_cert_chain = Some(certkey.cert);
 
   let maybe_ciphersuite = if sess.config.ignore_client_order {
     suites::choose_ciphersuite_preferring_server(&client_hello.cipher_suites,
@@ -343,9 +820,29 @@ fn handle_client_hello(sess: &mut ServerSessionImpl, m: Message) -> Result<ConnS
     return Err(incompatible(sess, "no ciphersuites in common"));
   }
 
+  if sess.handshake_data.done_retry && sess.handshake_data.ciphersuite != maybe_ciphersuite {
+    sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+    return Err(TLSError::PeerMisbehavedError("client varied ciphersuite over retry".to_string()));
+  }
+
   sess.handshake_data.ciphersuite = maybe_ciphersuite;
   info!("decided upon suite {:?}", maybe_ciphersuite.as_ref().unwrap());
 
+  if sess.common.is_tls13() {
+    return handle_client_hello_tls13(sess, &m, client_hello,
+                                     sigalgs_ext, eccurves_ext, private_key);
+  }
+
+  if sess.config.require_ems && !client_hello.ems_support_offered() {
+    return Err(incompatible(sess, "client does not support extended master secret"));
+  }
+
+  /* We support TLS1.3, so must tell TLS1.3 clients that this
+   * is a downgrade. */
+  if tls13_enabled {
+    sess.handshake_data.randoms.server[24..].clone_from_slice(DOWNGRADE_SENTINEL);
+  }
+
   /* Start handshake hash. */
   sess.handshake_data.start_handshake_hash();
   sess.handshake_data.transcript.add_message(&m);
@@ -373,13 +870,13 @@ fn handle_client_hello(sess: &mut ServerSessionImpl, m: Message) -> Result<ConnS
         let maybe_resume = sess.config.ticketer.decrypt(&ticket.0)
           .and_then(|plain| persist::ServerSessionValue::read_bytes(&plain));
 
-        if maybe_resume.is_some() {
+        if can_resume(client_hello, &maybe_resume) {
           return start_resumption(sess,
                                   client_hello,
                                   &client_hello.session_id,
                                   maybe_resume.unwrap());
         } else {
-          info!("Ticket didn't decrypt");
+          info!("Ticket didn't decrypt, or cannot be resumed");
         }
       }
 
@@ -396,7 +893,7 @@ fn handle_client_hello(sess: &mut ServerSessionImpl, m: Message) -> Result<ConnS
       persist.get(&client_hello.session_id)
     }.and_then(|x| persist::ServerSessionValue::read_bytes(&x));
 
-    if maybe_resume.is_some() {
+    if can_resume(client_hello, &maybe_resume) {
       return start_resumption(sess,
                               client_hello,
                               &client_hello.session_id,
@@ -406,8 +903,7 @@ fn handle_client_hello(sess: &mut ServerSessionImpl, m: Message) -> Result<ConnS
 
   /* Now we have chosen a ciphersuite, we can make kx decisions. */
   let sigalg = try!(
-    sess.handshake_data.ciphersuite.as_ref().unwrap()
-      .resolve_sig_alg(sigalgs_ext)
+    private_key.choose_scheme_tls12(sigalgs_ext)
       .ok_or_else(|| incompatible(sess, "no supported sigalg"))
   );
   let eccurve = try!(
@@ -425,6 +921,7 @@ fn handle_client_hello(sess: &mut ServerSessionImpl, m: Message) -> Result<ConnS
 
   try!(emit_server_hello(sess, client_hello));
   emit_certificate(sess);
+  emit_cert_status(sess);
   try!(emit_server_kx(sess, &sigalg, &eccurve, private_key));
   emit_certificate_req(sess);
   emit_server_hello_done(sess);
@@ -449,18 +946,21 @@ fn handle_certificate(sess: &mut ServerSessionImpl, m: Message) -> Result<ConnSt
   sess.handshake_data.transcript.add_message(&m);
   let cert_chain = extract_handshake!(m, HandshakePayload::Certificate).unwrap();
 
-  if cert_chain.is_empty() && !sess.config.client_auth_mandatory {
-    info!("client auth requested but no certificate supplied");
-    sess.handshake_data.doing_client_auth = false;
-    sess.handshake_data.transcript.abandon_client_auth();
-    return Ok(ConnState::ExpectClientKX);
+  if cert_chain.is_empty() {
+    if !sess.config.client_cert_verifier.client_auth_mandatory() {
+      info!("client auth requested but no certificate supplied");
+      sess.handshake_data.doing_client_auth = false;
+      sess.handshake_data.transcript.abandon_client_auth();
+      return Ok(ConnState::ExpectClientKX);
+    }
+
+    return Err(TLSError::NoCertificatesPresented);
   }
 
   debug!("certs {:?}", cert_chain);
 
   try!(
-    verify::verify_client_cert(&sess.config.client_auth_roots,
-                               &cert_chain)
+    sess.config.client_cert_verifier.verify_client_cert(&cert_chain)
   );
 
   sess.handshake_data.valid_client_cert_chain = Some(cert_chain.clone());
@@ -489,9 +989,17 @@ fn handle_client_kx(sess: &mut ServerSessionImpl, m: Message) -> Result<ConnStat
   );
 
   let hashalg = sess.handshake_data.ciphersuite.as_ref().unwrap().get_hash();
-  sess.secrets = Some(SessionSecrets::new(&sess.handshake_data.randoms,
-                                          hashalg,
-                                          &kxd.premaster_secret));
+  if sess.handshake_data.using_ems {
+    let handshake_hash = sess.handshake_data.transcript.get_current_hash();
+    sess.secrets = Some(SessionSecrets::new_ems(&sess.handshake_data.randoms,
+                                                &handshake_hash,
+                                                hashalg,
+                                                &kxd.premaster_secret));
+  } else {
+    sess.secrets = Some(SessionSecrets::new(&sess.handshake_data.randoms,
+                                            hashalg,
+                                            &kxd.premaster_secret));
+  }
   sess.start_encryption();
 
   if sess.handshake_data.doing_client_auth {
@@ -601,9 +1109,18 @@ fn emit_ccs(sess: &mut ServerSessionImpl) {
   sess.common.we_now_encrypting();
 }
 
+/// Remember `verify_data` if it's from the first Finished
+/// message of the handshake: this is tls-unique (RFC5929).
+fn note_finished(sess: &mut ServerSessionImpl, verify_data: &[u8]) {
+  if sess.handshake_data.tls_unique.is_empty() {
+    sess.handshake_data.tls_unique = verify_data.to_vec();
+  }
+}
+
 fn emit_finished(sess: &mut ServerSessionImpl) {
   let vh = sess.handshake_data.transcript.get_current_hash();
   let verify_data = sess.secrets.as_ref().unwrap().server_verify_data(&vh);
+  note_finished(sess, &verify_data);
   let verify_data_payload = Payload::new(verify_data);
 
   let f = Message {
@@ -627,6 +1144,7 @@ fn get_server_session_value(sess: &ServerSessionImpl) -> persist::ServerSessionV
 
   persist::ServerSessionValue::new(&scs.suite,
                                    sess.secrets.as_ref().unwrap().get_master_secret(),
+                                   sess.handshake_data.using_ems,
                                    client_certs)
 }
 
@@ -636,11 +1154,11 @@ fn handle_finished(sess: &mut ServerSessionImpl, m: Message) -> Result<ConnState
   let vh = sess.handshake_data.transcript.get_current_hash();
   let expect_verify_data = sess.secrets.as_ref().unwrap().client_verify_data(&vh);
 
-  use ring;
   try!(
     ring::constant_time::verify_slices_are_equal(&expect_verify_data, &finished.0)
       .map_err(|_| { error!("Finished wrong"); TLSError::DecryptError })
   );
+  note_finished(sess, &finished.0);
 
   /* Save session, perhaps */
   if !sess.handshake_data.doing_resume && !sess.handshake_data.session_id.is_empty() {
@@ -685,3 +1203,190 @@ pub static TRAFFIC: Handler = Handler {
   },
   handle: handle_traffic
 };
+
+/* --- Process client's Certificate for client auth (TLS1.3) --- */
+fn handle_certificate_tls13(sess: &mut ServerSessionImpl, m: Message) -> Result<ConnState, TLSError> {
+  let certp = extract_handshake!(m, HandshakePayload::CertificateTLS13).unwrap();
+  sess.handshake_data.transcript.add_message(&m);
+
+  /* We sent an empty context. */
+  if !certp.context.0.is_empty() {
+    warn!("certificate with non-empty context during handshake");
+    sess.common.send_fatal_alert(AlertDescription::DecodeError);
+    return Err(TLSError::CorruptMessagePayload(ContentType::Handshake));
+  }
+
+  /* We don't ask for any certificate extensions, so the client
+   * can't send any. */
+  if certp.any_entry_has_duplicate_extension() ||
+    certp.any_entry_has_unknown_extension() {
+    warn!("client certificate chain contains unsolicited/unknown extension");
+    sess.common.send_fatal_alert(AlertDescription::UnsupportedExtension);
+    return Err(TLSError::PeerMisbehavedError("bad client cert chain extensions".to_string()));
+  }
+
+  let cert_chain = certp.convert();
+
+  if cert_chain.is_empty() {
+    if !sess.config.client_cert_verifier.client_auth_mandatory() {
+      info!("client auth requested but no certificate supplied");
+      sess.handshake_data.doing_client_auth = false;
+      return Ok(ConnState::ExpectFinishedTLS13);
+    }
+
+    sess.common.send_fatal_alert(AlertDescription::CertificateRequired);
+    return Err(TLSError::NoCertificatesPresented);
+  }
+
+  debug!("certs {:?}", cert_chain);
+
+  try!(
+    sess.config.client_cert_verifier.verify_client_cert(&cert_chain)
+  );
+
+  sess.handshake_data.valid_client_cert_chain = Some(cert_chain);
+  Ok(ConnState::ExpectCertificateVerifyTLS13)
+}
+
+pub static EXPECT_CERTIFICATE_TLS13: Handler = Handler {
+  expect: Expectation {
+    content_types: &[ContentType::Handshake],
+    handshake_types: &[HandshakeType::Certificate]
+  },
+  handle: handle_certificate_tls13
+};
+
+/* --- Process client's certificate proof (TLS1.3) --- */
+fn handle_certificate_verify_tls13(sess: &mut ServerSessionImpl, m: Message) -> Result<ConnState, TLSError> {
+  let rc = {
+    let sig = extract_handshake!(m, HandshakePayload::CertificateVerify).unwrap();
+    let certs = sess.handshake_data.valid_client_cert_chain.as_ref().unwrap();
+    let handshake_hash = sess.handshake_data.transcript.get_current_hash();
+
+    verify::verify_tls13(&certs[0],
+                         sig,
+                         &handshake_hash,
+                         b"TLS 1.3, client CertificateVerify\x00")
+  };
+
+  if rc.is_err() {
+    sess.common.send_fatal_alert(AlertDescription::AccessDenied);
+    return Err(rc.unwrap_err());
+  } else {
+    debug!("client CertificateVerify OK");
+  }
+
+  sess.handshake_data.transcript.add_message(&m);
+  Ok(ConnState::ExpectFinishedTLS13)
+}
+
+pub static EXPECT_CERTIFICATE_VERIFY_TLS13: Handler = Handler {
+  expect: Expectation {
+    content_types: &[ContentType::Handshake],
+    handshake_types: &[HandshakeType::CertificateVerify]
+  },
+  handle: handle_certificate_verify_tls13
+};
+
+/* --- Process client's Finished (TLS1.3) --- */
+fn handle_finished_tls13(sess: &mut ServerSessionImpl, m: Message) -> Result<ConnState, TLSError> {
+  let finished = extract_handshake!(m, HandshakePayload::Finished).unwrap();
+
+  let handshake_hash = sess.handshake_data.transcript.get_current_hash();
+  let expect_verify_data = sess.handshake_data.key_schedule
+    .as_ref()
+    .unwrap()
+    .sign_finish(SecretKind::ClientHandshakeTrafficSecret, &handshake_hash);
+
+  try!(
+    ring::constant_time::verify_slices_are_equal(&expect_verify_data, &finished.0)
+      .map_err(|_| {
+        sess.common.send_fatal_alert(AlertDescription::DecryptError);
+        warn!("Finished wrong");
+        TLSError::DecryptError
+      })
+  );
+
+  sess.handshake_data.transcript.add_message(&m);
+
+  /* Now move to using application data keys for client traffic.
+   * Our traffic moved over when we sent our Finished. */
+  let suite = sess.handshake_data.ciphersuite.unwrap();
+  let key_schedule = sess.handshake_data.key_schedule.as_mut().unwrap();
+  let read_key = key_schedule.derive(SecretKind::ClientApplicationTrafficSecret,
+                                     &sess.handshake_data.hash_at_server_fin);
+  sess.config.key_log.log("CLIENT_TRAFFIC_SECRET_0",
+                          &sess.handshake_data.randoms.client,
+                          &read_key);
+  sess.common.set_message_decrypter(cipher::new_tls13_read(suite, &read_key));
+  key_schedule.current_client_traffic_secret = read_key;
+
+  Ok(ConnState::TrafficTLS13)
+}
+
+pub static EXPECT_FINISHED_TLS13: Handler = Handler {
+  expect: Expectation {
+    content_types: &[ContentType::Handshake],
+    handshake_types: &[HandshakeType::Finished]
+  },
+  handle: handle_finished_tls13
+};
+
+/* --- Process traffic (TLS1.3) --- */
+fn send_key_update_notify(sess: &mut ServerSessionImpl) {
+  let m = Message {
+    typ: ContentType::Handshake,
+    version: ProtocolVersion::TLSv1_2,
+    payload: MessagePayload::Handshake(HandshakeMessagePayload::build_key_update_notify())
+  };
+
+  /* The notification goes under the old key, and we move to the
+   * new one immediately afterwards. */
+  sess.common.send_msg(m, true);
+
+  let suite = sess.handshake_data.ciphersuite.unwrap();
+  let key_schedule = sess.handshake_data.key_schedule.as_mut().unwrap();
+  let write_key = key_schedule.derive_next(SecretKind::ServerApplicationTrafficSecret);
+  sess.common.set_message_encrypter(cipher::new_tls13_write(suite, &write_key));
+  key_schedule.current_server_traffic_secret = write_key;
+}
+
+fn handle_key_update(sess: &mut ServerSessionImpl, m: Message) -> Result<(), TLSError> {
+  let kur = extract_handshake!(m, HandshakePayload::KeyUpdate).unwrap();
+
+  match *kur {
+    KeyUpdateRequest::UpdateNotRequested => {},
+    KeyUpdateRequest::UpdateRequested => send_key_update_notify(sess),
+    _ => {
+      sess.common.send_fatal_alert(AlertDescription::IllegalParameter);
+      return Err(TLSError::CorruptMessagePayload(ContentType::Handshake));
+    }
+  }
+
+  /* Update our read-side keys. */
+  let suite = sess.handshake_data.ciphersuite.unwrap();
+  let key_schedule = sess.handshake_data.key_schedule.as_mut().unwrap();
+  let read_key = key_schedule.derive_next(SecretKind::ClientApplicationTrafficSecret);
+  sess.common.set_message_decrypter(cipher::new_tls13_read(suite, &read_key));
+  key_schedule.current_client_traffic_secret = read_key;
+
+  Ok(())
+}
+
+fn handle_traffic_tls13(sess: &mut ServerSessionImpl, m: Message) -> Result<ConnState, TLSError> {
+  if m.is_content_type(ContentType::ApplicationData) {
+    try!(handle_traffic(sess, m));
+  } else if m.is_handshake_type(HandshakeType::KeyUpdate) {
+    try!(handle_key_update(sess, m));
+  }
+
+  Ok(ConnState::TrafficTLS13)
+}
+
+pub static TRAFFIC_TLS13: Handler = Handler {
+  expect: Expectation {
+    content_types: &[ContentType::ApplicationData, ContentType::Handshake],
+    handshake_types: &[HandshakeType::KeyUpdate]
+  },
+  handle: handle_traffic_tls13
+};
@@ -1,20 +1,28 @@
 use ring;
+use ring::digest;
 use std::io::{Read, Write};
 use msgs::message::{Message, MessagePayload};
 use msgs::deframer::MessageDeframer;
 use msgs::fragmenter::{MessageFragmenter, MAX_FRAGMENT_LEN};
 use msgs::hsjoiner::HandshakeJoiner;
-use msgs::base::Payload;
+use msgs::base::{Payload, PayloadU8};
+use msgs::codec;
+use msgs::persist;
+use msgs::handshake::ASN1Cert;
 use msgs::codec::Codec;
 use msgs::enums::{ContentType, ProtocolVersion, AlertDescription, AlertLevel};
 use error::TLSError;
 use suites::SupportedCipherSuite;
-use cipher::MessageCipher;
+use cipher::{MessageDecrypter, MessageEncrypter};
+use cipher;
 use vecbuf::ChunkVecBuffer;
 use prf;
+use key_schedule::{KeySchedule, derive_traffic_key, derive_traffic_iv};
+use x509;
 use rand;
 
 use std::io;
+use std::fmt;
 use std::collections::VecDeque;
 
 /// Generalises ClientSession and ServerSession
@@ -75,6 +83,70 @@ pub trait Session : Read + Write + Send {
   /// means no protocol was agreed (because no protocols
   /// were offered or accepted by the peer).
   fn get_alpn_protocol(&self) -> Option<String>;
+
+  /// Retrieves the protocol version agreed with the peer.
+  ///
+  /// This returns None until the version is agreed.
+  fn get_protocol_version(&self) -> Option<ProtocolVersion>;
+
+  /// Derives `len` bytes of keying material from this session,
+  /// as described in RFC5705 for TLS1.2 and RFC8446 for TLS1.3.
+  /// Both ends get the same material given the same `label`
+  /// and `context`.
+  ///
+  /// In TLS1.2 a `context` of `None` is different from an empty
+  /// one; in TLS1.3 they are the same.  In TLS1.3 `len` is at most
+  /// 255 times the length of the ciphersuite's hash.
+  ///
+  /// This fails with `TLSError::HandshakeNotComplete` until the
+  /// handshake is complete.
+  fn export_keying_material(&self,
+                            label: &[u8],
+                            context: Option<&[u8]>,
+                            len: usize) -> Result<Vec<u8>, TLSError>;
+
+  /// Retrieves the tls-unique channel binding (RFC5929): the
+  /// verify_data of the first Finished message of the handshake.
+  ///
+  /// This is None until the handshake is complete, and for TLS1.3,
+  /// where tls-unique is not defined.
+  fn get_tls_unique(&self) -> Option<Vec<u8>>;
+
+  /// Retrieves the tls-server-end-point channel binding (RFC5929):
+  /// a hash of the server's end-entity certificate, using the hash
+  /// from the certificate's signature algorithm (or SHA-256, if
+  /// that is MD5 or SHA-1).
+  ///
+  /// This is None until the handshake is complete, if the
+  /// certificate's signature algorithm doesn't name a single hash
+  /// function (like Ed25519), and for clients resuming a TLS1.2
+  /// session, which don't see the server's certificate.
+  fn get_tls_server_end_point(&self) -> Option<Vec<u8>>;
+}
+
+/// Signature algorithm OIDs, and the hash tls-server-end-point
+/// uses for certificates signed with them.  MD5 and SHA-1 are
+/// replaced by SHA-256.
+static END_POINT_HASHES: &'static [(&'static [u8], &'static digest::Algorithm)] = &[
+  (b"\x2a\x86\x48\x86\xf7\x0d\x01\x01\x04", &digest::SHA256), /* md5WithRSAEncryption */
+  (b"\x2a\x86\x48\x86\xf7\x0d\x01\x01\x05", &digest::SHA256), /* sha1WithRSAEncryption */
+  (b"\x2a\x86\x48\x86\xf7\x0d\x01\x01\x0b", &digest::SHA256), /* sha256WithRSAEncryption */
+  (b"\x2a\x86\x48\x86\xf7\x0d\x01\x01\x0c", &digest::SHA384), /* sha384WithRSAEncryption */
+  (b"\x2a\x86\x48\x86\xf7\x0d\x01\x01\x0d", &digest::SHA512), /* sha512WithRSAEncryption */
+  (b"\x2a\x86\x48\xce\x3d\x04\x01", &digest::SHA256), /* ecdsa-with-SHA1 */
+  (b"\x2a\x86\x48\xce\x3d\x04\x03\x02", &digest::SHA256), /* ecdsa-with-SHA256 */
+  (b"\x2a\x86\x48\xce\x3d\x04\x03\x03", &digest::SHA384), /* ecdsa-with-SHA384 */
+  (b"\x2a\x86\x48\xce\x3d\x04\x03\x04", &digest::SHA512), /* ecdsa-with-SHA512 */
+];
+
+/// Compute the tls-server-end-point channel binding for the
+/// server certificate `cert`.
+pub fn tls_server_end_point(cert: &ASN1Cert) -> Option<Vec<u8>> {
+  let fields = try_ret!(x509::parse_certificate(&cert.0));
+  let oid = try_ret!(x509::DerReader::new(fields.signature_alg).read(x509::OID));
+
+  let &(_, hash) = try_ret!(END_POINT_HASHES.iter().find(|&&(alg, _)| alg == oid));
+  Some(digest::digest(hash, &cert.0).as_ref().to_vec())
 }
 
 #[derive(Clone, Debug)]
@@ -115,6 +187,16 @@ fn join_randoms(first: &[u8], second: &[u8]) -> [u8; 64] {
   randoms
 }
 
+/// Exporter labels which RFC5705 reserves, because TLS uses
+/// them with the PRF.
+static RESERVED_EXPORTER_LABELS: &'static [&'static [u8]] = &[
+  b"client finished",
+  b"server finished",
+  b"master secret",
+  b"key expansion",
+  b"extended master secret"
+];
+
 pub struct SessionSecrets {
   pub randoms: SessionRandoms,
   hash: &'static ring::digest::Algorithm,
@@ -140,6 +222,27 @@ impl SessionSecrets {
     ret
   }
 
+  /// Derive the master secret as described in RFC7627, binding
+  /// it to `hs_hash`: the handshake hash up to and including the
+  /// ClientKeyExchange.
+  pub fn new_ems(randoms: &SessionRandoms,
+                 hs_hash: &[u8],
+                 hashalg: &'static ring::digest::Algorithm,
+                 pms: &[u8]) -> SessionSecrets {
+    let mut ret = SessionSecrets {
+      randoms: randoms.clone(),
+      hash: hashalg,
+      master_secret: [0u8; 48]
+    };
+
+    prf::prf(&mut ret.master_secret,
+             ret.hash,
+             pms,
+             b"extended master secret",
+             hs_hash);
+    ret
+  }
+
   pub fn new_resume(randoms: &SessionRandoms,
                     hashalg: &'static ring::digest::Algorithm,
                     master_secret: &[u8]) -> SessionSecrets {
@@ -193,6 +296,307 @@ impl SessionSecrets {
   pub fn server_verify_data(&self, handshake_hash: &Vec<u8>) -> Vec<u8> {
     self.make_verify_data(handshake_hash, b"server finished")
   }
+
+  /// Fill `output` with keying material exported as described
+  /// in RFC5705.  A `context` of `None` is different from an
+  /// empty one.  The labels TLS itself uses with the PRF are
+  /// refused.
+  pub fn export_keying_material(&self,
+                                output: &mut [u8],
+                                label: &[u8],
+                                context: Option<&[u8]>) -> Result<(), TLSError> {
+    if RESERVED_EXPORTER_LABELS.contains(&label) {
+      return Err(TLSError::General("exporter label is reserved".to_string()));
+    }
+
+    let mut seed = Vec::new();
+    seed.extend_from_slice(&self.randoms.client);
+    seed.extend_from_slice(&self.randoms.server);
+
+    if let Some(context) = context {
+      if context.len() > 0xffff {
+        return Err(TLSError::General("exporter context too long".to_string()));
+      }
+      codec::encode_u16(context.len() as u16, &mut seed);
+      seed.extend_from_slice(context);
+    }
+
+    prf::prf(output,
+             self.hash,
+             &self.master_secret,
+             label,
+             &seed);
+    Ok(())
+  }
+}
+
+/// The record layer state for one direction of a session.
+///
+/// Its `Debug` output leaves out the key and IV.
+#[derive(PartialEq)]
+pub struct DirectionSecrets {
+  /// The AEAD key.
+  pub key: Vec<u8>,
+
+  /// The AEAD nonce for a record is this, with the record's
+  /// sequence number xored into the last eight bytes.
+  ///
+  /// For TLS1.2 AES-GCM suites this is only the four byte
+  /// implicit salt for records we receive, since they carry
+  /// their own explicit nonce.  For records we send, the salt
+  /// is followed by the eight byte starting point for the
+  /// explicit nonces.
+  pub iv: Vec<u8>,
+
+  /// The sequence number of the next record.
+  pub seq: u64
+}
+
+impl fmt::Debug for DirectionSecrets {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("DirectionSecrets")
+      .field("key", &"<redacted>")
+      .field("iv", &"<redacted>")
+      .field("seq", &self.seq)
+      .finish()
+  }
+}
+
+/// The record layer state of a session after its handshake,
+/// so that traffic can be protected by something else --
+/// for example, kernel TLS.
+///
+/// Its `Debug` output leaves out the key material.
+pub struct ExtractedSecrets {
+  /// The negotiated protocol version.
+  pub version: ProtocolVersion,
+
+  /// The negotiated cipher suite.
+  pub suite: &'static SupportedCipherSuite,
+
+  /// State for records we send.
+  pub tx: DirectionSecrets,
+
+  /// State for records we receive.
+  pub rx: DirectionSecrets
+}
+
+impl fmt::Debug for ExtractedSecrets {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("ExtractedSecrets")
+      .field("version", &self.version)
+      .field("suite", &self.suite.suite)
+      .field("tx", &self.tx)
+      .field("rx", &self.rx)
+      .finish()
+  }
+}
+
+impl ExtractedSecrets {
+  /// Extract the state of a TLS1.2 session with `secrets`.
+  pub fn from_tls12(scs: &'static SupportedCipherSuite,
+                    secrets: &SessionSecrets,
+                    common: &SessionCommon) -> ExtractedSecrets {
+    let key_block = secrets.make_key_block(scs.key_block_len());
+
+    /* This is the same layout cipher::new_tls12 uses.  The MAC
+     * keys are unused by our AEAD suites. */
+    let mut offs = 2 * scs.mac_key_len;
+    let client_write_key = &key_block[offs..offs+scs.enc_key_len]; offs += scs.enc_key_len;
+    let server_write_key = &key_block[offs..offs+scs.enc_key_len]; offs += scs.enc_key_len;
+    let client_write_iv = &key_block[offs..offs+scs.fixed_iv_len]; offs += scs.fixed_iv_len;
+    let server_write_iv = &key_block[offs..offs+scs.fixed_iv_len]; offs += scs.fixed_iv_len;
+    let explicit_nonce_offs = &key_block[offs..offs+scs.explicit_nonce_len];
+
+    /* Only our explicit nonces come from the key block. */
+    let tx = |key: &[u8], iv: &[u8], seq: u64| {
+      let mut full_iv = iv.to_vec();
+      full_iv.extend_from_slice(explicit_nonce_offs);
+      DirectionSecrets { key: key.to_vec(), iv: full_iv, seq: seq }
+    };
+    let rx = |key: &[u8], iv: &[u8], seq: u64| {
+      DirectionSecrets { key: key.to_vec(), iv: iv.to_vec(), seq: seq }
+    };
+
+    let (tx, rx) = if secrets.randoms.we_are_client {
+      (tx(client_write_key, client_write_iv, common.write_seq),
+       rx(server_write_key, server_write_iv, common.read_seq))
+    } else {
+      (tx(server_write_key, server_write_iv, common.write_seq),
+       rx(client_write_key, client_write_iv, common.read_seq))
+    };
+
+    ExtractedSecrets {
+      version: ProtocolVersion::TLSv1_2,
+      suite: scs,
+      tx: tx,
+      rx: rx
+    }
+  }
+
+  /// Extract the state of a TLS1.3 session from its current
+  /// application traffic secrets in `key_schedule`.
+  pub fn from_tls13(scs: &'static SupportedCipherSuite,
+                    key_schedule: &KeySchedule,
+                    we_are_client: bool,
+                    common: &SessionCommon) -> ExtractedSecrets {
+    let hash = scs.get_hash();
+    let direction = |secret: &[u8], seq: u64| {
+      DirectionSecrets {
+        key: derive_traffic_key(hash, secret, scs.enc_key_len),
+        iv: derive_traffic_iv(hash, secret, scs.fixed_iv_len),
+        seq: seq
+      }
+    };
+
+    let (tx_secret, rx_secret) = if we_are_client {
+      (&key_schedule.current_client_traffic_secret, &key_schedule.current_server_traffic_secret)
+    } else {
+      (&key_schedule.current_server_traffic_secret, &key_schedule.current_client_traffic_secret)
+    };
+
+    ExtractedSecrets {
+      version: ProtocolVersion::TLSv1_3,
+      suite: scs,
+      tx: direction(tx_secret, common.write_seq),
+      rx: direction(rx_secret, common.read_seq)
+    }
+  }
+}
+
+/// Begin saving a session in traffic using `scs`.  Its secrets
+/// are in `secrets` for TLS1.2, or `key_schedule` for TLS1.3.
+/// The caller fills in the parts of the result specific to
+/// clients or servers.
+pub fn save_session_state(scs: &'static SupportedCipherSuite,
+                          randoms: &SessionRandoms,
+                          secrets: Option<&SessionSecrets>,
+                          key_schedule: Option<&KeySchedule>,
+                          common: &SessionCommon) -> Result<persist::SessionState, TLSError> {
+  let empty = || PayloadU8::new(Vec::new());
+  let (client_traffic_secret, server_traffic_secret, exporter_secret) = match key_schedule {
+    Some(ks) => (ks.current_client_traffic_secret.clone(),
+                 ks.current_server_traffic_secret.clone(),
+                 ks.current_exporter_secret.clone()),
+    None => (Vec::new(), Vec::new(), Vec::new())
+  };
+
+  let mut state = persist::SessionState {
+    we_are_client: randoms.we_are_client,
+    version: ProtocolVersion::TLSv1_2,
+    cipher_suite: scs.suite,
+    client_random: PayloadU8::new(randoms.client.to_vec()),
+    server_random: PayloadU8::new(randoms.server.to_vec()),
+    master_secret: PayloadU8::new(secrets.map(|s| s.get_master_secret()).unwrap_or_else(Vec::new)),
+    client_traffic_secret: PayloadU8::new(client_traffic_secret),
+    server_traffic_secret: PayloadU8::new(server_traffic_secret),
+    exporter_secret: PayloadU8::new(exporter_secret),
+    write_seq: 0,
+    read_seq: 0,
+    peer_eof: false,
+    received_plaintext: Vec::new(),
+    received_tls: Vec::new(),
+    sendable_tls: Vec::new(),
+    alpn_protocol: empty(),
+    dns_name: empty(),
+    server_cert_chain: Vec::new(),
+    client_cert_chain: None,
+    tls_unique: empty()
+  };
+
+  try!(common.save_state(&mut state));
+  Ok(state)
+}
+
+pub fn bad_session_state() -> TLSError {
+  TLSError::General("saved session state is invalid".to_string())
+}
+
+/// Decode a saved session from `bytes`, checking it was saved
+/// by the same side as us.
+pub fn read_session_state(bytes: &[u8], we_are_client: bool) -> Result<persist::SessionState, TLSError> {
+  let state = try!(persist::SessionState::read_bytes(bytes).ok_or_else(bad_session_state));
+
+  if state.we_are_client != we_are_client {
+    return Err(TLSError::General("saved session state is for the other side".to_string()));
+  }
+
+  Ok(state)
+}
+
+/// Find the cipher suite used by the saved session `state` in
+/// `suites`.  This fails unless it and the session's protocol
+/// version are enabled.
+pub fn find_saved_suite(suites: &[&'static SupportedCipherSuite],
+                        versions: &[ProtocolVersion],
+                        state: &persist::SessionState)
+                        -> Result<&'static SupportedCipherSuite, TLSError> {
+  if !versions.contains(&state.version) {
+    return Err(TLSError::General("saved session's version is not enabled".to_string()));
+  }
+
+  suites.iter()
+    .find(|scs| scs.suite == state.cipher_suite && scs.usable_for_version(state.version))
+    .map(|scs| *scs)
+    .ok_or_else(|| TLSError::General("saved session's cipher suite is not enabled".to_string()))
+}
+
+/// Rebuild the record layer of `common` from `state`, which
+/// uses `scs`.  Returns the session's randoms, and its secrets
+/// for TLS1.2 or key schedule for TLS1.3.
+pub fn restore_session_state(scs: &'static SupportedCipherSuite,
+                             state: &persist::SessionState,
+                             common: &mut SessionCommon)
+                             -> Result<(SessionRandoms, Option<SessionSecrets>, Option<KeySchedule>), TLSError> {
+  if state.client_random.0.len() != 32 || state.server_random.0.len() != 32 {
+    return Err(bad_session_state());
+  }
+
+  let mut randoms = SessionRandoms {
+    we_are_client: state.we_are_client,
+    client: [0u8; 32],
+    server: [0u8; 32]
+  };
+  randoms.client.as_mut().write(&state.client_random.0).unwrap();
+  randoms.server.as_mut().write(&state.server_random.0).unwrap();
+
+  let (secrets, key_schedule) = match state.version {
+    ProtocolVersion::TLSv1_2 => {
+      if state.master_secret.0.len() != 48 {
+        return Err(bad_session_state());
+      }
+
+      let secrets = SessionSecrets::new_resume(&randoms, scs.get_hash(), &state.master_secret.0);
+      common.start_encryption_tls12(scs, &secrets);
+      (Some(secrets), None)
+    }
+    ProtocolVersion::TLSv1_3 => {
+      let hash_len = scs.get_hash().output_len;
+      if state.client_traffic_secret.0.len() != hash_len ||
+        state.server_traffic_secret.0.len() != hash_len ||
+        state.exporter_secret.0.len() != hash_len {
+        return Err(bad_session_state());
+      }
+
+      let (write_secret, read_secret) = if state.we_are_client {
+        (&state.client_traffic_secret.0, &state.server_traffic_secret.0)
+      } else {
+        (&state.server_traffic_secret.0, &state.client_traffic_secret.0)
+      };
+      common.set_message_encrypter(cipher::new_tls13_write(scs, write_secret));
+      common.set_message_decrypter(cipher::new_tls13_read(scs, read_secret));
+
+      let mut key_schedule = KeySchedule::new(scs.get_hash());
+      key_schedule.current_client_traffic_secret = state.client_traffic_secret.0.clone();
+      key_schedule.current_server_traffic_secret = state.server_traffic_secret.0.clone();
+      key_schedule.current_exporter_secret = state.exporter_secret.0.clone();
+      (None, Some(key_schedule))
+    }
+    _ => return Err(bad_session_state())
+  };
+
+  try!(common.restore_state(state));
+  Ok((randoms, secrets, key_schedule))
 }
 
 /* --- Common (to client and server) session functions --- */
@@ -200,7 +604,9 @@ static SEQ_SOFT_LIMIT: u64 = 0xffff_ffff_ffff_0000u64;
 static SEQ_HARD_LIMIT: u64 = 0xffff_ffff_ffff_fffeu64;
 
 pub struct SessionCommon {
-  message_cipher: Box<MessageCipher + Send + Sync>,
+  pub negotiated_version: Option<ProtocolVersion>,
+  message_encrypter: Box<MessageEncrypter + Send + Sync>,
+  message_decrypter: Box<MessageDecrypter + Send + Sync>,
   write_seq: u64,
   read_seq: u64,
   peer_eof: bool,
@@ -218,7 +624,9 @@ pub struct SessionCommon {
 impl SessionCommon {
   pub fn new(mtu: Option<usize>) -> SessionCommon {
     SessionCommon {
-      message_cipher: MessageCipher::invalid(),
+      negotiated_version: None,
+      message_encrypter: MessageEncrypter::invalid(),
+      message_decrypter: MessageDecrypter::invalid(),
       write_seq: 0,
       read_seq: 0,
       peer_eof: false,
@@ -234,14 +642,95 @@ impl SessionCommon {
     }
   }
 
+  pub fn is_tls13(&self) -> bool {
+    match self.negotiated_version {
+      Some(ProtocolVersion::TLSv1_3) => true,
+      _ => false
+    }
+  }
+
   pub fn has_readable_plaintext(&self) -> bool {
     !self.received_plaintext.is_empty()
   }
 
+  /// Record our sequence numbers and buffered data in `state`.
+  /// This fails if we're part-way through receiving a handshake
+  /// message.
+  pub fn save_state(&self, state: &mut persist::SessionState) -> Result<(), TLSError> {
+    if !self.handshake_joiner.empty() {
+      return Err(TLSError::General("session has a partial handshake message".to_string()));
+    }
+
+    state.version = self.negotiated_version.unwrap();
+    state.write_seq = self.write_seq;
+    state.read_seq = self.read_seq;
+    state.peer_eof = self.peer_eof;
+    state.received_plaintext = self.received_plaintext.to_vec();
+    state.received_tls = self.message_deframer.buffered_bytes();
+    state.sendable_tls = self.sendable_tls.to_vec();
+    Ok(())
+  }
+
+  /// Take up the sequence numbers and buffered data in `state`,
+  /// after the record layer ciphers have been set.  After this
+  /// we're in the traffic state.
+  pub fn restore_state(&mut self, state: &persist::SessionState) -> Result<(), TLSError> {
+    self.negotiated_version = Some(state.version);
+    if self.is_tls13() {
+      self.handshake_joiner.set_version(ProtocolVersion::TLSv1_3);
+    }
+
+    self.write_seq = state.write_seq;
+    self.read_seq = state.read_seq;
+    self.peer_eof = state.peer_eof;
+    self.we_encrypting = true;
+    self.peer_encrypting = true;
+    self.traffic = true;
+    self.received_plaintext.append(state.received_plaintext.clone());
+    self.sendable_tls.append(state.sendable_tls.clone());
+
+    let mut rd = &state.received_tls[..];
+    while !rd.is_empty() {
+      let used = try!(self.message_deframer.read(&mut rd)
+                      .map_err(|_| TLSError::General("bad buffered TLS data".to_string())));
+      if used == 0 || self.message_deframer.desynced {
+        return Err(TLSError::General("bad buffered TLS data".to_string()));
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Do we hold any data, in either direction, which hasn't
+  /// been processed or collected?
+  pub fn has_buffered_data(&self) -> bool {
+    !self.received_plaintext.is_empty() ||
+      !self.sendable_plaintext.is_empty() ||
+      !self.sendable_tls.is_empty() ||
+      self.message_deframer.has_pending() ||
+      !self.handshake_joiner.empty()
+  }
+
+  /// Start encrypting outgoing messages with `cipher`.  This resets
+  /// the write sequence number.
+  pub fn set_message_encrypter(&mut self, cipher: Box<MessageEncrypter + Send + Sync>) {
+    self.message_encrypter = cipher;
+    self.write_seq = 0;
+    self.we_encrypting = true;
+  }
+
+  /// Start decrypting incoming messages with `cipher`.  This resets
+  /// the read sequence number.
+  pub fn set_message_decrypter(&mut self, cipher: Box<MessageDecrypter + Send + Sync>) {
+    self.message_decrypter = cipher;
+    self.read_seq = 0;
+    self.peer_encrypting = true;
+  }
+
   pub fn encrypt_outgoing(&mut self, plain: Message) -> Message {
     let seq = self.write_seq;
     self.write_seq += 1;
-    self.message_cipher.encrypt(plain, seq).unwrap()
+    self.message_encrypter.encrypt(plain, seq).unwrap()
   }
 
   pub fn decrypt_incoming(&mut self, plain: Message) -> Result<Message, TLSError> {
@@ -255,7 +744,7 @@ impl SessionCommon {
 
     let seq = self.read_seq;
     self.read_seq += 1;
-    self.message_cipher.decrypt(plain, seq)
+    self.message_decrypter.decrypt(plain, seq)
   }
 
   pub fn process_alert(&mut self, msg: Message) -> Result<(), TLSError> {
@@ -400,8 +889,10 @@ impl SessionCommon {
     Ok(len)
   }
 
-  pub fn start_encryption(&mut self, suite: &'static SupportedCipherSuite, secrets: &SessionSecrets) {
-    self.message_cipher = MessageCipher::new(suite, secrets);
+  pub fn start_encryption_tls12(&mut self, suite: &'static SupportedCipherSuite, secrets: &SessionSecrets) {
+    let (dec, enc) = cipher::new_tls12(suite, secrets);
+    self.message_encrypter = enc;
+    self.message_decrypter = dec;
   }
 
   pub fn peer_now_encrypting(&mut self) {
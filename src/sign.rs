@@ -1,54 +1,445 @@
 use msgs::enums::{HashAlgorithm, SignatureAlgorithm};
+use msgs::handshake::{SignatureAndHashAlgorithm, ASN1Cert, DigitallySignedStruct};
+use msgs::handshake::{SupportedSignatureAlgorithms, SupportedMandatedSignatureAlgorithms};
+use error::TLSError;
+use util;
+use verify;
+use pemfile;
+use x509;
 use untrusted;
 use ring;
 use ring::signature;
+use time;
 use std::sync::Arc;
+use std::io;
+
+/// A certificate chain, and the private key for the first
+/// certificate in the chain.
+#[derive(Clone)]
+pub struct CertifiedKey {
+  /// The certificate chain, end-entity certificate first.
+  pub cert: Vec<ASN1Cert>,
+
+  /// The private key.
+  pub key: Arc<Box<Signer + Send + Sync>>,
+
+  /// An optional DER-encoded OCSP response for the end-entity
+  /// certificate, stapled if the client asks for it.
+  pub ocsp: Option<Vec<u8>>,
+
+  /// An optional list of signed certificate timestamps (RFC6962)
+  /// for the end-entity certificate, sent if the client asks for
+  /// them.  This is the TLS encoding of a
+  /// SignedCertificateTimestampList: a u16-length-prefixed list of
+  /// u16-length-prefixed SCTs.
+  pub sct_list: Option<Vec<u8>>
+}
+
+/// What `CertifiedKey::check` signs to see if the private key
+/// matches the end-entity certificate.
+static KEY_CHECK_MESSAGE: &'static [u8] = b"rustls certified key check";
+
+fn invalid(why: &str) -> TLSError {
+  TLSError::InvalidCertifiedKey(why.to_string())
+}
+
+impl CertifiedKey {
+  /// Make a `CertifiedKey` from DER-encoded certificates and a
+  /// DER-encoded private key of any supported type.  The result
+  /// is checked with `check`.
+  pub fn from_der(chain: Vec<Vec<u8>>, key_der: &[u8]) -> Result<CertifiedKey, TLSError> {
+    let key = try!(
+      any_supported_type(key_der)
+        .map_err(|_| if sec1_lacks_public_key(key_der) {
+          invalid("SEC1 private key has no public key, which is required")
+        } else {
+          invalid("private key is malformed or of an unsupported type")
+        })
+    );
+    let cert = chain.into_iter()
+      .map(|der| ASN1Cert::new(der))
+      .collect();
+
+    let certkey = CertifiedKey { cert: cert, key: Arc::new(key), ocsp: None, sct_list: None };
+    try!(certkey.check());
+    Ok(certkey)
+  }
+
+  /// Check this is something worth serving: the chain is non-empty
+  /// and well-formed, each certificate is issued by the next, the
+  /// private key matches the end-entity certificate, and the
+  /// end-entity certificate is currently valid.
+  ///
+  /// This says nothing about whether peers will trust the chain.
+  pub fn check(&self) -> Result<(), TLSError> {
+    if self.cert.is_empty() {
+      return Err(invalid("certificate chain is empty"));
+    }
+
+    let mut parsed = Vec::new();
+    for (i, cert) in self.cert.iter().enumerate() {
+      let fields = try!(
+        x509::parse_certificate(&cert.0)
+          .ok_or_else(|| invalid(&format!("certificate {} in chain is malformed", i)))
+      );
+      parsed.push(fields);
+    }
+
+    for (i, pair) in parsed.windows(2).enumerate() {
+      let (cert, issuer) = (&pair[0], &pair[1]);
+      let not_linked = || invalid(&format!("certificate {} in chain is not issued by certificate {}",
+                                           i, i + 1));
+
+      if cert.issuer != issuer.subject {
+        return Err(not_linked());
+      }
+
+      let spki = try!(
+        x509::read_all(issuer.spki, |rd| rd.read(x509::SEQUENCE))
+          .ok_or_else(|| not_linked())
+      );
+      try!(
+        verify::verify_signed_data_with_spki(spki, cert.signature_alg, cert.tbs, cert.signature)
+          .map_err(|_| not_linked())
+      );
+    }
+
+    let mismatch = || invalid("private key does not match end-entity certificate");
+    let offered = SupportedSignatureAlgorithms::supported_verify();
+    let scheme = try!(self.key.choose_scheme_tls12(&offered).ok_or_else(|| mismatch()));
+    let sig = try!(self.key.sign(&scheme, KEY_CHECK_MESSAGE).map_err(|_| mismatch()));
+    let dss = DigitallySignedStruct::new(&scheme, sig);
+    try!(
+      verify::verify_signed_struct(KEY_CHECK_MESSAGE, &self.cert[0], &dss)
+        .map_err(|_| mismatch())
+    );
+
+    let now = time::get_time();
+    if now < parsed[0].not_before {
+      return Err(invalid("end-entity certificate is not yet valid"));
+    }
+
+    if now > parsed[0].not_after {
+      return Err(invalid("end-entity certificate has expired"));
+    }
+
+    Ok(())
+  }
+}
 
 /// A thing that can sign a message.
 pub trait Signer {
-  /// Signs `message`, hashing it with `hash_alg` first.
-  fn sign(&self, hash_alg: &HashAlgorithm, message: &[u8]) -> Result<Vec<u8>, ()>;
+  /// Signs `message` using the signature scheme `scheme`.
+  fn sign(&self, scheme: &SignatureAndHashAlgorithm, message: &[u8]) -> Result<Vec<u8>, ()>;
+
+  /// Choose a TLS1.2 signature scheme, which this key can
+  /// produce, from those `offered` by the peer.
+  fn choose_scheme_tls12(&self, offered: &[SignatureAndHashAlgorithm]) -> Option<SignatureAndHashAlgorithm>;
+
+  /// Choose a TLS1.3 signature scheme, which this key can
+  /// produce, from those `offered` by the peer.
+  fn choose_scheme_tls13(&self, offered: &[SignatureAndHashAlgorithm]) -> Option<SignatureAndHashAlgorithm>;
 
   /// What kind of key we have.
   fn algorithm(&self) -> SignatureAlgorithm;
 }
 
-/// A Signer for RSA-PKCS1
+/// A Signer for RSA-PKCS1 or RSA-PSS
 pub struct RSASigner {
-  key: Arc<signature::RSAKeyPair>
+  key: signature::RsaKeyPair
 }
 
+/// PSS is preferred, where the peer supports it.
+static RSA_TLS12_SCHEMES: &'static [SignatureAndHashAlgorithm] = &[
+  SignatureAndHashAlgorithm { hash: HashAlgorithm::Intrinsic, sign: SignatureAlgorithm::RSAPSS_SHA512 },
+  SignatureAndHashAlgorithm { hash: HashAlgorithm::Intrinsic, sign: SignatureAlgorithm::RSAPSS_SHA384 },
+  SignatureAndHashAlgorithm { hash: HashAlgorithm::Intrinsic, sign: SignatureAlgorithm::RSAPSS_SHA256 },
+  SignatureAndHashAlgorithm { hash: HashAlgorithm::SHA512, sign: SignatureAlgorithm::RSA },
+  SignatureAndHashAlgorithm { hash: HashAlgorithm::SHA384, sign: SignatureAlgorithm::RSA },
+  SignatureAndHashAlgorithm { hash: HashAlgorithm::SHA256, sign: SignatureAlgorithm::RSA }
+];
+
+static RSA_TLS13_SCHEMES: &'static [SignatureAndHashAlgorithm] = &[
+  SignatureAndHashAlgorithm { hash: HashAlgorithm::Intrinsic, sign: SignatureAlgorithm::RSAPSS_SHA512 },
+  SignatureAndHashAlgorithm { hash: HashAlgorithm::Intrinsic, sign: SignatureAlgorithm::RSAPSS_SHA384 },
+  SignatureAndHashAlgorithm { hash: HashAlgorithm::Intrinsic, sign: SignatureAlgorithm::RSAPSS_SHA256 }
+];
+
 impl RSASigner {
+  /// Make a new `RSASigner` from a DER-encoded PKCS#1 RSA
+  /// private key.
   pub fn new(der: &[u8]) -> Result<RSASigner, ()> {
-    let key = signature::RSAKeyPair::from_der(untrusted::Input::from(der));
+    let key = signature::RsaKeyPair::from_der(untrusted::Input::from(der));
     key
-      .map(|s| RSASigner { key: Arc::new(s) })
+      .map(|s| RSASigner { key: s })
       .map_err(|_| ())
   }
 }
 
 impl Signer for RSASigner {
-  fn sign(&self, hash_alg: &HashAlgorithm, message: &[u8]) -> Result<Vec<u8>, ()> {
+  fn sign(&self, scheme: &SignatureAndHashAlgorithm, message: &[u8]) -> Result<Vec<u8>, ()> {
     let mut sig = vec![0; self.key.public_modulus_len()];
-    let pad = match hash_alg {
-      &HashAlgorithm::SHA256 => &signature::RSA_PKCS1_SHA256,
-      &HashAlgorithm::SHA384 => &signature::RSA_PKCS1_SHA384,
-      &HashAlgorithm::SHA512 => &signature::RSA_PKCS1_SHA512,
-      _ => unreachable!()
+    let pad: &'static signature::RsaEncoding = match (scheme.sign, scheme.hash) {
+      (SignatureAlgorithm::RSA, HashAlgorithm::SHA256) => &signature::RSA_PKCS1_SHA256,
+      (SignatureAlgorithm::RSA, HashAlgorithm::SHA384) => &signature::RSA_PKCS1_SHA384,
+      (SignatureAlgorithm::RSA, HashAlgorithm::SHA512) => &signature::RSA_PKCS1_SHA512,
+      (SignatureAlgorithm::RSAPSS_SHA256, _) => &signature::RSA_PSS_SHA256,
+      (SignatureAlgorithm::RSAPSS_SHA384, _) => &signature::RSA_PSS_SHA384,
+      (SignatureAlgorithm::RSAPSS_SHA512, _) => &signature::RSA_PSS_SHA512,
+      _ => return Err(())
     };
 
     let rng = ring::rand::SystemRandom::new();
-    let mut signer = try!(
-      signature::RSASigningState::new(self.key.clone())
-      .map_err(|_| ())
-    );
-
-    signer.sign(pad, &rng, message, &mut sig)
+    self.key.sign(pad, &rng, message, &mut sig)
       .map(|_| sig)
       .map_err(|_| ())
   }
 
+  fn choose_scheme_tls12(&self, offered: &[SignatureAndHashAlgorithm]) -> Option<SignatureAndHashAlgorithm> {
+    util::first_in_both(RSA_TLS12_SCHEMES, offered)
+  }
+
+  fn choose_scheme_tls13(&self, offered: &[SignatureAndHashAlgorithm]) -> Option<SignatureAndHashAlgorithm> {
+    util::first_in_both(RSA_TLS13_SCHEMES, offered)
+  }
+
   fn algorithm(&self) -> SignatureAlgorithm {
     SignatureAlgorithm::RSA
   }
 }
+
+/// A Signer for ECDSA keys on the P-256 or P-384 curves.
+///
+/// ECDSA keys are bound to a single hash function, so
+/// these can only produce one signature scheme: P-256
+/// keys sign with SHA256, P-384 keys with SHA384.  This is
+/// what TLS1.3 requires anyway.
+pub struct ECDSASigner {
+  key: signature::EcdsaKeyPair,
+  scheme: SignatureAndHashAlgorithm
+}
+
+impl ECDSASigner {
+  /// Make a new `ECDSASigner` from a DER-encoded PKCS#8
+  /// private key.  The curve is worked out from the key.
+  pub fn new(der: &[u8]) -> Result<ECDSASigner, ()> {
+    ECDSASigner::new_for_curve(der,
+                               &signature::ECDSA_P256_SHA256_ASN1_SIGNING,
+                               HashAlgorithm::SHA256)
+      .or_else(|_| ECDSASigner::new_for_curve(der,
+                                              &signature::ECDSA_P384_SHA384_ASN1_SIGNING,
+                                              HashAlgorithm::SHA384))
+  }
+
+  fn new_for_curve(der: &[u8],
+                   alg: &'static signature::EcdsaSigningAlgorithm,
+                   hash: HashAlgorithm) -> Result<ECDSASigner, ()> {
+    signature::EcdsaKeyPair::from_pkcs8(alg, untrusted::Input::from(der))
+      .map(|key| ECDSASigner {
+        key: key,
+        scheme: SignatureAndHashAlgorithm { hash: hash, sign: SignatureAlgorithm::ECDSA }
+      })
+      .map_err(|_| ())
+  }
+}
+
+impl Signer for ECDSASigner {
+  fn sign(&self, scheme: &SignatureAndHashAlgorithm, message: &[u8]) -> Result<Vec<u8>, ()> {
+    if *scheme != self.scheme {
+      return Err(());
+    }
+
+    let rng = ring::rand::SystemRandom::new();
+    self.key.sign(&rng, untrusted::Input::from(message))
+      .map(|sig| sig.as_ref().to_vec())
+      .map_err(|_| ())
+  }
+
+  fn choose_scheme_tls12(&self, offered: &[SignatureAndHashAlgorithm]) -> Option<SignatureAndHashAlgorithm> {
+    util::first_in_both(&[self.scheme], offered)
+  }
+
+  fn choose_scheme_tls13(&self, offered: &[SignatureAndHashAlgorithm]) -> Option<SignatureAndHashAlgorithm> {
+    util::first_in_both(&[self.scheme], offered)
+  }
+
+  fn algorithm(&self) -> SignatureAlgorithm {
+    SignatureAlgorithm::ECDSA
+  }
+}
+
+/// A Signer for Ed25519 keys.  There is only one Ed25519
+/// signature scheme, and it is usable in TLS1.2 and TLS1.3.
+pub struct Ed25519Signer {
+  key: signature::Ed25519KeyPair
+}
+
+static ED25519_SCHEME: SignatureAndHashAlgorithm = SignatureAndHashAlgorithm {
+  hash: HashAlgorithm::Intrinsic,
+  sign: SignatureAlgorithm::ED25519
+};
+
+impl Ed25519Signer {
+  /// Make a new `Ed25519Signer` from a DER-encoded PKCS#8
+  /// private key.  Both v1 keys (as written by openssl) and
+  /// v2 keys (which include the public key) are accepted.
+  pub fn new(der: &[u8]) -> Result<Ed25519Signer, ()> {
+    signature::Ed25519KeyPair::from_pkcs8_maybe_unchecked(untrusted::Input::from(der))
+      .map(|key| Ed25519Signer { key: key })
+      .map_err(|_| ())
+  }
+}
+
+impl Signer for Ed25519Signer {
+  fn sign(&self, scheme: &SignatureAndHashAlgorithm, message: &[u8]) -> Result<Vec<u8>, ()> {
+    if *scheme != ED25519_SCHEME {
+      return Err(());
+    }
+
+    Ok(self.key.sign(message).as_ref().to_vec())
+  }
+
+  fn choose_scheme_tls12(&self, offered: &[SignatureAndHashAlgorithm]) -> Option<SignatureAndHashAlgorithm> {
+    util::first_in_both(&[ED25519_SCHEME], offered)
+  }
+
+  fn choose_scheme_tls13(&self, offered: &[SignatureAndHashAlgorithm]) -> Option<SignatureAndHashAlgorithm> {
+    util::first_in_both(&[ED25519_SCHEME], offered)
+  }
+
+  fn algorithm(&self) -> SignatureAlgorithm {
+    SignatureAlgorithm::ED25519
+  }
+}
+
+/// Parse `der` as any supported key encoding/type, returning
+/// the first which works.  This tries an RSA key in PKCS#1
+/// format, then RSA, ECDSA and Ed25519 keys in PKCS#8 format,
+/// then an ECDSA key in SEC1 format.
+pub fn any_supported_type(der: &[u8]) -> Result<Box<Signer + Send + Sync>, ()> {
+  if let Ok(rsa) = RSASigner::new(der) {
+    return Ok(Box::new(rsa));
+  }
+
+  if let Ok(key) = pkcs8_signer(der) {
+    return Ok(key);
+  }
+
+  let pkcs8 = try!(sec1_to_pkcs8(der).ok_or(()));
+  ECDSASigner::new(&pkcs8)
+    .map(|ecdsa| Box::new(ecdsa) as Box<Signer + Send + Sync>)
+}
+
+/// Make a Signer from the PKCS#8 private key `der`, choosing
+/// its type from the key's algorithm identifier.
+fn pkcs8_signer(der: &[u8]) -> Result<Box<Signer + Send + Sync>, ()> {
+  let parsed = x509::read_all(der, |rd| {
+    let body = try_ret!(rd.read(x509::SEQUENCE));
+    x509::read_all(body, |rd| {
+      try_ret!(rd.read(x509::INTEGER));
+      let alg_id = try_ret!(rd.read(x509::SEQUENCE));
+      let key = try_ret!(rd.read(x509::OCTET_STRING));
+
+      /* attributes, and (in RFC5958) publicKey. */
+      try_ret!(rd.read_optional(x509::explicit_tag(0)));
+      try_ret!(rd.read_optional(x509::CONTEXT_SPECIFIC | 1));
+      Some((alg_id, key))
+    })
+  });
+
+  let (alg_id, key) = try!(parsed.ok_or(()));
+  let oid = try!(x509::DerReader::new(alg_id).read(x509::OID).ok_or(()));
+
+  if oid == x509::RSA_ENCRYPTION {
+    /* The contents are a PKCS#1 RSAPrivateKey. */
+    RSASigner::new(key)
+      .map(|rsa| Box::new(rsa) as Box<Signer + Send + Sync>)
+  } else if oid == x509::EC_PUBLIC_KEY {
+    ECDSASigner::new(der)
+      .map(|ecdsa| Box::new(ecdsa) as Box<Signer + Send + Sync>)
+  } else if oid == x509::ED25519 {
+    Ed25519Signer::new(der)
+      .map(|eddsa| Box::new(eddsa) as Box<Signer + Send + Sync>)
+  } else {
+    Err(())
+  }
+}
+
+/// Split a SEC1 ECPrivateKey (RFC5915) into the encodings of its
+/// version, private key, curve and (optional) public key.
+fn parse_sec1(der: &[u8]) -> Option<(&[u8], &[u8], &[u8], Option<&[u8]>)> {
+  x509::read_all(der, |rd| {
+    let body = try_ret!(rd.read(x509::SEQUENCE));
+    x509::read_all(body, |rd| {
+      let (_, version) = try_ret!(rd.read_with_encoding(x509::INTEGER));
+      let (_, private_key) = try_ret!(rd.read_with_encoding(x509::OCTET_STRING));
+      let params = try_ret!(rd.read(x509::explicit_tag(0)));
+      let (_, curve) = try_ret!(x509::read_all(params, |rd| rd.read_with_encoding(x509::OID)));
+      let public_key = if rd.peek_tag() == Some(x509::explicit_tag(1)) {
+        Some(try_ret!(rd.read_with_encoding(x509::explicit_tag(1))).1)
+      } else {
+        None
+      };
+      Some((version, private_key, curve, public_key))
+    })
+  })
+}
+
+/// Is `der` a SEC1 ECPrivateKey without the optional public key?
+/// We can't use these, because *ring* needs the public key and
+/// can't derive it.
+fn sec1_lacks_public_key(der: &[u8]) -> bool {
+  match parse_sec1(der) {
+    Some((_, _, _, None)) => true,
+    _ => false
+  }
+}
+
+/// Convert a SEC1 ECPrivateKey (RFC5915) to the PKCS#8 encoding
+/// *ring* accepts.  The curve moves from the ECPrivateKey to the
+/// PKCS#8 algorithm identifier, and the public key is required.
+fn sec1_to_pkcs8(der: &[u8]) -> Option<Vec<u8>> {
+  let (version, private_key, curve, public_key) = try_ret!(parse_sec1(der));
+  let public_key = try_ret!(public_key);
+
+  let mut inner = Vec::new();
+  inner.extend_from_slice(version);
+  inner.extend_from_slice(private_key);
+  inner.extend_from_slice(public_key);
+  x509::wrap_in_sequence(&mut inner);
+  x509::wrap_in_tag(x509::OCTET_STRING, &mut inner);
+
+  let mut alg_id = x509::EC_PUBLIC_KEY.to_vec();
+  x509::wrap_in_tag(x509::OID, &mut alg_id);
+  alg_id.extend_from_slice(curve);
+  x509::wrap_in_sequence(&mut alg_id);
+
+  let mut pkcs8 = vec![x509::INTEGER, 0x01, 0x00];
+  pkcs8.extend_from_slice(&alg_id);
+  pkcs8.extend_from_slice(&inner);
+  x509::wrap_in_sequence(&mut pkcs8);
+  Some(pkcs8)
+}
+
+/// Make a Signer for the first private key in the PEM file `rd`.
+/// This supports RSA keys in PKCS#1 or PKCS#8 format, ECDSA keys
+/// in PKCS#8 or SEC1 format, and Ed25519 keys in PKCS#8 format.
+/// The type of key is detected from the key itself.  SEC1 keys
+/// must include the optional public key.
+pub fn load_private_key(rd: &mut io::BufRead) -> Result<Box<Signer + Send + Sync>, ()> {
+  let keys = try!(pemfile::private_keys(rd));
+
+  match keys.first() {
+    Some(&pemfile::PrivateKey::RSA(ref der)) => {
+      RSASigner::new(der)
+        .map(|rsa| Box::new(rsa) as Box<Signer + Send + Sync>)
+    }
+    Some(&pemfile::PrivateKey::PKCS8(ref der)) => pkcs8_signer(der),
+    Some(&pemfile::PrivateKey::EC(ref der)) => {
+      let pkcs8 = try!(sec1_to_pkcs8(der).ok_or(()));
+      ECDSASigner::new(&pkcs8)
+        .map(|ecdsa| Box::new(ecdsa) as Box<Signer + Send + Sync>)
+    }
+    None => Err(())
+  }
+}
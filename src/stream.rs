@@ -0,0 +1,142 @@
+/* Blocking IO over a session and a transport. */
+
+use session::Session;
+
+use std::io::{Read, Write};
+use std::io;
+
+/// A session and the transport it runs over, together acting
+/// as a blocking TLS stream.
+///
+/// Reads and writes drive the handshake, and flush any TLS
+/// data the session produces, as needed.  `sock` is typically
+/// a `TcpStream`, and is used in blocking fashion.
+pub struct Stream<S: Read + Write> {
+  /// The session: a `ClientSession` or `ServerSession`.
+  pub sess: Box<Session>,
+
+  /// The underlying transport.
+  pub sock: S
+}
+
+impl<S: Read + Write> Stream<S> {
+  /// Make a new `Stream` using `sess` over `sock`.  No IO is
+  /// done until the stream is read from or written to.
+  pub fn new(sess: Box<Session>, sock: S) -> Stream<S> {
+    Stream { sess: sess, sock: sock }
+  }
+
+  /// Do IO with `sock` until the session can make no further
+  /// progress without the caller's help.
+  ///
+  /// During the handshake this means until the handshake is
+  /// complete.  Afterwards, it writes all pending TLS data
+  /// and, if the session wants it, does one read of TLS data
+  /// and processes it.
+  ///
+  /// Returns the number of bytes read from and written to
+  /// `sock`.  TLS errors are returned as `io::Error`s of kind
+  /// `InvalidData`, after any alert has been sent.
+  pub fn complete_io(&mut self) -> io::Result<(usize, usize)> {
+    let until_handshaked = self.sess.is_handshaking();
+    let mut eof = false;
+    let mut wrlen = 0;
+    let mut rdlen = 0;
+
+    loop {
+      while self.sess.wants_write() {
+        wrlen += try!(self.sess.write_tls(&mut self.sock));
+      }
+
+      if !until_handshaked && wrlen > 0 {
+        return Ok((rdlen, wrlen));
+      }
+
+      if !eof && self.sess.wants_read() {
+        match try!(self.sess.read_tls(&mut self.sock)) {
+          0 => eof = true,
+          n => rdlen += n
+        }
+      }
+
+      if let Err(err) = self.sess.process_new_packets() {
+        /* Try to tell the peer what went wrong. */
+        while self.sess.wants_write() {
+          if self.sess.write_tls(&mut self.sock).is_err() {
+            break;
+          }
+        }
+        return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+      }
+
+      match (eof, until_handshaked, self.sess.is_handshaking()) {
+        (_, true, false) => return Ok((rdlen, wrlen)),
+        (_, false, _) => return Ok((rdlen, wrlen)),
+        (true, true, true) => {
+          return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                    "connection closed during handshake"));
+        }
+        (false, true, true) => {}
+      }
+    }
+  }
+
+  /// Finish the handshake, and flush any TLS data we have
+  /// waiting, before doing IO for the caller.
+  fn complete_prior_io(&mut self) -> io::Result<()> {
+    if self.sess.is_handshaking() {
+      try!(self.complete_io());
+    }
+
+    if self.sess.wants_write() {
+      try!(self.complete_io());
+    }
+
+    Ok(())
+  }
+}
+
+impl<S: Read + Write> Read for Stream<S> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    try!(self.complete_prior_io());
+
+    /* Read TLS data until we have some plaintext, or the
+     * transport is closed.  A pass may only write -- for example,
+     * answering a KeyUpdate -- so that alone isn't EOF: only a
+     * read of nothing is. */
+    while self.sess.wants_read() {
+      match try!(self.complete_io()) {
+        (0, 0) => break,
+        _ => {}
+      }
+    }
+
+    self.sess.read(buf)
+  }
+}
+
+impl<S: Read + Write> Write for Stream<S> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    try!(self.complete_prior_io());
+
+    let len = try!(self.sess.write(buf));
+
+    /* Only send: complete_io would otherwise block reading. */
+    if self.sess.wants_write() {
+      try!(self.complete_io());
+    }
+
+    Ok(len)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    try!(self.complete_prior_io());
+    try!(self.sess.flush());
+
+    if self.sess.wants_write() {
+      try!(self.complete_io());
+    }
+
+    self.sock.flush()
+  }
+}
@@ -1,4 +1,5 @@
 use msgs::enums::{CipherSuite, HashAlgorithm, SignatureAlgorithm, NamedCurve};
+use msgs::enums::ProtocolVersion;
 use msgs::handshake::{SignatureAndHashAlgorithm, KeyExchangeAlgorithm};
 use msgs::handshake::SupportedSignatureAlgorithms;
 use msgs::handshake::{ClientECDHParams, ServerECDHParams};
@@ -26,6 +27,7 @@ pub struct KeyExchangeResult {
 /// An in-progress key exchange.  This has the algorithm,
 /// our private key, and our public key.
 pub struct KeyExchange {
+  pub group: NamedCurve,
   alg: &'static ring::agreement::Algorithm,
   privkey: ring::agreement::EphemeralPrivateKey,
   pub pubkey: Vec<u8>
@@ -55,11 +57,9 @@ impl KeyExchange {
     let ours = ring::agreement::EphemeralPrivateKey::generate(alg, &rng)
       .unwrap();
 
-    let mut pubkey = Vec::new();
-    pubkey.resize(ours.public_key_len(), 0u8);
-    ours.compute_public_key(pubkey.as_mut_slice()).unwrap();
+    let pubkey = ours.compute_public_key().unwrap().as_ref().to_vec();
 
-    Some(KeyExchange { alg: alg, privkey: ours, pubkey: pubkey })
+    Some(KeyExchange { group: *named_curve, alg: alg, privkey: ours, pubkey: pubkey })
   }
 
   pub fn server_complete(self, kx_params: &[u8]) -> Option<KeyExchangeResult> {
@@ -69,7 +69,7 @@ impl KeyExchange {
     self.complete(&ecdh_params.public.0)
   }
 
-  fn complete(self, peer: &[u8]) -> Option<KeyExchangeResult> {
+  pub fn complete(self, peer: &[u8]) -> Option<KeyExchangeResult> {
     let secret = ring::agreement::agree_ephemeral(
       self.privkey,
       self.alg,
@@ -174,6 +174,29 @@ impl SupportedCipherSuite {
   pub fn key_block_len(&self) -> usize {
     (self.mac_key_len + self.enc_key_len + self.fixed_iv_len) * 2 + self.explicit_nonce_len
   }
+
+  /// Return true if this suite is usable for TLS `version`.
+  pub fn usable_for_version(&self, version: ProtocolVersion) -> bool {
+    match version {
+      ProtocolVersion::TLSv1_3 => self.kx == KeyExchangeAlgorithm::BulkOnly,
+      ProtocolVersion::TLSv1_2 => self.kx != KeyExchangeAlgorithm::BulkOnly,
+      _ => false
+    }
+  }
+
+  /// Return true if a key exchange signed with `sigalg` is
+  /// acceptable for this suite.  RSA suites accept RSA-PSS
+  /// as well as PKCS#1 signatures, and ECDSA suites accept
+  /// Ed25519 (RFC8422).
+  pub fn usable_for_sigalg(&self, sigalg: &SignatureAlgorithm) -> bool {
+    match (&self.sign, sigalg) {
+      (&SignatureAlgorithm::RSA, &SignatureAlgorithm::RSAPSS_SHA256) |
+      (&SignatureAlgorithm::RSA, &SignatureAlgorithm::RSAPSS_SHA384) |
+      (&SignatureAlgorithm::RSA, &SignatureAlgorithm::RSAPSS_SHA512) |
+      (&SignatureAlgorithm::ECDSA, &SignatureAlgorithm::ED25519) => true,
+      (ours, theirs) => ours == theirs
+    }
+  }
 }
 
 pub static TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256: SupportedCipherSuite =
@@ -254,8 +277,53 @@ SupportedCipherSuite {
   explicit_nonce_len: 8
 };
 
+pub static TLS13_CHACHA20_POLY1305_SHA256: SupportedCipherSuite =
+SupportedCipherSuite {
+  suite: CipherSuite::TLS13_CHACHA20_POLY1305_SHA256,
+  kx: KeyExchangeAlgorithm::BulkOnly,
+  sign: SignatureAlgorithm::Anonymous,
+  bulk: BulkAlgorithm::CHACHA20_POLY1305,
+  hash: HashAlgorithm::SHA256,
+  mac_key_len: 0,
+  enc_key_len: 32,
+  fixed_iv_len: 12,
+  explicit_nonce_len: 0
+};
+
+pub static TLS13_AES_256_GCM_SHA384: SupportedCipherSuite =
+SupportedCipherSuite {
+  suite: CipherSuite::TLS13_AES_256_GCM_SHA384,
+  kx: KeyExchangeAlgorithm::BulkOnly,
+  sign: SignatureAlgorithm::Anonymous,
+  bulk: BulkAlgorithm::AES_256_GCM,
+  hash: HashAlgorithm::SHA384,
+  mac_key_len: 0,
+  enc_key_len: 32,
+  fixed_iv_len: 12,
+  explicit_nonce_len: 0
+};
+
+pub static TLS13_AES_128_GCM_SHA256: SupportedCipherSuite =
+SupportedCipherSuite {
+  suite: CipherSuite::TLS13_AES_128_GCM_SHA256,
+  kx: KeyExchangeAlgorithm::BulkOnly,
+  sign: SignatureAlgorithm::Anonymous,
+  bulk: BulkAlgorithm::AES_128_GCM,
+  hash: HashAlgorithm::SHA256,
+  mac_key_len: 0,
+  enc_key_len: 16,
+  fixed_iv_len: 12,
+  explicit_nonce_len: 0
+};
+
 /// A list of all the cipher suites supported by rustls.
-pub static ALL_CIPHERSUITES: [&'static SupportedCipherSuite; 6] = [
+pub static ALL_CIPHERSUITES: [&'static SupportedCipherSuite; 9] = [
+  // TLS1.3 suites
+  &TLS13_CHACHA20_POLY1305_SHA256,
+  &TLS13_AES_256_GCM_SHA384,
+  &TLS13_AES_128_GCM_SHA256,
+
+  // TLS1.2 suites
   &TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
   &TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
   &TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
@@ -292,14 +360,24 @@ pub fn choose_ciphersuite_preferring_server(
 pub fn reduce_given_sigalg(all: &[&'static SupportedCipherSuite], sigalg: &SignatureAlgorithm)
   -> Vec<&'static SupportedCipherSuite> {
   all.iter()
-     .filter(|&&suite| &suite.sign == sigalg)
+     .filter(|&&suite| suite.usable_for_sigalg(sigalg))
+     .cloned()
+     .collect()
+}
+
+/// Return a list of the ciphersuites in `all` with the suites
+/// incompatible with the chosen `version` removed.
+pub fn reduce_given_version(all: &[&'static SupportedCipherSuite], version: ProtocolVersion)
+  -> Vec<&'static SupportedCipherSuite> {
+  all.iter()
+     .filter(|&&suite| suite.usable_for_version(version))
      .cloned()
      .collect()
 }
 
 #[cfg(test)]
 mod test {
-  use msgs::enums::CipherSuite;
+  use msgs::enums::{CipherSuite, SignatureAlgorithm};
 
   #[test]
   fn test_client_pref() {
@@ -330,4 +408,19 @@ mod test {
     assert!(chosen.is_some());
     assert_eq!(chosen.unwrap(), &super::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384);
   }
+
+  #[test]
+  fn test_pss_usable_for_rsa_suites() {
+    let rsa = &super::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256;
+    let ecdsa = &super::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256;
+
+    assert!(rsa.usable_for_sigalg(&SignatureAlgorithm::RSA));
+    assert!(rsa.usable_for_sigalg(&SignatureAlgorithm::RSAPSS_SHA256));
+    assert!(rsa.usable_for_sigalg(&SignatureAlgorithm::RSAPSS_SHA512));
+    assert!(!rsa.usable_for_sigalg(&SignatureAlgorithm::ECDSA));
+    assert!(ecdsa.usable_for_sigalg(&SignatureAlgorithm::ECDSA));
+    assert!(!ecdsa.usable_for_sigalg(&SignatureAlgorithm::RSAPSS_SHA384));
+    assert!(ecdsa.usable_for_sigalg(&SignatureAlgorithm::ED25519));
+    assert!(!rsa.usable_for_sigalg(&SignatureAlgorithm::ED25519));
+  }
 }
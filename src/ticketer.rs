@@ -55,13 +55,13 @@ impl ProducesTickets for AEADTicketer {
     let mut out = Vec::new();
     out.extend_from_slice(&nonce);
     out.extend_from_slice(&message);
-    out.resize(nonce.len() + message.len() + self.alg.max_overhead_len(), 0u8);
+    out.resize(nonce.len() + message.len() + self.alg.tag_len(), 0u8);
 
     let rc = aead::seal_in_place(&self.enc,
-                                 &nonce,
+                                 aead::Nonce::assume_unique_for_key(nonce),
+                                 aead::Aad::empty(),
                                  &mut out[nonce.len()..],
-                                 self.alg.max_overhead_len(),
-                                 &[0u8; 0]);
+                                 self.alg.tag_len());
     if rc.is_err() {
       None
     } else {
@@ -72,21 +72,22 @@ impl ProducesTickets for AEADTicketer {
   /// Decrypt `ciphertext` and recover the original message.
   fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
     let nonce_len = self.alg.nonce_len();
-    let tag_len = self.alg.max_overhead_len();
+    let tag_len = self.alg.tag_len();
 
     if ciphertext.len() < nonce_len + tag_len {
       return None;
     }
 
-    let nonce = &ciphertext[0..nonce_len];
+    let nonce = try_ret!(aead::Nonce::try_assume_unique_for_key(&ciphertext[0..nonce_len]).ok());
     let mut out = Vec::new();
     out.extend_from_slice(&ciphertext[nonce_len..]);
 
     let len = aead::open_in_place(&self.dec,
                                   nonce,
+                                  aead::Aad::empty(),
                                   0,
-                                  &mut out,
-                                  &[0u8; 0]);
+                                  &mut out)
+      .map(|plain| plain.len());
 
     if len.is_err() {
       return None;
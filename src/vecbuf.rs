@@ -30,6 +30,15 @@ impl ChunkVecBuffer {
     self.chunks.remove(0)
   }
 
+  /// Return a copy of everything we hold, without consuming it.
+  pub fn to_vec(&self) -> Vec<u8> {
+    let mut ret = Vec::new();
+    for chunk in &self.chunks {
+      ret.extend_from_slice(chunk);
+    }
+    ret
+  }
+
   pub fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
     let mut offs = 0;
 
@@ -1,16 +1,22 @@
 use webpki;
 use time;
 use untrusted;
+use ring::signature;
 
 use msgs::handshake::ASN1Cert;
 use msgs::handshake::DigitallySignedStruct;
 use msgs::handshake::SignatureAndHashAlgorithm;
 use msgs::handshake::{DistinguishedName, DistinguishedNames};
 use error::TLSError;
+use crl::CRLStore;
 use pemfile;
 use x509;
 
 use std::io;
+use std::fs;
+use std::env;
+use std::ffi::OsString;
+use std::path::Path;
 
 /// Disable all verifications, for testing purposes.
 const DANGEROUS_DISABLE_VERIFY: bool = false;
@@ -20,19 +26,19 @@ type SignatureAlgorithms = &'static [&'static webpki::SignatureAlgorithm];
 /// Which signature verification mechanisms we support.  No particular
 /// order.
 static SUPPORTED_SIG_ALGS: SignatureAlgorithms = &[
-  &webpki::ECDSA_P256_SHA1,
   &webpki::ECDSA_P256_SHA256,
   &webpki::ECDSA_P256_SHA384,
-  &webpki::ECDSA_P256_SHA512,
-  &webpki::ECDSA_P384_SHA1,
   &webpki::ECDSA_P384_SHA256,
   &webpki::ECDSA_P384_SHA384,
-  &webpki::ECDSA_P384_SHA512,
   &webpki::RSA_PKCS1_2048_8192_SHA1,
   &webpki::RSA_PKCS1_2048_8192_SHA256,
   &webpki::RSA_PKCS1_2048_8192_SHA384,
   &webpki::RSA_PKCS1_2048_8192_SHA512,
-  &webpki::RSA_PKCS1_3072_8192_SHA384
+  &webpki::RSA_PKCS1_3072_8192_SHA384,
+  &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+  &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+  &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+  &webpki::ED25519
 ];
 
 /// This is like a webpki::TrustAnchor, except it owns
@@ -62,16 +68,53 @@ impl OwnedTrustAnchor {
   }
 }
 
+/// Certificate bundles used by various Linux distributions, in the
+/// order we try them.
+static SYSTEM_CERT_FILES: &'static [&'static str] = &[
+  "/etc/ssl/certs/ca-certificates.crt",
+  "/etc/pki/ca-trust/extracted/pem/tls-ca-bundle.pem",
+  "/etc/pki/tls/certs/ca-bundle.crt",
+  "/etc/pki/tls/cacert.pem",
+  "/etc/ssl/ca-bundle.pem",
+  "/etc/ssl/cert.pem"
+];
+
+/// Directories of certificates named by subject hash, used if
+/// there's no bundle.
+static SYSTEM_CERT_DIRS: &'static [&'static str] = &[
+  "/etc/ssl/certs",
+  "/etc/pki/tls/certs"
+];
+
+/// Does `name` look like an OpenSSL hashed certificate name,
+/// like `9d66eef0.0`?
+fn is_hashed_cert_name(name: &str) -> bool {
+  let mut parts = name.splitn(2, '.');
+  let hash = parts.next().unwrap_or("");
+  let seq = parts.next().unwrap_or("");
+
+  hash.len() == 8 && hash.chars().all(|c| c.is_digit(16)) &&
+    !seq.is_empty() && seq.chars().all(|c| c.is_digit(10))
+}
+
 /// A container for root certificates able to provide a root-of-trust
 /// for connection authentication.
 pub struct RootCertStore {
-  roots: Vec<OwnedTrustAnchor>
+  roots: Vec<OwnedTrustAnchor>,
+  crls: CRLStore
 }
 
 impl RootCertStore {
   /// Make a new, empty `RootCertStore`.
   pub fn empty() -> RootCertStore {
-    RootCertStore { roots: Vec::new() }
+    RootCertStore { roots: Vec::new(), crls: CRLStore::empty() }
+  }
+
+  /// Check chains verified against this store for revoked
+  /// certificates using `crls`.  This replaces any CRLs
+  /// previously set.
+  pub fn set_crls(&mut self, crls: CRLStore) {
+    self.crls = crls;
   }
 
   /// Say how many certificates are in the container.
@@ -143,6 +186,112 @@ impl RootCertStore {
 
     Ok((valid_count, invalid_count))
   }
+
+  /// Like `add_pem_file`, but reading the file at `path`.
+  pub fn add_pem_path<P: AsRef<Path>>(&mut self, path: P) -> Result<(usize, usize), ()> {
+    let file = try!(fs::File::open(path).map_err(|_| ()));
+    self.add_pem_file(&mut io::BufReader::new(file))
+  }
+
+  /// Add the certificates in the directory `dir`, which is laid out
+  /// by OpenSSL's `c_rehash`: only files named by subject hash
+  /// (like `9d66eef0.0`) are read.
+  ///
+  /// Returns the number of certificates added, and the number
+  /// which were unsuitable or unreadable.
+  pub fn add_hashed_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result<(usize, usize), ()> {
+    let entries = try!(fs::read_dir(dir).map_err(|_| ()));
+    let mut valid_count = 0;
+    let mut invalid_count = 0;
+
+    for entry in entries {
+      let entry = match entry {
+        Ok(entry) => entry,
+        Err(_) => continue
+      };
+
+      let hashed = entry.file_name()
+        .to_str()
+        .map(is_hashed_cert_name)
+        .unwrap_or(false);
+      if !hashed {
+        continue;
+      }
+
+      match self.add_pem_path(entry.path()) {
+        Ok((valid, invalid)) => {
+          valid_count += valid;
+          invalid_count += invalid;
+        }
+        Err(_) => invalid_count += 1
+      }
+    }
+
+    Ok((valid_count, invalid_count))
+  }
+
+  /// Make a new `RootCertStore` from the certificates the system
+  /// trusts, found in the same way as OpenSSL:
+  ///
+  /// * If `SSL_CERT_FILE` or `SSL_CERT_DIR` are set in the
+  ///   environment, only those are used.  `SSL_CERT_DIR` is a
+  ///   hashed directory, or a colon-separated list of them.
+  /// * Otherwise, the first certificate bundle found in the usual
+  ///   places for Linux distributions is used.
+  /// * Failing that, the first hashed directory found is used.
+  ///
+  /// Returns the store, the number of certificates added and the
+  /// number which were unsuitable.  Fails if no certificate source
+  /// could be read.
+  pub fn from_system() -> Result<(RootCertStore, usize, usize), ()> {
+    RootCertStore::from_system_with(env::var_os("SSL_CERT_FILE"),
+                                    env::var_os("SSL_CERT_DIR"))
+  }
+
+  /// Like `from_system`, but taking the values of `SSL_CERT_FILE`
+  /// and `SSL_CERT_DIR` as `cert_file` and `cert_dirs` rather than
+  /// reading them from the environment.
+  pub fn from_system_with(cert_file: Option<OsString>,
+                          cert_dirs: Option<OsString>) -> Result<(RootCertStore, usize, usize), ()> {
+    let mut store = RootCertStore::empty();
+    let mut valid_count = 0;
+    let mut invalid_count = 0;
+    let mut found = false;
+
+    {
+      let mut note = |rc: Result<(usize, usize), ()>| {
+        if let Ok((valid, invalid)) = rc {
+          valid_count += valid;
+          invalid_count += invalid;
+          found = true;
+        }
+      };
+
+      if cert_file.is_some() || cert_dirs.is_some() {
+        if let Some(file) = cert_file {
+          note(store.add_pem_path(file));
+        }
+
+        if let Some(dirs) = cert_dirs {
+          for dir in env::split_paths(&dirs) {
+            note(store.add_hashed_dir(dir));
+          }
+        }
+      } else if let Some(file) = SYSTEM_CERT_FILES.iter().find(|f| Path::new(f).is_file()) {
+        note(store.add_pem_path(file));
+      } else if let Some(dir) = SYSTEM_CERT_DIRS.iter().find(|d| Path::new(d).is_dir()) {
+        note(store.add_hashed_dir(dir));
+      }
+    }
+
+    if !found {
+      return Err(());
+    }
+
+    info!("from_system found {} valid and {} invalid certs",
+          valid_count, invalid_count);
+    Ok((store, valid_count, invalid_count))
+  }
 }
 
 /// Check `presented_certs` is non-empty and rooted in `roots`.
@@ -176,66 +325,162 @@ fn verify_common_cert<'a>(roots: &RootCertStore,
     return Ok(cert);
   }
 
-  cert.verify_is_valid_tls_server_cert(&SUPPORTED_SIG_ALGS,
-                                       &trustroots,
-                                       &chain,
-                                       time::get_time())
-    .map_err(|err| TLSError::WebPKIError(err))
-    .map(|_| cert)
+  let now = webpki::Time::from_seconds_since_unix_epoch(time::get_time().sec as u64);
+
+  try!(
+    cert.verify_is_valid_tls_server_cert(&SUPPORTED_SIG_ALGS,
+                                         &webpki::TLSServerTrustAnchors(&trustroots),
+                                         &chain,
+                                         now)
+      .map_err(|err| TLSError::WebPKIError(err))
+  );
+
+  try!(roots.crls.check_chain(presented_certs, &trustroots));
+  Ok(cert)
 }
 
-/// Verify a the certificate chain `presented_certs` against the roots
-/// configured in `roots`.  Make sure that `dns_name` is quoted by
-/// the top certificate in the chain.
-pub fn verify_server_cert(roots: &RootCertStore,
-                          presented_certs: &Vec<ASN1Cert>,
-                          dns_name: &str) -> Result<(), TLSError> {
-  let cert = try!(verify_common_cert(roots, presented_certs));
+/// Something that can verify a server certificate chain.
+pub trait ServerCertVerifier {
+  /// Verify the certificate chain `presented_certs` (end-entity
+  /// certificate first) for the server `dns_name`.  `roots` is the
+  /// `root_store` from the `ClientConfig`.  `ocsp_response` is any
+  /// OCSP response stapled by the server, and is empty if there
+  /// was none.
+  fn verify_server_cert(&self,
+                        roots: &RootCertStore,
+                        presented_certs: &[ASN1Cert],
+                        dns_name: &str,
+                        ocsp_response: &[u8]) -> Result<(), TLSError>;
+}
 
-  if DANGEROUS_DISABLE_VERIFY {
-    warn!("DANGEROUS_DISABLE_VERIFY is turned on, skipping server name verification");
-    return Ok(());
+/// The default `ServerCertVerifier`.  This uses webpki to check
+/// the chain is rooted in `roots`, and that the end-entity
+/// certificate is valid for `dns_name`.
+pub struct WebPKIVerifier {}
+
+impl ServerCertVerifier for WebPKIVerifier {
+  fn verify_server_cert(&self,
+                        roots: &RootCertStore,
+                        presented_certs: &[ASN1Cert],
+                        dns_name: &str,
+                        _ocsp_response: &[u8]) -> Result<(), TLSError> {
+    let cert = try!(verify_common_cert(roots, presented_certs));
+
+    if DANGEROUS_DISABLE_VERIFY {
+      warn!("DANGEROUS_DISABLE_VERIFY is turned on, skipping server name verification");
+      return Ok(());
+    }
+
+    let dns_name = try!(
+      webpki::DNSNameRef::try_from_ascii_str(dns_name)
+        .map_err(|_| TLSError::WebPKIError(webpki::Error::CertNotValidForName))
+    );
+
+    cert.verify_is_valid_for_dns_name(dns_name)
+      .map_err(|err| TLSError::WebPKIError(err))
   }
+}
 
-  cert.verify_is_valid_for_dns_name(untrusted::Input::from(dns_name.as_bytes()))
-    .map_err(|err| TLSError::WebPKIError(err))
+/// Something that decides whether and how a server does client
+/// authentication.
+pub trait ClientCertVerifier {
+  /// Return true to send a CertificateRequest to clients.
+  fn offer_client_auth(&self) -> bool;
+
+  /// Return true to fail handshakes with clients which don't
+  /// send a certificate.
+  fn client_auth_mandatory(&self) -> bool;
+
+  /// The subject names of acceptable certificate authorities,
+  /// advertised in the CertificateRequest.
+  fn client_auth_root_subjects(&self) -> DistinguishedNames;
+
+  /// Verify the non-empty certificate chain `presented_certs`
+  /// (end-entity certificate first) sent by the client.
+  fn verify_client_cert(&self, presented_certs: &[ASN1Cert]) -> Result<(), TLSError>;
 }
 
-/// Verify a certificate chain `presented_certs` is rooted in `roots`.
-/// Does no further checking of the certificate.
-pub fn verify_client_cert(roots: &RootCertStore,
-                          presented_certs: &Vec<ASN1Cert>) -> Result<(), TLSError> {
-  verify_common_cert(roots, presented_certs)
-    .map(|_| ())
+/// A `ClientCertVerifier` which never asks for client certificates.
+/// This is the default.
+pub struct NoClientAuth {}
+
+impl ClientCertVerifier for NoClientAuth {
+  fn offer_client_auth(&self) -> bool { false }
+
+  fn client_auth_mandatory(&self) -> bool { false }
+
+  fn client_auth_root_subjects(&self) -> DistinguishedNames {
+    DistinguishedNames::new()
+  }
+
+  fn verify_client_cert(&self, _presented_certs: &[ASN1Cert]) -> Result<(), TLSError> {
+    Err(TLSError::General("client authentication not offered".to_string()))
+  }
+}
+
+/// A `ClientCertVerifier` which uses webpki to check client
+/// certificate chains are rooted in a `RootCertStore`.  Does no
+/// further checking of the certificate.
+pub struct WebPKIClientVerifier {
+  roots: RootCertStore,
+  mandatory: bool
+}
+
+impl WebPKIClientVerifier {
+  /// Make a verifier accepting client certificates issued by
+  /// `roots`.  If `mandatory` is true, clients which don't
+  /// send a certificate are refused.
+  pub fn new(roots: RootCertStore, mandatory: bool) -> WebPKIClientVerifier {
+    WebPKIClientVerifier {
+      roots: roots,
+      mandatory: mandatory
+    }
+  }
+}
+
+impl ClientCertVerifier for WebPKIClientVerifier {
+  fn offer_client_auth(&self) -> bool { true }
+
+  fn client_auth_mandatory(&self) -> bool { self.mandatory }
+
+  fn client_auth_root_subjects(&self) -> DistinguishedNames {
+    self.roots.get_subjects()
+  }
+
+  fn verify_client_cert(&self, presented_certs: &[ASN1Cert]) -> Result<(), TLSError> {
+    verify_common_cert(&self.roots, presented_certs)
+      .map(|_| ())
+  }
 }
 
-static ECDSA_SHA1: SignatureAlgorithms = &[
-  &webpki::ECDSA_P256_SHA1, &webpki::ECDSA_P384_SHA1
-];
 static ECDSA_SHA256: SignatureAlgorithms = &[
   &webpki::ECDSA_P256_SHA256, &webpki::ECDSA_P384_SHA256
 ];
 static ECDSA_SHA384: SignatureAlgorithms = &[
   &webpki::ECDSA_P256_SHA384, &webpki::ECDSA_P384_SHA384
 ];
-static ECDSA_SHA512: SignatureAlgorithms = &[
-  &webpki::ECDSA_P256_SHA512, &webpki::ECDSA_P384_SHA512
-];
 
 static RSA_SHA1: SignatureAlgorithms = &[ &webpki::RSA_PKCS1_2048_8192_SHA1 ];
 static RSA_SHA256: SignatureAlgorithms = &[ &webpki::RSA_PKCS1_2048_8192_SHA256 ];
 static RSA_SHA384: SignatureAlgorithms = &[ &webpki::RSA_PKCS1_2048_8192_SHA384 ];
 static RSA_SHA512: SignatureAlgorithms = &[ &webpki::RSA_PKCS1_2048_8192_SHA512 ];
+static RSA_PSS_SHA256: SignatureAlgorithms = &[ &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY ];
+static RSA_PSS_SHA384: SignatureAlgorithms = &[ &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY ];
+static RSA_PSS_SHA512: SignatureAlgorithms = &[ &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY ];
+static ED25519: SignatureAlgorithms = &[ &webpki::ED25519 ];
 
 fn convert_alg(sh: &SignatureAndHashAlgorithm) -> Result<SignatureAlgorithms, TLSError> {
-  use msgs::enums::SignatureAlgorithm::{ECDSA, RSA};
-  use msgs::enums::HashAlgorithm::{SHA1, SHA256, SHA384, SHA512};
+  use msgs::enums::SignatureAlgorithm::{ECDSA, RSA, RSAPSS_SHA256, RSAPSS_SHA384, RSAPSS_SHA512};
+  use msgs::enums::SignatureAlgorithm::ED25519 as EdDSA;
+  use msgs::enums::HashAlgorithm::{SHA1, SHA256, SHA384, SHA512, Intrinsic};
 
   match (&sh.sign, &sh.hash) {
-    (&ECDSA, &SHA1)   => Ok(ECDSA_SHA1),
+    (&EdDSA, &Intrinsic) => Ok(ED25519),
+    (&RSAPSS_SHA256, &Intrinsic) => Ok(RSA_PSS_SHA256),
+    (&RSAPSS_SHA384, &Intrinsic) => Ok(RSA_PSS_SHA384),
+    (&RSAPSS_SHA512, &Intrinsic) => Ok(RSA_PSS_SHA512),
     (&ECDSA, &SHA256) => Ok(ECDSA_SHA256),
     (&ECDSA, &SHA384) => Ok(ECDSA_SHA384),
-    (&ECDSA, &SHA512) => Ok(ECDSA_SHA512),
     (&RSA, &SHA1)     => Ok(RSA_SHA1),
     (&RSA, &SHA256)   => Ok(RSA_SHA256),
     (&RSA, &SHA384)   => Ok(RSA_SHA384),
@@ -266,6 +511,178 @@ fn verify_sig_using_any_alg(cert: &webpki::EndEntityCert,
   Err(webpki::Error::UnsupportedSignatureAlgorithmForPublicKey)
 }
 
+/// Map the contents of an X.509 AlgorithmIdentifier to the
+/// algorithms it might mean.  Parameters are ignored: for the
+/// algorithms we support they are absent or NULL.
+fn convert_alg_id(alg_id: &[u8]) -> Option<SignatureAlgorithms> {
+  let oid = try_ret!(x509::DerReader::new(alg_id).read(x509::OID));
+
+  match oid {
+    b"\x2a\x86\x48\x86\xf7\x0d\x01\x01\x05" => Some(RSA_SHA1),
+    b"\x2a\x86\x48\x86\xf7\x0d\x01\x01\x0b" => Some(RSA_SHA256),
+    b"\x2a\x86\x48\x86\xf7\x0d\x01\x01\x0c" => Some(RSA_SHA384),
+    b"\x2a\x86\x48\x86\xf7\x0d\x01\x01\x0d" => Some(RSA_SHA512),
+    b"\x2a\x86\x48\xce\x3d\x04\x03\x02" => Some(ECDSA_SHA256),
+    b"\x2a\x86\x48\xce\x3d\x04\x03\x03" => Some(ECDSA_SHA384),
+    b"\x2b\x65\x70" => Some(ED25519),
+    _ => None
+  }
+}
+
+/// Verify `sig` over `message` using the public key in the
+/// DER-encoded certificate `signer_cert`.  `alg_id` is the contents
+/// of the X.509 AlgorithmIdentifier naming the signature algorithm.
+///
+/// This is for things signed by certificate holders outside of
+/// TLS, like OCSP responses.  It says nothing about whether
+/// `signer_cert` is trustworthy.
+pub fn verify_signed_data(signer_cert: &[u8],
+                          alg_id: &[u8],
+                          message: &[u8],
+                          sig: &[u8]) -> Result<(), TLSError> {
+  let possible_algs = try!(
+    convert_alg_id(alg_id)
+      .ok_or(TLSError::WebPKIError(webpki::Error::UnsupportedSignatureAlgorithm))
+  );
+  let cert_in = untrusted::Input::from(signer_cert);
+  let cert = try!(webpki::EndEntityCert::from(cert_in)
+                  .map_err(|err| TLSError::WebPKIError(err)));
+
+  verify_sig_using_any_alg(&cert, possible_algs, message, sig)
+    .map_err(|err| TLSError::WebPKIError(err))
+}
+
+/// Read a hash AlgorithmIdentifier (contents only), returning
+/// the hash OID.  The parameters must be absent or NULL.
+fn read_hash_alg_id(alg_id: &[u8]) -> Option<&[u8]> {
+  x509::read_all(alg_id, |rd| {
+    let oid = try_ret!(rd.read(x509::OID));
+    try_ret!(rd.read_optional(x509::NULL));
+    Some(oid)
+  })
+}
+
+/// Map RSASSA-PSS-params (RFC4055, contents only) to the *ring*
+/// algorithm which can verify such signatures.  *ring* only does
+/// MGF1 using the message hash, with a salt as long as the hash,
+/// so the parameters must say exactly that.
+fn convert_pss_params(params: &[u8]) -> Option<&'static signature::VerificationAlgorithm> {
+  let (hash, mgf_hash, salt_len) = try_ret!(x509::read_all(params, |rd| {
+    let hash = try_ret!(rd.read(x509::explicit_tag(0)));
+    let hash = try_ret!(x509::read_all(hash, |rd| rd.read(x509::SEQUENCE)));
+    let hash = try_ret!(read_hash_alg_id(hash));
+
+    let mgf = try_ret!(rd.read(x509::explicit_tag(1)));
+    let mgf = try_ret!(x509::read_all(mgf, |rd| rd.read(x509::SEQUENCE)));
+    let mgf_hash = try_ret!(x509::read_all(mgf, |rd| {
+      let mgf_oid = try_ret!(rd.read(x509::OID));
+      let mgf_hash = try_ret!(rd.read(x509::SEQUENCE));
+      if mgf_oid == b"\x2a\x86\x48\x86\xf7\x0d\x01\x01\x08" {
+        read_hash_alg_id(mgf_hash)
+      } else {
+        None
+      }
+    }));
+
+    let salt_len = try_ret!(rd.read(x509::explicit_tag(2)));
+    let salt_len = try_ret!(x509::read_all(salt_len, |rd| rd.read(x509::INTEGER)));
+
+    /* The trailer field, if present, must be the default. */
+    if let Some(trailer) = try_ret!(rd.read_optional(x509::explicit_tag(3))) {
+      if trailer != b"\x02\x01\x01" {
+        return None;
+      }
+    }
+
+    Some((hash, mgf_hash, salt_len))
+  }));
+
+  if hash != mgf_hash {
+    return None;
+  }
+
+  match (hash, salt_len) {
+    (b"\x60\x86\x48\x01\x65\x03\x04\x02\x01", b"\x20") => Some(&signature::RSA_PSS_2048_8192_SHA256),
+    (b"\x60\x86\x48\x01\x65\x03\x04\x02\x02", b"\x30") => Some(&signature::RSA_PSS_2048_8192_SHA384),
+    (b"\x60\x86\x48\x01\x65\x03\x04\x02\x03", b"\x40") => Some(&signature::RSA_PSS_2048_8192_SHA512),
+    _ => None
+  }
+}
+
+/// Map a public key AlgorithmIdentifier and signature
+/// AlgorithmIdentifier (both contents only) to the single
+/// *ring* algorithm which can verify such signatures.
+fn convert_key_and_alg_id(key_alg_id: &[u8],
+                          alg_id: &[u8]) -> Option<&'static signature::VerificationAlgorithm> {
+  let mut rd = x509::DerReader::new(key_alg_id);
+  let key_oid = try_ret!(rd.read(x509::OID));
+  let mut sig_rd = x509::DerReader::new(alg_id);
+  let sig_oid = try_ret!(sig_rd.read(x509::OID));
+
+  if key_oid == x509::RSA_ENCRYPTION {
+    return match sig_oid {
+      b"\x2a\x86\x48\x86\xf7\x0d\x01\x01\x05" => Some(&signature::RSA_PKCS1_2048_8192_SHA1),
+      b"\x2a\x86\x48\x86\xf7\x0d\x01\x01\x0b" => Some(&signature::RSA_PKCS1_2048_8192_SHA256),
+      b"\x2a\x86\x48\x86\xf7\x0d\x01\x01\x0c" => Some(&signature::RSA_PKCS1_2048_8192_SHA384),
+      b"\x2a\x86\x48\x86\xf7\x0d\x01\x01\x0d" => Some(&signature::RSA_PKCS1_2048_8192_SHA512),
+      b"\x2a\x86\x48\x86\xf7\x0d\x01\x01\x0a" => {
+        let params = try_ret!(sig_rd.read(x509::SEQUENCE));
+        convert_pss_params(params)
+      }
+      _ => None
+    };
+  }
+
+  if key_oid == x509::ED25519 && sig_oid == x509::ED25519 {
+    return Some(&signature::ED25519);
+  }
+
+  if key_oid != x509::EC_PUBLIC_KEY {
+    return None;
+  }
+
+  let curve = try_ret!(rd.read(x509::OID));
+  let sha256 = sig_oid == b"\x2a\x86\x48\xce\x3d\x04\x03\x02";
+  let sha384 = sig_oid == b"\x2a\x86\x48\xce\x3d\x04\x03\x03";
+
+  if curve == x509::SECP256R1 && sha256 {
+    Some(&signature::ECDSA_P256_SHA256_ASN1)
+  } else if curve == x509::SECP256R1 && sha384 {
+    Some(&signature::ECDSA_P256_SHA384_ASN1)
+  } else if curve == x509::SECP384R1 && sha256 {
+    Some(&signature::ECDSA_P384_SHA256_ASN1)
+  } else if curve == x509::SECP384R1 && sha384 {
+    Some(&signature::ECDSA_P384_SHA384_ASN1)
+  } else {
+    None
+  }
+}
+
+/// Like `verify_signed_data`, but using a bare public key: `spki`
+/// is the contents of a SubjectPublicKeyInfo.  This is needed for
+/// things signed by trust anchors, for which we don't keep the
+/// certificate.
+pub fn verify_signed_data_with_spki(spki: &[u8],
+                                    alg_id: &[u8],
+                                    message: &[u8],
+                                    sig: &[u8]) -> Result<(), TLSError> {
+  let unsupported = TLSError::WebPKIError(webpki::Error::UnsupportedSignatureAlgorithm);
+
+  let parsed = x509::read_all(spki, |rd| {
+    let key_alg_id = try_ret!(rd.read(x509::SEQUENCE));
+    let key = try_ret!(rd.read_bit_string());
+    Some((key_alg_id, key))
+  });
+  let (key_alg_id, key) = try!(parsed.ok_or(unsupported.clone()));
+  let alg = try!(convert_key_and_alg_id(key_alg_id, alg_id).ok_or(unsupported));
+
+  signature::verify(alg,
+                    untrusted::Input::from(key),
+                    untrusted::Input::from(message),
+                    untrusted::Input::from(sig))
+    .map_err(|_| TLSError::WebPKIError(webpki::Error::InvalidSignatureForPublicKey))
+}
+
 /// Verify the signed `message` using the public key quoted in
 /// `cert` and algorithm and signature in `dss`.
 ///
@@ -284,3 +701,59 @@ pub fn verify_signed_struct(message: &[u8],
     .map_err(|err| TLSError::WebPKIError(err))
 }
 
+
+/// TLS1.3 signature schemes name a single algorithm: ECDSA schemes
+/// also fix the curve, and PKCS#1 schemes are not allowed.
+fn convert_alg_tls13(sh: &SignatureAndHashAlgorithm) -> Result<SignatureAlgorithms, TLSError> {
+  use msgs::enums::SignatureAlgorithm::{ECDSA, RSAPSS_SHA256, RSAPSS_SHA384, RSAPSS_SHA512};
+  use msgs::enums::SignatureAlgorithm::ED25519 as EdDSA;
+  use msgs::enums::HashAlgorithm::{SHA256, SHA384, Intrinsic};
+
+  static ECDSA_P256_SHA256: SignatureAlgorithms = &[ &webpki::ECDSA_P256_SHA256 ];
+  static ECDSA_P384_SHA384: SignatureAlgorithms = &[ &webpki::ECDSA_P384_SHA384 ];
+
+  match (&sh.sign, &sh.hash) {
+    (&ECDSA, &SHA256) => Ok(ECDSA_P256_SHA256),
+    (&ECDSA, &SHA384) => Ok(ECDSA_P384_SHA384),
+    (&RSAPSS_SHA256, &Intrinsic) => Ok(RSA_PSS_SHA256),
+    (&RSAPSS_SHA384, &Intrinsic) => Ok(RSA_PSS_SHA384),
+    (&RSAPSS_SHA512, &Intrinsic) => Ok(RSA_PSS_SHA512),
+    (&EdDSA, &Intrinsic) => Ok(ED25519),
+    _ => {
+      let error_msg = format!("received unsupported sig scheme {:?} {:?}",
+                              sh.sign, sh.hash);
+      Err(TLSError::PeerMisbehavedError(error_msg))
+    }
+  }
+}
+
+/// Construct the message signed by a TLS1.3 CertificateVerify.
+/// `context_string_with_0` names the signing party, and is
+/// terminated by a zero byte.
+pub fn construct_tls13_verify_message(handshake_hash: &[u8],
+                                      context_string_with_0: &[u8]) -> Vec<u8> {
+  let mut msg = Vec::new();
+  msg.resize(64, 0x20u8);
+  msg.extend_from_slice(context_string_with_0);
+  msg.extend_from_slice(handshake_hash);
+  msg
+}
+
+/// Verify the TLS1.3 CertificateVerify signature `dss` over
+/// `handshake_hash`, using the public key in `cert`.
+///
+/// `cert` MUST have been authenticated before using this function.
+pub fn verify_tls13(cert: &ASN1Cert,
+                    dss: &DigitallySignedStruct,
+                    handshake_hash: &[u8],
+                    context_string_with_0: &[u8]) -> Result<(), TLSError> {
+  let possible_algs = try!(convert_alg_tls13(&dss.alg));
+  let cert_in = untrusted::Input::from(&cert.0);
+  let cert = try!(webpki::EndEntityCert::from(cert_in)
+                  .map_err(|err| TLSError::WebPKIError(err)));
+
+  let msg = construct_tls13_verify_message(handshake_hash, context_string_with_0);
+
+  verify_sig_using_any_alg(&cert, &possible_algs, &msg, &dss.sig.0)
+    .map_err(|err| TLSError::WebPKIError(err))
+}
@@ -1,6 +1,6 @@
-/* Additional x509/asn1 functions to those provided in webpki/ring. */
+/* Additional x509/asn1 functions to those provided in webpki. */
 
-use ring::der;
+use time;
 
 fn wrap_in_asn1_len(bytes: &mut Vec<u8>) {
   let len = bytes.len();
@@ -14,11 +14,349 @@ fn wrap_in_asn1_len(bytes: &mut Vec<u8>) {
     bytes.insert(0, 0x82u8);
     bytes.insert(1, ((len >> 8) & 0xff) as u8);
     bytes.insert(2, (len & 0xff) as u8);
+  } else {
+    bytes.insert(0, 0x83u8);
+    bytes.insert(1, ((len >> 16) & 0xff) as u8);
+    bytes.insert(2, ((len >> 8) & 0xff) as u8);
+    bytes.insert(3, (len & 0xff) as u8);
   }
 }
 
 /// Prepend stuff to `bytes` to put it in a DER SEQUENCE.
 pub fn wrap_in_sequence(bytes: &mut Vec<u8>) {
+  wrap_in_tag(SEQUENCE, bytes);
+}
+
+/// Prepend stuff to `bytes` to put it in a DER element
+/// with tag `tag`.
+pub fn wrap_in_tag(tag: u8, bytes: &mut Vec<u8>) {
   wrap_in_asn1_len(bytes);
-  bytes.insert(0, der::Tag::Sequence as u8);
+  bytes.insert(0, tag);
+}
+
+/* DER tags we need to parse certificates and related structures. */
+pub const BOOLEAN: u8 = 0x01;
+pub const INTEGER: u8 = 0x02;
+pub const BIT_STRING: u8 = 0x03;
+pub const OCTET_STRING: u8 = 0x04;
+pub const NULL: u8 = 0x05;
+pub const OID: u8 = 0x06;
+pub const ENUMERATED: u8 = 0x0a;
+pub const UTC_TIME: u8 = 0x17;
+pub const GENERALIZED_TIME: u8 = 0x18;
+pub const SEQUENCE: u8 = 0x30;
+pub const CONTEXT_SPECIFIC: u8 = 0x80;
+pub const CONSTRUCTED: u8 = 0x20;
+
+/* Public key algorithm identifiers. */
+
+/// rsaEncryption
+pub static RSA_ENCRYPTION: &'static [u8] = b"\x2a\x86\x48\x86\xf7\x0d\x01\x01\x01";
+
+/// id-ecPublicKey
+pub static EC_PUBLIC_KEY: &'static [u8] = b"\x2a\x86\x48\xce\x3d\x02\x01";
+
+/// id-Ed25519, which names both the key and signature algorithm.
+pub static ED25519: &'static [u8] = b"\x2b\x65\x70";
+
+/// secp256r1
+pub static SECP256R1: &'static [u8] = b"\x2a\x86\x48\xce\x3d\x03\x01\x07";
+
+/// secp384r1
+pub static SECP384R1: &'static [u8] = b"\x2b\x81\x04\x00\x22";
+
+/// The tag for an explicitly tagged `[n]` field.
+pub fn explicit_tag(n: u8) -> u8 {
+  CONTEXT_SPECIFIC | CONSTRUCTED | n
+}
+
+/// A simple reader for DER, in the style of `msgs::codec::Reader`.
+/// It only supports the definite, short-tag encodings that DER
+/// mandates.
+pub struct DerReader<'a> {
+  buf: &'a [u8],
+  offs: usize
+}
+
+impl<'a> DerReader<'a> {
+  pub fn new(bytes: &'a [u8]) -> DerReader<'a> {
+    DerReader { buf: bytes, offs: 0 }
+  }
+
+  pub fn at_end(&self) -> bool {
+    self.offs == self.buf.len()
+  }
+
+  /// Return the tag of the next element, without consuming it.
+  pub fn peek_tag(&self) -> Option<u8> {
+    self.buf.get(self.offs).cloned()
+  }
+
+  fn take_byte(&mut self) -> Option<u8> {
+    let b = try_ret!(self.buf.get(self.offs).cloned());
+    self.offs += 1;
+    Some(b)
+  }
+
+  /// Read any element.  Returns its tag, its contents, and
+  /// its complete encoding (including tag and length).
+  pub fn read_any(&mut self) -> Option<(u8, &'a [u8], &'a [u8])> {
+    let start = self.offs;
+    let tag = try_ret!(self.take_byte());
+
+    /* We don't support high tag numbers. */
+    if tag & 0x1f == 0x1f {
+      return None;
+    }
+
+    let len = match try_ret!(self.take_byte()) {
+      x if x < 0x80 => x as usize,
+      0x81 => try_ret!(self.take_byte()) as usize,
+      0x82 => {
+        let hi = try_ret!(self.take_byte()) as usize;
+        let lo = try_ret!(self.take_byte()) as usize;
+        (hi << 8) | lo
+      }
+      0x83 => {
+        let hi = try_ret!(self.take_byte()) as usize;
+        let mid = try_ret!(self.take_byte()) as usize;
+        let lo = try_ret!(self.take_byte()) as usize;
+        (hi << 16) | (mid << 8) | lo
+      }
+      _ => return None
+    };
+
+    if self.buf.len() - self.offs < len {
+      return None;
+    }
+
+    let body = &self.buf[self.offs..self.offs + len];
+    self.offs += len;
+    Some((tag, body, &self.buf[start..self.offs]))
+  }
+
+  /// Read an element which must have tag `tag`, returning its
+  /// contents.
+  pub fn read(&mut self, tag: u8) -> Option<&'a [u8]> {
+    self.read_with_encoding(tag)
+      .map(|(body, _)| body)
+  }
+
+  /// Read an element which must have tag `tag`, returning its
+  /// contents and its complete encoding.
+  pub fn read_with_encoding(&mut self, tag: u8) -> Option<(&'a [u8], &'a [u8])> {
+    let (got, body, all) = try_ret!(self.read_any());
+    if got != tag {
+      return None;
+    }
+    Some((body, all))
+  }
+
+  /// Read an element with tag `tag` if it is next, otherwise
+  /// return `Some(None)`.  Returns `None` on errors.
+  pub fn read_optional(&mut self, tag: u8) -> Option<Option<&'a [u8]>> {
+    if self.peek_tag() == Some(tag) {
+      self.read(tag).map(Some)
+    } else {
+      Some(None)
+    }
+  }
+
+  /// Read a BIT STRING containing a whole number of bytes.
+  pub fn read_bit_string(&mut self) -> Option<&'a [u8]> {
+    let body = try_ret!(self.read(BIT_STRING));
+    if body.is_empty() || body[0] != 0 {
+      return None;
+    }
+    Some(&body[1..])
+  }
+
+  /// Read a UTCTime or GeneralizedTime.
+  pub fn read_time(&mut self) -> Option<time::Timespec> {
+    let (tag, body, _) = try_ret!(self.read_any());
+    parse_time(tag, body)
+  }
+}
+
+/// Run `f` over the contents of `bytes`, failing if it doesn't
+/// consume all of them.
+pub fn read_all<'a, T, F>(bytes: &'a [u8], f: F) -> Option<T>
+  where F: FnOnce(&mut DerReader<'a>) -> Option<T> {
+  let mut rd = DerReader::new(bytes);
+  let ret = try_ret!(f(&mut rd));
+  if rd.at_end() {
+    Some(ret)
+  } else {
+    None
+  }
+}
+
+fn parse_digits(bytes: &[u8]) -> Option<i64> {
+  let mut r = 0i64;
+  for b in bytes {
+    if *b < b'0' || *b > b'9' {
+      return None;
+    }
+    r = r * 10 + (*b - b'0') as i64;
+  }
+  Some(r)
+}
+
+/// Days since the epoch of a civil date.  This is Howard Hinnant's
+/// `days_from_civil` algorithm.
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+  let y = if m <= 2 { y - 1 } else { y };
+  let era = (if y >= 0 { y } else { y - 399 }) / 400;
+  let yoe = y - era * 400;
+  let mp = (m + 9) % 12;
+  let doy = (153 * mp + 2) / 5 + d - 1;
+  let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+  era * 146097 + doe - 719468
+}
+
+/// Parse the contents of a UTCTime or GeneralizedTime.  Only the
+/// forms allowed by RFC5280 are accepted.
+pub fn parse_time(tag: u8, body: &[u8]) -> Option<time::Timespec> {
+  let (year, rest) = match (tag, body.len()) {
+    (UTC_TIME, 13) => {
+      let yy = try_ret!(parse_digits(&body[..2]));
+      (if yy >= 50 { 1900 + yy } else { 2000 + yy }, &body[2..])
+    }
+    (GENERALIZED_TIME, 15) => (try_ret!(parse_digits(&body[..4])), &body[4..]),
+    _ => return None
+  };
+
+  if rest[10] != b'Z' {
+    return None;
+  }
+
+  let month = try_ret!(parse_digits(&rest[0..2]));
+  let day = try_ret!(parse_digits(&rest[2..4]));
+  let hour = try_ret!(parse_digits(&rest[4..6]));
+  let min = try_ret!(parse_digits(&rest[6..8]));
+  let sec = try_ret!(parse_digits(&rest[8..10]));
+
+  if month < 1 || month > 12 || day < 1 || day > 31 ||
+    hour > 23 || min > 59 || sec > 59 {
+    return None;
+  }
+
+  let days = days_from_civil(year, month, day);
+  Some(time::Timespec::new(days * 86400 + hour * 3600 + min * 60 + sec, 0))
+}
+
+/// One X.509v3 extension.  We don't care about criticality.
+pub struct Extension<'a> {
+  pub id: &'a [u8],
+  pub value: &'a [u8]
+}
+
+/// The parts of an X.509 certificate we need beyond what webpki
+/// gives us.  Byte slices borrow from the certificate's encoding.
+pub struct CertificateFields<'a> {
+  /// The complete encoding of the TBSCertificate, which is what
+  /// the issuer signed.
+  pub tbs: &'a [u8],
+  /// The contents of the signature AlgorithmIdentifier.
+  pub signature_alg: &'a [u8],
+  pub signature: &'a [u8],
+  /// The contents of the serialNumber INTEGER.
+  pub serial: &'a [u8],
+  /// The complete encoding of the issuer Name.
+  pub issuer: &'a [u8],
+  pub not_before: time::Timespec,
+  pub not_after: time::Timespec,
+  /// The complete encoding of the subject Name.
+  pub subject: &'a [u8],
+  /// The complete encoding of the SubjectPublicKeyInfo.
+  pub spki: &'a [u8],
+  pub extensions: Vec<Extension<'a>>
+}
+
+impl<'a> CertificateFields<'a> {
+  /// Find the extension with the given DER-encoded OID contents.
+  pub fn find_extension(&self, id: &[u8]) -> Option<&Extension<'a>> {
+    self.extensions.iter().find(|ext| ext.id == id)
+  }
+
+  /// Return the subjectPublicKey bits from the SubjectPublicKeyInfo.
+  pub fn public_key(&self) -> Option<&'a [u8]> {
+    let spki = self.spki;
+    read_all(spki, |rd| {
+      let body = try_ret!(rd.read(SEQUENCE));
+      read_all(body, |rd| {
+        try_ret!(rd.read(SEQUENCE));
+        rd.read_bit_string()
+      })
+    })
+  }
+}
+
+fn parse_extensions<'a>(body: &'a [u8]) -> Option<Vec<Extension<'a>>> {
+  let mut ret = Vec::new();
+  let mut rd = DerReader::new(body);
+
+  while !rd.at_end() {
+    let ext = try_ret!(rd.read(SEQUENCE));
+    ret.push(try_ret!(read_all(ext, |rd| {
+      let id = try_ret!(rd.read(OID));
+      try_ret!(rd.read_optional(BOOLEAN));
+      let value = try_ret!(rd.read(OCTET_STRING));
+      Some(Extension { id: id, value: value })
+    })));
+  }
+
+  Some(ret)
+}
+
+/// Parse the DER-encoded certificate `der`.  This does no
+/// validation beyond checking its structure.
+pub fn parse_certificate<'a>(der: &'a [u8]) -> Option<CertificateFields<'a>> {
+  let outer = try_ret!(read_all(der, |rd| rd.read(SEQUENCE)));
+
+  read_all(outer, |rd| {
+    let (tbs_body, tbs) = try_ret!(rd.read_with_encoding(SEQUENCE));
+    let signature_alg = try_ret!(rd.read(SEQUENCE));
+    let signature = try_ret!(rd.read_bit_string());
+
+    read_all(tbs_body, |rd| {
+      try_ret!(rd.read_optional(explicit_tag(0)));
+      let serial = try_ret!(rd.read(INTEGER));
+      try_ret!(rd.read(SEQUENCE));
+      let (_, issuer) = try_ret!(rd.read_with_encoding(SEQUENCE));
+      let validity = try_ret!(rd.read(SEQUENCE));
+      let (not_before, not_after) = try_ret!(read_all(validity, |rd| {
+        let not_before = try_ret!(rd.read_time());
+        let not_after = try_ret!(rd.read_time());
+        Some((not_before, not_after))
+      }));
+      let (_, subject) = try_ret!(rd.read_with_encoding(SEQUENCE));
+      let (_, spki) = try_ret!(rd.read_with_encoding(SEQUENCE));
+
+      /* Skip issuerUniqueID and subjectUniqueID. */
+      try_ret!(rd.read_optional(CONTEXT_SPECIFIC | 1));
+      try_ret!(rd.read_optional(CONTEXT_SPECIFIC | 2));
+
+      let extensions = match try_ret!(rd.read_optional(explicit_tag(3))) {
+        Some(exts) => {
+          let exts = try_ret!(read_all(exts, |rd| rd.read(SEQUENCE)));
+          try_ret!(parse_extensions(exts))
+        }
+        None => Vec::new()
+      };
+
+      Some(CertificateFields {
+        tbs: tbs,
+        signature_alg: signature_alg,
+        signature: signature,
+        serial: serial,
+        issuer: issuer,
+        not_before: not_before,
+        not_after: not_after,
+        subject: subject,
+        spki: spki,
+        extensions: extensions
+      })
+    })
+  })
 }
@@ -2,8 +2,8 @@
 
 set -xe
 
-rm -rf rsa/ ecdsa/
-mkdir -p rsa/ ecdsa/
+rm -rf rsa/ ecdsa/ eddsa/
+mkdir -p rsa/ ecdsa/ eddsa/
 
 openssl req -nodes \
           -x509 \
@@ -32,11 +32,12 @@ openssl req -nodes \
           -subj "/CN=testserver.com"
 
 openssl rsa \
+          -traditional \
           -in rsa/end.key \
           -out rsa/end.rsa
 
 openssl req -nodes \
-          -newkey rsa:2560 \
+          -newkey rsa:3072 \
           -keyout rsa/client.key \
           -out rsa/client.req \
           -sha256 \
@@ -84,7 +85,38 @@ openssl req -nodes \
           -days 2000 \
           -subj "/CN=ponytown client"
 
-for kt in rsa ecdsa ; do
+# eddsa
+openssl req -nodes \
+          -x509 \
+          -newkey ed25519 \
+          -keyout eddsa/ca.key \
+          -out eddsa/ca.cert \
+          -batch \
+          -days 3650 \
+          -subj "/CN=ponytown EdDSA CA"
+
+openssl req -nodes \
+          -newkey ed25519 \
+          -keyout eddsa/inter.key \
+          -out eddsa/inter.req \
+          -batch \
+          -subj "/CN=ponytown EdDSA level 2 intermediate"
+
+openssl req -nodes \
+          -newkey ed25519 \
+          -keyout eddsa/end.key \
+          -out eddsa/end.req \
+          -batch \
+          -subj "/CN=testserver.com"
+
+openssl req -nodes \
+          -newkey ed25519 \
+          -keyout eddsa/client.key \
+          -out eddsa/client.req \
+          -batch \
+          -subj "/CN=ponytown client"
+
+for kt in rsa ecdsa eddsa ; do
   openssl x509 -req \
             -in $kt/inter.req \
             -out $kt/inter.cert \
@@ -123,3 +155,86 @@ for kt in rsa ecdsa ; do
 
   openssl asn1parse -in $kt/ca.cert -out $kt/ca.der > /dev/null
 done
+
+# stapled OCSP responses for rsa/end.cert: one good, one revoked
+printf 'V\t991231235959Z\t\t01C8\tunknown\t/CN=testserver.com\n' > rsa/ocsp-good.txt
+printf 'R\t991231235959Z\t160814000000Z,keyCompromise\t01C8\tunknown\t/CN=testserver.com\n' > rsa/ocsp-revoked.txt
+
+openssl ocsp -issuer rsa/inter.cert -cert rsa/end.cert -no_nonce -reqout rsa/ocsp.req
+
+for status in good revoked ; do
+  openssl ocsp -index rsa/ocsp-$status.txt \
+               -reqin rsa/ocsp.req \
+               -CA rsa/inter.cert \
+               -rsigner rsa/inter.cert \
+               -rkey rsa/inter.key \
+               -rmd sha256 \
+               -ndays 3650 \
+               -respout rsa/end.$status.ocsp
+done
+
+rm rsa/ocsp.req rsa/ocsp-good.txt rsa/ocsp-revoked.txt
+
+# a certificate transparency log, and an SCT from it for rsa/end.cert
+openssl ecparam -name prime256v1 -genkey -noout -out ct-log.key
+openssl ec -in ct-log.key -pubout -out ct-log.pem
+./make-sct.py ct-log.key ct-log.pem rsa/end.cert rsa/end.sct
+
+# CRLs: from rsa/inter.cert revoking nothing, and revoking rsa/end.cert and
+# rsa/client.cert; and from rsa/ca.cert revoking rsa/inter.cert
+mkdir -p crl-tmp
+cat > crl-tmp/ca.cnf <<CNF
+[ ca ]
+default_ca = crl_ca
+
+[ crl_ca ]
+database = crl-tmp/index.txt
+default_md = sha256
+crlnumber = crl-tmp/crlnumber
+CNF
+
+gencrl() {
+  printf "$3" > crl-tmp/index.txt
+  echo 01 > crl-tmp/crlnumber
+  openssl ca -gencrl -config crl-tmp/ca.cnf \
+             -cert rsa/$1.cert -keyfile rsa/$1.key \
+             -crldays 3650 -out rsa/$2.crl
+}
+
+gencrl inter inter.empty ''
+gencrl inter inter.revoked 'R\t991231235959Z\t160814000000Z,keyCompromise\t01C8\tunknown\t/CN=testserver.com\nR\t991231235959Z\t160814000000Z\t0315\tunknown\t/CN=ponytown client\n'
+gencrl ca ca.revoked 'R\t991231235959Z\t160814000000Z\t7B\tunknown\t/CN=ponytown RSA level 2 intermediate\n'
+
+rm -r crl-tmp
+
+# ecdsa/end.key in SEC1 format
+openssl ec -in ecdsa/end.key -out ecdsa/end.sec1
+
+# ... and again, without the optional public key
+openssl ec -in ecdsa/end.key -no_public -out ecdsa/end.nopub.sec1
+
+# an expired version of ecdsa/end.cert
+openssl x509 -req \
+          -in ecdsa/end.req \
+          -out ecdsa/end.expired.cert \
+          -CA ecdsa/inter.cert \
+          -CAkey ecdsa/inter.key \
+          -sha256 \
+          -not_before 20150101000000Z \
+          -not_after 20160101000000Z \
+          -set_serial 457 \
+          -extensions v3_end -extfile openssl.cnf
+
+# a version of rsa/end.cert signed with RSASSA-PSS
+openssl x509 -req \
+          -in rsa/end.req \
+          -out rsa/end.pss.cert \
+          -CA rsa/inter.cert \
+          -CAkey rsa/inter.key \
+          -sha256 \
+          -sigopt rsa_padding_mode:pss \
+          -sigopt rsa_pss_saltlen:digest \
+          -days 2000 \
+          -set_serial 458 \
+          -extensions v3_end -extfile openssl.cnf
+cat rsa/end.pss.cert rsa/inter.cert rsa/ca.cert > rsa/end.pss.fullchain
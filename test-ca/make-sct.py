@@ -0,0 +1,37 @@
+#!/usr/bin/env python3
+# Make a TLS-encoded SignedCertificateTimestampList for a certificate,
+# signed by a test certificate transparency log key.
+#
+# usage: make-sct.py <log.key> <log.pem> <cert.pem> <out.sct>
+
+import base64
+import hashlib
+import struct
+import subprocess
+import sys
+import time
+
+log_key, log_pub, cert_pem, out = sys.argv[1:]
+
+def pem_to_der(path):
+    lines = open(path).read().splitlines()
+    return base64.b64decode(''.join(l for l in lines if not l.startswith('-----')))
+
+cert = pem_to_der(cert_pem)
+log_id = hashlib.sha256(pem_to_der(log_pub)).digest()
+timestamp = int(time.time() * 1000) - 60000
+extensions = b''
+
+signed = struct.pack('>BBQH', 0, 0, timestamp, 0)
+signed += struct.pack('>I', len(cert))[1:] + cert
+signed += struct.pack('>H', len(extensions)) + extensions
+
+sig = subprocess.run(['openssl', 'dgst', '-sha256', '-sign', log_key],
+                     input=signed, stdout=subprocess.PIPE, check=True).stdout
+
+sct = struct.pack('>B', 0) + log_id + struct.pack('>Q', timestamp)
+sct += struct.pack('>H', len(extensions)) + extensions
+sct += struct.pack('>BBH', 4, 3, len(sig)) + sig
+
+sct_list = struct.pack('>H', len(sct)) + sct
+open(out, 'wb').write(struct.pack('>H', len(sct_list)) + sct_list)
@@ -1,22 +1,41 @@
 /* Assorted public API tests. */
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::fs;
-use std::io::{self, Write};
+use std::env;
+use std::process;
+use std::io::{self, Read, Write};
 
 extern crate rustls;
 use rustls::{ClientConfig, ClientSession};
 use rustls::{ServerConfig, ServerSession};
 use rustls::Session;
+use rustls::ProtocolVersion;
+use rustls::{RootCertStore, ServerCertVerifier, ClientCertVerifier, TLSError};
+use rustls::{CRLStore, WebPKIClientVerifier, ResolvesClientCert, ResolvesClientCertUsingIssuers};
+use rustls::{ResolvesCert, ResolvesServerCertUsingSNI, ResolvesMultipleServerCerts};
+use rustls::OCSPPolicy;
+use rustls::CTPolicy;
+use rustls::{KeyLog, KeyLogFile};
+use rustls::Stream;
+use rustls::DirectionSecrets;
+use rustls::{ALL_CIPHERSUITES, SupportedCipherSuite};
+use rustls::internal::msgs::enums::{AlertDescription, SignatureAlgorithm, HashAlgorithm};
+use rustls::internal::msgs::enums::{NamedCurve, ECPointFormat, ClientCertificateType};
+use rustls::sign;
 use rustls::internal::pemfile;
+use rustls::internal::msgs::handshake::{ASN1Cert, DistinguishedNames, SignatureAndHashAlgorithm};
+use rustls::internal::msgs::handshake::{SupportedSignatureAlgorithms, EllipticCurveList};
 
-fn transfer(left: &mut Session, right: &mut Session) {
+fn transfer(left: &mut Session, right: &mut Session) -> usize {
   let mut buf = [0u8; 262144];
+  let mut total = 0;
 
   while left.wants_write() {
     let sz = left.write_tls(&mut buf.as_mut()).unwrap();
     if sz == 0 {
-      return;
+      return total;
     }
+    total += sz;
 
     let mut offs = 0;
     loop {
@@ -26,6 +45,8 @@ fn transfer(left: &mut Session, right: &mut Session) {
       }
     }
   }
+
+  total
 }
 
 fn get_chain() -> Vec<Vec<u8>> {
@@ -44,9 +65,25 @@ fn get_key() -> Vec<u8> {
   ).unwrap()[0].clone()
 }
 
+fn get_pkcs8_chain(keytype: &str, which: &str) -> Vec<Vec<u8>> {
+  pemfile::certs(
+    &mut io::BufReader::new(
+      fs::File::open(format!("test-ca/{}/{}.fullchain", keytype, which)).unwrap()
+    )
+  ).unwrap()
+}
+
+fn get_pkcs8_key(keytype: &str, which: &str) -> Vec<u8> {
+  pemfile::pkcs8_private_keys(
+    &mut io::BufReader::new(
+      fs::File::open(format!("test-ca/{}/{}.key", keytype, which)).unwrap()
+    )
+  ).unwrap()[0].clone()
+}
+
 fn make_server_config() -> ServerConfig {
   let mut cfg = ServerConfig::new();
-  cfg.set_single_cert(get_chain(), get_key());
+  cfg.set_single_cert(get_chain(), get_key()).unwrap();
 
   cfg
 }
@@ -64,22 +101,56 @@ fn make_client_config() -> ClientConfig {
 fn do_handshake(client: &mut ClientSession, server: &mut ServerSession) {
   assert_eq!(server.is_handshaking(), true);
   assert_eq!(client.is_handshaking(), true);
-  transfer(client, server);
-  server.process_new_packets().unwrap();
-  assert_eq!(server.is_handshaking(), true);
-  assert_eq!(client.is_handshaking(), true);
-  transfer(server, client);
-  client.process_new_packets().unwrap();
-  assert_eq!(server.is_handshaking(), true);
-  assert_eq!(client.is_handshaking(), true);
-  transfer(client, server);
-  server.process_new_packets().unwrap();
-  assert_eq!(server.is_handshaking(), false);
-  assert_eq!(client.is_handshaking(), true);
-  transfer(server, client);
-  client.process_new_packets().unwrap();
+
+  complete_handshake(client, server, 0);
+}
+
+/* Finish a handshake in which `flights` flights have already
+ * been sent, checking it takes the expected number of round
+ * trips: each flight is half of one. */
+fn complete_handshake(client: &mut ClientSession, server: &mut ServerSession,
+                      mut flights: usize) {
+  for _ in 0..4 {
+    if !server.is_handshaking() && !client.is_handshaking() {
+      break;
+    }
+    if transfer(client, server) > 0 {
+      flights += 1;
+    }
+    server.process_new_packets().unwrap();
+
+    if !server.is_handshaking() && !client.is_handshaking() {
+      break;
+    }
+    if transfer(server, client) > 0 {
+      flights += 1;
+    }
+    client.process_new_packets().unwrap();
+  }
+
   assert_eq!(server.is_handshaking(), false);
   assert_eq!(client.is_handshaking(), false);
+
+  /* TLS1.2 takes two round trips; TLS1.3 takes one and a half. */
+  match client.get_protocol_version() {
+    Some(ProtocolVersion::TLSv1_3) => assert_eq!(flights, 3),
+    _ => assert_eq!(flights, 4)
+  }
+}
+
+/* Run `f` once for each protocol version. */
+fn for_each_version<F: FnMut(ProtocolVersion)>(mut f: F) {
+  for version in vec![ProtocolVersion::TLSv1_2, ProtocolVersion::TLSv1_3] {
+    f(version);
+  }
+}
+
+fn make_pair_for_version(version: ProtocolVersion) -> (ClientSession, ServerSession) {
+  let mut client_config = make_client_config();
+  client_config.versions = vec![version];
+
+  (ClientSession::new(&Arc::new(client_config), "localhost"),
+   ServerSession::new(&Arc::new(make_server_config())))
 }
 
 fn alpn_test(server_protos: Vec<String>,
@@ -134,93 +205,1582 @@ fn alpn() {
             None);
 }
 
-fn check_read(reader: &mut io::Read, bytes: &[u8]) {
-  let mut buf = Vec::new();
-  assert_eq!(bytes.len(), reader.read_to_end(&mut buf).unwrap());
-  assert_eq!(bytes.to_vec(), buf);
-}
-
 #[test]
-fn buffered_client_data_sent() {
-  let client_config = make_client_config();
+fn client_can_limit_versions() {
+  let mut client_config = make_client_config();
   let server_config = make_server_config();
+
+  client_config.versions = vec![ProtocolVersion::TLSv1_2];
+
   let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
   let mut server = ServerSession::new(&Arc::new(server_config));
 
   assert_eq!(5, client.write(b"hello").unwrap());
-  
+
   do_handshake(&mut client, &mut server);
   transfer(&mut client, &mut server);
   server.process_new_packets().unwrap();
-  
+
   check_read(&mut server, b"hello");
 }
 
 #[test]
-fn buffered_server_data_sent() {
+fn server_can_limit_versions() {
   let client_config = make_client_config();
-  let server_config = make_server_config();
+  let mut server_config = make_server_config();
+
+  server_config.versions = vec![ProtocolVersion::TLSv1_2];
+
   let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
   let mut server = ServerSession::new(&Arc::new(server_config));
 
-  assert_eq!(5, server.write(b"hello").unwrap());
-  
+  assert_eq!(5, client.write(b"hello").unwrap());
+
   do_handshake(&mut client, &mut server);
-  transfer(&mut server, &mut client);
-  client.process_new_packets().unwrap();
-  
-  check_read(&mut client, b"hello");
+  transfer(&mut client, &mut server);
+  server.process_new_packets().unwrap();
+
+  check_read(&mut server, b"hello");
 }
 
 #[test]
-fn buffered_both_data_sent() {
-  let client_config = make_client_config();
-  let server_config = make_server_config();
+fn version_mismatch_fails() {
+  let mut client_config = make_client_config();
+  let mut server_config = make_server_config();
+
+  client_config.versions = vec![ProtocolVersion::TLSv1_2];
+  server_config.versions = vec![ProtocolVersion::TLSv1_3];
+
   let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
   let mut server = ServerSession::new(&Arc::new(server_config));
 
-  assert_eq!(12, server.write(b"from-server!").unwrap());
-  assert_eq!(12, client.write(b"from-client!").unwrap());
-  
+  transfer(&mut client, &mut server);
+  assert!(server.process_new_packets().is_err());
+}
+
+#[test]
+fn ems_required_by_both_tls12() {
+  let mut client_config = make_client_config();
+  let mut server_config = make_server_config();
+
+  client_config.versions = vec![ProtocolVersion::TLSv1_2];
+  client_config.require_ems = true;
+  server_config.require_ems = true;
+
+  let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
+  let mut server = ServerSession::new(&Arc::new(server_config));
+
+  assert_eq!(5, client.write(b"hello").unwrap());
+
   do_handshake(&mut client, &mut server);
-  
-  transfer(&mut server, &mut client);
-  client.process_new_packets().unwrap();
   transfer(&mut client, &mut server);
   server.process_new_packets().unwrap();
 
-  check_read(&mut client, b"from-server!");
-  check_read(&mut server, b"from-client!");
+  check_read(&mut server, b"hello");
+}
+
+struct FixedVerifier {
+  result: Result<(), TLSError>
+}
+
+impl ServerCertVerifier for FixedVerifier {
+  fn verify_server_cert(&self,
+                        _roots: &RootCertStore,
+                        presented_certs: &[ASN1Cert],
+                        dns_name: &str,
+                        _ocsp_response: &[u8]) -> Result<(), TLSError> {
+    assert_eq!(presented_certs.len(), get_chain().len());
+    assert_eq!(dns_name, "localhost");
+    self.result.clone()
+  }
 }
 
 #[test]
-fn client_can_get_server_cert() {
-  let client_config = make_client_config();
-  let server_config = make_server_config();
+fn client_can_override_verifier() {
+  for_each_version(|version| {
+    let mut client_config = ClientConfig::new();
+    client_config.versions = vec![version];
+    client_config.verifier = Box::new(FixedVerifier { result: Ok(()) });
+    let server_config = make_server_config();
+
+    let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
+    let mut server = ServerSession::new(&Arc::new(server_config));
+
+    do_handshake(&mut client, &mut server);
+  });
+}
+
+#[test]
+fn client_verifier_can_reject() {
+  for_each_version(|version| {
+    let mut client_config = make_client_config();
+    client_config.versions = vec![version];
+    client_config.verifier = Box::new(FixedVerifier {
+      result: Err(TLSError::General("rejected".to_string()))
+    });
+    let server_config = make_server_config();
+
+    let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
+    let mut server = ServerSession::new(&Arc::new(server_config));
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    transfer(&mut server, &mut client);
+    assert_eq!(client.process_new_packets(),
+               Err(TLSError::General("rejected".to_string())));
+  });
+}
+
+fn pkcs8_keytype_test(keytype: &str, client_versions: Vec<ProtocolVersion>, client_auth: bool) {
+  let mut client_config = make_client_config();
+  let mut server_config = ServerConfig::new();
+
+  let mut rootbuf = io::BufReader::new(
+    fs::File::open(format!("test-ca/{}/ca.cert", keytype)).unwrap()
+  );
+  client_config.root_store.add_pem_file(&mut rootbuf).unwrap();
+  client_config.versions = client_versions;
+  server_config.set_single_cert(get_pkcs8_chain(keytype, "end"),
+                                get_pkcs8_key(keytype, "end")).unwrap();
+
+  if client_auth {
+    server_config.set_client_auth_roots(get_pkcs8_chain(keytype, "client"), true);
+    client_config.set_single_client_cert(get_pkcs8_chain(keytype, "client"),
+                                         get_pkcs8_key(keytype, "client")).unwrap();
+  }
+
   let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
   let mut server = ServerSession::new(&Arc::new(server_config));
 
   do_handshake(&mut client, &mut server);
+  assert_eq!(client.get_peer_certificates(), Some(get_pkcs8_chain(keytype, "end")));
 
-  let certs = client.get_peer_certificates();
-  assert_eq!(certs, Some(get_chain()));
+  if client_auth {
+    assert_eq!(server.get_peer_certificates(), Some(get_pkcs8_chain(keytype, "client")));
+  }
 }
 
 #[test]
-fn server_can_get_client_cert() {
+fn load_private_key_detects_type() {
+  let keys = vec![("rsa/end.rsa", SignatureAlgorithm::RSA),
+                  ("rsa/end.key", SignatureAlgorithm::RSA),
+                  ("ecdsa/end.key", SignatureAlgorithm::ECDSA),
+                  ("ecdsa/end.sec1", SignatureAlgorithm::ECDSA),
+                  ("eddsa/end.key", SignatureAlgorithm::ED25519)];
+
+  for (file, alg) in keys {
+    let mut keybuf = io::BufReader::new(
+      fs::File::open(format!("test-ca/{}", file)).unwrap()
+    );
+    assert_eq!(sign::load_private_key(&mut keybuf).unwrap().algorithm(), alg);
+  }
+}
+
+/* A Signer which records the schemes it is asked to sign with. */
+struct RecordingSigner {
+  inner: Box<sign::Signer + Send + Sync>,
+  used: Arc<Mutex<Vec<SignatureAndHashAlgorithm>>>
+}
+
+impl sign::Signer for RecordingSigner {
+  fn sign(&self, scheme: &SignatureAndHashAlgorithm, message: &[u8]) -> Result<Vec<u8>, ()> {
+    self.used.lock().unwrap().push(*scheme);
+    self.inner.sign(scheme, message)
+  }
+
+  fn choose_scheme_tls12(&self, offered: &[SignatureAndHashAlgorithm]) -> Option<SignatureAndHashAlgorithm> {
+    self.inner.choose_scheme_tls12(offered)
+  }
+
+  fn choose_scheme_tls13(&self, offered: &[SignatureAndHashAlgorithm]) -> Option<SignatureAndHashAlgorithm> {
+    self.inner.choose_scheme_tls13(offered)
+  }
+
+  fn algorithm(&self) -> SignatureAlgorithm {
+    self.inner.algorithm()
+  }
+}
+
+#[test]
+fn rsa_signer_prefers_pss_in_tls12() {
+  let signer = sign::any_supported_type(&get_key()).unwrap();
+  let pkcs1 = SignatureAndHashAlgorithm { hash: HashAlgorithm::SHA256, sign: SignatureAlgorithm::RSA };
+  let pss = SignatureAndHashAlgorithm { hash: HashAlgorithm::Intrinsic, sign: SignatureAlgorithm::RSAPSS_SHA256 };
+
+  assert_eq!(signer.choose_scheme_tls12(&[pkcs1, pss]), Some(pss));
+  assert_eq!(signer.choose_scheme_tls12(&[pkcs1]), Some(pkcs1));
+}
+
+#[test]
+fn server_signs_tls12_key_exchange_with_pss() {
+  let used = Arc::new(Mutex::new(Vec::new()));
+  let signer = RecordingSigner {
+    inner: sign::any_supported_type(&get_key()).unwrap(),
+    used: used.clone()
+  };
+  let certkey = sign::CertifiedKey {
+    cert: get_chain().into_iter().map(ASN1Cert::new).collect(),
+    key: Arc::new(Box::new(signer)),
+    ocsp: None,
+    sct_list: None
+  };
+
+  let resolver = ResolvesMultipleServerCerts::new();
+  resolver.add_default_certified_key(certkey);
+
   let mut client_config = make_client_config();
-  let mut server_config = make_server_config();
+  client_config.versions = vec![ProtocolVersion::TLSv1_2];
+  let mut server_config = ServerConfig::new();
+  server_config.cert_resolver = Box::new(resolver);
 
-  server_config.set_client_auth_roots(get_chain(), true);
-  client_config.set_single_client_cert(get_chain(), get_key());
+  let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
+  let mut server = ServerSession::new(&Arc::new(server_config));
+  do_handshake(&mut client, &mut server);
+
+  let pss = SignatureAndHashAlgorithm { hash: HashAlgorithm::Intrinsic, sign: SignatureAlgorithm::RSAPSS_SHA512 };
+  assert_eq!(*used.lock().unwrap(), vec![pss]);
+}
+
+#[test]
+fn server_accepts_sec1_and_pkcs8_rsa_keys() {
+  let sec1_key = pemfile::ec_private_keys(
+    &mut io::BufReader::new(
+      fs::File::open("test-ca/ecdsa/end.sec1").unwrap()
+    )
+  ).unwrap()[0].clone();
+
+  let mut client_config = make_client_config();
+  let mut rootbuf = io::BufReader::new(
+    fs::File::open("test-ca/ecdsa/ca.cert").unwrap()
+  );
+  client_config.root_store.add_pem_file(&mut rootbuf).unwrap();
+  let client_config = Arc::new(client_config);
+
+  let configs = vec![(get_pkcs8_chain("ecdsa", "end"), sec1_key),
+                     (get_chain(), get_pkcs8_key("rsa", "end"))];
+
+  for (chain, key) in configs {
+    let mut server_config = ServerConfig::new();
+    server_config.set_single_cert(chain, key).unwrap();
+
+    let mut client = ClientSession::new(&client_config, "localhost");
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+  }
+}
+
+#[test]
+fn server_accepts_pss_signed_chain() {
+  for_each_version(|version| {
+    let mut client_config = make_client_config();
+    let mut server_config = ServerConfig::new();
+
+    client_config.versions = vec![version];
+    server_config.set_single_cert(get_pkcs8_chain("rsa", "end.pss"), get_key()).unwrap();
+
+    let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+  });
+}
+
+fn assert_invalid_certified_key(result: Result<(), TLSError>, hint: &str) {
+  match result {
+    Err(TLSError::InvalidCertifiedKey(ref why)) if why.contains(hint) => {}
+    other => panic!("expected InvalidCertifiedKey containing '{}', got {:?}", hint, other)
+  }
+}
+
+#[test]
+fn server_config_refuses_bad_cert_or_key() {
+  let mut server_config = ServerConfig::new();
+  let chain = get_pkcs8_chain("ecdsa", "end");
+  let key = get_pkcs8_key("ecdsa", "end");
+
+  assert_invalid_certified_key(server_config.set_single_cert(vec![], key.clone()),
+                               "chain is empty");
+  assert_invalid_certified_key(server_config.set_single_cert(vec![b"not a cert".to_vec()], key.clone()),
+                               "certificate 0 in chain is malformed");
+  assert_invalid_certified_key(server_config.set_single_cert(chain.clone(), b"not a key".to_vec()),
+                               "private key is malformed");
+  assert_invalid_certified_key(server_config.set_single_cert(chain.clone(), get_key()),
+                               "does not match");
+
+  let unlinked = vec![chain[0].clone(), chain[2].clone()];
+  assert_invalid_certified_key(server_config.set_single_cert(unlinked, key.clone()),
+                               "certificate 0 in chain is not issued by certificate 1");
+
+  let mut expired = pemfile::certs(
+    &mut io::BufReader::new(
+      fs::File::open("test-ca/ecdsa/end.expired.cert").unwrap()
+    )
+  ).unwrap();
+  expired.extend_from_slice(&chain[1..]);
+  assert_invalid_certified_key(server_config.set_single_cert(expired, key.clone()),
+                               "expired");
+}
+
+#[test]
+fn server_config_refuses_sec1_key_without_public_key() {
+  let key = pemfile::ec_private_keys(
+    &mut io::BufReader::new(
+      fs::File::open("test-ca/ecdsa/end.nopub.sec1").unwrap()
+    )
+  ).unwrap()[0].clone();
+
+  let mut server_config = ServerConfig::new();
+  assert_invalid_certified_key(server_config.set_single_cert(get_pkcs8_chain("ecdsa", "end"), key),
+                               "SEC1 private key has no public key");
+}
+
+#[test]
+fn client_config_refuses_mismatched_key() {
+  let mut client_config = make_client_config();
+  assert_invalid_certified_key(
+    client_config.set_single_client_cert(get_chain(), get_pkcs8_key("ecdsa", "end")),
+    "does not match"
+  );
+}
+
+#[test]
+fn ecdsa_server_tls12() {
+  pkcs8_keytype_test("ecdsa", vec![ProtocolVersion::TLSv1_2], false);
+}
+
+#[test]
+fn ecdsa_server_tls13() {
+  pkcs8_keytype_test("ecdsa", vec![ProtocolVersion::TLSv1_3], false);
+}
+
+#[test]
+fn ecdsa_client_auth_tls12() {
+  pkcs8_keytype_test("ecdsa", vec![ProtocolVersion::TLSv1_2], true);
+}
+
+#[test]
+fn ecdsa_client_auth_tls13() {
+  pkcs8_keytype_test("ecdsa", vec![ProtocolVersion::TLSv1_3], true);
+}
+
+#[test]
+fn eddsa_server_tls12() {
+  pkcs8_keytype_test("eddsa", vec![ProtocolVersion::TLSv1_2], false);
+}
+
+#[test]
+fn eddsa_server_tls13() {
+  pkcs8_keytype_test("eddsa", vec![ProtocolVersion::TLSv1_3], false);
+}
+
+#[test]
+fn eddsa_client_auth_tls12() {
+  pkcs8_keytype_test("eddsa", vec![ProtocolVersion::TLSv1_2], true);
+}
+
+#[test]
+fn eddsa_client_auth_tls13() {
+  pkcs8_keytype_test("eddsa", vec![ProtocolVersion::TLSv1_3], true);
+}
+
+#[test]
+fn server_with_ocsp_response_works_without_status_request() {
+  for_each_version(|version| {
+    let mut client_config = make_client_config();
+    let mut server_config = ServerConfig::new();
+
+    client_config.versions = vec![version];
+    server_config.set_single_cert_with_ocsp(get_chain(), get_key(), b"not-really-ocsp".to_vec()).unwrap();
+
+    let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
+    let mut server = ServerSession::new(&Arc::new(server_config));
+
+    do_handshake(&mut client, &mut server);
+  });
+}
+
+#[test]
+fn server_with_scts_works_without_sct_request() {
+  for_each_version(|version| {
+    let mut client_config = make_client_config();
+    let mut server_config = ServerConfig::new();
+
+    client_config.versions = vec![version];
+    server_config.set_single_cert_with_ocsp_and_sct(get_chain(), get_key(),
+                                                    vec![], b"\x00\x06\x00\x04sct!".to_vec()).unwrap();
+
+    let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
+    let mut server = ServerSession::new(&Arc::new(server_config));
+
+    do_handshake(&mut client, &mut server);
+  });
+}
+
+fn get_ocsp(status: &str) -> Vec<u8> {
+  let mut bytes = Vec::new();
+  fs::File::open(format!("test-ca/rsa/end.{}.ocsp", status)).unwrap()
+    .read_to_end(&mut bytes).unwrap();
+  bytes
+}
+
+fn ocsp_connect(policy: OCSPPolicy,
+                version: ProtocolVersion,
+                ocsp: Vec<u8>) -> Result<ClientSession, TLSError> {
+  let mut client_config = make_client_config();
+  client_config.versions = vec![version];
+  client_config.ocsp_policy = policy;
+
+  let mut server_config = ServerConfig::new();
+  server_config.set_single_cert_with_ocsp(get_chain(), get_key(), ocsp).unwrap();
 
   let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
   let mut server = ServerSession::new(&Arc::new(server_config));
 
-  do_handshake(&mut client, &mut server);
+  while server.is_handshaking() || client.is_handshaking() {
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    transfer(&mut server, &mut client);
+    try!(client.process_new_packets());
+  }
 
-  let certs = server.get_peer_certificates();
-  assert_eq!(certs, Some(get_chain()));
+  Ok(client)
+}
+
+#[test]
+fn client_checks_good_stapled_ocsp() {
+  for_each_version(|version| {
+    for policy in vec![OCSPPolicy::SoftFail, OCSPPolicy::HardFail] {
+      let client = ocsp_connect(policy, version, get_ocsp("good")).unwrap();
+      assert_eq!(client.get_ocsp_response(), Some(&get_ocsp("good")[..]));
+    }
+
+    /* Not asked for, so not sent. */
+    let client = ocsp_connect(OCSPPolicy::Ignore, version, get_ocsp("good")).unwrap();
+    assert_eq!(client.get_ocsp_response(), None);
+  });
+}
+
+#[test]
+fn client_refuses_revoked_stapled_ocsp() {
+  for_each_version(|version| {
+    for policy in vec![OCSPPolicy::SoftFail, OCSPPolicy::HardFail] {
+      assert_eq!(ocsp_connect(policy, version, get_ocsp("revoked")).err(),
+                 Some(TLSError::CertificateRevoked));
+    }
+
+    assert!(ocsp_connect(OCSPPolicy::Ignore, version, get_ocsp("revoked")).is_ok());
+  });
+}
+
+#[test]
+fn client_ocsp_policy_for_missing_or_bad_response() {
+  for_each_version(|version| {
+    for ocsp in vec![vec![], b"not-really-ocsp".to_vec()] {
+      assert!(ocsp_connect(OCSPPolicy::SoftFail, version, ocsp.clone()).is_ok());
+
+      match ocsp_connect(OCSPPolicy::HardFail, version, ocsp) {
+        Err(TLSError::InvalidOCSPResponse(_)) => (),
+        _ => panic!("hard fail policy accepted missing/bad response")
+      }
+    }
+  });
+}
+
+fn make_ct_policy(min_valid_scts: usize) -> CTPolicy {
+  let mut policy = CTPolicy::new(min_valid_scts);
+  let mut logbuf = io::BufReader::new(
+    fs::File::open("test-ca/ct-log.pem").unwrap()
+  );
+  assert_eq!(policy.add_pem_file(&mut logbuf), Ok((1, 0)));
+  policy
+}
+
+fn get_sct_list() -> Vec<u8> {
+  let mut bytes = Vec::new();
+  fs::File::open("test-ca/rsa/end.sct").unwrap()
+    .read_to_end(&mut bytes).unwrap();
+  bytes
+}
+
+fn sct_connect(policy: CTPolicy,
+               version: ProtocolVersion,
+               sct_list: Vec<u8>) -> Result<ClientSession, TLSError> {
+  let mut client_config = make_client_config();
+  client_config.versions = vec![version];
+  client_config.ct_policy = Some(policy);
+
+  let mut server_config = ServerConfig::new();
+  server_config.set_single_cert_with_ocsp_and_sct(get_chain(), get_key(),
+                                                  vec![], sct_list).unwrap();
+
+  let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
+  let mut server = ServerSession::new(&Arc::new(server_config));
+
+  while server.is_handshaking() || client.is_handshaking() {
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    transfer(&mut server, &mut client);
+    try!(client.process_new_packets());
+  }
+
+  Ok(client)
+}
+
+#[test]
+fn client_accepts_valid_scts() {
+  for_each_version(|version| {
+    assert!(sct_connect(make_ct_policy(1), version, get_sct_list()).is_ok());
+  });
+}
+
+#[test]
+fn client_refuses_too_few_scts() {
+  for_each_version(|version| {
+    /* No SCTs, one SCT from an unknown log, and too few from known logs. */
+    let attempts = vec![
+      (make_ct_policy(1), vec![]),
+      (CTPolicy::new(1), get_sct_list()),
+      (make_ct_policy(2), get_sct_list())
+    ];
+
+    for (policy, sct_list) in attempts {
+      match sct_connect(policy, version, sct_list) {
+        Err(TLSError::CertificateTransparencyError(_)) => (),
+        _ => panic!("client accepted insufficient SCTs")
+      }
+    }
+  });
+}
+
+fn make_sni_client_config() -> ClientConfig {
+  let mut cfg = make_client_config();
+  let mut rootbuf = io::BufReader::new(
+    fs::File::open("test-ca/ecdsa/ca.cert").unwrap()
+  );
+  cfg.root_store.add_pem_file(&mut rootbuf).unwrap();
+  cfg
+}
+
+fn resolver_connect(resolver: Box<ResolvesCert + Send + Sync>,
+                    client_config: ClientConfig,
+                    name: &str) -> Result<ClientSession, TLSError> {
+  let mut server_config = ServerConfig::new();
+  server_config.cert_resolver = resolver;
+
+  let mut client = ClientSession::new(&Arc::new(client_config), name);
+  let mut server = ServerSession::new(&Arc::new(server_config));
+
+  transfer(&mut client, &mut server);
+  if let Err(err) = server.process_new_packets() {
+    transfer(&mut server, &mut client);
+    return client.process_new_packets()
+      .and(Err(err));
+  }
+
+  complete_handshake(&mut client, &mut server, 1);
+  Ok(client)
+}
+
+fn sni_connect(resolver: &ResolvesServerCertUsingSNI, name: &str) -> Result<ClientSession, TLSError> {
+  resolver_connect(Box::new(resolver.clone()), make_sni_client_config(), name)
+}
+
+#[test]
+fn sni_resolver_chooses_by_name() {
+  let resolver = ResolvesServerCertUsingSNI::new();
+  resolver.add("testserver.com", get_chain(), &get_key()).unwrap();
+  resolver.add("*.TestServer.com.", get_pkcs8_chain("ecdsa", "end"), &get_pkcs8_key("ecdsa", "end")).unwrap();
+
+  let client = sni_connect(&resolver, "testserver.com").unwrap();
+  assert_eq!(client.get_peer_certificates(), Some(get_chain()));
+
+  let client = sni_connect(&resolver, "second.testserver.com").unwrap();
+  assert_eq!(client.get_peer_certificates(), Some(get_pkcs8_chain("ecdsa", "end")));
+}
+
+#[test]
+fn sni_resolver_refuses_unknown_name() {
+  let resolver = ResolvesServerCertUsingSNI::new();
+  resolver.add("testserver.com", get_chain(), &get_key()).unwrap();
+
+  assert_eq!(sni_connect(&resolver, "localhost").err(),
+             Some(TLSError::AlertReceived(AlertDescription::UnrecognisedName)));
+
+  /* Wildcards only match one label. */
+  resolver.add("*.com", get_chain(), &get_key()).unwrap();
+  assert!(sni_connect(&resolver, "second.testserver.com").is_err());
+}
+
+#[test]
+fn sni_resolver_can_be_updated() {
+  let resolver = ResolvesServerCertUsingSNI::new();
+  assert!(sni_connect(&resolver, "localhost").is_err());
+
+  resolver.set_default(get_chain(), &get_key()).unwrap();
+  let client = sni_connect(&resolver, "localhost").unwrap();
+  assert_eq!(client.get_peer_certificates(), Some(get_chain()));
+
+  resolver.add("localhost", get_pkcs8_chain("ecdsa", "end"), &get_pkcs8_key("ecdsa", "end")).unwrap();
+  let client = sni_connect(&resolver, "localhost").unwrap();
+  assert_eq!(client.get_peer_certificates(), Some(get_pkcs8_chain("ecdsa", "end")));
+
+  assert!(resolver.remove("localhost"));
+  resolver.clear_default();
+  assert!(sni_connect(&resolver, "localhost").is_err());
+}
+
+/// Connect to `resolver` with a TLS1.2 client offering only
+/// suites authenticated with `sign`.
+fn multi_cert_connect(resolver: &ResolvesMultipleServerCerts,
+                      sign: SignatureAlgorithm) -> Result<ClientSession, TLSError> {
+  let mut client_config = make_sni_client_config();
+  client_config.versions = vec![ProtocolVersion::TLSv1_2];
+  client_config.ciphersuites.retain(|scs| scs.sign == sign);
+
+  resolver_connect(Box::new(resolver.clone()), client_config, "testserver.com")
+}
+
+#[test]
+fn multi_cert_resolver_chooses_by_client_suites() {
+  let resolver = ResolvesMultipleServerCerts::new();
+  resolver.add("testserver.com", get_pkcs8_chain("ecdsa", "end"), &get_pkcs8_key("ecdsa", "end")).unwrap();
+
+  let client = multi_cert_connect(&resolver, SignatureAlgorithm::ECDSA).unwrap();
+  assert_eq!(client.get_peer_certificates(), Some(get_pkcs8_chain("ecdsa", "end")));
+  assert!(multi_cert_connect(&resolver, SignatureAlgorithm::RSA).is_err());
+
+  resolver.add("testserver.com", get_chain(), &get_key()).unwrap();
+
+  let client = multi_cert_connect(&resolver, SignatureAlgorithm::ECDSA).unwrap();
+  assert_eq!(client.get_peer_certificates(), Some(get_pkcs8_chain("ecdsa", "end")));
+  let client = multi_cert_connect(&resolver, SignatureAlgorithm::RSA).unwrap();
+  assert_eq!(client.get_peer_certificates(), Some(get_chain()));
+}
+
+#[test]
+fn multi_cert_resolver_uses_default() {
+  let resolver = ResolvesMultipleServerCerts::new();
+  resolver.add("other.com", get_chain(), &get_key()).unwrap();
+  assert!(multi_cert_connect(&resolver, SignatureAlgorithm::RSA).is_err());
+
+  resolver.add_default(get_pkcs8_chain("ecdsa", "end"), &get_pkcs8_key("ecdsa", "end")).unwrap();
+  resolver.add_default(get_chain(), &get_key()).unwrap();
+
+  let client = multi_cert_connect(&resolver, SignatureAlgorithm::RSA).unwrap();
+  assert_eq!(client.get_peer_certificates(), Some(get_chain()));
+
+  resolver.clear_default();
+  assert!(multi_cert_connect(&resolver, SignatureAlgorithm::RSA).is_err());
+}
+
+fn multi_cert_resolve(resolver: &ResolvesMultipleServerCerts,
+                      version: ProtocolVersion,
+                      sigalgs: SupportedSignatureAlgorithms,
+                      ec_curves: EllipticCurveList) -> Option<Vec<ASN1Cert>> {
+  let suites: Vec<&'static SupportedCipherSuite> = ALL_CIPHERSUITES.iter()
+    .filter(|scs| scs.usable_for_version(version))
+    .cloned()
+    .collect();
+
+  resolver.resolve(None, &sigalgs, &ec_curves, &vec![ECPointFormat::Uncompressed],
+                   &suites, version)
+    .ok()
+    .map(|certkey| certkey.cert)
+}
+
+#[test]
+fn multi_cert_resolver_checks_schemes_for_version() {
+  let resolver = ResolvesMultipleServerCerts::new();
+  resolver.add_default(get_chain(), &get_key()).unwrap();
+
+  /* PKCS#1 signatures are not allowed in TLS1.3. */
+  let pkcs1 = vec![SignatureAndHashAlgorithm { hash: HashAlgorithm::SHA256, sign: SignatureAlgorithm::RSA }];
+  let curves = vec![NamedCurve::X25519];
+  assert!(multi_cert_resolve(&resolver, ProtocolVersion::TLSv1_2, pkcs1.clone(), curves.clone()).is_some());
+  assert!(multi_cert_resolve(&resolver, ProtocolVersion::TLSv1_3, pkcs1, curves).is_none());
+}
+
+#[test]
+fn multi_cert_resolver_checks_curves_only_in_tls12() {
+  let resolver = ResolvesMultipleServerCerts::new();
+  resolver.add_default(get_pkcs8_chain("ecdsa", "end"), &get_pkcs8_key("ecdsa", "end")).unwrap();
+
+  /* The key is on P-256, which the client doesn't list. */
+  let sigalgs = vec![SignatureAndHashAlgorithm { hash: HashAlgorithm::SHA256, sign: SignatureAlgorithm::ECDSA }];
+  let curves = vec![NamedCurve::X25519];
+  assert!(multi_cert_resolve(&resolver, ProtocolVersion::TLSv1_2, sigalgs.clone(), curves.clone()).is_none());
+  assert!(multi_cert_resolve(&resolver, ProtocolVersion::TLSv1_3, sigalgs, curves).is_some());
+}
+
+#[test]
+fn exported_keying_material_agrees() {
+  for_each_version(|version| {
+    let (mut client, mut server) = make_pair_for_version(version);
+
+    assert_eq!(client.export_keying_material(b"label", None, 32),
+               Err(TLSError::HandshakeNotComplete));
+    assert_eq!(server.export_keying_material(b"label", None, 32),
+               Err(TLSError::HandshakeNotComplete));
+
+    do_handshake(&mut client, &mut server);
+
+    let ekm = client.export_keying_material(b"label", Some(&b"context"[..]), 64).unwrap();
+    assert_eq!(ekm.len(), 64);
+    assert_eq!(server.export_keying_material(b"label", Some(&b"context"[..]), 64), Ok(ekm.clone()));
+    assert!(server.export_keying_material(b"other label", Some(&b"context"[..]), 64) != Ok(ekm.clone()));
+    assert!(server.export_keying_material(b"label", Some(&b"other context"[..]), 64) != Ok(ekm.clone()));
+  });
+}
+
+#[test]
+fn exporter_refuses_reserved_labels_in_tls12() {
+  let (mut client, mut server) = make_pair_for_version(ProtocolVersion::TLSv1_2);
+  do_handshake(&mut client, &mut server);
+
+  let reserved: Vec<&[u8]> = vec![b"client finished", b"server finished", b"master secret",
+                                  b"key expansion", b"extended master secret"];
+  for label in reserved {
+    assert_eq!(client.export_keying_material(label, None, 32),
+               Err(TLSError::General("exporter label is reserved".to_string())));
+  }
+}
+
+#[test]
+fn exporter_refuses_long_labels_in_tls13() {
+  let (mut client, mut server) = make_pair_for_version(ProtocolVersion::TLSv1_3);
+  do_handshake(&mut client, &mut server);
+
+  assert!(client.export_keying_material(&[b'a'; 249], None, 32).is_ok());
+  assert_eq!(client.export_keying_material(&[b'a'; 250], None, 32),
+             Err(TLSError::General("exporter label too long".to_string())));
+}
+
+#[test]
+fn channel_bindings_agree() {
+  for_each_version(|version| {
+    let (mut client, mut server) = make_pair_for_version(version);
+
+    assert_eq!(client.get_tls_unique(), None);
+    assert_eq!(server.get_tls_unique(), None);
+    assert_eq!(client.get_tls_server_end_point(), None);
+    assert_eq!(server.get_tls_server_end_point(), None);
+
+    do_handshake(&mut client, &mut server);
+
+    if version == ProtocolVersion::TLSv1_2 {
+      let unique = client.get_tls_unique().unwrap();
+      assert_eq!(unique.len(), 12);
+      assert_eq!(server.get_tls_unique(), Some(unique));
+    } else {
+      assert_eq!(client.get_tls_unique(), None);
+      assert_eq!(server.get_tls_unique(), None);
+    }
+
+    /* The test end-entity certificate is signed with SHA-256. */
+    let end_point = client.get_tls_server_end_point().unwrap();
+    assert_eq!(end_point.len(), 32);
+    assert_eq!(server.get_tls_server_end_point(), Some(end_point));
+  });
+}
+
+struct KeyLogToVec {
+  lines: Arc<Mutex<Vec<(String, Vec<u8>, Vec<u8>)>>>
+}
+
+impl KeyLog for KeyLogToVec {
+  fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+    self.lines.lock().unwrap()
+      .push((label.to_string(), client_random.to_vec(), secret.to_vec()));
+  }
+}
+
+#[test]
+fn key_log_agrees() {
+  for_each_version(|version| {
+    let client_lines = Arc::new(Mutex::new(Vec::new()));
+    let server_lines = Arc::new(Mutex::new(Vec::new()));
+
+    let mut client_config = make_client_config();
+    client_config.versions = vec![version];
+    client_config.key_log = Box::new(KeyLogToVec { lines: client_lines.clone() });
+
+    let mut server_config = make_server_config();
+    server_config.key_log = Box::new(KeyLogToVec { lines: server_lines.clone() });
+
+    let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+
+    let mut client_lines = client_lines.lock().unwrap().clone();
+    let mut server_lines = server_lines.lock().unwrap().clone();
+    client_lines.sort();
+    server_lines.sort();
+    assert_eq!(client_lines, server_lines);
+
+    let labels: Vec<&str> = client_lines.iter()
+      .map(|&(ref label, _, _)| &label[..])
+      .collect();
+
+    if version == ProtocolVersion::TLSv1_2 {
+      assert_eq!(labels, vec!["CLIENT_RANDOM"]);
+      assert_eq!(client_lines[0].2.len(), 48);
+    } else {
+      assert_eq!(labels, vec!["CLIENT_HANDSHAKE_TRAFFIC_SECRET",
+                              "CLIENT_TRAFFIC_SECRET_0",
+                              "EXPORTER_SECRET",
+                              "SERVER_HANDSHAKE_TRAFFIC_SECRET",
+                              "SERVER_TRAFFIC_SECRET_0"]);
+    }
+
+    for &(_, ref client_random, _) in &client_lines {
+      assert_eq!(client_random.len(), 32);
+    }
+  });
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter()
+    .map(|b| format!("{:02x}", b))
+    .collect()
+}
+
+#[test]
+fn key_log_file_writes_nss_format() {
+  for_each_version(|version| {
+    let path = env::temp_dir().join(format!("rustls-test-keylog-{:?}-{}", version, process::id()));
+    let expected = Arc::new(Mutex::new(Vec::new()));
+
+    let mut client_config = make_client_config();
+    client_config.versions = vec![version];
+    client_config.key_log = Box::new(KeyLogFile::for_path(&path));
+
+    let mut server_config = make_server_config();
+    server_config.key_log = Box::new(KeyLogToVec { lines: expected.clone() });
+
+    let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+
+    let mut written = String::new();
+    fs::File::open(&path).unwrap().read_to_string(&mut written).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let mut written: Vec<&str> = written.lines().collect();
+    let mut expected: Vec<String> = expected.lock().unwrap().iter()
+      .map(|&(ref label, ref client_random, ref secret)| {
+        format!("{} {} {}", label, to_hex(client_random), to_hex(secret))
+      })
+      .collect();
+    written.sort();
+    expected.sort();
+    assert_eq!(written, expected);
+
+    let labels: Vec<&str> = written.iter()
+      .map(|line| line.split(' ').next().unwrap())
+      .collect();
+    if version == ProtocolVersion::TLSv1_2 {
+      assert_eq!(labels, vec!["CLIENT_RANDOM"]);
+    } else {
+      assert_eq!(labels, vec!["CLIENT_HANDSHAKE_TRAFFIC_SECRET",
+                              "CLIENT_TRAFFIC_SECRET_0",
+                              "EXPORTER_SECRET",
+                              "SERVER_HANDSHAKE_TRAFFIC_SECRET",
+                              "SERVER_TRAFFIC_SECRET_0"]);
+    }
+  });
+}
+
+/* Do a handshake, then send a record each way, so the sequence
+ * numbers have moved on. */
+fn make_used_pair_for_version(version: ProtocolVersion) -> (ClientSession, ServerSession) {
+  let (mut client, mut server) = make_pair_for_version(version);
+  do_handshake(&mut client, &mut server);
+
+  client.write(b"hello").unwrap();
+  transfer(&mut client, &mut server);
+  server.process_new_packets().unwrap();
+  check_read(&mut server, b"hello");
+
+  server.write(b"world").unwrap();
+  transfer(&mut server, &mut client);
+  client.process_new_packets().unwrap();
+  check_read(&mut client, b"world");
+
+  (client, server)
+}
+
+/* `rx` is what the other end extracted for the records `tx` sends. */
+fn check_directions_agree(tx: &DirectionSecrets, rx: &DirectionSecrets) {
+  assert_eq!(tx.key, rx.key);
+  assert_eq!(tx.seq, rx.seq);
+  assert!(tx.iv.starts_with(&rx.iv));
+}
+
+#[test]
+fn extract_secrets_agree() {
+  for_each_version(|version| {
+    let (client, server) = make_pair_for_version(version);
+    assert_eq!(client.extract_secrets().err(), Some(TLSError::HandshakeNotComplete));
+    assert_eq!(server.extract_secrets().err(), Some(TLSError::HandshakeNotComplete));
+
+    let (client, server) = make_used_pair_for_version(version);
+    let client_secrets = client.extract_secrets().unwrap();
+    let server_secrets = server.extract_secrets().unwrap();
+    assert_eq!(client_secrets.version, version);
+    assert_eq!(server_secrets.version, version);
+    assert_eq!(client_secrets.suite, server_secrets.suite);
+    check_directions_agree(&client_secrets.tx, &server_secrets.rx);
+    check_directions_agree(&server_secrets.tx, &client_secrets.rx);
+    assert_eq!(client_secrets.tx.key.len(), client_secrets.suite.enc_key_len);
+    assert_eq!(client_secrets.tx.iv.len(), 12);
+    assert!(client_secrets.tx.seq > 0);
+    assert!(client_secrets.rx.seq > 0);
+    assert!(client_secrets.tx.key != client_secrets.rx.key);
+  });
+}
+
+#[test]
+fn extract_secrets_gives_only_salt_for_tls12_gcm_rx() {
+  let mut client_config = make_client_config();
+  client_config.versions = vec![ProtocolVersion::TLSv1_2];
+  client_config.ciphersuites.retain(|scs| scs.explicit_nonce_len > 0);
+
+  let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
+  let mut server = ServerSession::new(&Arc::new(make_server_config()));
+  do_handshake(&mut client, &mut server);
+
+  let secrets = client.extract_secrets().unwrap();
+  assert_eq!(secrets.tx.iv.len(), 12);
+  assert_eq!(secrets.rx.iv.len(), 4);
+}
+
+#[test]
+fn extract_secrets_debug_hides_keys() {
+  let (client, _) = make_used_pair_for_version(ProtocolVersion::TLSv1_3);
+  let secrets = client.extract_secrets().unwrap();
+  let debug = format!("{:?}", secrets);
+
+  assert!(!debug.contains(&format!("{:?}", secrets.tx.key)));
+  assert!(!debug.contains(&format!("{:?}", secrets.tx.iv)));
+  assert!(!debug.contains(&format!("{:?}", secrets.rx.key)));
+  assert!(!debug.contains(&format!("{:?}", secrets.rx.iv)));
+  assert!(debug.contains("seq"));
+}
+
+#[test]
+fn extract_secrets_refuses_buffered_data() {
+  let mut client = ClientSession::new(&Arc::new(make_client_config()), "localhost");
+  let mut server = ServerSession::new(&Arc::new(make_server_config()));
+  do_handshake(&mut client, &mut server);
+
+  client.write(b"hello").unwrap();
+  transfer(&mut client, &mut server);
+  server.process_new_packets().unwrap();
+
+  assert_eq!(server.extract_secrets().err(),
+             Some(TLSError::General("session has buffered data".to_string())));
+}
+
+#[test]
+fn saved_sessions_carry_on() {
+  for_each_version(|version| {
+    let make_configs = || {
+      let mut client_config = make_client_config();
+      client_config.versions = vec![version];
+      client_config.alpn_protocols = vec!["foo".to_string()];
+      let mut server_config = make_server_config();
+      server_config.alpn_protocols = vec!["foo".to_string()];
+      (Arc::new(client_config), Arc::new(server_config))
+    };
+
+    let (client_config, server_config) = make_configs();
+    let client = ClientSession::new(&client_config, "localhost");
+    assert_eq!(client.save_state().err(), Some(TLSError::HandshakeNotComplete));
+
+    let mut client = ClientSession::new(&client_config, "localhost");
+    let mut server = ServerSession::new(&server_config);
+    do_handshake(&mut client, &mut server);
+    let ekm = client.export_keying_material(b"label", None, 32).unwrap();
+
+    /* Leave data in flight both ways. */
+    client.write(b"hello").unwrap();
+    transfer(&mut client, &mut server);
+    server.write(b"world").unwrap();
+
+    let client_state = client.save_state().unwrap();
+    let server_state = server.save_state().unwrap();
+
+    let (client_config, server_config) = make_configs();
+    assert!(ClientSession::restore_state(&client_config, &server_state).is_err());
+    assert!(ServerSession::restore_state(&server_config, &client_state).is_err());
+    assert!(ClientSession::restore_state(&client_config, &client_state[..10]).is_err());
+
+    let mut client = ClientSession::restore_state(&client_config, &client_state).unwrap();
+    let mut server = ServerSession::restore_state(&server_config, &server_state).unwrap();
+    assert!(!client.is_handshaking());
+    assert!(!server.is_handshaking());
+    assert_eq!(client.get_alpn_protocol(), Some("foo".to_string()));
+    assert_eq!(server.get_alpn_protocol(), Some("foo".to_string()));
+    assert!(client.get_peer_certificates().is_some());
+    assert_eq!(client.export_keying_material(b"label", None, 32), Ok(ekm.clone()));
+    assert_eq!(server.export_keying_material(b"label", None, 32), Ok(ekm));
+
+    server.process_new_packets().unwrap();
+    check_read(&mut server, b"hello");
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    check_read(&mut client, b"world");
+
+    client.write(b"again").unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server, b"again");
+  });
+}
+
+#[test]
+fn saved_session_needs_suite_enabled() {
+  let mut client = ClientSession::new(&Arc::new(make_client_config()), "localhost");
+  let mut server = ServerSession::new(&Arc::new(make_server_config()));
+  do_handshake(&mut client, &mut server);
+
+  let mut client_config = make_client_config();
+  client_config.versions = vec![ProtocolVersion::TLSv1_2];
+  let client_state = client.save_state().unwrap();
+
+  assert_eq!(ClientSession::restore_state(&Arc::new(client_config), &client_state).err(),
+             Some(TLSError::General("saved session's version is not enabled".to_string())));
+}
+
+/// A transport whose other end is a server session.
+struct OtherSession<'a> {
+  sess: &'a mut ServerSession
+}
+
+impl<'a> io::Read for OtherSession<'a> {
+  fn read(&mut self, mut b: &mut [u8]) -> io::Result<usize> {
+    self.sess.write_tls(&mut b)
+  }
+}
+
+impl<'a> io::Write for OtherSession<'a> {
+  fn write(&mut self, mut b: &[u8]) -> io::Result<usize> {
+    let len = try!(self.sess.read_tls(&mut b));
+    try!(self.sess.process_new_packets()
+         .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)));
+    Ok(len)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+#[test]
+fn stream_round_trip() {
+  for_each_version(|version| {
+    let (client, mut server) = make_pair_for_version(version);
+
+    let mut stream = Stream::new(Box::new(client), OtherSession { sess: &mut server });
+    assert!(stream.sess.is_handshaking());
+
+    stream.write_all(b"hello").unwrap();
+    assert!(!stream.sess.is_handshaking());
+    assert!(!stream.sock.sess.is_handshaking());
+    check_read(&mut *stream.sock.sess, b"hello");
+
+    stream.sock.sess.write(b"world").unwrap();
+    let mut buf = [0u8; 5];
+    stream.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"world");
+
+    assert_eq!(stream.complete_io().unwrap(), (0, 0));
+  });
+}
+
+/// A transport which gives out `input` one chunk per read, and
+/// panics if read past that.  Writes are kept in `output`, or
+/// fail if `fail_writes`.
+struct ScriptedTransport {
+  input: Vec<Vec<u8>>,
+  output: Vec<u8>,
+  fail_writes: bool
+}
+
+impl ScriptedTransport {
+  fn new(input: Vec<Vec<u8>>) -> ScriptedTransport {
+    ScriptedTransport { input: input, output: Vec::new(), fail_writes: false }
+  }
+}
+
+impl io::Read for ScriptedTransport {
+  fn read(&mut self, b: &mut [u8]) -> io::Result<usize> {
+    assert!(!self.input.is_empty(), "unexpected read");
+    let chunk = self.input.remove(0);
+    b[..chunk.len()].copy_from_slice(&chunk);
+    Ok(chunk.len())
+  }
+}
+
+impl io::Write for ScriptedTransport {
+  fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+    if self.fail_writes {
+      return Err(io::Error::new(io::ErrorKind::BrokenPipe, "write failed"));
+    }
+    self.output.extend_from_slice(b);
+    Ok(b.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// A session which, the first time it processes packets, wants
+/// to write something extra -- like a reply to a KeyUpdate.
+struct ExtraWriteSession {
+  inner: ClientSession,
+  extra_pending: bool,
+  extra_sent: bool
+}
+
+impl io::Read for ExtraWriteSession {
+  fn read(&mut self, b: &mut [u8]) -> io::Result<usize> {
+    self.inner.read(b)
+  }
+}
+
+impl io::Write for ExtraWriteSession {
+  fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+    self.inner.write(b)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.inner.flush()
+  }
+}
+
+impl Session for ExtraWriteSession {
+  fn read_tls(&mut self, rd: &mut io::Read) -> io::Result<usize> {
+    self.inner.read_tls(rd)
+  }
+
+  fn write_tls(&mut self, wr: &mut io::Write) -> io::Result<usize> {
+    if self.extra_pending {
+      self.extra_pending = false;
+      self.extra_sent = true;
+      return wr.write(b"x");
+    }
+    self.inner.write_tls(wr)
+  }
+
+  fn process_new_packets(&mut self) -> Result<(), TLSError> {
+    self.extra_pending = !self.extra_sent;
+    self.inner.process_new_packets()
+  }
+
+  fn wants_read(&self) -> bool {
+    self.inner.wants_read()
+  }
+
+  fn wants_write(&self) -> bool {
+    self.extra_pending || self.inner.wants_write()
+  }
+
+  fn is_handshaking(&self) -> bool {
+    self.inner.is_handshaking()
+  }
+
+  fn send_close_notify(&mut self) {
+    self.inner.send_close_notify()
+  }
+
+  fn get_peer_certificates(&self) -> Option<Vec<Vec<u8>>> {
+    self.inner.get_peer_certificates()
+  }
+
+  fn get_alpn_protocol(&self) -> Option<String> {
+    self.inner.get_alpn_protocol()
+  }
+
+  fn get_protocol_version(&self) -> Option<ProtocolVersion> {
+    self.inner.get_protocol_version()
+  }
+
+  fn export_keying_material(&self,
+                            label: &[u8],
+                            context: Option<&[u8]>,
+                            len: usize) -> Result<Vec<u8>, TLSError> {
+    self.inner.export_keying_material(label, context, len)
+  }
+
+  fn get_tls_unique(&self) -> Option<Vec<u8>> {
+    self.inner.get_tls_unique()
+  }
+
+  fn get_tls_server_end_point(&self) -> Option<Vec<u8>> {
+    self.inner.get_tls_server_end_point()
+  }
+}
+
+#[test]
+fn stream_read_carries_on_after_writing() {
+  let (mut client, mut server) = make_pair_for_version(ProtocolVersion::TLSv1_3);
+  do_handshake(&mut client, &mut server);
+
+  /* Deliver a record in two parts, so the first read gives
+   * no plaintext, and is followed by a pass which only writes. */
+  server.write(b"world").unwrap();
+  let mut record = Vec::new();
+  server.write_tls(&mut record).unwrap();
+  let rest = record.split_off(3);
+
+  let sess = ExtraWriteSession { inner: client, extra_pending: false, extra_sent: false };
+  let mut stream = Stream::new(Box::new(sess), ScriptedTransport::new(vec![record, rest]));
+
+  let mut buf = [0u8; 5];
+  assert_eq!(stream.read(&mut buf).unwrap(), 5);
+  assert_eq!(&buf, b"world");
+  assert_eq!(stream.sock.output, b"x".to_vec());
+}
+
+#[test]
+fn stream_write_does_not_read() {
+  let (mut client, mut server) = make_pair_for_version(ProtocolVersion::TLSv1_3);
+  do_handshake(&mut client, &mut server);
+
+  let mut stream = Stream::new(Box::new(client), ScriptedTransport::new(vec![]));
+  assert_eq!(stream.write(b"").unwrap(), 0);
+  assert!(stream.sock.output.is_empty());
+
+  assert_eq!(stream.write(b"hello").unwrap(), 5);
+  assert!(!stream.sock.output.is_empty());
+}
+
+#[test]
+fn stream_write_reports_transport_errors() {
+  let (mut client, mut server) = make_pair_for_version(ProtocolVersion::TLSv1_3);
+  do_handshake(&mut client, &mut server);
+
+  let mut transport = ScriptedTransport::new(vec![]);
+  transport.fail_writes = true;
+  let mut stream = Stream::new(Box::new(client), transport);
+
+  let err = stream.write(b"hello").unwrap_err();
+  assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+}
+
+#[test]
+fn stream_reports_tls_errors() {
+  let mut client_config = make_client_config();
+  client_config.root_store = RootCertStore::empty();
+
+  let client = ClientSession::new(&Arc::new(client_config), "localhost");
+  let mut server = ServerSession::new(&Arc::new(make_server_config()));
+  let mut stream = Stream::new(Box::new(client), OtherSession { sess: &mut server });
+
+  let err = stream.write(b"hello").unwrap_err();
+  assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+fn check_read(reader: &mut io::Read, bytes: &[u8]) {
+  let mut buf = Vec::new();
+  assert_eq!(bytes.len(), reader.read_to_end(&mut buf).unwrap());
+  assert_eq!(bytes.to_vec(), buf);
+}
+
+#[test]
+fn buffered_client_data_sent() {
+  let client_config = make_client_config();
+  let server_config = make_server_config();
+  let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
+  let mut server = ServerSession::new(&Arc::new(server_config));
+
+  assert_eq!(5, client.write(b"hello").unwrap());
+  
+  do_handshake(&mut client, &mut server);
+  transfer(&mut client, &mut server);
+  server.process_new_packets().unwrap();
+  
+  check_read(&mut server, b"hello");
+}
+
+#[test]
+fn buffered_server_data_sent() {
+  let client_config = make_client_config();
+  let server_config = make_server_config();
+  let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
+  let mut server = ServerSession::new(&Arc::new(server_config));
+
+  assert_eq!(5, server.write(b"hello").unwrap());
+  
+  do_handshake(&mut client, &mut server);
+  transfer(&mut server, &mut client);
+  client.process_new_packets().unwrap();
+  
+  check_read(&mut client, b"hello");
+}
+
+#[test]
+fn buffered_both_data_sent() {
+  let client_config = make_client_config();
+  let server_config = make_server_config();
+  let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
+  let mut server = ServerSession::new(&Arc::new(server_config));
+
+  assert_eq!(12, server.write(b"from-server!").unwrap());
+  assert_eq!(12, client.write(b"from-client!").unwrap());
+  
+  do_handshake(&mut client, &mut server);
+  
+  transfer(&mut server, &mut client);
+  client.process_new_packets().unwrap();
+  transfer(&mut client, &mut server);
+  server.process_new_packets().unwrap();
+
+  check_read(&mut client, b"from-server!");
+  check_read(&mut server, b"from-client!");
+}
+
+#[test]
+fn client_can_get_server_cert() {
+  let client_config = make_client_config();
+  let server_config = make_server_config();
+  let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
+  let mut server = ServerSession::new(&Arc::new(server_config));
+
+  do_handshake(&mut client, &mut server);
+
+  let certs = client.get_peer_certificates();
+  assert_eq!(certs, Some(get_chain()));
+}
+
+#[test]
+fn server_can_get_client_cert() {
+  let mut client_config = make_client_config();
+  let mut server_config = make_server_config();
+
+  server_config.set_client_auth_roots(get_chain(), true);
+  client_config.set_single_client_cert(get_chain(), get_key()).unwrap();
+
+  let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
+  let mut server = ServerSession::new(&Arc::new(server_config));
+
+  do_handshake(&mut client, &mut server);
+
+  let certs = server.get_peer_certificates();
+  assert_eq!(certs, Some(get_chain()));
+}
+
+#[test]
+fn client_resolver_chooses_by_acceptable_issuers() {
+  let mut resolver = ResolvesClientCertUsingIssuers::new();
+  for keytype in &["rsa", "ecdsa"] {
+    resolver.add(get_pkcs8_chain(keytype, "client"), &get_pkcs8_key(keytype, "client")).unwrap();
+  }
+
+  let mut client_config = make_client_config();
+  client_config.client_auth_cert_resolver = Box::new(resolver);
+  let client_config = Arc::new(client_config);
+
+  for keytype in &["rsa", "ecdsa"] {
+    let chain = get_pkcs8_chain(keytype, "client");
+    let mut server_config = make_server_config();
+    server_config.set_client_auth_roots(vec![chain[2].clone()], true);
+
+    let mut client = ClientSession::new(&client_config, "localhost");
+    let mut server = ServerSession::new(&Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+
+    assert_eq!(server.get_peer_certificates(), Some(chain));
+  }
+
+  /* No identity is issued under the EdDSA CA. */
+  let mut server_config = make_server_config();
+  server_config.set_client_auth_roots(vec![get_pkcs8_chain("eddsa", "client")[2].clone()], true);
+
+  let mut client = ClientSession::new(&client_config, "localhost");
+  let mut server = ServerSession::new(&Arc::new(server_config));
+  transfer(&mut client, &mut server);
+  server.process_new_packets().unwrap();
+  transfer(&mut server, &mut client);
+  client.process_new_packets().unwrap();
+  transfer(&mut client, &mut server);
+  assert_eq!(server.process_new_packets(), Err(TLSError::NoCertificatesPresented));
+}
+
+fn client_cert_resolve(resolver: &ResolvesClientCertUsingIssuers,
+                       version: ProtocolVersion,
+                       certtypes: Vec<ClientCertificateType>) -> Option<Vec<ASN1Cert>> {
+  let sigalgs = vec![
+    SignatureAndHashAlgorithm { hash: HashAlgorithm::SHA256, sign: SignatureAlgorithm::RSA },
+    SignatureAndHashAlgorithm { hash: HashAlgorithm::SHA256, sign: SignatureAlgorithm::ECDSA },
+    SignatureAndHashAlgorithm { hash: HashAlgorithm::SHA384, sign: SignatureAlgorithm::ECDSA }
+  ];
+
+  resolver.resolve(&Vec::new(), &sigalgs, &certtypes, version)
+    .map(|(chain, _)| chain)
+}
+
+fn asn1_chain(chain: Vec<Vec<u8>>) -> Vec<ASN1Cert> {
+  chain.into_iter().map(|der| ASN1Cert::new(der)).collect()
+}
+
+#[test]
+fn client_resolver_checks_schemes_for_version() {
+  let mut resolver = ResolvesClientCertUsingIssuers::new();
+  for keytype in &["rsa", "ecdsa"] {
+    resolver.add(get_pkcs8_chain(keytype, "client"), &get_pkcs8_key(keytype, "client")).unwrap();
+  }
+
+  let both = vec![ClientCertificateType::RSASign, ClientCertificateType::ECDSASign];
+  assert_eq!(client_cert_resolve(&resolver, ProtocolVersion::TLSv1_2, both),
+             Some(asn1_chain(get_pkcs8_chain("rsa", "client"))));
+
+  /* PKCS#1 signatures are not allowed in TLS1.3, so the RSA
+   * identity is passed over. */
+  assert_eq!(client_cert_resolve(&resolver, ProtocolVersion::TLSv1_3, vec![]),
+             Some(asn1_chain(get_pkcs8_chain("ecdsa", "client"))));
+}
+
+#[test]
+fn client_resolver_checks_certtypes_in_tls12() {
+  let mut resolver = ResolvesClientCertUsingIssuers::new();
+  for keytype in &["ecdsa", "rsa"] {
+    resolver.add(get_pkcs8_chain(keytype, "client"), &get_pkcs8_key(keytype, "client")).unwrap();
+  }
+
+  assert_eq!(client_cert_resolve(&resolver, ProtocolVersion::TLSv1_2,
+                                 vec![ClientCertificateType::RSASign]),
+             Some(asn1_chain(get_pkcs8_chain("rsa", "client"))));
+  assert_eq!(client_cert_resolve(&resolver, ProtocolVersion::TLSv1_2,
+                                 vec![ClientCertificateType::ECDSASign]),
+             Some(asn1_chain(get_pkcs8_chain("ecdsa", "client"))));
+  assert_eq!(client_cert_resolve(&resolver, ProtocolVersion::TLSv1_2,
+                                 vec![ClientCertificateType::DSSSign]),
+             None);
+}
+
+#[test]
+fn root_store_from_system_uses_given_sources() {
+  let dir = env::temp_dir().join(format!("rustls-test-hashed-dir-{}", process::id()));
+  fs::create_dir_all(&dir).unwrap();
+  fs::copy("test-ca/rsa/ca.cert", dir.join("c8d4e8a2.0")).unwrap();
+  fs::copy("test-ca/ecdsa/ca.cert", dir.join("ecdsa-ca.pem")).unwrap();
+
+  let (store, valid, invalid) =
+    RootCertStore::from_system_with(Some("test-ca/rsa/end.chain".into()), None).unwrap();
+  assert_eq!((store.len(), valid, invalid), (2, 2, 0));
+
+  let (store, valid, invalid) =
+    RootCertStore::from_system_with(None, Some(dir.clone().into_os_string())).unwrap();
+  assert_eq!((store.len(), valid, invalid), (1, 1, 0));
+
+  assert!(RootCertStore::from_system_with(Some("test-ca/does-not-exist".into()), None).is_err());
+
+  fs::remove_dir_all(&dir).unwrap();
+}
+
+fn get_crls(name: &str) -> CRLStore {
+  let mut crls = CRLStore::empty();
+  let mut crlbuf = io::BufReader::new(
+    fs::File::open(format!("test-ca/rsa/{}.crl", name)).unwrap()
+  );
+  assert_eq!(crls.add_pem_file(&mut crlbuf), Ok((1, 0)));
+  crls
+}
+
+fn crl_connect(client_config: ClientConfig,
+               server_config: ServerConfig) -> (Result<(), TLSError>, Result<(), TLSError>) {
+  let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
+  let mut server = ServerSession::new(&Arc::new(server_config));
+
+  while server.is_handshaking() || client.is_handshaking() {
+    transfer(&mut client, &mut server);
+    if let Err(err) = server.process_new_packets() {
+      return (Ok(()), Err(err));
+    }
+    transfer(&mut server, &mut client);
+    if let Err(err) = client.process_new_packets() {
+      return (Err(err), Ok(()));
+    }
+  }
+
+  (Ok(()), Ok(()))
+}
+
+#[test]
+fn client_checks_server_cert_against_crls() {
+  let mut client_config = make_client_config();
+  client_config.root_store.set_crls(get_crls("inter.empty"));
+  assert_eq!(crl_connect(client_config, make_server_config()),
+             (Ok(()), Ok(())));
+
+  for name in vec!["inter.revoked", "ca.revoked"] {
+    let mut client_config = make_client_config();
+    client_config.root_store.set_crls(get_crls(name));
+    assert_eq!(crl_connect(client_config, make_server_config()),
+               (Err(TLSError::CertificateRevoked), Ok(())));
+  }
+}
+
+#[test]
+fn server_checks_client_cert_against_crls() {
+  for (name, expect) in vec![("inter.empty", Ok(())),
+                             ("inter.revoked", Err(TLSError::CertificateRevoked)),
+                             ("ca.revoked", Err(TLSError::CertificateRevoked))] {
+    let mut client_config = make_client_config();
+    client_config.set_single_client_cert(get_chain(), get_key()).unwrap();
+
+    let mut roots = RootCertStore::empty();
+    roots.add(&get_chain()[2]).unwrap();
+    roots.set_crls(get_crls(name));
+
+    let mut server_config = make_server_config();
+    server_config.client_cert_verifier = Box::new(WebPKIClientVerifier::new(roots, true));
+
+    let (_, server_result) = crl_connect(client_config, server_config);
+    assert_eq!(server_result, expect);
+  }
+}
+
+struct RejectingClientVerifier {}
+
+impl ClientCertVerifier for RejectingClientVerifier {
+  fn offer_client_auth(&self) -> bool { true }
+
+  fn client_auth_mandatory(&self) -> bool { true }
+
+  fn client_auth_root_subjects(&self) -> DistinguishedNames {
+    DistinguishedNames::new()
+  }
+
+  fn verify_client_cert(&self, presented_certs: &[ASN1Cert]) -> Result<(), TLSError> {
+    assert_eq!(presented_certs.len(), get_chain().len());
+    Err(TLSError::General("rejected".to_string()))
+  }
+}
+
+#[test]
+fn server_client_verifier_can_reject() {
+  for_each_version(|version| {
+    let mut client_config = make_client_config();
+    let mut server_config = make_server_config();
+
+    client_config.versions = vec![version];
+    client_config.set_single_client_cert(get_chain(), get_key()).unwrap();
+    server_config.client_cert_verifier = Box::new(RejectingClientVerifier {});
+
+    let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
+    let mut server = ServerSession::new(&Arc::new(server_config));
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    transfer(&mut client, &mut server);
+    assert_eq!(server.process_new_packets(),
+               Err(TLSError::General("rejected".to_string())));
+  });
 }
 
 fn check_read_and_close(reader: &mut io::Read, expect: &[u8]) {
@@ -240,7 +1800,7 @@ fn server_close_notify() {
   let mut server_config = make_server_config();
 
   server_config.set_client_auth_roots(get_chain(), true);
-  client_config.set_single_client_cert(get_chain(), get_key());
+  client_config.set_single_client_cert(get_chain(), get_key()).unwrap();
 
   let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
   let mut server = ServerSession::new(&Arc::new(server_config));
@@ -267,7 +1827,7 @@ fn client_close_notify() {
   let mut server_config = make_server_config();
 
   server_config.set_client_auth_roots(get_chain(), true);
-  client_config.set_single_client_cert(get_chain(), get_key());
+  client_config.set_single_client_cert(get_chain(), get_key()).unwrap();
 
   let mut client = ClientSession::new(&Arc::new(client_config), "localhost");
   let mut server = ServerSession::new(&Arc::new(server_config));
@@ -106,6 +106,7 @@ pub struct TlsClient {
   pub cache: Option<String>,
   pub suites: Vec<String>,
   pub protos: Vec<String>,
+  pub protovers: Vec<String>,
   pub no_tickets: bool,
   pub verbose: bool,
   pub mtu: Option<usize>,
@@ -129,6 +130,7 @@ impl TlsClient {
       mtu: None,
       suites: Vec::new(),
       protos: Vec::new(),
+      protovers: Vec::new(),
       expect_fails: false,
       expect_output: Vec::new(),
       expect_log: Vec::new()
@@ -192,6 +194,11 @@ impl TlsClient {
     self
   }
 
+  pub fn protover(&mut self, version: &str) -> &mut TlsClient {
+    self.protovers.push(version.to_string());
+    self
+  }
+
   pub fn fails(&mut self) -> &mut TlsClient {
     self.expect_fails = true;
     self
@@ -244,6 +251,11 @@ impl TlsClient {
       args.push(proto.as_ref());
     }
 
+    for version in &self.protovers {
+      args.push("--protover");
+      args.push(version.as_ref());
+    }
+
     if self.verbose {
       args.push("--verbose");
     }
@@ -399,8 +411,10 @@ pub struct TlsServer {
   pub cafile: String,
   pub suites: Vec<String>,
   pub protos: Vec<String>,
+  pub protovers: Vec<String>,
   used_suites: Vec<String>,
   used_protos: Vec<String>,
+  used_protovers: Vec<String>,
   pub resumes: bool,
   pub tickets: bool,
   pub client_auth_roots: String,
@@ -422,8 +436,10 @@ impl TlsServer {
       verbose: false,
       suites: Vec::new(),
       protos: Vec::new(),
+      protovers: Vec::new(),
       used_suites: Vec::new(),
       used_protos: Vec::new(),
+      used_protovers: Vec::new(),
       resumes: false,
       tickets: false,
       client_auth_roots: String::new(),
@@ -464,6 +480,11 @@ impl TlsServer {
     self
   }
 
+  pub fn protover(&mut self, version: &str) -> &mut Self {
+    self.protovers.push(version.to_string());
+    self
+  }
+
   pub fn resumes(&mut self) -> &mut Self {
     self.resumes = true;
     self
@@ -506,6 +527,12 @@ impl TlsServer {
       args.push(proto.as_ref());
     }
 
+    self.used_protovers = self.protovers.clone();
+    for version in &self.used_protovers {
+      args.push("--protover");
+      args.push(version.as_ref());
+    }
+
     if self.resumes {
       args.push("--resumption");
     }
@@ -10,6 +10,7 @@ fn curve_nistp256() {
   server.arg("-named_curve").arg("prime256v1");
   server.run();
   server.client()
+    .protover("1.2")
     .verbose()
     .expect_log("ECDHE curve is ECParameters { curve_type: NamedCurve, named_curve: secp256r1 }")
     .go();
@@ -22,6 +23,7 @@ fn curve_nistp384() {
   server.arg("-named_curve").arg("secp384r1");
   server.run();
   server.client()
+    .protover("1.2")
     .verbose()
     .expect_log("ECDHE curve is ECParameters { curve_type: NamedCurve, named_curve: secp384r1 }")
     .go();
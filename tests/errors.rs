@@ -11,6 +11,7 @@ fn no_tls12() {
   server.run();
 
   server.client()
+    .protover("1.2")
     .verbose()
     .fails()
     .expect_log("TLS alert received:")
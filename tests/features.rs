@@ -24,12 +24,14 @@ fn alpn_offer() {
 
   // Basic workingness.
   server.client()
+    .protover("1.2")
     .proto("breakfast")
     .expect_log("ALPN protocol is Some(\"breakfast\")")
     .go();
 
   // Client preference has little effect (we're testing openssl here really)
   server.client()
+    .protover("1.2")
     .proto("edgware")
     .proto("ponytown")
     .expect_log("ALPN protocol is Some(\"ponytown\")")
@@ -39,6 +41,7 @@ fn alpn_offer() {
   // (Except it doesn't, because openssl rightly ignores this part
   // of the RFC.)
   server.client()
+    .protover("1.2")
     .proto("mayfair")
     .expect_log("ALPN protocol is None")
     .go();
@@ -87,6 +90,7 @@ fn client_auth_by_client() {
   server.run();
 
   server.client()
+    .protover("1.2")
     .client_auth("test-ca/rsa/end.fullchain", "test-ca/rsa/end.rsa")
     .expect_log("Got CertificateRequest")
     .expect_log("Attempting client auth")
@@ -104,8 +108,9 @@ fn client_auth_requested_but_unsupported() {
   server.run();
 
   server.client()
+    .protover("1.2")
     .expect_log("Got CertificateRequest")
-    .expect_log("Client auth requested but no cert/sigalg available")
+    .expect_log("Client auth requested but no cert selected")
     .expect("no client certificate available\n")
     .expect("Ciphers common between both SSL end points:\n")
     .go();
@@ -120,8 +125,9 @@ fn client_auth_required_but_unsupported() {
   server.run();
 
   server.client()
+    .protover("1.2")
     .expect_log("Got CertificateRequest")
-    .expect_log("Client auth requested but no cert/sigalg available")
+    .expect_log("Client auth requested but no cert selected")
     .expect("TLS error: AlertReceived(HandshakeFailure)")
     .fails()
     .go();
@@ -132,7 +138,8 @@ fn client_auth_required_but_unsupported() {
 #[test]
 fn client_auth_by_server_accepted() {
   let mut server = TlsServer::new(9050);
-  server.client_auth_roots("test-ca/rsa/client.chain")
+  server.protover("1.2")
+        .client_auth_roots("test-ca/rsa/client.chain")
         .http_mode()
         .run();
 
@@ -154,7 +161,8 @@ fn client_auth_by_server_accepted() {
 #[test]
 fn client_auth_by_server_required() {
   let mut server = TlsServer::new(9060);
-  server.client_auth_roots("test-ca/rsa/client.chain")
+  server.protover("1.2")
+        .client_auth_roots("test-ca/rsa/client.chain")
         .client_auth_required()
         .http_mode()
         .run();
@@ -183,6 +191,7 @@ fn client_resumes() {
   // no resumption without client support
   for _ in 0..2 {
     server.client()
+      .protover("1.2")
       .no_tickets()
       .expect_log("No cached session for")
       .expect_log("Not resuming any session")
@@ -193,6 +202,7 @@ fn client_resumes() {
   let _ = fs::remove_file(cache_filename);
 
   server.client()
+    .protover("1.2")
     .cache(cache_filename)
     .no_tickets()
     .expect_log("No cached session for")
@@ -202,6 +212,7 @@ fn client_resumes() {
     .go();
 
   server.client()
+    .protover("1.2")
     .cache(cache_filename)
     .no_tickets()
     .expect_log("Resuming session")
@@ -213,7 +224,8 @@ fn client_resumes() {
 #[test]
 fn server_resumes() {
   let mut server = TlsServer::new(9080);
-  server.resumes()
+  server.protover("1.2")
+        .resumes()
         .http_mode()
         .run();
 
@@ -251,7 +263,8 @@ fn server_resumes() {
 #[test]
 fn server_resumes_with_tickets() {
   let mut server = TlsServer::new(9090);
-  server.tickets()
+  server.protover("1.2")
+        .tickets()
         .http_mode()
         .run();
 